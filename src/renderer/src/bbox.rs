@@ -1,7 +1,7 @@
-use crate::vec::{Point3};
+use crate::vec::{Point3, Vec3};
 use crate::ray::Ray;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct AaBb {
     pub min: Point3,
     pub max: Point3,
@@ -13,7 +13,7 @@ impl AaBb {
         AaBb { min, max }
     }
 
-    pub fn hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> bool {
+    pub fn hit(&self, ray: &Ray, mut tmin: f32, mut tmax: f32) -> bool {
         for a in 0..3 {
             let invd = 1.0 / ray.direction[a];
             let mut t0 = (self.min[a] - ray.origin[a]) * invd;
@@ -21,8 +21,8 @@ impl AaBb {
             if invd < 0.0f32 {
                 std::mem::swap(&mut t0, &mut t1);
             }
-            let tmin = if t0 > tmin { t0 } else { tmin };
-            let tmax = if t1 > tmax { t1 } else { tmax };
+            tmin = if t0 > tmin { t0 } else { tmin };
+            tmax = if t1 < tmax { t1 } else { tmax };
             if tmax <= tmin {
                 return false;
             }
@@ -30,6 +30,47 @@ impl AaBb {
         true
     }
 
+    /// Slab test with a precomputed `1 / direction`, returns the entry distance.
+    /// Zero components need a large finite inverse, `inf` turns into NaN on the slab planes.
+    #[inline]
+    pub fn intersect(&self, origin: &Point3, inv_dir: &Vec3, t_min: f32, t_max: f32) -> Option<f32> {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for a in 0..3 {
+            let near = (self.min[a] - origin[a]) * inv_dir[a];
+            let far = (self.max[a] - origin[a]) * inv_dir[a];
+            t0 = t0.max(near.min(far));
+            t1 = t1.min(near.max(far));
+        }
+        if t0 <= t1 {
+            Some(t0)
+        } else {
+            None
+        }
+    }
+
+    pub fn empty() -> AaBb {
+        AaBb::new(Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+                  Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY))
+    }
+
+    pub fn grow(&mut self, p: &Point3) {
+        self.min = Point3::new(self.min.x.min(p.x), self.min.y.min(p.y), self.min.z.min(p.z));
+        self.max = Point3::new(self.max.x.max(p.x), self.max.y.max(p.y), self.max.z.max(p.z));
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        (&self.min + &self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = &self.max - &self.min;
+        if d.x < 0.0 {
+            return 0.0;
+        }
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
     pub fn surrounding_box(box0: &AaBb, box1: &AaBb) -> AaBb {
         let small = Point3::new(f32::min(box0.min.x, box1.min.x),
                                 f32::min(box0.min.y, box1.min.y),
@@ -56,6 +97,37 @@ mod tests {
         let  ray = Ray::new(&Point3::new(1.0, 1.0, 0.0), &Vec3::new(2.0, 2.0, 0.0));
         assert!(aabb.hit(&ray, 0.0001, f32::INFINITY));
         let  ray = Ray::new(&Point3::new(1.0, 1.0, 0.0), &Vec3::new(2.0, 1.0, 0.0));
+        // Enters at the (3, 2) corner and leaves through x = 5 at y = 3
+        assert!(aabb.hit(&ray, 0.0001, f32::INFINITY));
+    }
+
+    #[test]
+    fn test_hit_slabs() {
+        let aabb = AaBb::new(Point3::new(3.0, 2.0, 0.0), Point3::new(5.0, 4.0, 0.0));
+        // Inside the x slab for t in 1..2, only entering the y slab at t = 3
+        let ray = Ray::new(&Point3::new(1.0, -1.0, 0.0), &Vec3::new(2.0, 1.0, 0.0));
+        assert!(!aabb.hit(&ray, 0.0001, f32::INFINITY));
+        let ray = Ray::new(&Point3::new(1.0, 1.0, 0.0), &Vec3::new(2.0, -1.0, 0.0));
         assert!(!aabb.hit(&ray, 0.0001, f32::INFINITY));
+        // The box is in front of the ray, but past t_max
+        let ray = Ray::new(&Point3::new(1.0, 1.0, 0.0), &Vec3::new(2.0, 2.0, 0.0));
+        assert!(!aabb.hit(&ray, 0.0001, 0.5));
+    }
+
+    #[test]
+    fn test_intersect() {
+        let aabb = AaBb::new(Point3::new(-1.0, -1.0, -1.0), Point3::ONE);
+        let inv = Vec3::new(1.0, 1e30, 1e30);
+        let t = aabb.intersect(&Point3::new(-5.0, 0.0, 0.0), &inv, 0.0, f32::INFINITY);
+        assert_eq!(t, Some(4.0));
+        assert_eq!(aabb.intersect(&Point3::new(-5.0, 2.0, 0.0), &inv, 0.0, f32::INFINITY), None);
+        assert_eq!(aabb.intersect(&Point3::new(-5.0, 0.0, 0.0), &inv, 0.0, 3.0), None);
+        // On the lower boundary plane, the upper one belongs to the next box
+        assert_eq!(aabb.intersect(&Point3::new(-5.0, -1.0, 0.0), &inv, 0.0, f32::INFINITY), Some(4.0));
+        let mut b = AaBb::empty();
+        assert_eq!(b.surface_area(), 0.0);
+        b.grow(&Point3::ZERO);
+        b.grow(&Point3::new(1.0, 2.0, 3.0));
+        assert_eq!(b.surface_area(), 22.0);
     }
 }
\ No newline at end of file
@@ -1,25 +1,311 @@
-use crate::hittable::{Hittable};
-use std::rc::Rc;
+use crate::bbox::AaBb;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec::{Point3, Vec3};
+use std::sync::Arc;
 
+const MAX_LEAF: usize = 4;
+const BINS: usize = 12;
+/// Below this depth splits are balanced, which bounds the traversal stack
+const MAX_SAH_DEPTH: usize = 32;
 
-pub struct BVH {
-    pub left: Rc<dyn Hittable>,
-    pub right: Rc<dyn Hittable>,
+/// Interior nodes have `count == 0`, the left child follows the node and `offset` is the right
+/// child. Leaves hold `indices[offset..offset + count]`.
+#[derive(Clone, Debug)]
+struct Node {
+    bbox: AaBb,
+    offset: u32,
+    count: u32,
 }
 
-impl BVH {
-    pub fn new() -> BVH {
-        unimplemented!()
+/// Flat BVH over primitive bounds, built with binned SAH.
+///
+/// It only knows boxes, the owner intersects its own primitives in the `traverse` callback.
+/// Meshes use it over triangles, `Bvh` over objects.
+#[derive(Clone, Debug, Default)]
+pub struct BvhTree {
+    nodes: Vec<Node>,
+    indices: Vec<u32>,
+}
+
+impl BvhTree {
+    pub fn build(bounds: &[AaBb]) -> BvhTree {
+        let mut tree = BvhTree { nodes: vec![], indices: (0..bounds.len() as u32).collect() };
+        if !bounds.is_empty() {
+            let centroids: Vec<Point3> = bounds.iter().map(|b| b.centroid()).collect();
+            tree.build_node(bounds, &centroids, 0, bounds.len(), 0);
+        }
+        tree
+    }
+
+    pub fn bbox(&self) -> Option<&AaBb> {
+        self.nodes.first().map(|n| &n.bbox)
+    }
+
+    fn build_node(&mut self, bounds: &[AaBb], centroids: &[Point3], start: usize, end: usize, depth: usize) {
+        let mut bbox = AaBb::empty();
+        let mut centroid_box = AaBb::empty();
+        for i in &self.indices[start..end] {
+            bbox = AaBb::surrounding_box(&bbox, &bounds[*i as usize]);
+            centroid_box.grow(&centroids[*i as usize]);
+        }
+        let node = self.nodes.len();
+        self.nodes.push(Node { bbox: bbox.clone(), offset: start as u32, count: (end - start) as u32 });
+        let count = end - start;
+        if count <= MAX_LEAF {
+            return;
+        }
+        let extent = &centroid_box.max - &centroid_box.min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        if extent[axis] <= 0.0 {
+            return;
+        }
+        let (lo, scale) = (centroid_box.min[axis], BINS as f32 / extent[axis]);
+        let bin = |c: &Point3| (((c[axis] - lo) * scale) as usize).min(BINS - 1);
+
+        let mut bin_boxes = vec![AaBb::empty(); BINS];
+        let mut bin_counts = [0usize; BINS];
+        for i in &self.indices[start..end] {
+            let b = bin(&centroids[*i as usize]);
+            bin_boxes[b] = AaBb::surrounding_box(&bin_boxes[b], &bounds[*i as usize]);
+            bin_counts[b] += 1;
+        }
+        // Cost of splitting after each bin, sweeping from the right first
+        let mut right_cost = [0.0f32; BINS];
+        let (mut acc, mut n) = (AaBb::empty(), 0);
+        for b in (1..BINS).rev() {
+            acc = AaBb::surrounding_box(&acc, &bin_boxes[b]);
+            n += bin_counts[b];
+            right_cost[b - 1] = acc.surface_area() * n as f32;
+        }
+        let (mut acc, mut n) = (AaBb::empty(), 0);
+        let mut best = (f32::INFINITY, 0);
+        for b in 0..BINS - 1 {
+            acc = AaBb::surrounding_box(&acc, &bin_boxes[b]);
+            n += bin_counts[b];
+            let cost = acc.surface_area() * n as f32 + right_cost[b];
+            if n > 0 && n < count && cost < best.0 {
+                best = (cost, b);
+            }
+        }
+        let leaf_cost = bbox.surface_area() * count as f32;
+        if best.0 >= leaf_cost && count <= 4 * MAX_LEAF {
+            return;
+        }
+
+        let mut mid = start;
+        if best.0.is_finite() && depth < MAX_SAH_DEPTH {
+            for i in start..end {
+                if bin(&centroids[self.indices[i] as usize]) <= best.1 {
+                    self.indices.swap(i, mid);
+                    mid += 1;
+                }
+            }
+        } else {
+            // All centroids in one bin or a very deep tree, fall back to a median split
+            mid = (start + end) / 2;
+            self.indices[start..end].select_nth_unstable_by(mid - start, |a, b| {
+                let (a, b) = (centroids[*a as usize][axis], centroids[*b as usize][axis]);
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            });
+        }
+        self.build_node(bounds, centroids, start, mid, depth + 1);
+        let right = self.nodes.len() as u32;
+        self.build_node(bounds, centroids, mid, end, depth + 1);
+        self.nodes[node].offset = right;
+        self.nodes[node].count = 0;
+    }
+
+    /// Visit the primitives whose boxes the ray enters, near to far. `hit(prim, t_max)` returns
+    /// the distance of a hit closer than `t_max`, which then shrinks the search.
+    pub fn traverse(&self, ray: &Ray, t_min: f32, mut t_max: f32, mut hit: impl FnMut(usize, f32) -> Option<f32>) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let inv = |d: f32| if d == 0.0 { 1e30 } else { 1.0 / d };
+        let d = &ray.direction;
+        let inv_dir = Vec3::new(inv(d.x), inv(d.y), inv(d.z));
+        let origin = &ray.origin;
+        if self.nodes[0].bbox.intersect(origin, &inv_dir, t_min, t_max).is_none() {
+            return false;
+        }
+        let mut been_hit = false;
+        let mut stack = [0u32; 64];
+        let mut sp = 1;
+        while sp > 0 {
+            sp -= 1;
+            let node = &self.nodes[stack[sp] as usize];
+            if node.count > 0 {
+                let first = node.offset as usize;
+                for i in &self.indices[first..first + node.count as usize] {
+                    if let Some(t) = hit(*i as usize, t_max) {
+                        t_max = t;
+                        been_hit = true;
+                    }
+                }
+                continue;
+            }
+            let (left, right) = (stack[sp] + 1, node.offset);
+            let tl = self.nodes[left as usize].bbox.intersect(origin, &inv_dir, t_min, t_max);
+            let tr = self.nodes[right as usize].bbox.intersect(origin, &inv_dir, t_min, t_max);
+            // Push the far child first so the near one is popped next
+            let mut push = |n: u32| {
+                stack[sp] = n;
+                sp += 1;
+            };
+            match (tl, tr) {
+                (Some(l), Some(r)) if l <= r => {
+                    push(right);
+                    push(left);
+                }
+                (Some(_), Some(_)) => {
+                    push(left);
+                    push(right);
+                }
+                (Some(_), None) => push(left),
+                (None, Some(_)) => push(right),
+                (None, None) => {}
+            }
+        }
+        been_hit
+    }
+}
+
+/// Hittable objects in a BVH. Objects without bounds are kept aside and always tested.
+///
+/// As the world's top level over instances, and inside a group as the bottom level of the
+/// geometry all its instances share.
+pub struct Bvh {
+    pub objects: Vec<Arc<dyn Hittable>>,
+    unbounded: Vec<Arc<dyn Hittable>>,
+    tree: BvhTree,
+}
+
+impl Bvh {
+    pub fn new(objects: Vec<Arc<dyn Hittable>>) -> Bvh {
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = objects.into_iter().partition(|o| o.bbox(0.0, 1.0).is_some());
+        let bounds: Vec<AaBb> = bounded.iter().map(|o| o.bbox(0.0, 1.0).unwrap()).collect();
+        Bvh { tree: BvhTree::build(&bounds), objects: bounded, unbounded }
+    }
+}
 
+impl Hittable for Bvh {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let mut closest_so_far = t_max;
+        let mut been_hit = false;
+        for obj in &self.unbounded {
+            if rec.hit_candidate(obj.as_ref(), ray, t_min, closest_so_far) {
+                been_hit = true;
+                closest_so_far = rec.t;
+            }
+        }
+        let objects = &self.objects;
+        let tree_hit = self.tree.traverse(ray, t_min, closest_so_far, |i, t_max| {
+            if rec.hit_candidate(objects[i].as_ref(), ray, t_min, t_max) {
+                Some(rec.t)
+            } else {
+                None
+            }
+        });
+        been_hit || tree_hit
+    }
+
+    fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.tree.bbox().cloned()
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        None
+    }
+
+    fn set_material(&mut self, _mat: Arc<dyn Material>) {}
+
+    fn name(&self) -> &'static str {
+        "Bvh"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        let objects = self.unbounded.iter().chain(&self.objects).map(|o| o.describe(writer));
+        Ok(ObjectDesc::Group { objects: objects.collect::<Result<_, _>>()? })
     }
 }
 
-// impl Hittable for BVH {
-//     fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
-//         unimplemented!()
-//     }
-//
-//     fn bbox(&self, t0: f32, t1: f32) -> Option<AaBb> {
-//         unimplemented!()
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::HittableList;
+    use crate::{CurveKind, CurveSegment, Curves, Lambertian, Sphere};
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_bvh_matches_list() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(3);
+        let objects: Vec<Arc<dyn Hittable>> = (0..500)
+            .map(|_| {
+                let c = Vec3::random_in(-10.0, 10.0, &mut rng);
+                Arc::new(Sphere::new(c, rng.gen_range(0.05, 0.5), None)) as Arc<dyn Hittable>
+            })
+            .collect();
+        let list = HittableList::new(objects.clone());
+        let bvh = Bvh::new(objects);
+        let bbox = bvh.bbox(0.0, 1.0).unwrap();
+        assert_eq!(bbox, list.bbox(0.0, 1.0).unwrap());
+
+        let mat = Lambertian { color: Vec3::ONE };
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let ray = Ray::new(&Vec3::random_in(-12.0, 12.0, &mut rng), &Vec3::random_in(-1.0, 1.0, &mut rng));
+            let (mut a, mut b) = (HitRecord::new(&mat), HitRecord::new(&mat));
+            let hit = list.hit(&ray, 0.001, f32::INFINITY, &mut a);
+            assert_eq!(hit, bvh.hit(&ray, 0.001, f32::INFINITY, &mut b));
+            if hit {
+                hits += 1;
+                assert!((a.t - b.t).abs() < 1e-4);
+            }
+        }
+        assert!(hits > 100);
+    }
+
+    #[test]
+    fn test_fresh_records() {
+        // The strand behind the sphere is hit first, its tangent must not stick to the sphere hit
+        let strand = CurveSegment::new([Point3::new(-2.0, 0.0, -5.0), Point3::new(-1.0, 0.0, -5.0), Point3::new(1.0, 0.0, -5.0), Point3::new(2.0, 0.0, -5.0)], [0.2, 0.2]);
+        let curves: Arc<dyn Hittable> = Arc::new(Curves::new(vec![strand], CurveKind::Cylinder, None));
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new((0.0, 0.0, 0.0), 1.0, None));
+        let list = HittableList::new(vec![curves, sphere]);
+        let mat = Lambertian { color: Vec3::ONE };
+        let mut rec = HitRecord::new(&mat);
+        let ray = Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(list.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.t - 4.0).abs() < 1e-4);
+        assert_eq!(rec.tangent, Vec3::ZERO);
+    }
+
+    #[test]
+    fn test_degenerate() {
+        // Identical centroids leave no axis to split along, all boxes end in a single leaf
+        let bounds = vec![AaBb::new(Point3::ZERO, Point3::ONE); 100];
+        let tree = BvhTree::build(&bounds);
+        assert_eq!(tree.nodes.len(), 1);
+        let ray = Ray::new(&Vec3::new(0.5, 0.5, -1.0), &Vec3::new(0.0, 0.0, 1.0));
+        let mut visited = 0;
+        tree.traverse(&ray, 0.0, f32::INFINITY, |_, _| {
+            visited += 1;
+            None
+        });
+        assert_eq!(visited, 100);
+        assert!(BvhTree::build(&[]).bbox().is_none());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::ray::Ray;
 use crate::vec::{Point3, Vec3};
-use std::str::FromStr;
+use rand::Rng;
 
 #[derive(Clone, Default, Debug)]
 pub struct Camera {
@@ -9,14 +9,17 @@ pub struct Camera {
     pub horizontal: Vec3,
     pub vertical: Vec3,
     pub lens_radius: f32,
+    /// Rays get times spread evenly over `shutter_open..shutter_close`
+    pub shutter_open: f32,
+    pub shutter_close: f32,
     u: Vec3,
     v: Vec3,
     w: Vec3,
 }
 
 impl Camera {
-    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, vfov: u32, aspect_ratio: f32, aperture: f32, focus_dist: f32) -> Camera {
-        let theta = vfov as f32 * std::f32::consts::PI / 180.0;
+    pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, vfov: f32, aspect_ratio: f32, aperture: f32, focus_dist: f32) -> Camera {
+        let theta = vfov.to_radians();
         let viewport_height = (theta / 2.0).tan() * 2.0;
         let viewport_width = aspect_ratio * viewport_height;
         let w = (&lookfrom - lookat).unit();
@@ -32,58 +35,44 @@ impl Camera {
             horizontal,
             vertical,
             lens_radius: aperture / 2.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
             u,
             v,
             w,
         }
     }
 
+    pub fn with_shutter(mut self, open: f32, close: f32) -> Camera {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    /// Stretch the viewport horizontally to match the aspect ratio of the image.
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
+        let current = self.horizontal.length() / self.vertical.length();
+        if !current.is_finite() || current == 0.0 {
+            return;
+        }
+        let horizontal = &self.horizontal * (aspect_ratio / current);
+        self.lower_left_corner = &self.lower_left_corner + &self.horizontal / 2.0 - &horizontal / 2.0;
+        self.horizontal = horizontal;
+    }
+
     pub fn get_ray(&self, s: f32, t: f32, rng: &mut impl rand::RngCore) -> Ray {
         let rd = Vec3::random_unit_vector(rng) * self.lens_radius;
         let offset = &self.u * rd.x + &self.v * rd.y;
+        // A closed shutter takes no random numbers, still images render as before
+        let time = if self.shutter_close > self.shutter_open {
+            self.shutter_open + rng.gen::<f32>() * (self.shutter_close - self.shutter_open)
+        } else {
+            self.shutter_open
+        };
         Ray::new(
             &(&self.origin + &offset),
             &(&self.lower_left_corner + &self.horizontal * s + &self.vertical * t - &self.origin - &offset),
         )
-    }
-}
-
-impl FromStr for Camera {
-    type Err = ();
-
-    /// camera [20 10 10] [0 1 0] [30] [0.04]
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.is_empty() || !s.starts_with("camera") {
-            return Err(());
-        }
-        let mut values = s.split(" ");
-
-        fn next<T: std::str::FromStr>(iter: &mut std::str::Split<&str>) -> Result<T, ()> {
-            match iter.next() {
-                Some(v) => v.parse::<T>().map_err(|_| ()),
-                None => Err(())
-            }
-        }
-        let _ = next::<String>(&mut values);
-        let lookfrom = Point3::new(
-            next::<u32>(&mut values)? as f32,
-            next::<u32>(&mut values)? as f32,
-            next::<u32>(&mut values)? as f32);
-        let lookat = Point3::new(
-            next::<u32>(&mut values)? as f32,
-            next::<u32>(&mut values)? as f32,
-            next::<u32>(&mut values)? as f32);
-        let fov = next::<u32>(&mut values)?;
-        let app = next::<f32>(&mut values)?;
-        let focus = (&lookfrom - &lookat).length();
-        Ok(Camera::new(
-            lookfrom,
-            lookat,
-            Vec3::new(0.0, 1.0, 0.0),
-            fov,
-            1.7777,
-            app,
-            focus,
-        ))
+        .with_time(time)
     }
 }
@@ -0,0 +1,271 @@
+use crate::errors::SpriosError;
+use crate::sampler::Distribution;
+use crate::settings::RenderSettings;
+use crate::vec::Vec3;
+use crate::light::Light;
+use crate::World;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const MAGIC: &[u8; 8] = b"SPRCKPT\0";
+const VERSION: u32 = 3;
+
+/// Where and how often `render_from` saves the state of a progressive render.
+#[derive(Clone, Debug)]
+pub struct CheckpointConfig {
+    pub path: PathBuf,
+    pub interval: Duration,
+    pub scene_hash: u64,
+}
+
+impl CheckpointConfig {
+    pub fn new(path: impl Into<PathBuf>, interval: Duration, world: &World) -> CheckpointConfig {
+        CheckpointConfig {
+            path: path.into(),
+            interval,
+            scene_hash: scene_hash(world),
+        }
+    }
+}
+
+/// Accumulation film and everything needed to continue sampling it.
+pub struct Checkpoint {
+    pub settings: RenderSettings,
+    pub scene_hash: u64,
+    /// Number of completed passes over the whole image
+    pub samples_done: u32,
+    pub film: Vec<f32>,
+}
+
+impl Checkpoint {
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SpriosError> {
+        let path = path.as_ref();
+        // Write next to the target and rename, a crash never leaves a truncated checkpoint
+        let tmp = path.with_extension("tmp");
+        self.write(&tmp).map_err(|e| checkpoint_error(&tmp, e))?;
+        std::fs::rename(&tmp, path).map_err(|e| checkpoint_error(path, e))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Checkpoint, SpriosError> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).map_err(|e| checkpoint_error(path, e))?;
+        let mut r = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic).map_err(|e| checkpoint_error(path, e))?;
+        if &magic != MAGIC {
+            return Err(SpriosError::CheckpointError(format!("{} is not a checkpoint file", path.display())));
+        }
+        Self::read(&mut r).map_err(|e| checkpoint_error(path, e))
+    }
+
+    fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut w = BufWriter::new(std::fs::File::create(path)?);
+        let s = &self.settings;
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        for v in &[s.width, s.height, s.bucket, s.samples] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        let distribution: u8 = match s.distribution {
+            Distribution::Random => 0,
+            Distribution::Jittered => 1,
+        };
+        w.write_all(&[distribution, s.denoise as u8, s.shutter.is_some() as u8])?;
+        w.write_all(&s.seed.to_le_bytes())?;
+        let (open, close) = s.shutter.unwrap_or((0.0, 0.0));
+        w.write_all(&open.to_bits().to_le_bytes())?;
+        w.write_all(&close.to_bits().to_le_bytes())?;
+        w.write_all(&self.scene_hash.to_le_bytes())?;
+        w.write_all(&self.samples_done.to_le_bytes())?;
+        for v in &self.film {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        w.flush()
+    }
+
+    fn read(r: &mut impl Read) -> std::io::Result<Checkpoint> {
+        use std::io::{Error, ErrorKind};
+        fn u32_(r: &mut impl Read) -> std::io::Result<u32> {
+            let mut b = [0u8; 4];
+            r.read_exact(&mut b)?;
+            Ok(u32::from_le_bytes(b))
+        }
+        fn u64_(r: &mut impl Read) -> std::io::Result<u64> {
+            let mut b = [0u8; 8];
+            r.read_exact(&mut b)?;
+            Ok(u64::from_le_bytes(b))
+        }
+        let version = u32_(r)?;
+        if version != VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, format!("unsupported version {}", version)));
+        }
+        let (width, height, bucket, samples) = (u32_(r)?, u32_(r)?, u32_(r)?, u32_(r)?);
+        let mut flags = [0u8; 3];
+        r.read_exact(&mut flags)?;
+        let distribution = match flags[0] {
+            0 => Distribution::Random,
+            1 => Distribution::Jittered,
+            v => return Err(Error::new(ErrorKind::InvalidData, format!("unknown distribution {}", v))),
+        };
+        let seed = u64_(r)?;
+        let (open, close) = (f32::from_bits(u32_(r)?), f32::from_bits(u32_(r)?));
+        let scene_hash = u64_(r)?;
+        let samples_done = u32_(r)?;
+        let values = (width as usize).checked_mul(height as usize).and_then(|n| n.checked_mul(3));
+        let values = values.ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("image size {}x{} too large", width, height)))?;
+        let film = (0..values)
+            .map(|_| u32_(r).map(f32::from_bits))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Checkpoint {
+            settings: RenderSettings {
+                width,
+                height,
+                bucket,
+                samples,
+                distribution,
+                denoise: flags[1] != 0,
+                seed,
+                time_limit: None,
+                shutter: if flags[2] != 0 { Some((open, close)) } else { None },
+            },
+            scene_hash,
+            samples_done,
+            film,
+        })
+    }
+}
+
+fn checkpoint_error(path: &Path, e: std::io::Error) -> SpriosError {
+    SpriosError::CheckpointError(format!("{}: {}", path.display(), e))
+}
+
+struct Fnv(u64);
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+
+    fn write_vec(&mut self, v: &Vec3) {
+        for c in &[v.x, v.y, v.z] {
+            self.write(&c.to_bits().to_le_bytes());
+        }
+    }
+}
+
+/// Stable fingerprint of the camera, background and objects of a world,
+/// used to refuse resuming a checkpoint against a different scene.
+pub fn scene_hash(world: &World) -> u64 {
+    let mut h = Fnv(0xcbf2_9ce4_8422_2325);
+    let cam = &world.camera;
+    h.write_vec(&cam.origin);
+    h.write_vec(&cam.lower_left_corner);
+    h.write_vec(&cam.horizontal);
+    h.write_vec(&cam.vertical);
+    h.write(&cam.lens_radius.to_bits().to_le_bytes());
+    h.write(&cam.shutter_open.to_bits().to_le_bytes());
+    h.write(&cam.shutter_close.to_bits().to_le_bytes());
+    h.write_vec(&world.background);
+    if let Some(env) = &world.environment {
+        h.write_vec(env);
+    }
+    if let Some(fog) = &world.fog {
+        h.write(&fog.height.unwrap_or(f32::INFINITY).to_bits().to_le_bytes());
+        h.write(&fog.medium.absorption.to_bits().to_le_bytes());
+        h.write(&fog.medium.scattering.to_bits().to_le_bytes());
+        h.write_vec(&fog.medium.color);
+        h.write(&fog.medium.g.to_bits().to_le_bytes());
+    }
+    for obj in &world.objects {
+        h.write(obj.name().as_bytes());
+        if let Some(bbox) = obj.bbox(0.0, 1.0) {
+            h.write_vec(&bbox.min);
+            h.write_vec(&bbox.max);
+        }
+        // Kind and parameters, a colour alone misses fuzz or IOR changes
+        if let Some(mat) = obj.material() {
+            match mat.describe() {
+                Some(desc) => h.write(format!("{:?}", desc).as_bytes()),
+                None => h.write_vec(mat.color()),
+            }
+        }
+    }
+    for light in &world.lights {
+        match light {
+            Light::Point { position, intensity } => {
+                h.write(b"point");
+                h.write_vec(position);
+                h.write_vec(intensity);
+            }
+            Light::Directional { direction, intensity } => {
+                h.write(b"directional");
+                h.write_vec(direction);
+                h.write_vec(intensity);
+            }
+        }
+    }
+    h.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Lambertian, Metal, Sphere, SettingsBuilder};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_save_load() {
+        let settings = SettingsBuilder::new().size(4, Some(2)).samples(3).seed(42).shutter(Some((0.0, 0.5))).build();
+        let ckpt = Checkpoint {
+            settings,
+            scene_hash: 7,
+            samples_done: 5,
+            film: (0..24).map(|v| v as f32 * 0.5).collect(),
+        };
+        let path = std::env::temp_dir().join(format!("sprios_test_save_load_{}.ckpt", std::process::id()));
+        ckpt.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.settings.width, 4);
+        assert_eq!(loaded.settings.seed, 42);
+        assert_eq!(loaded.settings.shutter, Some((0.0, 0.5)));
+        assert_eq!(loaded.samples_done, 5);
+        assert_eq!(loaded.film, ckpt.film);
+    }
+
+    #[test]
+    fn test_scene_hash() {
+        let mut world = World::new();
+        let empty = scene_hash(&world);
+        world.add(Arc::new(Sphere::new((0.0, 0.0, 0.0), 1.0, Some(Arc::new(Lambertian { color: Vec3::ONE })))));
+        assert_ne!(empty, scene_hash(&world));
+        assert_eq!(scene_hash(&world), scene_hash(&world));
+
+        // Same colour, different fuzz
+        let metal = |fuzz| {
+            let mut world = World::new();
+            world.add(Arc::new(Sphere::new((0.0, 0.0, 0.0), 1.0, Some(Arc::new(Metal { color: Vec3::ONE, fuzz })))));
+            scene_hash(&world)
+        };
+        assert_ne!(metal(0.0), metal(0.3));
+    }
+
+    #[test]
+    fn test_bad_size() {
+        let settings = SettingsBuilder::new().size(4, Some(2)).build();
+        let ckpt = Checkpoint { settings, scene_hash: 0, samples_done: 1, film: vec![0.0; 24] };
+        let path = std::env::temp_dir().join(format!("sprios_test_bad_size_{}.ckpt", std::process::id()));
+        ckpt.save(&path).unwrap();
+        // Width and height follow the magic and the version
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[12..20].copy_from_slice(&[0xff; 8]);
+        std::fs::write(&path, bytes).unwrap();
+        let error = Checkpoint::load(&path).err().unwrap().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.contains("too large"), "{}", error);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::bbox::AaBb;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec::Point3;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Saved as a plain string, RON can't read unit variants nested in tagged enums
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "&'static str")]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    /// `left` with `right` cut away
+    Difference,
+}
+
+impl FromStr for CsgOp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CsgOp, String> {
+        match s {
+            "union" => Ok(CsgOp::Union),
+            "intersection" => Ok(CsgOp::Intersection),
+            "difference" => Ok(CsgOp::Difference),
+            _ => Err(format!("unknown operation '{}'", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for CsgOp {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<CsgOp, String> {
+        s.parse()
+    }
+}
+
+impl From<CsgOp> for &'static str {
+    fn from(op: CsgOp) -> &'static str {
+        match op {
+            CsgOp::Union => "union",
+            CsgOp::Intersection => "intersection",
+            CsgOp::Difference => "difference",
+        }
+    }
+}
+
+impl CsgOp {
+    fn inside(self, a: bool, b: bool) -> bool {
+        match self {
+            CsgOp::Union => a || b,
+            CsgOp::Intersection => a && b,
+            CsgOp::Difference => a && !b,
+        }
+    }
+}
+
+/// Boolean combination of two closed solids.
+///
+/// Walks the surface crossings of both along the ray, `front_face` of each crossing
+/// tells whether the ray enters or leaves a solid.
+pub struct Csg {
+    pub op: CsgOp,
+    pub left: Arc<dyn Hittable>,
+    pub right: Arc<dyn Hittable>,
+    /// Overrides the materials of both sides
+    pub material: Option<Arc<dyn Material>>,
+}
+
+impl Csg {
+    pub fn new(op: CsgOp, left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Csg {
+        Csg { op, left, right, material: None }
+    }
+}
+
+/// Whether the ray starts inside a solid, judged by its first crossing
+fn starts_inside(records: &[HitRecord]) -> bool {
+    records.first().is_some_and(|r| !r.front_face)
+}
+
+impl Hittable for Csg {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        match self.hits(ray, t_min, t_max).into_iter().next() {
+            Some(first) => {
+                *rec = first;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn hits<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32) -> Vec<HitRecord<'obj>> {
+        // The state at t_min needs the crossings past t_max too
+        let a = self.left.hits(ray, t_min, f32::INFINITY);
+        let b = self.right.hits(ray, t_min, f32::INFINITY);
+        let (mut in_a, mut in_b) = (starts_inside(&a), starts_inside(&b));
+        let mut inside = self.op.inside(in_a, in_b);
+        let mut out = vec![];
+        let (mut a, mut b) = (a.into_iter().peekable(), b.into_iter().peekable());
+        loop {
+            let from_a = match (a.peek(), b.peek()) {
+                (Some(ra), Some(rb)) => ra.t <= rb.t,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let mut rec = if from_a { a.next().unwrap() } else { b.next().unwrap() };
+            if rec.t >= t_max {
+                break;
+            }
+            if from_a {
+                in_a = rec.front_face;
+            } else {
+                in_b = rec.front_face;
+            }
+            let now = self.op.inside(in_a, in_b);
+            if now != inside {
+                // The normal already faces the ray, only the side changes for cut surfaces
+                rec.front_face = now;
+                if let Some(mat) = &self.material {
+                    rec.mat = mat.as_ref();
+                }
+                out.push(rec);
+                inside = now;
+            }
+        }
+        out
+    }
+
+    fn bbox(&self, t0: f32, t1: f32) -> Option<AaBb> {
+        let (a, b) = (self.left.bbox(t0, t1), self.right.bbox(t0, t1));
+        match self.op {
+            CsgOp::Union => Some(AaBb::surrounding_box(&a?, &b?)),
+            CsgOp::Intersection => match (a, b) {
+                (Some(a), Some(b)) => {
+                    let min = Point3::new(a.min.x.max(b.min.x), a.min.y.max(b.min.y), a.min.z.max(b.min.z));
+                    let max = Point3::new(a.max.x.min(b.max.x), a.max.y.min(b.max.y), a.max.z.min(b.max.z));
+                    Some(AaBb::new(min, max))
+                }
+                (a, b) => a.or(b),
+            },
+            CsgOp::Difference => a,
+        }
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        match &self.material {
+            Some(mat) => Some(mat.as_ref()),
+            None => self.left.material(),
+        }
+    }
+
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        self.material = Some(mat);
+    }
+
+    fn name(&self) -> &'static str {
+        "Csg"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        let material = match &self.material {
+            Some(mat) => Some(writer.material(mat.as_ref())?),
+            None => None,
+        };
+        Ok(ObjectDesc::Csg {
+            op: self.op,
+            left: Box::new(self.left.describe(writer)?),
+            right: Box::new(self.right.describe(writer)?),
+            material,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::{Color, Vec3};
+    use crate::{Cuboid, Lambertian, Sphere};
+
+    fn crossings(object: &dyn Hittable, origin: Point3, dir: Vec3) -> Vec<(f32, bool)> {
+        let ray = Ray::new(&origin, &dir);
+        object.hits(&ray, 0.001, f32::INFINITY).iter().map(|r| (r.t, r.front_face)).collect()
+    }
+
+    fn approx(a: &[(f32, bool)], b: &[(f32, bool)]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x.0 - y.0).abs() < 1e-4 && x.1 == y.1)
+    }
+
+    #[test]
+    fn test_ops() {
+        let a: Arc<dyn Hittable> = Arc::new(Sphere::new((0.0, 0.0, 0.0), 1.0, None));
+        let b: Arc<dyn Hittable> = Arc::new(Sphere::new((1.0, 0.0, 0.0), 1.0, None));
+        let origin = Point3::new(-5.0, 0.0, 0.0);
+        let dir = Vec3::new(1.0, 0.0, 0.0);
+        // a spans 4..6 and b 5..7 along the ray
+        let union = Csg::new(CsgOp::Union, Arc::clone(&a), Arc::clone(&b));
+        assert!(approx(&crossings(&union, origin.clone(), dir.clone()), &[(4.0, true), (7.0, false)]));
+        let both = Csg::new(CsgOp::Intersection, Arc::clone(&a), Arc::clone(&b));
+        assert!(approx(&crossings(&both, origin.clone(), dir.clone()), &[(5.0, true), (6.0, false)]));
+        let cut = Csg::new(CsgOp::Difference, Arc::clone(&a), Arc::clone(&b));
+        assert!(approx(&crossings(&cut, origin.clone(), dir.clone()), &[(4.0, true), (5.0, false)]));
+        let bbox = both.bbox(0.0, 1.0).unwrap();
+        assert_eq!((bbox.min.x, bbox.max.x), (0.0, 1.0));
+
+        // The cut surface of b faces out of the result
+        let mat = Lambertian { color: Color::ONE };
+        let mut rec = HitRecord::new(&mat);
+        let back = Ray::new(&Point3::new(5.0, 0.0, 0.0), &Vec3::new(-1.0, 0.0, 0.0));
+        assert!(cut.hit(&back, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.t - 5.0).abs() < 1e-4 && rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(1.0, 0.0, 0.0));
+        // Nothing left of the cut in the middle of b
+        assert!(!cut.hit(&Ray::new(&Point3::new(1.5, 5.0, 0.0), &Vec3::new(0.0, -1.0, 0.0)), 0.001, f32::INFINITY, &mut rec));
+    }
+
+    #[test]
+    fn test_nested() {
+        // A box with a hole drilled through, starting inside the hole
+        let cube: Arc<dyn Hittable> = Arc::new(Cuboid::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0), None));
+        let drill: Arc<dyn Hittable> = Arc::new(Sphere::new((0.0, 0.0, 0.0), 0.5, None));
+        let holed: Arc<dyn Hittable> = Arc::new(Csg::new(CsgOp::Difference, cube, drill));
+        let ball: Arc<dyn Hittable> = Arc::new(Sphere::new((0.0, 0.0, 0.0), 0.25, None));
+        let both = Csg::new(CsgOp::Union, holed, ball);
+        let hits = crossings(&both, Point3::new(0.0, 0.0, 0.4), Vec3::new(0.0, 0.0, 1.0));
+        assert!(approx(&hits, &[(0.1, true), (0.6, false)]), "{:?}", hits);
+        let hits = crossings(&both, Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let expected = [(4.0, true), (4.5, false), (4.75, true), (5.25, false), (5.5, true), (6.0, false)];
+        assert!(approx(&hits, &expected), "{:?}", hits);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::bbox::AaBb;
+use crate::bvh::BvhTree;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec::{Color, Point3, Vec3};
+use crate::Lambertian;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Saved as a plain string like `CsgOp`
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "&'static str")]
+pub enum CurveKind {
+    /// Ribbon turned to face each ray, for thin hair and fur
+    Flat,
+    /// Round tube, for thick strands and grass seen up close
+    Cylinder,
+}
+
+impl FromStr for CurveKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CurveKind, String> {
+        match s {
+            "flat" => Ok(CurveKind::Flat),
+            "cylinder" => Ok(CurveKind::Cylinder),
+            _ => Err(format!("unknown curve kind '{}'", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for CurveKind {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<CurveKind, String> {
+        s.parse()
+    }
+}
+
+impl From<CurveKind> for &'static str {
+    fn from(kind: CurveKind) -> &'static str {
+        match kind {
+            CurveKind::Flat => "flat",
+            CurveKind::Cylinder => "cylinder",
+        }
+    }
+}
+
+/// Cubic Bézier segment, `u` is its stretch of the strand for texture coordinates
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CurveSegment {
+    pub points: [Point3; 4],
+    /// Width at the start and the end
+    pub width: [f32; 2],
+    pub u: [f32; 2],
+}
+
+impl CurveSegment {
+    pub fn new(points: [Point3; 4], width: [f32; 2]) -> CurveSegment {
+        CurveSegment { points, width, u: [0.0, 1.0] }
+    }
+
+    fn width_at(&self, s: f32) -> f32 {
+        self.width[0] + (self.width[1] - self.width[0]) * s
+    }
+}
+
+fn lerp(t: f32, a: &Vec3, b: &Vec3) -> Vec3 {
+    a * (1.0 - t) + b * t
+}
+
+/// Polar form of the curve, equal arguments give points on it
+fn blossom(p: &[Vec3; 4], u0: f32, u1: f32, u2: f32) -> Vec3 {
+    let a = [lerp(u0, &p[0], &p[1]), lerp(u0, &p[1], &p[2]), lerp(u0, &p[2], &p[3])];
+    let b = [lerp(u1, &a[0], &a[1]), lerp(u1, &a[1], &a[2])];
+    lerp(u2, &b[0], &b[1])
+}
+
+/// Control points of the part of the curve between `t0` and `t1`
+fn sub_curve(p: &[Vec3; 4], t0: f32, t1: f32) -> [Vec3; 4] {
+    [blossom(p, t0, t0, t0), blossom(p, t0, t0, t1), blossom(p, t0, t1, t1), blossom(p, t1, t1, t1)]
+}
+
+/// Point and derivative at `t`
+fn eval(p: &[Vec3; 4], t: f32) -> (Vec3, Vec3) {
+    let a = [lerp(t, &p[0], &p[1]), lerp(t, &p[1], &p[2]), lerp(t, &p[2], &p[3])];
+    let b = [lerp(t, &a[0], &a[1]), lerp(t, &a[1], &a[2])];
+    let d = &b[1] - &b[0];
+    // Coincident control points leave no derivative at the ends
+    let d = if d.length_squared() > 0.0 { d * 3.0 } else { &p[3] - &p[0] };
+    (lerp(t, &b[0], &b[1]), d)
+}
+
+/// Part of a segment with its own box, long curves are split for tighter bounds
+struct Piece {
+    segment: u32,
+    t0: f32,
+    t1: f32,
+    points: [Vec3; 4],
+}
+
+/// Ray from the origin along +z, curves are intersected in this space
+struct RaySpace {
+    origin: Point3,
+    axes: [Vec3; 3],
+    length: f32,
+}
+
+impl RaySpace {
+    fn new(ray: &Ray) -> RaySpace {
+        let length = ray.direction.length();
+        let z = &ray.direction / length;
+        let a = if z.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let x = Vec3::cross(&a, &z).unit();
+        let y = Vec3::cross(&z, &x);
+        RaySpace { origin: ray.origin.clone(), axes: [x, y, z], length }
+    }
+
+    fn to_local(&self, p: &Vec3) -> Vec3 {
+        let d = p - &self.origin;
+        Vec3::new(d.dot(&self.axes[0]), d.dot(&self.axes[1]), d.dot(&self.axes[2]))
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        &self.axes[0] * v.x + &self.axes[1] * v.y + &self.axes[2] * v.z
+    }
+}
+
+/// Closest hit of a curve so far: depth along the ray, segment parameter, across and axis point
+struct CurveHit {
+    z: f32,
+    s: f32,
+    v: f32,
+    axis: Vec3,
+    tangent: Vec3,
+    width: f32,
+}
+
+/// Cubic Bézier curves with varying width in their own BVH.
+///
+/// Follows the recursive subdivision of pbrt: pieces are split until they are nearly straight
+/// and then tested as segments against the ray, which looks down the z axis of ray space.
+/// `Cylinder` curves then push the hit onto the front of a round tube.
+pub struct Curves {
+    pub segments: Vec<CurveSegment>,
+    pub kind: CurveKind,
+    pub material: Arc<dyn Material>,
+    pieces: Vec<Piece>,
+    bvh: BvhTree,
+}
+
+impl Curves {
+    pub fn new(segments: Vec<CurveSegment>, kind: CurveKind, mat: Option<Arc<dyn Material>>) -> Curves {
+        let mut pieces = vec![];
+        for (i, seg) in segments.iter().enumerate() {
+            let length: f32 = seg.points.windows(2).map(|w| (&w[1] - &w[0]).length()).sum();
+            let width = seg.width[0].max(seg.width[1]).max(1e-6);
+            let count = ((length / (4.0 * width)).ceil() as usize).clamp(1, 8);
+            for k in 0..count {
+                let (t0, t1) = (k as f32 / count as f32, (k + 1) as f32 / count as f32);
+                pieces.push(Piece { segment: i as u32, t0, t1, points: sub_curve(&seg.points, t0, t1) });
+            }
+        }
+        let bounds: Vec<AaBb> = pieces.iter().map(|p| Self::piece_bounds(&segments[p.segment as usize], p)).collect();
+        Curves {
+            bvh: BvhTree::build(&bounds),
+            segments,
+            kind,
+            material: mat.unwrap_or_else(|| Arc::new(Lambertian { color: Color::new(0.8, 0.8, 0.8) })),
+            pieces,
+        }
+    }
+
+    fn piece_bounds(seg: &CurveSegment, piece: &Piece) -> AaBb {
+        let mut b = AaBb::empty();
+        for p in &piece.points {
+            b.grow(p);
+        }
+        let r = seg.width_at(piece.t0).max(seg.width_at(piece.t1)) / 2.0;
+        AaBb::new(&b.min - Vec3::new(r, r, r), &b.max + Vec3::new(r, r, r))
+    }
+
+    /// Subdivision depth that makes the piece flat to a twentieth of its width
+    fn max_depth(points: &[Vec3; 4], width: f32) -> u32 {
+        let mut l0: f32 = 0.0;
+        for i in 0..2 {
+            let d = &points[i] - &points[i + 1] * 2.0 + &points[i + 2];
+            l0 = l0.max(d.x.abs()).max(d.y.abs()).max(d.z.abs());
+        }
+        let eps = width * 0.05;
+        if l0 <= 0.0 || eps <= 0.0 {
+            return 0;
+        }
+        ((std::f32::consts::SQRT_2 * 6.0 * l0 / (8.0 * eps)).log2() * 0.5).round().clamp(0.0, 10.0) as u32
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn recurse(&self, seg: &CurveSegment, cp: &[Vec3; 4], t0: f32, t1: f32, depth: u32, z_min: f32, best: &mut Option<CurveHit>, z_max: &mut f32) {
+        let r = seg.width_at(t0).max(seg.width_at(t1)) / 2.0;
+        let mut b = AaBb::empty();
+        for p in cp {
+            b.grow(p);
+        }
+        if b.min.x - r > 0.0 || b.max.x + r < 0.0 || b.min.y - r > 0.0 || b.max.y + r < 0.0 {
+            return;
+        }
+        if b.max.z + r < z_min || b.min.z - r > *z_max {
+            return;
+        }
+        if depth > 0 {
+            let mid = (t0 + t1) / 2.0;
+            let left = sub_curve(cp, 0.0, 0.5);
+            let right = sub_curve(cp, 0.5, 1.0);
+            self.recurse(seg, &left, t0, mid, depth - 1, z_min, best, z_max);
+            self.recurse(seg, &right, mid, t1, depth - 1, z_min, best, z_max);
+            return;
+        }
+        // The ray has to pass between the lines square to the curve at both ends
+        let edge = (cp[1].y - cp[0].y) * -cp[0].y + cp[0].x * (cp[0].x - cp[1].x);
+        if edge < 0.0 {
+            return;
+        }
+        let edge = (cp[2].y - cp[3].y) * -cp[3].y + cp[3].x * (cp[3].x - cp[2].x);
+        if edge < 0.0 {
+            return;
+        }
+        let (sx, sy) = (cp[3].x - cp[0].x, cp[3].y - cp[0].y);
+        let len2 = sx * sx + sy * sy;
+        if len2 == 0.0 {
+            return;
+        }
+        let w = ((-cp[0].x * sx - cp[0].y * sy) / len2).clamp(0.0, 1.0);
+        let s = t0 + (t1 - t0) * w;
+        let width = seg.width_at(s);
+        let (pc, dpc) = eval(cp, w);
+        let dist2 = pc.x * pc.x + pc.y * pc.y;
+        if dist2 > width * width * 0.25 {
+            return;
+        }
+        let dist = dist2.sqrt();
+        let z = match self.kind {
+            CurveKind::Flat => pc.z,
+            CurveKind::Cylinder => pc.z - (width * width * 0.25 - dist2).max(0.0).sqrt(),
+        };
+        if z < z_min || z > *z_max {
+            return;
+        }
+        let side = dpc.x * -pc.y + pc.x * dpc.y;
+        let v = if side > 0.0 { 0.5 + dist / width } else { 0.5 - dist / width };
+        *z_max = z;
+        *best = Some(CurveHit { z, s, v, axis: pc, tangent: dpc, width });
+    }
+}
+
+impl Hittable for Curves {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let space = RaySpace::new(ray);
+        let mut closest: Option<(usize, CurveHit)> = None;
+        self.bvh.traverse(ray, t_min, t_max, |i, t_max| {
+            let piece = &self.pieces[i];
+            let seg = &self.segments[piece.segment as usize];
+            let cp = [space.to_local(&piece.points[0]), space.to_local(&piece.points[1]), space.to_local(&piece.points[2]), space.to_local(&piece.points[3])];
+            let depth = Self::max_depth(&cp, seg.width_at(piece.t0).max(seg.width_at(piece.t1)));
+            let mut best = None;
+            let mut z_max = t_max * space.length;
+            self.recurse(seg, &cp, piece.t0, piece.t1, depth, t_min * space.length, &mut best, &mut z_max);
+            let hit = best?;
+            let t = hit.z / space.length;
+            closest = Some((piece.segment as usize, hit));
+            Some(t)
+        });
+        let (segment, hit) = match closest {
+            Some(hit) => hit,
+            None => return false,
+        };
+        let seg = &self.segments[segment];
+        rec.t = hit.z / space.length;
+        rec.p = ray.at(rec.t);
+        rec.mat = self.material.as_ref();
+        rec.u = seg.u[0] + (seg.u[1] - seg.u[0]) * hit.s;
+        rec.v = hit.v;
+        rec.color = Color::ONE;
+        rec.tangent = space.to_world(&hit.tangent).unit();
+        rec.front_face = true;
+        rec.normal = match self.kind {
+            CurveKind::Flat => -space.axes[2].clone(),
+            CurveKind::Cylinder => {
+                // From the axis to the hit, square to the tangent
+                let axis = &space.origin + space.to_world(&hit.axis);
+                let out = &rec.p - &axis;
+                let out = &out - &rec.tangent * out.dot(&rec.tangent);
+                if out.length_squared() > 1e-12 * hit.width * hit.width {
+                    out.unit()
+                } else {
+                    -space.axes[2].clone()
+                }
+            }
+        };
+        true
+    }
+
+    fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
+        self.bvh.bbox().cloned()
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        self.material = mat;
+    }
+
+    fn name(&self) -> &'static str {
+        "Curves"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        Ok(ObjectDesc::Curves {
+            kind: self.kind,
+            material: Some(writer.material(self.material.as_ref())?),
+            segments: self.segments.clone(),
+        })
+    }
+}
+
+/// Read strands from text, one per line as `width0 width1` and then `3n + 1` points for `n`
+/// Bézier segments sharing their ends. The width changes evenly along the strand and `#` starts
+/// a comment.
+pub fn read_curves(source: &str) -> Result<Vec<CurveSegment>, String> {
+    let mut segments = vec![];
+    for (n, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let values: Vec<f32> = line
+            .split_whitespace()
+            .map(|s| s.parse().map_err(|_| format!("line {}: bad number '{}'", n + 1, s)))
+            .collect::<Result<_, _>>()?;
+        // 2 widths and 3 coordinates for each of the 3 * count + 1 points
+        let count = values.len().saturating_sub(5) / 9;
+        if count == 0 || values.len() != 5 + 9 * count {
+            return Err(format!("line {}: needs 2 widths and 3n + 1 points", n + 1));
+        }
+        let points: Vec<Point3> = values[2..].chunks(3).map(|c| Point3::new(c[0], c[1], c[2])).collect();
+        let width = |u: f32| values[0] + (values[1] - values[0]) * u;
+        for i in 0..count {
+            let (u0, u1) = (i as f32 / count as f32, (i + 1) as f32 / count as f32);
+            let p = &points[3 * i..3 * i + 4];
+            segments.push(CurveSegment {
+                points: [p[0].clone(), p[1].clone(), p[2].clone(), p[3].clone()],
+                width: [width(u0), width(u1)],
+                u: [u0, u1],
+            });
+        }
+    }
+    Ok(segments)
+}
+
+pub fn load_curves(path: impl AsRef<Path>, kind: CurveKind, mat: Option<Arc<dyn Material>>) -> Result<Curves, SpriosError> {
+    let path = path.as_ref();
+    let error = |e: &dyn std::fmt::Display| SpriosError::ImportError(format!("{}: {}", path.display(), e));
+    let source = std::fs::read_to_string(path).map_err(|e| error(&e))?;
+    let segments = read_curves(&source).map_err(|e| error(&e))?;
+    Ok(Curves::new(segments, kind, mat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arc(kind: CurveKind) -> Curves {
+        // Bends from x = -2 up through y = 1.5 and down to x = 2, in the z = 0 plane
+        let points = [Point3::new(-2.0, 0.0, 0.0), Point3::new(-1.0, 2.0, 0.0), Point3::new(1.0, 2.0, 0.0), Point3::new(2.0, 0.0, 0.0)];
+        Curves::new(vec![CurveSegment::new(points, [0.2, 0.1])], kind, None)
+    }
+
+    fn hit(curves: &Curves, origin: Point3) -> Option<HitRecord<'_>> {
+        let mut rec = HitRecord::new(curves.material.as_ref());
+        let ray = Ray::new(&origin, &Vec3::new(0.0, 0.0, -1.0));
+        curves.hit(&ray, 0.001, f32::INFINITY, &mut rec).then_some(rec)
+    }
+
+    #[test]
+    fn test_hit() {
+        let flat = arc(CurveKind::Flat);
+        assert_eq!(flat.pieces.len(), 8);
+        let bbox = flat.bbox(0.0, 1.0).unwrap();
+        assert!(bbox.max.y < 1.7 && bbox.max.y > 1.5 && bbox.min.x < -2.0);
+        // The top of the arc at u = 0.5 is 0.15 wide
+        let rec = hit(&flat, Point3::new(0.0, 1.5, 5.0)).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-4 && (rec.u - 0.5).abs() < 1e-3 && (rec.v - 0.5).abs() < 0.02);
+        assert!((rec.tangent.x - 1.0).abs() < 1e-3);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(hit(&flat, Point3::new(0.0, 1.56, 5.0)).is_some());
+        assert!(hit(&flat, Point3::new(0.0, 1.6, 5.0)).is_none());
+        assert!(hit(&flat, Point3::new(0.0, 1.0, 5.0)).is_none());
+        // Across the strand v goes from one edge to the other
+        let top = hit(&flat, Point3::new(0.0, 1.55, 5.0)).unwrap();
+        let bottom = hit(&flat, Point3::new(0.0, 1.45, 5.0)).unwrap();
+        assert!((top.v - bottom.v).abs() > 0.5);
+
+        // A tube is hit in front of its axis
+        let tube = arc(CurveKind::Cylinder);
+        let rec = hit(&tube, Point3::new(0.0, 1.5, 5.0)).unwrap();
+        assert!((rec.t - (5.0 - 0.075)).abs() < 1e-3);
+        assert!(rec.normal.z > 0.99);
+        let rec = hit(&tube, Point3::new(0.0, 1.55, 5.0)).unwrap();
+        assert!(rec.normal.y > 0.5 && rec.t > 4.925);
+    }
+
+    #[test]
+    fn test_read() {
+        let src = "# strand of two segments\n0.1 0.05  0 0 0  0 1 0  0 2 0  0 3 0  0 4 0  0 5 0  0 6 0\n";
+        let segments = read_curves(src).unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].u, [0.5, 1.0]);
+        assert!((segments[1].width[0] - 0.075).abs() < 1e-6);
+        assert_eq!(segments[1].points[0], Point3::new(0.0, 3.0, 0.0));
+        assert!(read_curves("0.1 0.1 0 0 0 1 1 1").is_err());
+        assert_eq!(read_curves("0.1 x").unwrap_err(), "line 1: bad number 'x'");
+        assert_eq!(CurveKind::from_str("tube").unwrap_err(), "unknown curve kind 'tube'");
+    }
+}
@@ -0,0 +1,229 @@
+use crate::hittable::{HitRecord, Hittable};
+use crate::settings::RenderSettings;
+use crate::vec::Vec3;
+use crate::{Camera, Lambertian, World};
+use rand::{Rng, SeedableRng};
+
+const FEATURE_SAMPLES: u32 = 4;
+const MISS_DEPTH: f32 = 1.0e6;
+
+/// Per pixel albedo, normal and depth of the first hit, used to guide the denoiser.
+pub struct FeatureBuffers {
+    pub width: u32,
+    pub height: u32,
+    pub albedo: Vec<f32>,
+    pub normal: Vec<f32>,
+    pub depth: Vec<f32>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct DenoiseParams {
+    pub radius: u32,
+    pub sigma_spatial: f32,
+    pub sigma_color: f32,
+    pub sigma_albedo: f32,
+    pub sigma_normal: f32,
+    pub sigma_depth: f32,
+}
+
+impl Default for DenoiseParams {
+    fn default() -> Self {
+        DenoiseParams {
+            radius: 5,
+            sigma_spatial: 3.0,
+            sigma_color: 0.6,
+            sigma_albedo: 0.1,
+            sigma_normal: 0.2,
+            sigma_depth: 0.05,
+        }
+    }
+}
+
+impl FeatureBuffers {
+    pub fn new(width: u32, height: u32) -> FeatureBuffers {
+        let len = (width * height) as usize;
+        FeatureBuffers {
+            width,
+            height,
+            albedo: vec![0.0; len * 3],
+            normal: vec![0.0; len * 3],
+            depth: vec![MISS_DEPTH; len],
+        }
+    }
+
+    /// Shoot a few primary rays per pixel and average what they see first.
+    pub fn render(settings: &RenderSettings, world: &World, num_threads: usize) -> FeatureBuffers {
+        let mut features = FeatureBuffers::new(settings.width, settings.height);
+        let width = settings.width as usize;
+        let rows_per_thread = (settings.height as usize).div_ceil(num_threads.max(1)).max(1);
+        let camera = &settings.camera(&world.camera);
+        std::thread::scope(|scope| {
+            let chunks = features
+                .albedo
+                .chunks_mut(rows_per_thread * width * 3)
+                .zip(features.normal.chunks_mut(rows_per_thread * width * 3))
+                .zip(features.depth.chunks_mut(rows_per_thread * width));
+            for (chunk, ((albedo, normal), depth)) in chunks.enumerate() {
+                scope.spawn(move || {
+                    let mut rng = rand::rngs::SmallRng::seed_from_u64(chunk as u64);
+                    let first_row = chunk * rows_per_thread;
+                    for (i, d) in depth.iter_mut().enumerate() {
+                        let x = (i % width) as u32;
+                        let y = (first_row + i / width) as u32;
+                        let (a, n, z) = primary_features(settings, world, camera, x, y, &mut rng);
+                        albedo[i * 3] = a.x;
+                        albedo[i * 3 + 1] = a.y;
+                        albedo[i * 3 + 2] = a.z;
+                        normal[i * 3] = n.x;
+                        normal[i * 3 + 1] = n.y;
+                        normal[i * 3 + 2] = n.z;
+                        *d = z;
+                    }
+                });
+            }
+        });
+        features
+    }
+}
+
+fn primary_features(
+    settings: &RenderSettings,
+    world: &World,
+    camera: &Camera,
+    x: u32,
+    y: u32,
+    rng: &mut rand::rngs::SmallRng,
+) -> (Vec3, Vec3, f32) {
+    let tmp_mat = Lambertian { color: Vec3::ZERO };
+    let mut albedo = Vec3::ZERO;
+    let mut normal = Vec3::ZERO;
+    let mut depth = 0.0;
+    for _ in 0..FEATURE_SAMPLES {
+        let (sx, sy) = rng.gen::<(f32, f32)>();
+        let u = (x as f32 + sx) / (settings.width - 1) as f32;
+        let v = ((settings.height - y) as f32 + sy) / (settings.height - 1) as f32;
+        let ray = camera.get_ray(u, v, rng);
+        let mut rec = HitRecord::new(&tmp_mat);
+        if world.hit(&ray, 0.001, f32::INFINITY, &mut rec) {
+            albedo += &rec.mat.albedo(&rec);
+            normal += &rec.normal;
+            depth += rec.t * ray.direction.length();
+        } else {
+            albedo += &world.background;
+            depth += MISS_DEPTH;
+        }
+    }
+    let n = FEATURE_SAMPLES as f32;
+    (albedo / n, normal / n, depth / n)
+}
+
+#[inline]
+fn dist_sq(a: &[f32], i: usize, j: usize) -> f32 {
+    let (i, j) = (i * 3, j * 3);
+    let dx = a[i] - a[j];
+    let dy = a[i + 1] - a[j + 1];
+    let dz = a[i + 2] - a[j + 2];
+    dx * dx + dy * dy + dz * dz
+}
+
+/// Cross-bilateral filter of an accumulation buffer (sum of `num_samples` passes).
+///
+/// The lighting is demodulated by the albedo before filtering so texture and
+/// material edges survive, then the result is scaled back to the same units as the input.
+pub fn denoise(sum: &[f32], num_samples: u32, features: &FeatureBuffers) -> Vec<f32> {
+    denoise_with(sum, num_samples, features, &DenoiseParams::default())
+}
+
+pub fn denoise_with(
+    sum: &[f32],
+    num_samples: u32,
+    features: &FeatureBuffers,
+    params: &DenoiseParams,
+) -> Vec<f32> {
+    let width = features.width as usize;
+    let height = features.height as usize;
+    assert_eq!(sum.len(), width * height * 3, "Image and feature buffers differ in size");
+    if num_samples == 0 {
+        return sum.to_vec();
+    }
+    const EPS: f32 = 1.0e-3;
+    let scale = 1.0 / num_samples as f32;
+    let irradiance: Vec<f32> = sum
+        .iter()
+        .zip(features.albedo.iter())
+        .map(|(c, a)| c * scale / a.max(EPS))
+        .collect();
+
+    let radius = params.radius as isize;
+    let inv_spatial = 1.0 / (2.0 * params.sigma_spatial * params.sigma_spatial);
+    let inv_color = 1.0 / (2.0 * params.sigma_color * params.sigma_color);
+    let inv_albedo = 1.0 / (2.0 * params.sigma_albedo * params.sigma_albedo);
+    let inv_normal = 1.0 / (2.0 * params.sigma_normal * params.sigma_normal);
+    let inv_depth = 1.0 / (2.0 * params.sigma_depth * params.sigma_depth);
+
+    let mut out = vec![0.0; sum.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let p = y * width + x;
+            let depth_p = features.depth[p].max(EPS);
+            let mut acc = Vec3::ZERO;
+            let mut weight_sum = 0.0;
+            for dy in -radius..=radius {
+                let qy = y as isize + dy;
+                if qy < 0 || qy >= height as isize {
+                    continue;
+                }
+                for dx in -radius..=radius {
+                    let qx = x as isize + dx;
+                    if qx < 0 || qx >= width as isize {
+                        continue;
+                    }
+                    let q = qy as usize * width + qx as usize;
+                    let rel_depth = (features.depth[p] - features.depth[q]) / depth_p;
+                    let exponent = (dx * dx + dy * dy) as f32 * inv_spatial
+                        + dist_sq(&irradiance, p, q).min(4.0) * inv_color
+                        + dist_sq(&features.albedo, p, q) * inv_albedo
+                        + dist_sq(&features.normal, p, q) * inv_normal
+                        + rel_depth * rel_depth * inv_depth;
+                    let w = (-exponent).exp();
+                    acc += &(Vec3::new(irradiance[q * 3], irradiance[q * 3 + 1], irradiance[q * 3 + 2]) * w);
+                    weight_sum += w;
+                }
+            }
+            let filtered = acc / weight_sum;
+            for c in 0..3 {
+                let albedo = features.albedo[p * 3 + c].max(EPS);
+                out[p * 3 + c] = filtered[c] * albedo * num_samples as f32;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flat_image_is_preserved() {
+        let mut features = FeatureBuffers::new(8, 6);
+        features.albedo.iter_mut().for_each(|a| *a = 0.5);
+        let sum = vec![1.5; 8 * 6 * 3];
+        let out = denoise(&sum, 3, &features);
+        for v in out {
+            assert!((v - 1.5).abs() < 1.0e-4);
+        }
+    }
+
+    #[test]
+    fn test_noise_is_reduced() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(7);
+        let mut features = FeatureBuffers::new(32, 32);
+        features.albedo.iter_mut().for_each(|a| *a = 1.0);
+        features.depth.iter_mut().for_each(|d| *d = 10.0);
+        let sum: Vec<f32> = (0..32 * 32 * 3).map(|_| 0.5 + rng.gen_range(-0.2, 0.2)).collect();
+        let variance = |buf: &[f32]| buf.iter().map(|v| (v - 0.5) * (v - 0.5)).sum::<f32>() / buf.len() as f32;
+        let out = denoise(&sum, 1, &features);
+        assert!(variance(&out) < variance(&sum) * 0.25);
+    }
+}
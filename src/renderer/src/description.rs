@@ -0,0 +1,875 @@
+use crate::bbox::AaBb;
+use crate::bvh::Bvh;
+use crate::errors::SpriosError;
+use crate::hittable::Hittable;
+use crate::instance::{Instance, Transform};
+use crate::light::Light;
+use crate::vec::{Color, Mat4, Point3, Vec3};
+use crate::{check_shutter, load_heightfield, load_voxels, BezierPatches, Camera, Cone, ConstantMedium, Csg, CsgOp, Cuboid, CurveKind, Curves, CurveSegment, Cylinder, Dielectric, Disk, Emissive, Fog, Hair, Lambertian, Material, Medium, Metal, MovingSphere, Paraboloid, ParticleCloud, Pbr, Placed, Plane, Quad, Sdf, SdfNode, SettingsBuilder, Sphere, Torus, TriangleMesh, Volume, World};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraDesc {
+    pub from: Point3,
+    pub at: Point3,
+    pub up: Vec3,
+    pub fov: f32,
+    pub aspect: f32,
+    #[serde(default)]
+    pub aperture: f32,
+    pub focus: f32,
+    #[serde(default)]
+    pub shutter_open: f32,
+    #[serde(default)]
+    pub shutter_close: f32,
+}
+
+impl CameraDesc {
+    /// Recover the look-at parameters, None for a camera that was never set up
+    pub fn from_camera(cam: &Camera) -> Option<CameraDesc> {
+        let (width, height) = (cam.horizontal.length(), cam.vertical.length());
+        if height == 0.0 || width == 0.0 {
+            return None;
+        }
+        let center = &cam.lower_left_corner + &cam.horizontal / 2.0 + &cam.vertical / 2.0;
+        let focus = (&cam.origin - &center).length();
+        Some(CameraDesc {
+            from: cam.origin.clone(),
+            at: center,
+            up: cam.vertical.unit(),
+            fov: (2.0 * (height / 2.0 / focus).atan()).to_degrees(),
+            aspect: width / height,
+            aperture: cam.lens_radius * 2.0,
+            focus,
+            shutter_open: cam.shutter_open,
+            shutter_close: cam.shutter_close,
+        })
+    }
+
+    pub fn to_camera(&self) -> Camera {
+        Camera::new(self.from.clone(), self.at.clone(), self.up.clone(), self.fov, self.aspect, self.aperture, self.focus)
+            .with_shutter(self.shutter_open, self.shutter_close)
+    }
+}
+
+/// Render settings stored with a scene, unset values keep the defaults of the renderer
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SettingsDesc {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub samples: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub denoise: Option<bool>,
+    /// Open and close times replacing the shutter of the camera
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shutter: Option<[f32; 2]>,
+}
+
+impl SettingsDesc {
+    pub fn apply(&self, mut builder: SettingsBuilder) -> SettingsBuilder {
+        if let Some(width) = self.width {
+            builder = builder.size(width, self.height);
+        }
+        if let Some(v) = self.samples {
+            builder = builder.samples(v);
+        }
+        if let Some(v) = self.bucket {
+            builder = builder.bucket(v);
+        }
+        if let Some(v) = self.seed {
+            builder = builder.seed(v);
+        }
+        if let Some(v) = self.denoise {
+            builder = builder.denoise(v);
+        }
+        if let Some([open, close]) = self.shutter {
+            builder = builder.shutter(Some((open, close)));
+        }
+        builder
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialDesc {
+    Diffuse { color: Color },
+    Metal { color: Color, #[serde(default)] fuzz: f32 },
+    Pbr { color: Color, metallic: f32, roughness: f32 },
+    Glass { ior: f32 },
+    Hair { color: Color, specular: f32, shininess: f32 },
+    Emissive {
+        color: Color,
+        #[serde(default)]
+        two_sided: bool,
+        #[serde(default)]
+        reverse: bool,
+    },
+}
+
+impl MaterialDesc {
+    pub fn to_material(&self) -> Arc<dyn Material> {
+        match self {
+            MaterialDesc::Diffuse { color } => Arc::new(Lambertian { color: color.clone() }),
+            MaterialDesc::Metal { color, fuzz } => Arc::new(Metal { color: color.clone(), fuzz: *fuzz }),
+            MaterialDesc::Pbr { color, metallic, roughness } => Arc::new(Pbr {
+                color: color.clone(),
+                metallic: *metallic,
+                roughness: *roughness,
+                color_texture: None,
+                metallic_roughness_texture: None,
+            }),
+            MaterialDesc::Glass { ior } => Arc::new(Dielectric { ior: *ior }),
+            MaterialDesc::Hair { color, specular, shininess } => {
+                Arc::new(Hair { color: color.clone(), specular: *specular, shininess: *shininess })
+            }
+            MaterialDesc::Emissive { color, two_sided, reverse } => {
+                Arc::new(Emissive { color: color.clone(), two_sided: *two_sided, reverse: *reverse })
+            }
+        }
+    }
+}
+
+/// Materials are referenced by their name in `SceneDescription::materials`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ObjectDesc {
+    Sphere {
+        center: Point3,
+        radius: f32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    /// At `center0` at time 0 and `center1` at time 1
+    MovingSphere {
+        center0: Point3,
+        center1: Point3,
+        radius: f32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    Quad {
+        origin: Point3,
+        u: Vec3,
+        v: Vec3,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    /// Axis aligned box between two corners
+    Box {
+        min: Point3,
+        max: Point3,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    /// Infinite plane
+    Plane {
+        point: Point3,
+        normal: Vec3,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    Disk {
+        center: Point3,
+        normal: Vec3,
+        radius: f32,
+        #[serde(default)]
+        inner_radius: f32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    /// Around the y axis placed by `transform`, `sweep` in degrees
+    Cylinder {
+        radius: f32,
+        height: f32,
+        #[serde(default = "full_sweep")]
+        sweep: f32,
+        #[serde(default = "with_caps")]
+        caps: bool,
+        #[serde(default)]
+        transform: Mat4,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    Cone {
+        radius: f32,
+        height: f32,
+        #[serde(default = "full_sweep")]
+        sweep: f32,
+        #[serde(default = "with_caps")]
+        caps: bool,
+        #[serde(default)]
+        transform: Mat4,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    Paraboloid {
+        radius: f32,
+        height: f32,
+        #[serde(default = "full_sweep")]
+        sweep: f32,
+        #[serde(default = "with_caps")]
+        caps: bool,
+        #[serde(default)]
+        transform: Mat4,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    Torus {
+        major: f32,
+        minor: f32,
+        #[serde(default = "full_sweep")]
+        sweep: f32,
+        #[serde(default)]
+        transform: Mat4,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    Mesh {
+        positions: Vec<Point3>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        normals: Vec<Vec3>,
+        triangles: Vec<[u32; 3]>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        uvs: Vec<[f32; 2]>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        colors: Vec<Color>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    Group {
+        objects: Vec<ObjectDesc>,
+    },
+    /// Heights from a grayscale image
+    Heightfield {
+        file: String,
+        size: Vec3,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    /// `min` and `max` bound the surface
+    Sdf {
+        min: Point3,
+        max: Point3,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+        shape: SdfNode,
+    },
+    /// TOML needs the plain values ahead of the nested objects
+    Csg {
+        op: CsgOp,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+        left: Box<ObjectDesc>,
+        right: Box<ObjectDesc>,
+    },
+    /// Bézier strands sharing a material
+    Curves {
+        kind: CurveKind,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+        segments: Vec<CurveSegment>,
+    },
+    /// Spheres in flat arrays, `radii` holds one radius for all or one each and
+    /// `material_ids` index `materials`
+    Particles {
+        materials: Vec<String>,
+        positions: Vec<Point3>,
+        radii: Vec<f32>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        colors: Vec<Color>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        material_ids: Vec<u16>,
+    },
+    /// Bicubic Bézier patches, 16 control points each
+    Patches {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+        patches: Vec<[Point3; 16]>,
+    },
+    /// Participating medium inside a closed boundary
+    Medium {
+        medium: Medium,
+        boundary: Box<ObjectDesc>,
+    },
+    /// Voxel densities stretched over `min..max`, `dims` are given for raw files
+    Volume {
+        file: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dims: Option<[usize; 3]>,
+        min: Point3,
+        max: Point3,
+        scale: f32,
+        color: Color,
+        #[serde(default)]
+        g: f32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        temperature: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        temperature_dims: Option<[usize; 3]>,
+        #[serde(default)]
+        emission: Color,
+    },
+    /// A copy of an entry of `SceneDescription::prototypes`
+    Instance {
+        prototype: String,
+        transform: Mat4,
+        /// Placement at time 1 for moving instances, only translation and scale blend exactly
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        motion: Option<Mat4>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+}
+
+impl ObjectDesc {
+    /// Visits the names of the files the object is read from
+    fn for_each_file(&mut self, f: &mut dyn FnMut(&mut String)) {
+        match self {
+            ObjectDesc::Heightfield { file, .. } => f(file),
+            ObjectDesc::Volume { file, temperature, .. } => {
+                f(file);
+                if let Some(file) = temperature {
+                    f(file);
+                }
+            }
+            ObjectDesc::Group { objects } => objects.iter_mut().for_each(|o| o.for_each_file(f)),
+            ObjectDesc::Csg { left, right, .. } => {
+                left.for_each_file(f);
+                right.for_each_file(f);
+            }
+            ObjectDesc::Medium { boundary, .. } => boundary.for_each_file(f),
+            _ => {}
+        }
+    }
+}
+
+/// Plain data version of a World that loads from and saves to JSON, TOML and RON.
+/// Files the scene refers to are relative to the scene file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneDescription {
+    pub background: Color,
+    /// Uniform light for rays that miss, replacing the sky
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<Color>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fog: Option<Fog>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera: Option<CameraDesc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<SettingsDesc>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lights: Vec<Light>,
+    /// Geometry shared by instances
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prototypes: BTreeMap<String, ObjectDesc>,
+    #[serde(default)]
+    pub objects: Vec<ObjectDesc>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SceneFormat {
+    Json,
+    Toml,
+    Ron,
+}
+
+impl SceneFormat {
+    pub fn from_path(path: &Path) -> Option<SceneFormat> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(SceneFormat::Json),
+            "toml" => Some(SceneFormat::Toml),
+            "ron" => Some(SceneFormat::Ron),
+            _ => None,
+        }
+    }
+}
+
+fn serialize_error(e: impl std::fmt::Display) -> SpriosError {
+    SpriosError::SerializeError(e.to_string())
+}
+
+fn full_sweep() -> f32 {
+    360.0
+}
+
+fn with_caps() -> bool {
+    true
+}
+
+/// Directory that files referenced by the scene at `path` are relative to
+fn scene_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// `file` relative to `dir`, both absolute. Stays absolute without a common root.
+fn relative_path(file: &Path, dir: &Path) -> PathBuf {
+    let common = file.components().zip(dir.components()).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        return file.to_path_buf();
+    }
+    let mut relative: PathBuf = dir.components().skip(common).map(|_| Component::ParentDir).collect();
+    relative.extend(file.components().skip(common));
+    relative
+}
+
+fn placement(matrix: &Mat4) -> Result<Transform, SpriosError> {
+    Transform::new(*matrix).ok_or_else(|| serialize_error("Transform can't be inverted"))
+}
+
+/// Collects the shared materials and prototypes while objects describe themselves.
+pub struct SceneWriter {
+    materials: BTreeMap<String, MaterialDesc>,
+    material_names: HashMap<usize, String>,
+    prototypes: BTreeMap<String, ObjectDesc>,
+    prototype_names: HashMap<usize, String>,
+}
+
+impl SceneWriter {
+    /// Name of a material in the table, unnamed ones get a generated name
+    pub fn material(&mut self, mat: &dyn Material) -> Result<String, SpriosError> {
+        let key = mat as *const dyn Material as *const u8 as usize;
+        if let Some(name) = self.material_names.get(&key) {
+            return Ok(name.clone());
+        }
+        let desc = mat.describe().ok_or_else(|| serialize_error("Material can't be serialized"))?;
+        let mut n = self.materials.len();
+        let name = loop {
+            let name = format!("material{}", n);
+            if !self.materials.contains_key(&name) {
+                break name;
+            }
+            n += 1;
+        };
+        self.materials.insert(name.clone(), desc);
+        self.material_names.insert(key, name.clone());
+        Ok(name)
+    }
+
+    /// Name of shared geometry, described the first time it is seen
+    pub fn prototype(&mut self, object: &Arc<dyn Hittable>) -> Result<String, SpriosError> {
+        let key = Arc::as_ptr(object) as *const u8 as usize;
+        if let Some(name) = self.prototype_names.get(&key) {
+            return Ok(name.clone());
+        }
+        let desc = object.describe(self)?;
+        let name = format!("prototype{}", self.prototypes.len());
+        self.prototypes.insert(name.clone(), desc);
+        self.prototype_names.insert(key, name.clone());
+        Ok(name)
+    }
+}
+
+impl SceneDescription {
+    pub fn from_world(world: &World) -> Result<SceneDescription, SpriosError> {
+        let mut writer = SceneWriter {
+            materials: BTreeMap::new(),
+            material_names: HashMap::new(),
+            prototypes: BTreeMap::new(),
+            prototype_names: HashMap::new(),
+        };
+        for (name, mat) in world.named_materials() {
+            let desc = mat.describe().ok_or_else(|| serialize_error(format!("Material {} can't be serialized", name)))?;
+            writer.materials.insert(name.to_string(), desc);
+            writer.material_names.insert(Arc::as_ptr(mat) as *const u8 as usize, name.to_string());
+        }
+        let objects = world.objects.iter().map(|o| o.describe(&mut writer)).collect::<Result<Vec<_>, _>>()?;
+        Ok(SceneDescription {
+            background: world.background.clone(),
+            environment: world.environment.clone(),
+            fog: world.fog.clone(),
+            camera: CameraDesc::from_camera(&world.camera),
+            settings: None,
+            materials: writer.materials,
+            lights: world.lights.clone(),
+            prototypes: writer.prototypes,
+            objects,
+        })
+    }
+
+    pub fn to_world(&self) -> Result<World, SpriosError> {
+        let mut world = World::new();
+        world.background = self.background.clone();
+        world.environment = self.environment.clone();
+        world.fog = self.fog.clone();
+        if let Some(cam) = &self.camera {
+            check_shutter(cam.shutter_open, cam.shutter_close).map_err(|e| serialize_error(format!("Camera {}", e)))?;
+            world.camera = cam.to_camera();
+        }
+        if let Some([open, close]) = self.settings.as_ref().and_then(|s| s.shutter) {
+            check_shutter(open, close).map_err(|e| serialize_error(format!("Settings {}", e)))?;
+        }
+        for (name, desc) in &self.materials {
+            world.add_material(Some(name), desc.to_material());
+        }
+        world.lights = self.lights.clone();
+        let mut reader = SceneReader { world: &world, prototypes: HashMap::new(), descs: &self.prototypes };
+        let objects = self.objects.iter().map(|o| reader.object(o)).collect::<Result<Vec<_>, _>>()?;
+        for object in objects {
+            world.add(object);
+        }
+        Ok(world)
+    }
+
+    fn for_each_file(&mut self, f: &mut dyn FnMut(&mut String)) {
+        for object in self.prototypes.values_mut().chain(self.objects.iter_mut()) {
+            object.for_each_file(f);
+        }
+    }
+
+    pub fn from_str(source: &str, format: SceneFormat) -> Result<SceneDescription, SpriosError> {
+        match format {
+            SceneFormat::Json => serde_json::from_str(source).map_err(serialize_error),
+            SceneFormat::Toml => toml::from_str(source).map_err(serialize_error),
+            SceneFormat::Ron => ron::de::from_str(source).map_err(serialize_error),
+        }
+    }
+
+    pub fn to_string(&self, format: SceneFormat) -> Result<String, SpriosError> {
+        match format {
+            SceneFormat::Json => serde_json::to_string_pretty(self).map_err(serialize_error),
+            SceneFormat::Toml => toml::to_string(self).map_err(serialize_error),
+            SceneFormat::Ron => ron::ser::to_string_pretty(self, Default::default()).map_err(serialize_error),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<SceneDescription, SpriosError> {
+        let path = path.as_ref();
+        let format = SceneFormat::from_path(path)
+            .ok_or_else(|| serialize_error(format!("{}: not a json, toml or ron file", path.display())))?;
+        let source = std::fs::read_to_string(path).map_err(|e| serialize_error(format!("{}: {}", path.display(), e)))?;
+        let mut desc = Self::from_str(&source, format).map_err(|e| match e {
+            SpriosError::SerializeError(e) => serialize_error(format!("{}: {}", path.display(), e)),
+            e => e,
+        })?;
+        let dir = scene_dir(path);
+        desc.for_each_file(&mut |file| *file = dir.join(&file).display().to_string());
+        Ok(desc)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SpriosError> {
+        let path = path.as_ref();
+        let format = SceneFormat::from_path(path)
+            .ok_or_else(|| serialize_error(format!("{}: scenes are saved as json, toml or ron", path.display())))?;
+        let mut desc = self.clone();
+        if let Ok(dir) = scene_dir(path).canonicalize() {
+            desc.for_each_file(&mut |file| {
+                if let Ok(absolute) = Path::new(file).canonicalize() {
+                    *file = relative_path(&absolute, &dir).display().to_string();
+                }
+            });
+        }
+        std::fs::write(path, desc.to_string(format)?).map_err(|e| serialize_error(format!("{}: {}", path.display(), e)))
+    }
+}
+
+struct SceneReader<'a> {
+    world: &'a World,
+    descs: &'a BTreeMap<String, ObjectDesc>,
+    prototypes: HashMap<String, Arc<dyn Hittable>>,
+}
+
+impl SceneReader<'_> {
+    fn material(&self, name: &Option<String>) -> Result<Option<Arc<dyn Material>>, SpriosError> {
+        match name {
+            Some(name) => {
+                let mat = self.world.find_material(name);
+                mat.map(Some).ok_or_else(|| serialize_error(format!("Unknown material '{}'", name)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn object(&mut self, desc: &ObjectDesc) -> Result<Arc<dyn Hittable>, SpriosError> {
+        Ok(match desc {
+            ObjectDesc::Sphere { center, radius, material } => {
+                Arc::new(Sphere::new(center.clone(), *radius, self.material(material)?))
+            }
+            ObjectDesc::MovingSphere { center0, center1, radius, material } => {
+                Arc::new(MovingSphere::new(center0.clone(), center1.clone(), *radius, self.material(material)?))
+            }
+            ObjectDesc::Quad { origin, u, v, material } => {
+                if Vec3::cross(u, v).length_squared() == 0.0 {
+                    return Err(serialize_error("Quad edges are parallel"));
+                }
+                Arc::new(Quad::new(origin.clone(), u.clone(), v.clone(), self.material(material)?))
+            }
+            ObjectDesc::Box { min, max, material } => {
+                if (0..3).any(|a| min[a] == max[a]) {
+                    return Err(serialize_error("Box has zero extent"));
+                }
+                Arc::new(Cuboid::new(min.clone(), max.clone(), self.material(material)?))
+            }
+            ObjectDesc::Plane { point, normal, material } => {
+                if normal.length_squared() == 0.0 {
+                    return Err(serialize_error("Plane normal is zero"));
+                }
+                Arc::new(Plane::new(point.clone(), normal.clone(), self.material(material)?))
+            }
+            ObjectDesc::Disk { center, normal, radius, inner_radius, material } => {
+                if normal.length_squared() == 0.0 || *inner_radius < 0.0 || inner_radius >= radius {
+                    return Err(serialize_error("Disk needs a normal and 0 <= inner_radius < radius"));
+                }
+                let mat = self.material(material)?;
+                Arc::new(Disk::new(center.clone(), normal.clone(), *radius, *inner_radius, mat))
+            }
+            ObjectDesc::Cylinder { radius, height, sweep, caps, transform, material } => {
+                let shape = Placed::new(Cylinder { radius: *radius, height: *height }, self.material(material)?).with_sweep(*sweep).with_caps(*caps);
+                Arc::new(shape.with_transform(placement(transform)?))
+            }
+            ObjectDesc::Cone { radius, height, sweep, caps, transform, material } => {
+                let shape = Placed::new(Cone { radius: *radius, height: *height }, self.material(material)?).with_sweep(*sweep).with_caps(*caps);
+                Arc::new(shape.with_transform(placement(transform)?))
+            }
+            ObjectDesc::Paraboloid { radius, height, sweep, caps, transform, material } => {
+                let shape = Placed::new(Paraboloid { radius: *radius, height: *height }, self.material(material)?).with_sweep(*sweep).with_caps(*caps);
+                Arc::new(shape.with_transform(placement(transform)?))
+            }
+            ObjectDesc::Torus { major, minor, sweep, transform, material } => {
+                let shape = Placed::new(Torus { major: *major, minor: *minor }, self.material(material)?).with_sweep(*sweep);
+                Arc::new(shape.with_transform(placement(transform)?))
+            }
+            ObjectDesc::Mesh { positions, normals, triangles, uvs, colors, material } => {
+                let count = positions.len() as u32;
+                if triangles.iter().flatten().any(|i| *i >= count) {
+                    return Err(serialize_error("Mesh triangle index out of range"));
+                }
+                if !normals.is_empty() && normals.len() != positions.len() {
+                    return Err(serialize_error("Mesh needs one normal per position"));
+                }
+                if !uvs.is_empty() && uvs.len() != positions.len() {
+                    return Err(serialize_error("Mesh needs one uv per position"));
+                }
+                if !colors.is_empty() && colors.len() != positions.len() {
+                    return Err(serialize_error("Mesh needs one colour per position"));
+                }
+                let mat = self.material(material)?;
+                let mesh = TriangleMesh::new(positions.clone(), normals.clone(), triangles.clone(), mat);
+                Arc::new(mesh.with_uvs(uvs.clone()).with_colors(colors.clone()))
+            }
+            ObjectDesc::Group { objects } => {
+                let objects = objects.iter().map(|o| self.object(o)).collect::<Result<Vec<_>, _>>()?;
+                Arc::new(Bvh::new(objects))
+            }
+            ObjectDesc::Heightfield { file, size, material } => {
+                if size.x <= 0.0 || size.z <= 0.0 {
+                    return Err(serialize_error("Heightfield needs a positive width and depth"));
+                }
+                Arc::new(load_heightfield(file, size.clone(), self.material(material)?)?)
+            }
+            ObjectDesc::Sdf { min, max, material, shape } => {
+                if min.x > max.x || min.y > max.y || min.z > max.z {
+                    return Err(serialize_error("sdf: bounds min is above max"));
+                }
+                let bounds = AaBb::new(min.clone(), max.clone());
+                Arc::new(Sdf::new(shape.clone(), bounds, self.material(material)?))
+            }
+            ObjectDesc::Csg { op, left, right, material } => {
+                let mut csg = Csg::new(*op, self.object(left)?, self.object(right)?);
+                csg.material = self.material(material)?;
+                Arc::new(csg)
+            }
+            ObjectDesc::Volume { file, dims, min, max, scale, color, g, temperature, temperature_dims, emission } => {
+                if !(min.x < max.x && min.y < max.y && min.z < max.z) || *scale < 0.0 || *g <= -1.0 || *g >= 1.0 {
+                    return Err(serialize_error("Volume needs min < max, a scale >= 0 and g in -1..1"));
+                }
+                let density = Arc::new(load_voxels(file, *dims)?);
+                let volume = Volume::new(density, AaBb::new(min.clone(), max.clone()), *scale, color.clone(), *g);
+                match temperature {
+                    Some(file) => Arc::new(volume.with_temperature(Arc::new(load_voxels(file, *temperature_dims)?), emission.clone())),
+                    None => Arc::new(volume),
+                }
+            }
+            ObjectDesc::Curves { kind, material, segments } => Arc::new(Curves::new(segments.clone(), *kind, self.material(material)?)),
+            ObjectDesc::Particles { materials, positions, radii, colors, material_ids } => {
+                if (!colors.is_empty() && colors.len() != positions.len()) || (!material_ids.is_empty() && material_ids.len() != positions.len()) {
+                    return Err(serialize_error("Particles need one colour and material id per particle"));
+                }
+                if material_ids.iter().any(|id| *id as usize >= materials.len()) {
+                    return Err(serialize_error("Particle material id out of range"));
+                }
+                let materials = materials.iter().map(|m| self.material(&Some(m.clone()))).collect::<Result<Vec<_>, _>>()?;
+                let cloud = ParticleCloud::new(positions.clone(), radii.clone(), None).map_err(|e| serialize_error(format!("Particles: {}", e)))?;
+                let cloud = cloud.with_colors(colors.clone());
+                Arc::new(cloud.with_materials(materials.into_iter().flatten().collect()).with_material_ids(material_ids.clone()))
+            }
+            ObjectDesc::Patches { material, patches } => Arc::new(BezierPatches::new(patches.clone(), self.material(material)?)),
+            ObjectDesc::Medium { medium, boundary } => Arc::new(ConstantMedium::new(self.object(boundary)?, medium.clone())),
+            ObjectDesc::Instance { prototype, transform, motion, material } => {
+                let object = match self.prototypes.get(prototype) {
+                    Some(object) => Arc::clone(object),
+                    None => {
+                        let desc = self.descs.get(prototype);
+                        let desc = desc.ok_or_else(|| serialize_error(format!("Unknown prototype '{}'", prototype)))?;
+                        let object = self.object(desc)?;
+                        self.prototypes.insert(prototype.clone(), Arc::clone(&object));
+                        object
+                    }
+                };
+                let transform = Transform::new(*transform)
+                    .ok_or_else(|| serialize_error(format!("Instance of {} has a singular transform", prototype)))?;
+                let mut instance = Instance::new(object, transform);
+                if let Some(end) = motion {
+                    let end = Transform::new(*end)
+                        .ok_or_else(|| serialize_error(format!("Instance of {} has a singular motion", prototype)))?;
+                    instance = instance.with_motion(end);
+                }
+                instance.material = self.material(material)?;
+                Arc::new(instance)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene() -> World {
+        let src = "camera 13 2 3 0 0 0 20 0.1 close=0.5\n\
+                   background 0.2 0.3 0.4\n\
+                   fog 0.01 0.02 0.9 0.9 1 height=3\n\
+                   material grey diffuse 0.5 0.5 0.5\n\
+                   material fur hair 0.4 0.3 0.2 specular=0.5\n\
+                   curve 0 0 0 0 1 0 1 1 0 1 2 0 0.1 0.05 kind=cylinder fur\n\
+                   light point 0 10 0 100 100 100\n\
+                   light directional 0 -1 -1 1 1 1\n\
+                   sphere 0 -1000 0 1000 grey\n\
+                   metal 0.9 0.9 0.9 fuzz=0.1\n\
+                   sphere 0 1 0 1\n\
+                   moving_sphere 5 1 0 5 2 0 0.5 grey\n\
+                   quad -1 0 -1 2 0 0 0 2 0 grey\n\
+                   box 3 0 3 4 1 4 grey\n\
+                   plane 0 -2 0 0 1 0 grey\n\
+                   disk 0 3 0 0 0 1 2 inner=0.5\n\
+                   cylinder 0 0 0 0 2 0 1 sweep=270 caps=false\n\
+                   torus 0 1 0 1 1 0 2 0.5 grey\n\
+                   sdf \"smooth(0.2, sphere(1), move(1 0 0, box(0.5)))\" -2 -2 -2 3 2 2\n\
+                   csg difference grey\n\
+                   box -1 -1 -1 1 1 1\n\
+                   sphere 0 0 0 1.2\n\
+                   end\n\
+                   medium 0.1 0.5 1 1 1 0.3\n\
+                   sphere 3 1 3 1\n\
+                   end\n\
+                   group tree hidden\n\
+                   sphere 0 1 0 0.5 grey\n\
+                   sphere 0 2 0 0.3\n\
+                   end\n\
+                   transform translate 2 0 0\n\
+                   instance tree\n\
+                   transform translate 2 0 0\n\
+                   motion translate 0 1 0\n\
+                   instance tree grey\n";
+        crate::loader::load_str("test", src).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let world = scene();
+        let desc = SceneDescription::from_world(&world).unwrap();
+        assert_eq!(desc.objects.len(), 15);
+        assert_eq!(desc.fog.as_ref().unwrap().height, Some(3.0));
+        assert_eq!(desc.camera.as_ref().unwrap().shutter_close, 0.5);
+        assert_eq!(desc.prototypes.len(), 1);
+        assert_eq!(desc.lights.len(), 2);
+        let cam = desc.camera.as_ref().unwrap();
+        assert!((cam.fov - 20.0).abs() < 1e-3);
+        assert!((&cam.at - Point3::ZERO).length() < 1e-4);
+
+        for format in &[SceneFormat::Json, SceneFormat::Toml, SceneFormat::Ron] {
+            let text = desc.to_string(*format).unwrap();
+            let back = SceneDescription::from_str(&text, *format).unwrap();
+            assert_eq!(back, desc, "{:?}", format);
+        }
+
+        // The camera is rebuilt from derived vectors, the rest survives exactly
+        let loaded = desc.to_world().unwrap();
+        let mut again = SceneDescription::from_world(&loaded).unwrap();
+        let cam2 = again.camera.take().unwrap();
+        assert!((&cam2.from - &cam.from).length() < 1e-4 && (cam2.fov - cam.fov).abs() < 1e-3);
+        assert_eq!(again, SceneDescription { camera: None, ..desc });
+    }
+
+    #[test]
+    fn test_errors() {
+        let json = r#"{"background": [0, 0, 0], "objects": [{"type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "gold"}]}"#;
+        let desc = SceneDescription::from_str(json, SceneFormat::Json).unwrap();
+        assert!(desc.to_world().is_err());
+        assert!(SceneDescription::from_str("{", SceneFormat::Json).is_err());
+        assert!(SceneDescription::from_str("background = 1", SceneFormat::Toml).is_err());
+        let json = r#"{"background": [0, 0, 0], "settings": {"shutter": [2, 3]}}"#;
+        let error = SceneDescription::from_str(json, SceneFormat::Json).unwrap().to_world().err().unwrap();
+        assert!(error.to_string().contains("within 0..1"), "{}", error);
+        // An empty world has no camera, TOML still needs values before tables
+        let empty = SceneDescription::from_world(&World::new()).unwrap();
+        let text = empty.to_string(SceneFormat::Toml).unwrap();
+        assert_eq!(SceneDescription::from_str(&text, SceneFormat::Toml).unwrap(), empty);
+    }
+
+    fn object_error(object: &str) -> String {
+        let json = format!(r#"{{"background": [0, 0, 0], "objects": [{}]}}"#, object);
+        let desc = SceneDescription::from_str(&json, SceneFormat::Json).unwrap();
+        desc.to_world().err().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_invalid_objects() {
+        let error = object_error(r#"{"type": "quad", "origin": [0, 0, 0], "u": [1, 0, 0], "v": [2, 0, 0]}"#);
+        assert!(error.contains("parallel"), "{}", error);
+        let error = object_error(r#"{"type": "box", "min": [0, 0, 0], "max": [1, 0, 1]}"#);
+        assert!(error.contains("zero extent"), "{}", error);
+        let error = object_error(r#"{"type": "plane", "point": [0, 0, 0], "normal": [0, 0, 0]}"#);
+        assert!(error.contains("normal is zero"), "{}", error);
+        let error = object_error(r#"{"type": "disk", "center": [0, 0, 0], "normal": [0, 1, 0], "radius": 1, "inner_radius": 1}"#);
+        assert!(error.contains("inner_radius < radius"), "{}", error);
+        let error = object_error(r#"{"type": "heightfield", "file": "missing.png", "size": [0, 1, 1]}"#);
+        assert!(error.contains("positive width"), "{}", error);
+        let error = object_error(r#"{"type": "volume", "file": "missing.nrrd", "min": [0, 0, 0], "max": [1, 0, 1], "scale": 1, "color": [1, 1, 1]}"#);
+        assert!(error.contains("min < max"), "{}", error);
+        let error = object_error(r#"{"type": "volume", "file": "missing.nrrd", "min": [0, 0, 0], "max": [1, 1, 1], "scale": -1, "color": [1, 1, 1]}"#);
+        assert!(error.contains("scale >= 0"), "{}", error);
+        let error = object_error(r#"{"type": "sdf", "min": [0, 2, 0], "max": [1, 1, 1], "shape": {"type": "sphere", "radius": 1}}"#);
+        assert!(error.contains("bounds min is above max"), "{}", error);
+    }
+
+    #[test]
+    fn test_relative_files() {
+        let dir = std::env::temp_dir().join("sprios_relative_files");
+        std::fs::create_dir_all(dir.join("images")).unwrap();
+        let image = dir.join("images").join("height.pgm");
+        std::fs::write(&image, b"P2\n2 2\n255\n0 255\n255 0\n").unwrap();
+        let voxels = dir.join("smoke.raw");
+        std::fs::write(&voxels, [0u8, 255]).unwrap();
+        let src = format!(
+            "heightfield {:?} 1 1 1\nvolume {:?} 0 0 0 1 1 1 1 dims=2,1,1\n",
+            image.display().to_string(),
+            voxels.display().to_string()
+        );
+        let world = crate::loader::load_str("test", &src).unwrap();
+
+        let scene = dir.join("scene.json");
+        world.save(&scene).unwrap();
+        let text = std::fs::read_to_string(&scene).unwrap();
+        let expected = Path::new("images").join("height.pgm").display().to_string();
+        assert!(text.contains(&serde_json::to_string(&expected).unwrap()), "{}", text);
+        assert!(text.contains("\"smoke.raw\""), "{}", text);
+        let desc = SceneDescription::load(&scene).unwrap();
+        assert!(desc.to_world().is_ok());
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -1,11 +1,54 @@
 use std::num::{ParseFloatError, ParseIntError};
 use std::error::Error;
+use std::fmt::{Display, Formatter};
+use crate::lexer::Pos;
+
+/// Scene error located in a file, line and column
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub file: String,
+    pub pos: Pos,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(file: &str, pos: Pos, message: String) -> Diagnostic {
+        Diagnostic { file: file.to_string(), pos, message }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.pos.line, self.pos.column, self.message)
+    }
+}
 
 #[derive(Clone, Debug)]
 pub enum SpriosError {
     WorldParseError(String),
+    CheckpointError(String),
+    SceneErrors(Vec<Diagnostic>),
+    SerializeError(String),
+    ImportError(String),
+}
+
+impl Display for SpriosError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpriosError::WorldParseError(e) => write!(f, "Scene error: {}", e),
+            SpriosError::CheckpointError(e) => write!(f, "Checkpoint error: {}", e),
+            SpriosError::SerializeError(e) => write!(f, "Scene file error: {}", e),
+            SpriosError::ImportError(e) => write!(f, "Import error: {}", e),
+            SpriosError::SceneErrors(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", errors.join("\n"))
+            }
+        }
+    }
 }
 
+impl Error for SpriosError {}
+
 impl From<std::num::ParseFloatError> for SpriosError {
 
     fn from(e: ParseFloatError) -> Self {
@@ -0,0 +1,434 @@
+use crate::bvh::Bvh;
+use crate::errors::SpriosError;
+use crate::hittable::Hittable;
+use crate::instance::{Instance, Transform};
+use crate::light::Light;
+use crate::material::{Material, Pbr};
+use crate::mesh::TriangleMesh;
+use crate::subdiv::PolyMesh;
+use crate::texture::Texture;
+use crate::vec::{Color, Mat4, Point3, Vec3};
+use crate::{Camera, World};
+use gltf::khr_lights_punctual::Kind;
+use gltf::mesh::Mode;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Files that require any other extension fail to load
+const SUPPORTED_EXTENSIONS: &[&str] = &["KHR_lights_punctual"];
+
+/// Contents of a glTF file ready to be added to a world
+pub struct GltfScene {
+    pub objects: Vec<Arc<dyn Hittable>>,
+    pub materials: Vec<Arc<dyn Material>>,
+    pub lights: Vec<Light>,
+    /// The first camera of the node hierarchy
+    pub camera: Option<Camera>,
+}
+
+impl GltfScene {
+    pub fn into_world(self) -> World {
+        let mut world = World::new();
+        for mat in self.materials {
+            world.add_material(None, mat);
+        }
+        for object in self.objects {
+            world.add(object);
+        }
+        world.lights = self.lights;
+        if let Some(camera) = self.camera {
+            world.camera = camera;
+        }
+        world
+    }
+}
+
+fn import_error(path: &Path, message: impl Display) -> SpriosError {
+    SpriosError::ImportError(format!("{}: {}", path.display(), message))
+}
+
+struct Importer<'a> {
+    path: &'a Path,
+    buffers: Vec<gltf::buffer::Data>,
+    images: Vec<gltf::image::Data>,
+    /// Keyed by image and whether it holds sRGB colour
+    textures: HashMap<(usize, bool), Arc<Texture>>,
+    materials: HashMap<usize, Arc<dyn Material>>,
+    meshes: HashMap<usize, Arc<dyn Hittable>>,
+    /// Levels of subdivision for every mesh
+    subdiv: Option<u32>,
+    scene: GltfScene,
+}
+
+/// Welds the vertices glTF splits along seams and hard edges back together by position, with
+/// their uvs kept per corner. Adding 0 folds -0 into 0.
+fn cage(positions: &[Point3], uvs: &[[f32; 2]], triangles: &[[u32; 3]]) -> PolyMesh {
+    let mut vertex_of = HashMap::new();
+    let mut welded = vec![];
+    let mut remap = Vec::with_capacity(positions.len());
+    for p in positions {
+        let bits = [(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()];
+        remap.push(*vertex_of.entry(bits).or_insert_with(|| {
+            welded.push(p.clone());
+            welded.len() as u32 - 1
+        }));
+    }
+    let faces = triangles.iter().map(|t| t.iter().map(|i| remap[*i as usize]).collect()).collect();
+    let cage = PolyMesh::new(welded, faces);
+    if uvs.is_empty() {
+        return cage;
+    }
+    let mut uv_of = HashMap::new();
+    let mut cage_uvs = vec![];
+    let mut uv_remap = Vec::with_capacity(uvs.len());
+    for (uv, v) in uvs.iter().zip(&remap) {
+        uv_remap.push(*uv_of.entry((*v, uv[0].to_bits(), uv[1].to_bits())).or_insert_with(|| {
+            cage_uvs.push(*uv);
+            cage_uvs.len() as u32 - 1
+        }));
+    }
+    let uv_faces = triangles.iter().map(|t| t.iter().map(|i| uv_remap[*i as usize]).collect()).collect();
+    cage.with_face_uvs(cage_uvs, uv_faces)
+}
+
+impl Importer<'_> {
+    fn texture(&mut self, image: usize, srgb: bool) -> Arc<Texture> {
+        let images = &self.images;
+        let texture = self.textures.entry((image, srgb)).or_insert_with(|| {
+            use gltf::image::Format::*;
+            let data = &images[image];
+            let (channels, bytes) = match data.format {
+                R8 => (1, 1),
+                R8G8 => (2, 1),
+                R8G8B8 | B8G8R8 => (3, 1),
+                R8G8B8A8 | B8G8R8A8 => (4, 1),
+                R16 => (1, 2),
+                R16G16 => (2, 2),
+                R16G16B16 => (3, 2),
+                R16G16B16A16 => (4, 2),
+            };
+            // Keep the high byte of 16 bit channels, little endian
+            let mut pixels: Vec<u8> = data.pixels.iter().skip(bytes - 1).step_by(bytes).copied().collect();
+            if let B8G8R8 | B8G8R8A8 = data.format {
+                pixels.chunks_exact_mut(channels).for_each(|p| p.swap(0, 2));
+            }
+            Arc::new(Texture::from_bytes(data.width, data.height, channels, &pixels, srgb))
+        });
+        Arc::clone(texture)
+    }
+
+    fn material(&mut self, mat: gltf::Material) -> Option<Arc<dyn Material>> {
+        let index = mat.index()?;
+        if let Some(mat) = self.materials.get(&index) {
+            return Some(Arc::clone(mat));
+        }
+        let pbr = mat.pbr_metallic_roughness();
+        let base = pbr.base_color_factor();
+        let color_texture = pbr.base_color_texture().map(|info| self.texture(info.texture().source().index(), true));
+        let metallic_roughness_texture =
+            pbr.metallic_roughness_texture().map(|info| self.texture(info.texture().source().index(), false));
+        let material: Arc<dyn Material> = Arc::new(Pbr {
+            color: Color::new(base[0], base[1], base[2]),
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            color_texture,
+            metallic_roughness_texture,
+        });
+        self.materials.insert(index, Arc::clone(&material));
+        self.scene.materials.push(Arc::clone(&material));
+        Some(material)
+    }
+
+    /// Every primitive becomes a triangle mesh, meshes with several share a BVH. Subdivided
+    /// primitives get normals of their own.
+    fn mesh(&mut self, mesh: gltf::Mesh) -> Result<Option<Arc<dyn Hittable>>, SpriosError> {
+        if let Some(object) = self.meshes.get(&mesh.index()) {
+            return Ok(Some(Arc::clone(object)));
+        }
+        let name = mesh.name().map(str::to_string).unwrap_or_else(|| format!("#{}", mesh.index()));
+        let mut primitives: Vec<Arc<dyn Hittable>> = vec![];
+        for primitive in mesh.primitives() {
+            let buffers = &self.buffers;
+            let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+            let positions: Vec<Point3> = match reader.read_positions() {
+                Some(positions) => positions.map(Point3::from).collect(),
+                None => return Err(import_error(self.path, format!("mesh {} has no positions", name))),
+            };
+            let normals: Vec<Vec3> = reader.read_normals().map(|n| n.map(Vec3::from).collect()).unwrap_or_default();
+            let uvs: Vec<[f32; 2]> = reader.read_tex_coords(0).map(|t| t.into_f32().collect()).unwrap_or_default();
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let mismatch = |len: usize| len != 0 && len != positions.len();
+            if mismatch(normals.len()) || mismatch(uvs.len()) {
+                return Err(import_error(self.path, format!("mesh {} has attributes of different lengths", name)));
+            }
+            if indices.iter().any(|i| *i as usize >= positions.len()) {
+                return Err(import_error(self.path, format!("mesh {} has indices out of range", name)));
+            }
+            let triangles: Vec<[u32; 3]> = match primitive.mode() {
+                Mode::Triangles => indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect(),
+                Mode::TriangleStrip => (0..indices.len().saturating_sub(2))
+                    .map(|i| match i % 2 {
+                        0 => [indices[i], indices[i + 1], indices[i + 2]],
+                        _ => [indices[i + 1], indices[i], indices[i + 2]],
+                    })
+                    .collect(),
+                Mode::TriangleFan => {
+                    (1..indices.len().saturating_sub(1)).map(|i| [indices[0], indices[i], indices[i + 1]]).collect()
+                }
+                mode => {
+                    let message = format!("mesh {}: primitive mode {:?} is not supported, only triangles", name, mode);
+                    return Err(import_error(self.path, message));
+                }
+            };
+            if triangles.is_empty() {
+                continue;
+            }
+            let mat = self.material(primitive.material());
+            let mesh = match self.subdiv {
+                Some(levels) => cage(&positions, &uvs, &triangles).subdivide(levels, mat),
+                None => TriangleMesh::new(positions, normals, triangles, mat).with_uvs(uvs),
+            };
+            primitives.push(Arc::new(mesh));
+        }
+        let object: Arc<dyn Hittable> = match primitives.len() {
+            0 => return Ok(None),
+            1 => primitives.pop().unwrap(),
+            _ => Arc::new(Bvh::new(primitives)),
+        };
+        self.meshes.insert(mesh.index(), Arc::clone(&object));
+        Ok(Some(object))
+    }
+
+    fn camera(&self, camera: gltf::Camera, m: &Mat4) -> Result<Camera, SpriosError> {
+        let perspective = match camera.projection() {
+            gltf::camera::Projection::Perspective(p) => p,
+            gltf::camera::Projection::Orthographic(_) => {
+                return Err(import_error(self.path, "orthographic cameras are not supported"))
+            }
+        };
+        // Cameras look down -z with +y up
+        let from = m.transform_point(&Point3::ZERO);
+        let at = m.transform_point(&Point3::new(0.0, 0.0, -1.0));
+        let up = m.transform_vector(&Vec3::new(0.0, 1.0, 0.0));
+        let aspect = perspective.aspect_ratio().unwrap_or(16.0 / 9.0);
+        Ok(Camera::new(from, at, up, perspective.yfov().to_degrees(), aspect, 0.0, 1.0))
+    }
+
+    /// Spot lights are imported as point lights, intensities are taken as they are
+    fn light(&self, light: gltf::khr_lights_punctual::Light, m: &Mat4) -> Light {
+        let intensity = Color::from(light.color()) * light.intensity();
+        match light.kind() {
+            Kind::Directional => {
+                let direction = m.transform_vector(&Vec3::new(0.0, 0.0, -1.0));
+                Light::Directional { direction, intensity }
+            }
+            Kind::Point | Kind::Spot { .. } => Light::Point { position: m.transform_point(&Point3::ZERO), intensity },
+        }
+    }
+
+    fn node(&mut self, node: gltf::Node, parent: &Mat4) -> Result<(), SpriosError> {
+        let m = parent * &Mat4::new(node.transform().matrix()).transpose();
+        if let Some(mesh) = node.mesh() {
+            if let Some(object) = self.mesh(mesh)? {
+                if m == Mat4::IDENTITY {
+                    self.scene.objects.push(object);
+                } else {
+                    let transform = Transform::new(m).ok_or_else(|| {
+                        import_error(self.path, format!("node {} has a singular transform", node.index()))
+                    })?;
+                    self.scene.objects.push(Arc::new(Instance::new(object, transform)));
+                }
+            }
+        }
+        if let Some(camera) = node.camera() {
+            if self.scene.camera.is_none() {
+                self.scene.camera = Some(self.camera(camera, &m)?);
+            }
+        }
+        if let Some(light) = node.light() {
+            let light = self.light(light, &m);
+            self.scene.lights.push(light);
+        }
+        for child in node.children() {
+            self.node(child, &m)?;
+        }
+        Ok(())
+    }
+}
+
+/// Read a `.gltf` or `.glb` file with its buffers and images
+pub fn import_gltf(path: impl AsRef<Path>) -> Result<GltfScene, SpriosError> {
+    import(path.as_ref(), None)
+}
+
+/// Like `import_gltf` with every mesh smoothed as a subdivision surface `levels` times
+pub fn import_gltf_subdivided(path: impl AsRef<Path>, levels: u32) -> Result<GltfScene, SpriosError> {
+    import(path.as_ref(), Some(levels))
+}
+
+fn import(path: &Path, subdiv: Option<u32>) -> Result<GltfScene, SpriosError> {
+    let (document, buffers, images) = gltf::import(path).map_err(|e| import_error(path, e))?;
+    let unsupported: Vec<&str> = document.extensions_required().filter(|e| !SUPPORTED_EXTENSIONS.contains(e)).collect();
+    if !unsupported.is_empty() {
+        return Err(import_error(path, format!("required extensions not supported: {}", unsupported.join(", "))));
+    }
+    let scene = match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => scene,
+        None => return Err(import_error(path, "no scene to import")),
+    };
+    let mut importer = Importer {
+        path,
+        buffers,
+        images,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        meshes: HashMap::new(),
+        subdiv,
+        scene: GltfScene { objects: vec![], materials: vec![], lights: vec![], camera: None },
+    };
+    for node in scene.nodes() {
+        importer.node(node, &Mat4::IDENTITY)?;
+    }
+    Ok(importer.scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::HitRecord;
+    use crate::{Lambertian, Ray};
+
+    // One triangle (0,0,0) (1,0,0) (0,1,0) as three float positions
+    const BUFFER: &str = "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA";
+
+    /// Fresh directory for one test, removed by the test when it is done
+    fn test_dir(test: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("sprios_gltf_{}_{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(dir: &std::path::Path, name: &str, json: &str) -> std::path::PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, json).unwrap();
+        path
+    }
+
+    fn scene(extensions: &str) -> String {
+        format!(
+            r#"{{
+            "asset": {{"version": "2.0"}},
+            {}
+            "scene": 0,
+            "scenes": [{{"nodes": [0, 1, 2, 3]}}],
+            "nodes": [
+                {{"mesh": 0, "translation": [0, 0, -5]}},
+                {{"mesh": 0, "translation": [3, 0, -5], "scale": [2, 2, 2]}},
+                {{"camera": 0, "translation": [0, 0, 1]}},
+                {{"extensions": {{"KHR_lights_punctual": {{"light": 0}}}}, "translation": [0, 4, 0]}}
+            ],
+            "cameras": [{{"type": "perspective", "perspective": {{"yfov": 0.7, "aspectRatio": 1.5, "znear": 0.1}}}}],
+            "extensions": {{"KHR_lights_punctual": {{"lights": [{{"type": "point", "color": [1, 0.5, 0.5], "intensity": 10}}]}}}},
+            "materials": [{{"pbrMetallicRoughness": {{"baseColorFactor": [0.8, 0.1, 0.1, 1], "metallicFactor": 0.0}}}}],
+            "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "material": 0}}]}}],
+            "accessors": [{{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                           "min": [0, 0, 0], "max": [1, 1, 0]}}],
+            "bufferViews": [{{"buffer": 0, "byteLength": 36}}],
+            "buffers": [{{"byteLength": 36, "uri": "{}"}}]
+        }}"#,
+            extensions, BUFFER
+        )
+    }
+
+    #[test]
+    fn test_import() {
+        let dir = test_dir("import");
+        let path = write(&dir, "scene.gltf", &scene(r#""extensionsUsed": ["KHR_lights_punctual"],"#));
+        let imported = import_gltf(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(imported.objects.len(), 2);
+        assert_eq!(imported.materials.len(), 1);
+        assert_eq!(
+            imported.lights,
+            vec![Light::Point { position: Point3::new(0.0, 4.0, 0.0), intensity: Color::new(10.0, 5.0, 5.0) }]
+        );
+        let camera = imported.camera.as_ref().unwrap();
+        assert_eq!(camera.origin, Point3::new(0.0, 0.0, 1.0));
+
+        let world = imported.into_world();
+        let mat = Lambertian { color: Color::ZERO };
+        let mut rec = HitRecord::new(&mat);
+        // The second instance is scaled by two
+        let ray = Ray::new(&Vec3::new(4.5, 0.2, 0.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(world.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.t - 5.0).abs() < 1e-5);
+        assert_eq!(rec.mat.color(), &Color::new(0.8, 0.1, 0.1));
+        let ray = Ray::new(&Vec3::new(0.9, 0.9, 0.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(!world.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+    }
+
+    #[test]
+    fn test_subdivided() {
+        // A square as two triangles of their own, the uvs of the diagonal differ across it at (1, 1)
+        let positions: Vec<Point3> = [[0, 0], [1, 0], [1, 1], [0, 0], [1, 1], [0, 1]]
+            .iter()
+            .map(|[x, y]| Point3::new(*x as f32, *y as f32, 0.0))
+            .collect();
+        let uvs = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 0.0], [0.5, 1.0], [0.0, 1.0]];
+        let triangles = [[0, 1, 2], [3, 4, 5]];
+        let welded = cage(&positions, &uvs, &triangles);
+        assert_eq!(welded.positions.len(), 4);
+        assert_eq!(welded.faces, [[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(welded.uvs.len(), 5);
+        assert_eq!(welded.uv_faces, [[0, 1, 2], [0, 3, 4]]);
+        let mesh = welded.subdivide(1, None);
+        assert_eq!(mesh.triangles.len(), 8);
+        // 9 points, with (1, 1) and the middle of the diagonal split in two but not the origin
+        assert_eq!(mesh.positions.len(), 11);
+        let mut points: Vec<_> = mesh.positions.iter().map(|p| [p.x.to_bits(), p.y.to_bits()]).collect();
+        points.sort_unstable();
+        points.dedup();
+        assert_eq!(points.len(), 9);
+
+        let dir = test_dir("subdivided");
+        write(&dir, "scene.gltf", &scene(r#""extensionsUsed": ["KHR_lights_punctual"],"#));
+        let file = dir.join("scene.rsc");
+        let world = crate::load_str(file.to_str().unwrap(), "gltf \"scene.gltf\" subdiv 2\n").unwrap();
+        assert_eq!(world.objects.len(), 2);
+        let err = crate::load_str(file.to_str().unwrap(), "gltf \"scene.gltf\" camera subdiv=9\n").err().unwrap().to_string();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(err.ends_with("gltf: subdiv needs a whole number of levels up to 8"), "{}", err);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let json = scene(r#""extensionsUsed": ["KHR_draco_mesh_compression"], "extensionsRequired": ["KHR_draco_mesh_compression"],"#);
+        let dir = test_dir("unsupported");
+        let path = write(&dir, "scene.gltf", &json);
+        let err = import_gltf(&path).err().unwrap().to_string();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(err.contains("KHR_draco_mesh_compression"), "{}", err);
+        assert!(import_gltf("missing.glb").is_err());
+    }
+
+    #[test]
+    fn test_directive() {
+        let dir = test_dir("directive");
+        write(&dir, "scene.gltf", &scene(r#""extensionsUsed": ["KHR_lights_punctual"],"#));
+        let file = dir.join("scene.rsc");
+        let src = "transform translate 0 1 0\ngltf \"scene.gltf\" camera\n";
+        let world = crate::load_str(file.to_str().unwrap(), src).unwrap();
+        let names: Vec<_> = world.objects.iter().map(|o| o.name()).collect();
+        assert_eq!(names, ["Instance", "Instance"]);
+        assert_eq!(world.lights[0], Light::Point { position: Point3::new(0.0, 5.0, 0.0), intensity: Color::new(10.0, 5.0, 5.0) });
+        assert_eq!(world.camera.origin, Point3::new(0.0, 0.0, 1.0));
+        let err = crate::load_str(file.to_str().unwrap(), "gltf \"missing.glb\"").err().unwrap().to_string();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(err.contains("missing.glb"), "{}", err);
+    }
+}
@@ -0,0 +1,330 @@
+use crate::bbox::AaBb;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec::{Color, Point3, Vec3};
+use crate::Lambertian;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Lowest and highest sample of each node, one node covers 2x2 nodes of the level below
+struct Level {
+    columns: usize,
+    rows: usize,
+    ranges: Vec<(f32, f32)>,
+}
+
+/// Distance, the grid indices of the triangle and its barycentrics
+type CellHit = (f32, [(usize, usize); 3], f32, f32);
+
+/// Grid of height samples traced directly, without building triangles.
+///
+/// The grid spans `0..size.x` and `0..size.z` with heights of `0..1` scaled by `size.y`.
+/// Each cell between four samples is split into two triangles with interpolated normals,
+/// texture coordinates run along x and z with `v` following the image rows.
+/// A min/max quadtree of the cells skips the parts of the grid the ray passes over.
+pub struct Heightfield {
+    pub columns: usize,
+    pub rows: usize,
+    pub size: Vec3,
+    pub material: Arc<dyn Material>,
+    /// Image the heights were read from, what scene files save
+    pub file: Option<PathBuf>,
+    heights: Vec<f32>,
+    /// Cells first, a single node covering the grid last
+    levels: Vec<Level>,
+}
+
+impl Heightfield {
+    /// Heights go row by row, `columns` along x and `rows` along z. Needs at least 2x2 samples.
+    pub fn new(columns: usize, rows: usize, heights: Vec<f32>, size: Vec3, mat: Option<Arc<dyn Material>>) -> Result<Heightfield, String> {
+        if columns < 2 || rows < 2 {
+            return Err("a heightfield needs at least 2x2 samples".to_string());
+        }
+        if heights.len() != columns * rows {
+            return Err(format!("{} heights for {}x{} samples", heights.len(), columns, rows));
+        }
+        let mut cells = Level { columns: columns - 1, rows: rows - 1, ranges: vec![] };
+        for j in 0..rows - 1 {
+            for i in 0..columns - 1 {
+                let corners = [(i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)];
+                let range = corners.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), (x, z)| {
+                    let h = heights[z * columns + x];
+                    (lo.min(h), hi.max(h))
+                });
+                cells.ranges.push(range);
+            }
+        }
+        let mut levels = vec![cells];
+        loop {
+            let below = levels.last().unwrap();
+            if below.columns == 1 && below.rows == 1 {
+                break;
+            }
+            let mut level = Level { columns: below.columns.div_ceil(2), rows: below.rows.div_ceil(2), ranges: vec![] };
+            for j in 0..level.rows {
+                for i in 0..level.columns {
+                    let mut range = (f32::INFINITY, f32::NEG_INFINITY);
+                    for z in 2 * j..(2 * j + 2).min(below.rows) {
+                        for x in 2 * i..(2 * i + 2).min(below.columns) {
+                            let (lo, hi) = below.ranges[z * below.columns + x];
+                            range = (range.0.min(lo), range.1.max(hi));
+                        }
+                    }
+                    level.ranges.push(range);
+                }
+            }
+            levels.push(level);
+        }
+        Ok(Heightfield {
+            columns,
+            rows,
+            size,
+            material: mat.unwrap_or_else(|| Arc::new(Lambertian { color: Color::new(0.8, 0.8, 0.8) })),
+            file: None,
+            heights,
+            levels,
+        })
+    }
+
+    /// Distance between samples along x and z
+    fn spacing(&self) -> (f32, f32) {
+        (self.size.x / (self.columns - 1) as f32, self.size.z / (self.rows - 1) as f32)
+    }
+
+    fn height(&self, i: usize, j: usize) -> f32 {
+        self.heights[j * self.columns + i]
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Point3 {
+        let (dx, dz) = self.spacing();
+        Point3::new(i as f32 * dx, self.height(i, j) * self.size.y, j as f32 * dz)
+    }
+
+    /// Central differences of the neighbouring samples, one sided on the edges
+    fn vertex_normal(&self, i: usize, j: usize) -> Vec3 {
+        let (dx, dz) = self.spacing();
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(self.columns - 1));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(self.rows - 1));
+        let slope_x = (self.height(i1, j) - self.height(i0, j)) * self.size.y / ((i1 - i0) as f32 * dx);
+        let slope_z = (self.height(i, j1) - self.height(i, j0)) * self.size.y / ((j1 - j0) as f32 * dz);
+        Vec3::new(-slope_x, 1.0, -slope_z).unit()
+    }
+
+    /// Bounds of a node, padded so flat nodes still have volume
+    fn node_bbox(&self, level: usize, i: usize, j: usize) -> AaBb {
+        let (dx, dz) = self.spacing();
+        let cells = &self.levels[0];
+        let span = 1 << level;
+        let (lo, hi) = self.levels[level].ranges[j * self.levels[level].columns + i];
+        let pad = 1e-4;
+        AaBb::new(
+            Point3::new((i * span) as f32 * dx - pad, lo * self.size.y - pad, (j * span) as f32 * dz - pad),
+            Point3::new(
+                ((i + 1) * span).min(cells.columns) as f32 * dx + pad,
+                hi * self.size.y + pad,
+                ((j + 1) * span).min(cells.rows) as f32 * dz + pad,
+            ),
+        )
+    }
+
+    /// Möller-Trumbore against one triangle, returns distance and barycentrics
+    fn triangle(p0: &Point3, p1: &Point3, p2: &Point3, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let pvec = Vec3::cross(&ray.direction, &e2);
+        let det = e1.dot(&pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = &ray.origin - p0;
+        let u = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let qvec = Vec3::cross(&tvec, &e1);
+        let v = ray.direction.dot(&qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = e2.dot(&qvec) * inv_det;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        Some((t, u, v))
+    }
+
+    /// Closest hit in a cell, with the three vertices and barycentrics
+    fn hit_cell(&self, i: usize, j: usize, ray: &Ray, t_min: f32, t_max: f32) -> Option<CellHit> {
+        let tris = [[(i, j), (i, j + 1), (i + 1, j + 1)], [(i, j), (i + 1, j + 1), (i + 1, j)]];
+        let mut closest = None;
+        let mut t_max = t_max;
+        for tri in &tris {
+            let [a, b, c] = *tri;
+            let (p0, p1, p2) = (self.vertex(a.0, a.1), self.vertex(b.0, b.1), self.vertex(c.0, c.1));
+            if let Some((t, u, v)) = Self::triangle(&p0, &p1, &p2, ray, t_min, t_max) {
+                t_max = t;
+                closest = Some((t, *tri, u, v));
+            }
+        }
+        closest
+    }
+}
+
+impl Hittable for Heightfield {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let inv = |d: f32| if d == 0.0 { 1e30 } else { 1.0 / d };
+        let d = &ray.direction;
+        let inv_dir = Vec3::new(inv(d.x), inv(d.y), inv(d.z));
+        // Children are pushed far first so the near ones come off the stack first
+        let flip_x = d.x < 0.0;
+        let flip_z = d.z < 0.0;
+        let mut stack = vec![(self.levels.len() - 1, 0, 0)];
+        let mut closest = None;
+        let mut t_max = t_max;
+        while let Some((level, i, j)) = stack.pop() {
+            if self.node_bbox(level, i, j).intersect(&ray.origin, &inv_dir, t_min, t_max).is_none() {
+                continue;
+            }
+            if level == 0 {
+                if let Some(hit) = self.hit_cell(i, j, ray, t_min, t_max) {
+                    t_max = hit.0;
+                    closest = Some(hit);
+                }
+                continue;
+            }
+            let below = &self.levels[level - 1];
+            for n in (0..4).rev() {
+                let (ci, cj) = (2 * i + ((n & 1 == 1) != flip_x) as usize, 2 * j + ((n & 2 == 2) != flip_z) as usize);
+                if ci < below.columns && cj < below.rows {
+                    stack.push((level - 1, ci, cj));
+                }
+            }
+        }
+        let (t, [a, b, c], u, v) = match closest {
+            Some(hit) => hit,
+            None => return false,
+        };
+        let (p0, p1, p2) = (self.vertex(a.0, a.1), self.vertex(b.0, b.1), self.vertex(c.0, c.1));
+        let geometric = Vec3::cross(&(&p1 - &p0), &(&p2 - &p0)).unit();
+        let w = 1.0 - u - v;
+        let normal = self.vertex_normal(a.0, a.1) * w + self.vertex_normal(b.0, b.1) * u + self.vertex_normal(c.0, c.1) * v;
+        let normal = normal.unit();
+        rec.t = t;
+        rec.p = ray.at(t);
+        rec.mat = self.material.as_ref();
+        rec.u = rec.p.x / self.size.x;
+        rec.v = rec.p.z / self.size.z;
+        rec.color = Color::ONE;
+        rec.front_face = ray.direction.dot(&geometric) < 0.0;
+        rec.normal = if rec.front_face { normal } else { -normal };
+        true
+    }
+
+    fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
+        Some(self.node_bbox(self.levels.len() - 1, 0, 0))
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        self.material = mat;
+    }
+
+    #[inline]
+    fn name(&self) -> &'static str {
+        "Heightfield"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        let file = self.file.as_ref().ok_or_else(|| SpriosError::SerializeError("Heightfield has no image file".to_string()))?;
+        Ok(ObjectDesc::Heightfield {
+            file: file.display().to_string(),
+            size: self.size.clone(),
+            material: Some(writer.material(self.material.as_ref())?),
+        })
+    }
+}
+
+/// Read a grayscale PNG or PGM, 16 bit images keep their precision.
+/// Black is height 0 and white the full `size.y`.
+pub fn load_heightfield(path: impl AsRef<Path>, size: Vec3, mat: Option<Arc<dyn Material>>) -> Result<Heightfield, SpriosError> {
+    let path = path.as_ref();
+    let error = |e: &dyn std::fmt::Display| SpriosError::ImportError(format!("{}: {}", path.display(), e));
+    let image = image::open(path).map_err(|e| error(&e))?.into_luma16();
+    let (columns, rows) = (image.width() as usize, image.height() as usize);
+    let heights = image.pixels().map(|p| p.0[0] as f32 / u16::MAX as f32).collect();
+    let mut field = Heightfield::new(columns, rows, heights, size, mat).map_err(|e| error(&e))?;
+    field.file = Some(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+    Ok(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp() -> Heightfield {
+        // Rises along x from 0 to 1 over 4 samples, 3 rows
+        let heights = (0..12).map(|i| (i % 4) as f32 / 3.0).collect();
+        Heightfield::new(4, 3, heights, Vec3::new(3.0, 3.0, 2.0), None).unwrap()
+    }
+
+    #[test]
+    fn test_hit() {
+        let field = ramp();
+        assert_eq!(field.levels.len(), 3);
+        assert_eq!(field.levels[2].ranges, vec![(0.0, 1.0)]);
+        let bbox = field.bbox(0.0, 1.0).unwrap();
+        assert!((bbox.max.x - 3.0).abs() < 1e-3 && (bbox.max.y - 3.0).abs() < 1e-3 && (bbox.max.z - 2.0).abs() < 1e-3);
+
+        let mat = Lambertian { color: Color::ONE };
+        let mut rec = HitRecord::new(&mat);
+        // Straight down onto the 45 degree slope
+        let ray = Ray::new(&Point3::new(1.5, 10.0, 0.5), &Vec3::new(0.0, -1.0, 0.0));
+        assert!(field.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.t - 8.5).abs() < 1e-4 && rec.front_face);
+        let n = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((&rec.normal - Vec3::new(-n, n, 0.0)).length() < 1e-4);
+        assert!((rec.u - 0.5).abs() < 1e-5 && (rec.v - 0.25).abs() < 1e-5);
+        // Along the slope from below the surface the ray comes out of the back
+        let ray = Ray::new(&Point3::new(2.5, 1.0, 1.0), &Vec3::new(-1.0, 0.0, 0.0));
+        assert!(field.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.t - 1.5).abs() < 1e-4 && !rec.front_face);
+        // Outside the grid
+        let ray = Ray::new(&Point3::new(3.5, 10.0, 0.5), &Vec3::new(0.0, -1.0, 0.0));
+        assert!(!field.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+    }
+
+    #[test]
+    fn test_grazing() {
+        // Flat with a single peak, rays skimming the plane only hit the peak
+        let mut heights = vec![0.0; 64 * 64];
+        heights[32 * 64 + 40] = 1.0;
+        let field = Heightfield::new(64, 64, heights, Vec3::new(63.0, 1.0, 63.0), None).unwrap();
+        let mat = Lambertian { color: Color::ONE };
+        let mut rec = HitRecord::new(&mat);
+        let ray = Ray::new(&Point3::new(-1.0, 0.5, 32.0), &Vec3::new(1.0, 0.0, 0.0));
+        assert!(field.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.p.x - 39.5).abs() < 1e-3);
+        let ray = Ray::new(&Point3::new(64.0, 0.5, 10.0), &Vec3::new(-1.0, 0.0, 0.0));
+        assert!(!field.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+    }
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join("sprios_heightfield.pgm");
+        std::fs::write(&path, b"P2\n3 2\n255\n0 128 255\n255 255 255\n").unwrap();
+        let field = load_heightfield(&path, Vec3::new(2.0, 1.0, 1.0), None).unwrap();
+        assert_eq!((field.columns, field.rows), (3, 2));
+        assert!((field.height(1, 0) - 128.0 / 255.0).abs() < 1e-3);
+        assert!(load_heightfield("missing.png", Vec3::ONE, None).is_err());
+        assert!(Heightfield::new(1, 3, vec![0.0; 3], Vec3::ONE, None).is_err());
+        assert!(Heightfield::new(2, 2, vec![0.0; 3], Vec3::ONE, None).is_err());
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -1,11 +1,11 @@
-use crate::errors::{SpriosError, SpriosError::WorldParseError};
 use crate::material::Material;
 use crate::ray::Ray;
-use crate::vec::{Point3, Vec3};
+use crate::vec::{Color, Point3, Vec3};
 use crate::bbox::AaBb;
-use std::str::FromStr;
-use crate::Sphere;
 use std::sync::Arc;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::Lambertian;
 
 #[derive(Clone)]
 pub struct HitRecord<'obj> {
@@ -14,6 +14,13 @@ pub struct HitRecord<'obj> {
     pub mat: &'obj dyn Material,
     pub p: Point3,
     pub t: f32,
+    /// Surface coordinates for textures
+    pub u: f32,
+    pub v: f32,
+    /// Vertex colour multiplying the albedo, ONE on surfaces without colours
+    pub color: Color,
+    /// Direction of the strand on curves, ZERO on surfaces
+    pub tangent: Vec3,
 }
 
 impl<'obj> HitRecord<'obj> {
@@ -24,6 +31,10 @@ impl<'obj> HitRecord<'obj> {
             mat,
             p: Point3::ZERO,
             t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            color: Color::ONE,
+            tangent: Vec3::ZERO,
         }
     }
     pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: &Vec3) {
@@ -34,29 +45,98 @@ impl<'obj> HitRecord<'obj> {
             -outward_normal.clone()
         };
     }
+
+    /// Hit `object` on a fresh record, kept only on a hit. Primitives that don't set the
+    /// coordinates, colour or tangent leave the defaults instead of those of a farther hit.
+    pub fn hit_candidate(&mut self, object: &'obj dyn Hittable, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        let mut candidate = HitRecord::new(self.mat);
+        if !object.hit(ray, t_min, t_max, &mut candidate) {
+            return false;
+        }
+        *self = candidate;
+        true
+    }
 }
 
+/// Placeholder until a primitive fills in its own material
+static NO_MATERIAL: Lambertian = Lambertian { color: Color::ZERO };
+/// Most crossings `Hittable::crossings` reports for one ray
+pub(crate) const MAX_CROSSINGS: usize = 64;
+
 pub trait Hittable: Send + Sync {
     fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool;
     fn bbox(&self, t0: f32, t1: f32) -> Option<AaBb>;
     fn material(&self) -> Option<&dyn Material>;
-    fn set_material(&mut self, mat: Box<dyn Material>);
+    fn set_material(&mut self, mat: Arc<dyn Material>);
     fn name(&self) -> &'static str;
+    /// Every surface crossing in `t_min..t_max` ordered by distance, what CSG combines.
+    /// The default steps along the ray with repeated `hit` calls
+    fn hits<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32) -> Vec<HitRecord<'obj>> {
+        let step = 1e-4 / ray.direction.length();
+        let mut out: Vec<HitRecord<'obj>> = vec![];
+        let mut rec = HitRecord::new(&NO_MATERIAL);
+        let mut t = t_min;
+        while out.len() < MAX_CROSSINGS && self.hit(ray, t, t_max, &mut rec) {
+            t = rec.t + step;
+            out.push(rec.clone());
+        }
+        out
+    }
+    /// Plain data version for scene files, shared materials and geometry go through `writer`
+    fn describe(&self, _writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        Err(SpriosError::SerializeError(format!("{} can't be saved", self.name())))
+    }
+}
+
+/// A group of objects hit as one, what scene `group` blocks build
+#[derive(Default)]
+pub struct HittableList {
+    pub objects: Vec<Arc<dyn Hittable>>,
+}
+
+impl HittableList {
+    pub fn new(objects: Vec<Arc<dyn Hittable>>) -> HittableList {
+        HittableList { objects }
+    }
 }
 
-pub fn from_string(s: &str) -> Result<Arc<dyn Hittable>, SpriosError> {
-    let mut split = s.split_whitespace();
-    let shape = split.next().ok_or(WorldParseError("empty object".to_string()))?;
-    let parms = split
-        .map(|v| v.parse::<f32>())
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|_|WorldParseError("Could not parse object parms".to_string()))?;
-    match shape {
-        "sphere" => {
-            let center = Point3::new(parms[0], parms[1], parms[2]);
-            return Ok(Arc::new(Sphere::new(center, parms[3], None)));
+impl Hittable for HittableList {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let mut closest_so_far = t_max;
+        let mut been_hit = false;
+        for obj in &self.objects {
+            if rec.hit_candidate(obj.as_ref(), ray, t_min, closest_so_far) {
+                been_hit = true;
+                closest_so_far = rec.t;
+            }
+        }
+        been_hit
+    }
+
+    fn bbox(&self, t0: f32, t1: f32) -> Option<AaBb> {
+        let mut out: Option<AaBb> = None;
+        for obj in &self.objects {
+            let bbox = obj.bbox(t0, t1)?;
+            out = Some(match out {
+                Some(b) => AaBb::surrounding_box(&b, &bbox),
+                None => bbox,
+            });
         }
-        _ => {}
+        out
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        None
+    }
+
+    fn set_material(&mut self, _mat: Arc<dyn Material>) {}
+
+    fn name(&self) -> &'static str {
+        "Group"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        let objects = self.objects.iter().map(|o| o.describe(writer)).collect::<Result<_, _>>()?;
+        Ok(ObjectDesc::Group { objects })
     }
-    Err(WorldParseError("Not a Hittable".to_string()))
 }
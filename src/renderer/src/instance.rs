@@ -0,0 +1,213 @@
+use crate::bbox::AaBb;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::{motion_blend, Ray};
+use crate::vec::{Mat4, Point3, Vec3};
+use std::sync::Arc;
+
+/// Object to world matrix together with its inverse
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub matrix: Mat4,
+    pub inverse: Mat4,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform { matrix: Mat4::IDENTITY, inverse: Mat4::IDENTITY };
+
+    /// None when the matrix can't be inverted
+    pub fn new(matrix: Mat4) -> Option<Transform> {
+        Some(Transform { matrix, inverse: matrix.inverse()? })
+    }
+
+    /// `self * other`, `other` is applied first
+    pub fn compose(&self, other: &Transform) -> Transform {
+        Transform {
+            matrix: self.matrix * other.matrix,
+            inverse: other.inverse * self.inverse,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.matrix == Mat4::IDENTITY
+    }
+
+    /// Element wise blend of the matrices, None when the blend can't be inverted.
+    /// Only exact for transforms that differ by translation and scale, a rotation between
+    /// them would shear and shrink the object halfway.
+    pub fn lerp(&self, other: &Transform, s: f32) -> Option<Transform> {
+        let mut matrix = self.matrix;
+        for (row, end) in matrix.m.iter_mut().zip(&other.matrix.m) {
+            for (v, e) in row.iter_mut().zip(end) {
+                *v += (e - *v) * s;
+            }
+        }
+        Transform::new(matrix)
+    }
+
+    pub fn transform_bbox(&self, bbox: &AaBb) -> AaBb {
+        let (lo, hi) = (&bbox.min, &bbox.max);
+        let mut min = Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = -&min;
+        for i in 0..8 {
+            let corner = Point3::new(
+                if i & 1 == 0 { lo.x } else { hi.x },
+                if i & 2 == 0 { lo.y } else { hi.y },
+                if i & 4 == 0 { lo.z } else { hi.z },
+            );
+            let p = self.matrix.transform_point(&corner);
+            min = Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        AaBb::new(min, max)
+    }
+}
+
+/// Shared geometry placed in the world by a transform.
+///
+/// Rays are moved into object space without normalizing the direction, so `t` is the same
+/// in both spaces. The material, if set, overrides the one of the shared object.
+/// With a `motion` the placement moves from `transform` at time 0 to `motion` at time 1.
+/// The two are blended with `Transform::lerp`, so they may differ by translation and scale only.
+pub struct Instance {
+    pub object: Arc<dyn Hittable>,
+    pub transform: Transform,
+    pub motion: Option<Transform>,
+    pub material: Option<Arc<dyn Material>>,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable>, transform: Transform) -> Instance {
+        Instance { object, transform, motion: None, material: None }
+    }
+
+    pub fn with_motion(mut self, end: Transform) -> Instance {
+        self.motion = Some(end);
+        self
+    }
+
+    /// Placement at `time`, None if the blended matrix is singular
+    fn transform_at(&self, time: f32) -> Option<Transform> {
+        match &self.motion {
+            Some(end) => self.transform.lerp(end, motion_blend(time)),
+            None => Some(self.transform),
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let transform = match self.transform_at(ray.time) {
+            Some(transform) => transform,
+            None => return false,
+        };
+        let inv = &transform.inverse;
+        let local = Ray::new(&inv.transform_point(&ray.origin), &inv.transform_vector(&ray.direction)).with_time(ray.time);
+        if !self.object.hit(&local, t_min, t_max, rec) {
+            return false;
+        }
+        rec.p = ray.at(rec.t);
+        // The inverse transpose keeps the sign of dot(normal, direction), front_face stays valid
+        rec.normal = inv.transform_normal(&rec.normal).unit();
+        if rec.tangent != Vec3::ZERO {
+            rec.tangent = transform.matrix.transform_vector(&rec.tangent).unit();
+        }
+        if let Some(mat) = &self.material {
+            rec.mat = mat.as_ref();
+        }
+        true
+    }
+
+    /// Blended matrices move each point along a line, the boxes at both ends cover the whole motion
+    fn bbox(&self, t0: f32, t1: f32) -> Option<AaBb> {
+        let bbox = self.object.bbox(t0, t1)?;
+        let start = self.transform.transform_bbox(&bbox);
+        match &self.motion {
+            Some(end) => Some(AaBb::surrounding_box(&start, &end.transform_bbox(&bbox))),
+            None => Some(start),
+        }
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        match &self.material {
+            Some(mat) => Some(mat.as_ref()),
+            None => self.object.material(),
+        }
+    }
+
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        self.material = Some(mat);
+    }
+
+    fn name(&self) -> &'static str {
+        "Instance"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        let material = match &self.material {
+            Some(mat) => Some(writer.material(mat.as_ref())?),
+            None => None,
+        };
+        Ok(ObjectDesc::Instance {
+            prototype: writer.prototype(&self.object)?,
+            transform: self.transform.matrix,
+            motion: self.motion.map(|t| t.matrix),
+            material,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::HittableList;
+    use crate::vec::Vec3;
+    use crate::{Lambertian, Sphere};
+
+    #[test]
+    fn test_instance_hit() {
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new((0.0, 0.0, 0.0), 1.0, None));
+        let m = Mat4::translate(&Vec3::new(0.0, 0.0, -5.0)) * Mat4::scale(&Vec3::new(2.0, 1.0, 1.0));
+        let mut inst = Instance::new(Arc::clone(&sphere), Transform::new(m).unwrap());
+        let red: Arc<dyn Material> = Arc::new(Lambertian { color: Vec3::new(1.0, 0.0, 0.0) });
+        inst.set_material(Arc::clone(&red));
+
+        let bbox = inst.bbox(0.0, 1.0).unwrap();
+        assert_eq!(bbox.min, Vec3::new(-2.0, -1.0, -6.0));
+        assert_eq!(bbox.max, Vec3::new(2.0, 1.0, -4.0));
+
+        let default = Lambertian { color: Vec3::ONE };
+        let mut rec = HitRecord::new(&default);
+        let ray = Ray::new(&Vec3::new(-10.0, 0.0, -5.0), &Vec3::new(1.0, 0.0, 0.0));
+        assert!(inst.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.t - 8.0).abs() < 1e-5);
+        assert_eq!(rec.normal, Vec3::new(-1.0, 0.0, 0.0));
+        assert!(rec.front_face);
+        assert_eq!(rec.mat.color(), red.color());
+
+        let group = HittableList::new(vec![Arc::new(inst), sphere]);
+        let mut rec = HitRecord::new(&default);
+        let ray = Ray::new(&Vec3::new(0.0, 0.0, 10.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(group.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.t - 9.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_motion() {
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new((0.0, 0.0, 0.0), 1.0, None));
+        let end = Transform::new(Mat4::translate(&Vec3::new(4.0, 0.0, 0.0))).unwrap();
+        let inst = Instance::new(sphere, Transform::IDENTITY).with_motion(end);
+        let bbox = inst.bbox(0.0, 1.0).unwrap();
+        assert_eq!((bbox.min.x, bbox.max.x), (-1.0, 5.0));
+
+        let default = Lambertian { color: Vec3::ONE };
+        let mut rec = HitRecord::new(&default);
+        let down = |x: f32, time: f32| Ray::new(&Vec3::new(x, 5.0, 0.0), &Vec3::new(0.0, -1.0, 0.0)).with_time(time);
+        assert!(inst.hit(&down(0.0, 0.0), 0.001, f32::INFINITY, &mut rec));
+        assert!(!inst.hit(&down(0.0, 0.75), 0.001, f32::INFINITY, &mut rec));
+        assert!(inst.hit(&down(3.0, 0.75), 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.p.x - 3.0).abs() < 1e-5 && (rec.t - 4.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::errors::Diagnostic;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    Ident(String),
+    Number(f32),
+    /// Only produced by a lexer created `with_integers`
+    Integer(i64),
+    Str(String),
+    Equals,
+    Comma,
+    OpenBracket,
+    CloseBracket,
+    Newline,
+    Eof,
+}
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub pos: Pos,
+}
+
+pub struct Lexer<'src> {
+    file: &'src str,
+    chars: std::iter::Peekable<std::str::Chars<'src>>,
+    line: usize,
+    column: usize,
+    integers: bool,
+}
+
+impl<'src> Lexer<'src> {
+    pub fn new(file: &'src str, source: &'src str) -> Lexer<'src> {
+        Lexer {
+            file,
+            chars: source.chars().peekable(),
+            line: 1,
+            column: 1,
+            integers: false,
+        }
+    }
+
+    /// Lex literals without a fraction or exponent as exact `Integer`s rather than `Number`s
+    pub fn with_integers(mut self) -> Lexer<'src> {
+        self.integers = true;
+        self
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn take_while(&mut self, mut pred: impl FnMut(char) -> bool) -> String {
+        let mut s = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !pred(c) {
+                break;
+            }
+            s.push(c);
+            self.bump();
+        }
+        s
+    }
+
+    fn error(&self, pos: Pos, message: String) -> Diagnostic {
+        Diagnostic::new(self.file, pos, message)
+    }
+
+    /// Tokenize the whole source, lexing errors are collected and the bad input skipped.
+    pub fn tokenize(mut self) -> (Vec<Token>, Vec<Diagnostic>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            let pos = Pos { line: self.line, column: self.column };
+            let c = match self.chars.peek() {
+                Some(&c) => c,
+                None => {
+                    tokens.push(Token { kind: TokenKind::Eof, pos });
+                    break;
+                }
+            };
+            let kind = match c {
+                '\n' => {
+                    self.bump();
+                    TokenKind::Newline
+                }
+                c if c.is_whitespace() => {
+                    self.bump();
+                    continue;
+                }
+                '#' => {
+                    self.take_while(|c| c != '\n');
+                    continue;
+                }
+                '=' => {
+                    self.bump();
+                    TokenKind::Equals
+                }
+                ',' => {
+                    self.bump();
+                    TokenKind::Comma
+                }
+                '[' => {
+                    self.bump();
+                    TokenKind::OpenBracket
+                }
+                ']' => {
+                    self.bump();
+                    TokenKind::CloseBracket
+                }
+                '"' => {
+                    self.bump();
+                    let s = self.take_while(|c| c != '"' && c != '\n');
+                    if self.chars.peek() != Some(&'"') {
+                        errors.push(self.error(pos, "Unterminated string".to_string()));
+                        continue;
+                    }
+                    self.bump();
+                    TokenKind::Str(s)
+                }
+                c if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                    let mut prev = ' ';
+                    let text = self.take_while(|c| {
+                        let ok = c.is_ascii_alphanumeric()
+                            || c == '.'
+                            || ((c == '-' || c == '+') && (prev == ' ' || prev == 'e' || prev == 'E'));
+                        prev = c;
+                        ok
+                    });
+                    let integer = text.trim_start_matches(['-', '+']);
+                    if self.integers && !integer.is_empty() && integer.chars().all(|c| c.is_ascii_digit()) {
+                        match text.parse::<i64>() {
+                            Ok(v) => TokenKind::Integer(v),
+                            Err(_) => {
+                                errors.push(self.error(pos, format!("Invalid integer '{}'", text)));
+                                continue;
+                            }
+                        }
+                    } else {
+                        match text.parse::<f32>() {
+                            Ok(v) => TokenKind::Number(v),
+                            Err(_) => {
+                                errors.push(self.error(pos, format!("Invalid number '{}'", text)));
+                                continue;
+                            }
+                        }
+                    }
+                }
+                c if c.is_alphabetic() || c == '_' => {
+                    TokenKind::Ident(self.take_while(|c| c.is_alphanumeric() || c == '_' || c == '.'))
+                }
+                c => {
+                    self.bump();
+                    errors.push(self.error(pos, format!("Unexpected character '{}'", c)));
+                    continue;
+                }
+            };
+            tokens.push(Token { kind, pos });
+        }
+        (tokens, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<TokenKind> {
+        let (tokens, errors) = Lexer::new("test", src).tokenize();
+        assert!(errors.is_empty(), "{:?}", errors);
+        tokens.into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn test_tokens() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("sphere 0 -1.5 .5 1e-3 # comment\nmetal fuzz=0.1 \"lib.rsm\""),
+            vec![
+                Ident("sphere".into()),
+                Number(0.0),
+                Number(-1.5),
+                Number(0.5),
+                Number(1e-3),
+                Newline,
+                Ident("metal".into()),
+                Ident("fuzz".into()),
+                Equals,
+                Number(0.1),
+                Str("lib.rsm".into()),
+                Eof
+            ]
+        );
+        assert_eq!(kinds("a=1,2,-3"), vec![Ident("a".into()), Equals, Number(1.0), Comma, Number(2.0), Comma, Number(-3.0), Eof]);
+    }
+
+    #[test]
+    fn test_integers() {
+        use TokenKind::*;
+        let (tokens, errors) = Lexer::new("test", "16777217 -3 2.0 1e3 99999999999999999999").with_integers().tokenize();
+        let kinds: Vec<_> = tokens.into_iter().map(|t| t.kind).collect();
+        assert_eq!(kinds, vec![Integer(16_777_217), Integer(-3), Number(2.0), Number(1e3), Eof]);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        let (_, errors) = Lexer::new("test", "sphere 1.2.3 $\n\"open").tokenize();
+        assert_eq!(errors.len(), 3);
+        assert_eq!((errors[0].pos.line, errors[0].pos.column), (1, 8));
+        assert_eq!((errors[1].pos.line, errors[1].pos.column), (1, 14));
+        assert_eq!(errors[2].pos.line, 2);
+    }
+}
@@ -2,31 +2,82 @@ mod bbox;
 mod buckets;
 mod bvh;
 mod camera;
+mod checkpoint;
+mod csg;
+mod curve;
+mod denoise;
+mod description;
+mod gltf_import;
+mod heightfield;
 mod hittable;
 mod imagebuffer;
+mod instance;
+mod lexer;
+mod light;
+mod loader;
 mod material;
+mod medium;
+mod mesh;
+mod parser;
+mod particles;
+mod patch;
+mod pbrt;
+mod plane;
+mod ply;
+mod quad;
+mod quadric;
 mod ray;
 mod sampler;
+mod sdf;
 mod settings;
 mod sphere;
+mod subdiv;
+mod texture;
 mod utils;
 mod vec;
+mod volume;
 mod world;
 mod errors;
 
 use crate::buckets::Bucket;
 use crate::buckets::BucketGrid;
 use crate::utils::Clip;
+pub use bbox::AaBb;
+pub use bvh::{Bvh, BvhTree};
 pub use camera::Camera;
+pub use csg::{Csg, CsgOp};
+pub use curve::{load_curves, read_curves, CurveKind, CurveSegment, Curves};
+pub use checkpoint::{scene_hash, Checkpoint, CheckpointConfig};
+pub use denoise::{denoise, denoise_with, DenoiseParams, FeatureBuffers};
+pub use description::{CameraDesc, MaterialDesc, ObjectDesc, SceneDescription, SceneFormat, SettingsDesc};
+pub use errors::{Diagnostic, SpriosError};
+pub use gltf_import::{import_gltf, GltfScene};
+pub use heightfield::{load_heightfield, Heightfield};
+pub use light::{Light, LightSample};
+pub use loader::load_str;
 pub use material::*;
-pub use ray::Ray;
+pub use medium::{ConstantMedium, Fog, Medium, Phase};
+pub use mesh::TriangleMesh;
+pub use particles::{load_particles, read_particles_binary, read_particles_csv, ParticleCloud};
+pub use patch::{load_bpt, read_bpt, BezierPatches};
+pub use pbrt::{load_pbrt, load_pbrt_str, PbrtScene};
+pub use plane::{Disk, Plane};
+pub use ply::{load_ply, load_ply_cage, read_ply, read_ply_cage};
+pub use quad::{Cuboid, Quad};
+pub use quadric::{axis_transform, Cone, Cylinder, Paraboloid, Placed, Shape, Torus};
+pub use ray::{check_shutter, Ray};
+pub use sdf::{parse_sdf, Sdf, SdfNode};
 pub use sampler::{create_sampler, Distribution, PureRandom};
 pub use settings::{RenderSettings, SettingsBuilder};
-pub use sphere::Sphere;
-pub use vec::{Color, Point3, Vec3};
+pub use sphere::{MovingSphere, Sphere};
+pub use subdiv::PolyMesh;
+pub use texture::Texture;
+pub use vec::{Color, Mat4, Point3, Vec3};
+pub use volume::{load_voxels, Volume, VoxelGrid};
 pub use world::World;
 
-use crate::hittable::HitRecord;
+pub use crate::hittable::{HitRecord, Hittable, HittableList};
+pub use instance::{Instance, Transform};
 use rand::SeedableRng;
 use rand::Rng;
 use std::collections::VecDeque;
@@ -43,6 +94,7 @@ pub struct RenderStats {
     pub fps: f64,
     pub num_ray_shot: u64,
     pub num_ray_hits: u64,
+    pub samples: u32,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -50,11 +102,13 @@ pub struct SampleStat {
     pub sample: u32,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum RenderEvent {
     Completed(RenderStats),
     SampleDone(SampleStat),
     Percent(u8),
+    /// Writing the checkpoint after a pass failed, the render goes on
+    CheckpointFailed(SpriosError),
 }
 
 pub struct RayStat {
@@ -78,15 +132,42 @@ fn ray_color(ray: &Ray, world: &World, depth: u32, rng: &mut rand::rngs::SmallRn
         return Color::ZERO;
     }
     let tmp_mat = Lambertian { color: Color::ZERO };
+    let fog_phase = world.fog.as_ref().map(|fog| fog.medium.phase());
     let mut rec = HitRecord::new(&tmp_mat);
 
-    use crate::hittable::Hittable;
-    if world.hit(ray, 0.001, f32::INFINITY, &mut rec) {
+    let mut hit = world.hit(ray, 0.001, f32::INFINITY, &mut rec);
+    if let (Some(fog), Some(phase)) = (&world.fog, &fog_phase) {
+        let t_max = if hit { rec.t } else { f32::INFINITY };
+        if let Some(t) = fog.sample(ray, 0.001, t_max, rng) {
+            rec = medium::collision(phase, ray, t);
+            hit = true;
+        }
+    }
+    if hit {
         ray_stat.add_hit();
+        // Rays never hit delta lights, so they are sampled at every bounce
+        let mut direct = rec.mat.emitted(&rec);
+        let wo = -ray.direction.unit();
+        for light in &world.lights {
+            let sample = light.sample(&rec.p);
+            let f = rec.mat.eval(&rec, &wo, &sample.wi);
+            if f == Color::ZERO {
+                continue;
+            }
+            let mut shadow = HitRecord::new(&tmp_mat);
+            let shadow_ray = Ray::new(&rec.p, &sample.wi).with_time(ray.time);
+            if !world.hit(&shadow_ray, 0.001, sample.distance * 0.999, &mut shadow) {
+                let fog = world.fog.as_ref().map_or(1.0, |fog| fog.transmittance(&shadow_ray, 0.001, sample.distance));
+                direct += &(f * &sample.radiance * fog);
+            }
+        }
         if let Some(ray) = rec.mat.scatter(ray, &rec, Some(rng)) {
-            return rec.mat.color() * ray_color(&ray, world, depth - 1, rng);
+            return direct + rec.mat.albedo(&rec) * ray_color(&ray, world, depth - 1, rng);
         }
-        return Color::ZERO;
+        return direct;
+    }
+    if let Some(env) = &world.environment {
+        return env.clone();
     }
     let dir = ray.direction.unit();
     let t = 0.5 * (dir.y + 1.0);
@@ -100,21 +181,97 @@ pub fn render<EV>(
     world: Arc<World>,
     event: EV,
 ) -> RenderStats
+where
+    EV: Fn(RenderEvent) + Send + Sync + 'static,
+{
+    render_from(settings, image_ptr, num_threads, world, 0, None, event)
+}
+
+/// Continue a render saved with a checkpoint.
+///
+/// The film from the checkpoint is copied into the image buffer and sampling continues
+/// until `settings.samples` is reached, so a finished render can be refined by asking
+/// for more samples. New checkpoints are written to the same file.
+pub fn resume<EV>(
+    config: CheckpointConfig,
+    settings: RenderSettings,
+    image_ptr: Arc<AtomicPtr<f32>>,
+    num_threads: usize,
+    world: Arc<World>,
+    event: EV,
+) -> Result<RenderStats, SpriosError>
+where
+    EV: Fn(RenderEvent) + Send + Sync + 'static,
+{
+    let ckpt = Checkpoint::load(&config.path)?;
+    if ckpt.settings.width != settings.width || ckpt.settings.height != settings.height {
+        return Err(SpriosError::CheckpointError(format!(
+            "Checkpoint resolution {}x{} does not match {}x{}",
+            ckpt.settings.width, ckpt.settings.height, settings.width, settings.height
+        )));
+    }
+    if ckpt.scene_hash != config.scene_hash {
+        return Err(SpriosError::CheckpointError("Checkpoint was rendered from a different scene".to_string()));
+    }
+    let len = (settings.width * settings.height * 3) as usize;
+    let image = unsafe { std::slice::from_raw_parts_mut(image_ptr.load(Ordering::Relaxed), len) };
+    image.copy_from_slice(&ckpt.film);
+    // Keep the original seed and shutter, the pass index offsets the random streams of new samples
+    let settings = RenderSettings { seed: ckpt.settings.seed, shutter: ckpt.settings.shutter, ..settings };
+    Ok(render_from(settings, image_ptr, num_threads, world, ckpt.samples_done, Some(config), event))
+}
+
+#[inline]
+fn bucket_seed(seed: u64, sample: usize, bucket: usize) -> u64 {
+    // splitmix64 finalizer, decorrelates neighbouring buckets and passes
+    let mut z = seed ^ ((sample as u64) << 32 | bucket as u64);
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Render passes `first_sample + 1..=settings.samples²` on top of what the image buffer holds.
+pub fn render_from<EV>(
+    settings: RenderSettings,
+    image_ptr: Arc<AtomicPtr<f32>>,
+    num_threads: usize,
+    world: Arc<World>,
+    first_sample: u32,
+    checkpoint: Option<CheckpointConfig>,
+    event: EV,
+) -> RenderStats
 where
     EV: Fn(RenderEvent) + Send + Sync + 'static,
 {
     const MAX_DEPTH: u32 = 10;
     let num_samples = settings.samples.pow(2) as usize;
+    let first_sample = first_sample as usize;
     let timer = Instant::now();
+    let mut last_checkpoint = Instant::now();
     let pool = ThreadPool::new(num_threads);
     let event = Arc::new(event);
     let grid = BucketGrid::new(settings.width, settings.height, settings.bucket);
     let broker = Arc::new(Mutex::new(VecDeque::new()));
-    for s in 1..=num_samples {
+    let features = if settings.denoise {
+        Some(FeatureBuffers::render(&settings, &world, num_threads))
+    } else {
+        None
+    };
+    let image_len = (settings.width * settings.height * 3) as usize;
+    let camera = Arc::new(settings.camera(&world.camera));
+    let mut samples_done = first_sample;
+    for s in first_sample + 1..=num_samples {
+        // The limit is checked between passes so the film always holds whole passes
+        if let Some(limit) = settings.time_limit {
+            if s > first_sample + 1 && timer.elapsed() >= limit {
+                break;
+            }
+        }
         let total_buckets = {
             let mut b = broker.lock().unwrap();
             b.clear();
-            b.extend(grid.buckets());
+            b.extend(grid.buckets().enumerate());
             b.len() as u32
         };
         for _ in 0..pool.max_count() {
@@ -122,6 +279,7 @@ where
             let broker = Arc::clone(&broker);
             let image_ptr = Arc::clone(&image_ptr);
             let world = Arc::clone(&world);
+            let camera = Arc::clone(&camera);
             pool.execute(move || loop {
                 let mut broker = broker.lock().unwrap();
                 let bucket = broker.pop_front();
@@ -130,19 +288,19 @@ where
                 if bucket.is_none() {
                     break;
                 }
-                let bucket = bucket.unwrap();
+                let (bucket_idx, bucket) = bucket.unwrap();
                 event(RenderEvent::Percent(
                     ((1.0 - buckets_left as f32 / total_buckets as f32) * 100.0) as u8,
                 ));
                 // let sampler = create_sampler(num_samples, settings.distribution, rng);
-                let mut rng = rand::rngs::SmallRng::from_entropy();
+                let mut rng = rand::rngs::SmallRng::seed_from_u64(bucket_seed(settings.seed, s, bucket_idx));
                 // let mut samples_iter = sampler.samples();
                 for (y, x) in bucket.pixels() {
                     // let s = samples_iter.next().unwrap();
                     let (sx, sy) = rng.gen::<(f32, f32)>();
                     let u = (x as f32 + sx) / (settings.width - 1) as f32;
                     let v = ((settings.height - y) as f32 + sy) / (settings.height - 1) as f32;
-                    let ray = world.camera.get_ray(u, v, &mut rng);
+                    let ray = camera.get_ray(u, v, &mut rng);
                     let clr = ray_color(&ray, &world, MAX_DEPTH, &mut rng);
                     let idx = ((y * settings.width + x) * 3) as usize;
                     let ptr = image_ptr.load(Ordering::Relaxed);
@@ -159,11 +317,34 @@ where
         }
         std::thread::sleep(std::time::Duration::from_millis(50));
         pool.join();
+        samples_done = s;
+        let out_of_time = settings.time_limit.is_some_and(|limit| timer.elapsed() >= limit);
+        if let Some(config) = &checkpoint {
+            if s == num_samples || out_of_time || last_checkpoint.elapsed() >= config.interval {
+                let film = unsafe { std::slice::from_raw_parts(image_ptr.load(Ordering::Relaxed), image_len) };
+                let ckpt = Checkpoint {
+                    settings,
+                    scene_hash: config.scene_hash,
+                    samples_done: s as u32,
+                    film: film.to_vec(),
+                };
+                if let Err(e) = ckpt.save(&config.path) {
+                    event(RenderEvent::CheckpointFailed(e));
+                }
+                last_checkpoint = Instant::now();
+            }
+        }
         event(RenderEvent::SampleDone(SampleStat { sample: s as u32 }));
     }
+    if let Some(features) = features {
+        let image = unsafe { std::slice::from_raw_parts_mut(image_ptr.load(Ordering::Relaxed), image_len) };
+        let denoised = denoise(image, samples_done as u32, &features);
+        image.copy_from_slice(&denoised);
+    }
     let render_time = timer.elapsed().as_secs_f64();
     let fps = 1.0 / render_time;
-    let num_ray_shot = settings.width as u128 * settings.height as u128 * num_samples as u128;
+    let num_passes = samples_done - first_sample;
+    let num_ray_shot = settings.width as u128 * settings.height as u128 * num_passes as u128;
     let mrays = (num_ray_shot as f64 * fps) / 1.0e6;
     RenderStats {
         render_time,
@@ -171,6 +352,7 @@ where
         fps,
         num_ray_shot: num_ray_shot as u64,
         num_ray_hits: ray_stat.num_ray_hits.load(Ordering::Relaxed),
+        samples: samples_done as u32,
     }
 }
 
@@ -190,7 +372,7 @@ mod tests {
         world.add(Arc::new(Sphere::new(
             (0.0, -100.5, -1.0),
             100.0,
-            Some(Box::new(Lambertian {
+            Some(Arc::new(Lambertian {
                 color: (0.5, 0.5, 0.5).into(),
             }),
         ))));
@@ -198,7 +380,7 @@ mod tests {
             Point3::new(0.0, 0.0, 2.0),
             Point3::new(0.0, 0.0, -1.0),
             Vec3::new(0.0, 1.0, 0.0),
-            40,
+            40.0,
             300 as f32 / 200 as f32,
             0.0,
             f32::INFINITY,
@@ -208,4 +390,36 @@ mod tests {
         render(set, img_ptr, 2, world, |_| {});
         assert_eq!(buf.len(), 300 * 200 * 3);
     }
+
+    #[test]
+    fn test_resume() {
+        let mut buf = vec![0.0f32; 30 * 20 * 3];
+        let img_ptr = Arc::new(AtomicPtr::new(buf.as_mut_ptr()));
+        let mut world = World::new();
+        world.add(Arc::new(Sphere::new((0.0, 0.0, -1.0), 0.5, None)));
+        world.camera = Camera::new(
+            Point3::new(0.0, 0.0, 2.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.5,
+            0.0,
+            3.0,
+        );
+        let world = Arc::new(world);
+        let path = std::env::temp_dir().join("sprios_test_resume.ckpt");
+        let config = CheckpointConfig::new(&path, std::time::Duration::from_secs(0), &world);
+        let set = SettingsBuilder::new().samples(1).size(30, Some(20)).build();
+        render_from(set, Arc::clone(&img_ptr), 2, Arc::clone(&world), 0, Some(config.clone()), |_| {});
+        assert_eq!(Checkpoint::load(&path).unwrap().samples_done, 1);
+
+        let mut resumed = vec![0.0f32; 30 * 20 * 3];
+        let img_ptr = Arc::new(AtomicPtr::new(resumed.as_mut_ptr()));
+        let set = SettingsBuilder::new().samples(2).size(30, Some(20)).build();
+        resume(config, set, img_ptr, 2, world, |_| {}).unwrap();
+        let ckpt = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(ckpt.samples_done, 4);
+        assert!(resumed.iter().sum::<f32>() > buf.iter().sum::<f32>());
+    }
 }
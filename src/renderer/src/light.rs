@@ -0,0 +1,70 @@
+use crate::vec::{Color, Mat4, Point3, Vec3};
+use serde::{Deserialize, Serialize};
+
+/// Delta lights, sampled explicitly at every diffuse hit since rays can never hit them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Light {
+    /// Radiant intensity falls off with the squared distance
+    Point { position: Point3, intensity: Color },
+    /// Light arriving along `direction` from infinitely far away
+    Directional { direction: Vec3, intensity: Color },
+}
+
+/// Incoming light at a point
+pub struct LightSample {
+    /// Unit vector towards the light
+    pub wi: Vec3,
+    pub distance: f32,
+    pub radiance: Color,
+}
+
+impl Light {
+    /// The same light placed by `m`
+    pub fn transform(&self, m: &Mat4) -> Light {
+        match self {
+            Light::Point { position, intensity } => {
+                Light::Point { position: m.transform_point(position), intensity: intensity.clone() }
+            }
+            Light::Directional { direction, intensity } => {
+                Light::Directional { direction: m.transform_vector(direction), intensity: intensity.clone() }
+            }
+        }
+    }
+
+    pub fn sample(&self, p: &Point3) -> LightSample {
+        match self {
+            Light::Point { position, intensity } => {
+                let to_light = position - p;
+                let distance = to_light.length();
+                LightSample {
+                    wi: &to_light / distance,
+                    distance,
+                    radiance: intensity / (distance * distance),
+                }
+            }
+            Light::Directional { direction, intensity } => LightSample {
+                wi: -direction.unit(),
+                distance: f32::INFINITY,
+                radiance: intensity.clone(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample() {
+        let light = Light::Point { position: Point3::new(0.0, 2.0, 0.0), intensity: Color::new(4.0, 4.0, 4.0) };
+        let s = light.sample(&Point3::ZERO);
+        assert_eq!(s.wi, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(s.distance, 2.0);
+        assert_eq!(s.radiance, Color::ONE);
+
+        let sun = Light::Directional { direction: Vec3::new(0.0, -2.0, 0.0), intensity: Color::ONE };
+        assert_eq!(sun.sample(&Point3::ZERO).wi, Vec3::new(0.0, 1.0, 0.0));
+    }
+}
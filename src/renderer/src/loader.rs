@@ -0,0 +1,938 @@
+use crate::bbox::AaBb;
+use crate::errors::{Diagnostic, SpriosError};
+use crate::bvh::Bvh;
+use crate::hittable::Hittable;
+use crate::instance::{Instance, Transform};
+use crate::lexer::Pos;
+use crate::light::Light;
+use crate::parser::{parse, Args, Statement};
+use crate::vec::{Mat4, Point3, Vec3};
+use crate::{axis_transform, check_shutter, load_bpt, load_curves, load_heightfield, load_particles, load_voxels, Camera, Cone, ConstantMedium, Csg, CsgOp, Cuboid, CurveKind, Curves, CurveSegment, Cylinder, Dielectric, Disk, Emissive, Fog, Hair, Lambertian, Material, Medium, Metal, MovingSphere, Paraboloid, parse_sdf, Placed, Plane, Quad, Sdf, Sphere, Torus, Volume, World};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Open `group` or `csg` block, the root of the scene is the first one
+struct Frame {
+    name: Option<String>,
+    hidden: bool,
+    file: String,
+    pos: Pos,
+    /// Applied to the objects that follow, relative to the parent
+    transform: Transform,
+    /// Placement at shutter close set by `motion`, None when the objects don't move
+    motion: Option<Transform>,
+    objects: Vec<Arc<dyn Hittable>>,
+    /// Lights of the block, placed like its objects
+    lights: Vec<Light>,
+    /// Set for `csg` blocks, which combine their objects instead of grouping them
+    csg: Option<(CsgOp, Option<Arc<dyn Material>>)>,
+    /// Set for `medium` blocks, whose objects bound the medium
+    medium: Option<Medium>,
+}
+
+impl Frame {
+    fn new(name: Option<String>, hidden: bool, file: &str, pos: Pos) -> Frame {
+        Frame { name, hidden, file: file.to_string(), pos, transform: Transform::IDENTITY, motion: None, objects: vec![], lights: vec![], csg: None, medium: None }
+    }
+
+    fn keyword(&self) -> &'static str {
+        if self.csg.is_some() {
+            "csg"
+        } else if self.medium.is_some() {
+            "medium"
+        } else {
+            "group"
+        }
+    }
+}
+
+/// Builds a World from parsed statements.
+///
+/// `material name type ...` adds a named entry to the material table that objects reference
+/// by name. A bare `diffuse`/`metal`/`glass`/`emissive` line still applies to the next object only.
+/// `group [name] [hidden]` ... `end` collects objects, `transform` changes the placement of the
+/// following objects of the block and `instance name` places another copy of a named group.
+/// `motion translate|scale` moves the following objects there by time 1, `motion identity` stops
+/// them. Motion can't rotate, see `Transform::lerp`. `moving_sphere c0 c1 radius [material]` moves
+/// between two centres and `camera ... open=0 close=1` sets the shutter times within 0..1.
+/// `csg union|intersection|difference [material]` ... `end` folds its objects left to right
+/// into one solid, e.g. the first object minus all the others.
+/// `medium absorption scattering color [g]` ... `end` fills its closed objects with smoke and
+/// `fog absorption scattering color [g] [height=y]` fills the world, or the part below `height`.
+/// `heightfield file sx sy sz [material]` spans `0..sx` and `0..sz` with white at height `sy`.
+/// `volume file min max density [color] [g] [temperature=file emission=color]` renders smoke
+/// from a `.nrrd` grid, or a `.raw` one with `dims=x,y,z`.
+/// `curve p0 p1 p2 p3 width [width1] [kind=flat|cylinder] [material]` adds a Bézier strand and
+/// `curves file [kind=...] [material]` loads many, see `read_curves` for the format.
+/// `ply file [material] [subdiv levels] [crease=degrees]` smooths the mesh as a subdivision
+/// surface, with edges turning more than `crease` kept sharp.
+/// `gltf file [camera] [subdiv levels]` adds the meshes, materials and lights of a glTF asset,
+/// with `subdiv` its vertices are welded by position and the meshes smoothed like `ply`.
+/// `particles file [radius=r] [layout=prcm] [materials="a b"] [material]` adds many spheres
+/// from CSV or binary records, material ids in the file pick from `materials`.
+/// `patches file [material]` loads bicubic Bézier patches from a `.bpt` file like the teapot.
+/// `light point position intensity` and `light directional direction intensity` are placed like
+/// objects, so instances of a group repeat its lights and hidden groups only light their instances.
+/// `sdf "expression" min max [material]` ray marches a distance function inside the given bounds.
+struct SceneLoader {
+    world: World,
+    material: Option<Arc<dyn Material>>,
+    errors: Vec<Diagnostic>,
+    /// Files being loaded, to catch import cycles
+    stack: Vec<PathBuf>,
+    frames: Vec<Frame>,
+    /// Named groups with their lights, which every instance places again
+    groups: HashMap<String, (Arc<dyn Hittable>, Vec<Light>)>,
+}
+
+impl SceneLoader {
+    fn new(file: &str) -> SceneLoader {
+        SceneLoader {
+            world: World::new(),
+            material: None,
+            errors: vec![],
+            stack: vec![],
+            frames: vec![Frame::new(None, false, file, Pos { line: 1, column: 1 })],
+            groups: HashMap::new(),
+        }
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// Add to the open block, placed by its current transform
+    fn add_object(&mut self, object: Arc<dyn Hittable>) {
+        let frame = self.frame();
+        match frame.motion {
+            Some(end) => frame.objects.push(Arc::new(Instance::new(object, frame.transform).with_motion(end))),
+            None if frame.transform.is_identity() => frame.objects.push(object),
+            None => frame.objects.push(Arc::new(Instance::new(object, frame.transform))),
+        }
+    }
+
+    /// Add to the open block, placed by its current transform. Lights don't follow `motion`.
+    fn add_light(&mut self, light: Light) {
+        let frame = self.frame();
+        let light = light.transform(&frame.transform.matrix);
+        frame.lights.push(light);
+    }
+
+    fn end_group(&mut self, file: &str, pos: Pos) -> Result<(), Diagnostic> {
+        if self.frames.len() == 1 {
+            return Err(Diagnostic::new(file, pos, "'end' without 'group'".to_string()));
+        }
+        let frame = self.frames.pop().unwrap();
+        if !frame.hidden {
+            for light in &frame.lights {
+                self.add_light(light.clone());
+            }
+        }
+        if let Some(medium) = frame.medium {
+            let mut objects = frame.objects;
+            let boundary: Arc<dyn Hittable> = match objects.len() {
+                0 => return Err(Diagnostic::new(file, frame.pos, "medium: needs a boundary object".to_string())),
+                1 => objects.pop().unwrap(),
+                _ => Arc::new(Bvh::new(objects)),
+            };
+            self.add_object(Arc::new(ConstantMedium::new(boundary, medium)));
+            return Ok(());
+        }
+        if let Some((op, material)) = frame.csg {
+            let mut objects = frame.objects.into_iter();
+            let (first, second) = match (objects.next(), objects.next()) {
+                (Some(first), Some(second)) => (first, second),
+                _ => return Err(Diagnostic::new(file, frame.pos, "csg: needs at least 2 objects".to_string())),
+            };
+            let mut csg = Csg::new(op, first, second);
+            for object in objects {
+                csg = Csg::new(op, Arc::new(csg), object);
+            }
+            csg.material = material;
+            self.add_object(Arc::new(csg));
+            return Ok(());
+        }
+        // Each group gets its own bottom level BVH, shared by all its instances
+        let empty = frame.objects.is_empty();
+        let group: Arc<dyn Hittable> = Arc::new(Bvh::new(frame.objects));
+        if !frame.hidden && !empty {
+            self.add_object(Arc::clone(&group));
+        }
+        if let Some(name) = frame.name {
+            self.groups.insert(name, (group, frame.lights));
+        }
+        Ok(())
+    }
+
+    fn medium(args: &mut Args, pos: Pos) -> Result<Medium, Diagnostic> {
+        let absorption = args.float("absorption")?;
+        let scattering = args.float("scattering")?;
+        let color = args.vec3("color")?;
+        let g = args.float_or("g", 0.0)?;
+        if absorption < 0.0 || scattering < 0.0 || g <= -1.0 || g >= 1.0 {
+            return Err(args.error(pos, "medium: coefficients can't be negative and g is in -1..1".to_string()));
+        }
+        Ok(Medium { absorption, scattering, color, g })
+    }
+
+    /// None for `identity`, which resets the transform of the block
+    fn transform(args: &mut Args, pos: Pos) -> Result<Option<Transform>, Diagnostic> {
+        let (kind, kind_pos) = args.string("type")?;
+        let matrix = match kind.as_str() {
+            "identity" => return Ok(None),
+            "translate" => Mat4::translate(&args.vec3("offset")?),
+            "rotate" => {
+                let degrees = args.float("angle")?;
+                let axis = args.vec3("axis")?;
+                if axis.length_squared() == 0.0 {
+                    return Err(args.error(pos, "transform: rotation axis can't be zero".to_string()));
+                }
+                Mat4::rotate(degrees, &axis)
+            }
+            "scale" => match args.list("factor")?.as_slice() {
+                [s] => Mat4::scale(&Vec3::new(*s, *s, *s)),
+                [x, y, z] => Mat4::scale(&Vec3::new(*x, *y, *z)),
+                v => return Err(args.error(pos, format!("transform: scale needs 1 or 3 values, got {}", v.len()))),
+            },
+            "matrix" => {
+                let v = args.floats("values", 16)?;
+                let mut m = Mat4::IDENTITY;
+                for (i, row) in m.m.iter_mut().enumerate() {
+                    row.copy_from_slice(&v[i * 4..i * 4 + 4]);
+                }
+                m
+            }
+            k => return Err(args.error(kind_pos, format!("Unknown transform '{}'", k))),
+        };
+        let t = Transform::new(matrix).ok_or_else(|| args.error(pos, "transform: matrix can't be inverted".to_string()))?;
+        Ok(Some(t))
+    }
+
+    fn load(&mut self, file: &str, source: &str, library: bool) {
+        let (statements, errors) = parse(file, source);
+        self.errors.extend(errors);
+        for stmt in &statements {
+            if let Err(e) = self.statement(file, stmt, library) {
+                self.errors.push(e);
+            }
+        }
+    }
+
+    fn import(&mut self, file: &str, args: &mut Args) -> Result<(), Diagnostic> {
+        let (name, pos) = args.string("file")?;
+        let path = Self::external_path(file, &name);
+        let source = std::fs::read_to_string(&path)
+            .map_err(|e| args.error(pos, format!("import: {}: {}", path.display(), e)))?;
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if self.stack.contains(&canonical) {
+            return Err(args.error(pos, format!("import: {} imports itself", path.display())));
+        }
+        self.stack.push(canonical);
+        self.load(&path.display().to_string(), &source, true);
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// Files referenced by a scene are relative to it
+    fn external_path(file: &str, name: &str) -> PathBuf {
+        Path::new(file).parent().unwrap_or_else(|| Path::new("")).join(name)
+    }
+
+    /// `subdiv levels` or `subdiv=levels` of a mesh statement, before or after its other arguments
+    fn subdiv_levels(keyword: &str, args: &mut Args, pos: Pos) -> Result<Option<u32>, Diagnostic> {
+        match args.keyed_float_or("subdiv")? {
+            Some(levels) if !(0.0..=8.0).contains(&levels) || levels.fract() != 0.0 => {
+                Err(args.error(pos, format!("{}: subdiv needs a whole number of levels up to 8", keyword)))
+            }
+            levels => Ok(levels.map(|l| l as u32)),
+        }
+    }
+
+    fn ply(&mut self, file: &str, args: &mut Args) -> Result<(), Diagnostic> {
+        let (name, pos) = args.string("file")?;
+        let mut levels = Self::subdiv_levels("ply", args, pos)?;
+        let mat = self.object_material(args)?;
+        if levels.is_none() {
+            levels = Self::subdiv_levels("ply", args, pos)?;
+        }
+        let mut cage = crate::ply::load_ply_cage(Self::external_path(file, &name)).map_err(|e| args.error(pos, e.to_string()))?;
+        if args.has("crease") {
+            cage = cage.with_crease_angle(args.float("crease")?);
+        }
+        let mesh = match levels {
+            Some(levels) => cage.subdivide(levels, mat),
+            None => cage.triangulate(mat),
+        };
+        self.add_object(Arc::new(mesh));
+        Ok(())
+    }
+
+    /// Objects and lights of a glTF asset join the current group, `camera` also takes its camera
+    fn gltf(&mut self, file: &str, args: &mut Args) -> Result<(), Diagnostic> {
+        let (name, pos) = args.string("file")?;
+        let mut levels = Self::subdiv_levels("gltf", args, pos)?;
+        let use_camera = match args.string_or("flag")? {
+            Some((flag, _)) if flag == "camera" => true,
+            Some((flag, pos)) => return Err(args.error(pos, format!("gltf: unknown flag '{}'", flag))),
+            None => false,
+        };
+        if levels.is_none() {
+            levels = Self::subdiv_levels("gltf", args, pos)?;
+        }
+        let path = Self::external_path(file, &name);
+        let scene = match levels {
+            Some(levels) => crate::gltf_import::import_gltf_subdivided(&path, levels),
+            None => crate::gltf_import::import_gltf(&path),
+        };
+        let scene = scene.map_err(|e| args.error(pos, e.to_string()))?;
+        for mat in scene.materials {
+            self.world.add_material(None, mat);
+        }
+        for object in scene.objects {
+            self.add_object(object);
+        }
+        for light in scene.lights {
+            self.add_light(light);
+        }
+        if use_camera {
+            match scene.camera {
+                Some(camera) => self.world.camera = camera,
+                None => return Err(args.error(pos, format!("gltf: {} has no camera", path.display()))),
+            }
+        }
+        Ok(())
+    }
+
+    fn material(kind: &str, args: &mut Args) -> Result<Option<Arc<dyn Material>>, Diagnostic> {
+        let mat: Arc<dyn Material> = match kind {
+            "diffuse" => Arc::new(Lambertian { color: args.vec3("color")? }),
+            "metal" => {
+                let color = args.vec3("color")?;
+                let fuzz = args.float_or("fuzz", 0.0)?;
+                Arc::new(Metal { color, fuzz })
+            }
+            "glass" => Arc::new(Dielectric { ior: args.float_or("ior", 1.5)? }),
+            "hair" => {
+                let color = args.vec3("color")?;
+                let specular = args.float_or("specular", 0.3)?;
+                let shininess = args.float_or("shininess", 40.0)?;
+                Arc::new(Hair { color, specular, shininess })
+            }
+            "emissive" => {
+                let color = args.vec3("color")?;
+                let two_sided = match args.string_or("flag")? {
+                    Some((flag, _)) if flag == "two_sided" => true,
+                    Some((flag, pos)) => return Err(args.error(pos, format!("emissive: unknown flag '{}'", flag))),
+                    None => false,
+                };
+                Arc::new(Emissive { color, two_sided, reverse: false })
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(mat))
+    }
+
+    /// The material named on an object, or the pending anonymous one
+    fn object_material(&mut self, args: &mut Args) -> Result<Option<Arc<dyn Material>>, Diagnostic> {
+        match args.string_or("material")? {
+            Some((name, pos)) => {
+                let mat = self.world.find_material(&name);
+                mat.map(Some).ok_or_else(|| args.error(pos, format!("Unknown material '{}'", name)))
+            }
+            None => Ok(self.material.take()),
+        }
+    }
+
+    fn statement(&mut self, file: &str, stmt: &Statement, library: bool) -> Result<(), Diagnostic> {
+        let mut args = Args::new(file, stmt);
+        match stmt.keyword.as_str() {
+            "import" => self.import(file, &mut args)?,
+            "material" => {
+                let (name, _) = args.string("name")?;
+                let (kind, pos) = args.string("type")?;
+                let mat = Self::material(&kind, &mut args)?
+                    .ok_or_else(|| args.error(pos, format!("Unknown material type '{}'", kind)))?;
+                self.world.add_material(Some(&name), mat);
+            }
+            k if library => {
+                return Err(args.error(stmt.pos, format!("'{}' is not allowed in a material library", k)))
+            }
+            "camera" => {
+                let from = args.vec3("from")?;
+                let at = args.vec3("at")?;
+                let fov = args.float("fov")?;
+                let aperture = args.float_or("aperture", 0.0)?;
+                let up = args.vec3_or("up", Vec3::new(0.0, 1.0, 0.0))?;
+                let focus = args.float_or("focus", (&from - &at).length())?;
+                let aspect = args.float_or("aspect", 16.0 / 9.0)?;
+                let open = args.float_or("open", 0.0)?;
+                let close = args.float_or("close", open)?;
+                check_shutter(open, close).map_err(|e| args.error(stmt.pos, format!("camera: {}", e)))?;
+                self.world.camera = Camera::new(from, at, up, fov, aspect, aperture, focus).with_shutter(open, close);
+            }
+            "background" => self.world.background = args.vec3("color")?,
+            "environment" => self.world.environment = Some(args.vec3("color")?),
+            "light" => {
+                let (kind, pos) = args.string("type")?;
+                let light = match kind.as_str() {
+                    "point" => Light::Point { position: args.vec3("position")?, intensity: args.vec3("intensity")? },
+                    "directional" => Light::Directional { direction: args.vec3("direction")?, intensity: args.vec3("intensity")? },
+                    _ => return Err(args.error(pos, format!("Unknown light type '{}'", kind))),
+                };
+                self.add_light(light);
+            }
+            "group" => {
+                let name = args.string_or("name")?.map(|(name, _)| name);
+                let hidden = match args.string_or("flag")? {
+                    Some((flag, _)) if flag == "hidden" => true,
+                    Some((flag, pos)) => return Err(args.error(pos, format!("group: unknown flag '{}'", flag))),
+                    None => false,
+                };
+                args.finish()?;
+                self.frames.push(Frame::new(name, hidden, file, stmt.pos));
+                return Ok(());
+            }
+            "csg" => {
+                let (op, pos) = args.string("operation")?;
+                let op: CsgOp = op.parse().map_err(|e| args.error(pos, format!("csg: {}", e)))?;
+                let mut frame = Frame::new(None, false, file, stmt.pos);
+                frame.csg = Some((op, self.object_material(&mut args)?));
+                args.finish()?;
+                self.frames.push(frame);
+                return Ok(());
+            }
+            "medium" => {
+                let mut frame = Frame::new(None, false, file, stmt.pos);
+                frame.medium = Some(Self::medium(&mut args, stmt.pos)?);
+                args.finish()?;
+                self.frames.push(frame);
+                return Ok(());
+            }
+            "fog" => {
+                let height = if args.has("height") { Some(args.float("height")?) } else { None };
+                self.world.fog = Some(Fog { height, medium: Self::medium(&mut args, stmt.pos)? });
+            }
+            "end" => {
+                args.finish()?;
+                return self.end_group(file, stmt.pos);
+            }
+            "transform" => {
+                let frame = self.frame();
+                let (start, end) = (frame.transform, frame.motion);
+                match Self::transform(&mut args, stmt.pos)? {
+                    Some(t) => {
+                        frame.transform = start.compose(&t);
+                        frame.motion = end.map(|end| end.compose(&t));
+                    }
+                    None => {
+                        frame.transform = Transform::IDENTITY;
+                        frame.motion = None;
+                    }
+                }
+            }
+            "motion" => {
+                let frame = self.frame();
+                let end = frame.motion.unwrap_or(frame.transform);
+                let motion = Self::transform(&mut args, stmt.pos)?;
+                // Blending matrices only interpolates translation and scale
+                if motion.is_some_and(|t| (0..3).any(|i| (0..3).any(|j| i != j && t.matrix.m[i][j] != 0.0))) {
+                    return Err(args.error(stmt.pos, "motion: only translate and scale can move".to_string()));
+                }
+                frame.motion = motion.map(|t| end.compose(&t));
+            }
+            "instance" => {
+                let (name, pos) = args.string("group")?;
+                let group = self.groups.get(&name).cloned();
+                let (group, lights) = group.ok_or_else(|| args.error(pos, format!("Unknown group '{}'", name)))?;
+                for light in lights {
+                    self.add_light(light);
+                }
+                let mut instance = Instance::new(group, self.frame().transform);
+                instance.motion = self.frame().motion;
+                instance.material = self.object_material(&mut args)?;
+                self.frame().objects.push(Arc::new(instance));
+            }
+            "gltf" => self.gltf(file, &mut args)?,
+            "ply" => self.ply(file, &mut args)?,
+            "sphere" => {
+                let center = args.vec3("center")?;
+                let radius = args.float("radius")?;
+                let mat = self.object_material(&mut args)?;
+                self.add_object(Arc::new(Sphere::new(center, radius, mat)));
+            }
+            "moving_sphere" => {
+                let center0 = args.vec3("center0")?;
+                let center1 = args.vec3("center1")?;
+                let radius = args.float("radius")?;
+                let mat = self.object_material(&mut args)?;
+                self.add_object(Arc::new(MovingSphere::new(center0, center1, radius, mat)));
+            }
+            "quad" => {
+                let origin = args.vec3("origin")?;
+                let u = args.vec3("u")?;
+                let v = args.vec3("v")?;
+                if Vec3::cross(&u, &v).length_squared() == 0.0 {
+                    return Err(args.error(stmt.pos, "quad: edges are parallel".to_string()));
+                }
+                let mat = self.object_material(&mut args)?;
+                self.add_object(Arc::new(Quad::new(origin, u, v, mat)));
+            }
+            "plane" => {
+                let point = args.vec3("point")?;
+                let normal = args.vec3("normal")?;
+                if normal.length_squared() == 0.0 {
+                    return Err(args.error(stmt.pos, "plane: normal is zero".to_string()));
+                }
+                let mat = self.object_material(&mut args)?;
+                self.add_object(Arc::new(Plane::new(point, normal, mat)));
+            }
+            "disk" => {
+                let center = args.vec3("center")?;
+                let normal = args.vec3("normal")?;
+                let radius = args.float("radius")?;
+                let inner = args.float_or("inner", 0.0)?;
+                if normal.length_squared() == 0.0 || inner < 0.0 || inner >= radius {
+                    return Err(args.error(stmt.pos, "disk: needs a normal and 0 <= inner < radius".to_string()));
+                }
+                let mat = self.object_material(&mut args)?;
+                self.add_object(Arc::new(Disk::new(center, normal, radius, inner, mat)));
+            }
+            "cylinder" | "cone" | "paraboloid" => {
+                // From the base to the top, the apex of a cone or the rim of a paraboloid
+                let base = args.vec3("base")?;
+                let top = args.vec3("top")?;
+                let radius = args.float("radius")?;
+                let sweep = args.float_or("sweep", 360.0)?;
+                let caps = args.bool_or("caps", true)?;
+                let axis = &top - &base;
+                if axis.length_squared() == 0.0 || radius <= 0.0 || sweep <= 0.0 {
+                    return Err(args.error(stmt.pos, format!("{}: needs distinct ends, a radius and a sweep", stmt.keyword)));
+                }
+                let (height, transform) = (axis.length(), axis_transform(&base, &axis));
+                let mat = self.object_material(&mut args)?;
+                let shape: Arc<dyn Hittable> = match stmt.keyword.as_str() {
+                    "cylinder" => Arc::new(Placed::new(Cylinder { radius, height }, mat).with_sweep(sweep).with_caps(caps).with_transform(transform)),
+                    "cone" => Arc::new(Placed::new(Cone { radius, height }, mat).with_sweep(sweep).with_caps(caps).with_transform(transform)),
+                    _ => Arc::new(Placed::new(Paraboloid { radius, height }, mat).with_sweep(sweep).with_caps(caps).with_transform(transform)),
+                };
+                self.add_object(shape);
+            }
+            "torus" => {
+                let center = args.vec3("center")?;
+                let axis = args.vec3("axis")?;
+                let major = args.float("major")?;
+                let minor = args.float("minor")?;
+                let sweep = args.float_or("sweep", 360.0)?;
+                if axis.length_squared() == 0.0 || minor <= 0.0 || major <= 0.0 || sweep <= 0.0 {
+                    return Err(args.error(stmt.pos, "torus: needs an axis, both radii and a sweep".to_string()));
+                }
+                let mat = self.object_material(&mut args)?;
+                let torus = Placed::new(Torus { major, minor }, mat).with_sweep(sweep).with_transform(axis_transform(&center, &axis));
+                self.add_object(Arc::new(torus));
+            }
+            "heightfield" => {
+                let (name, pos) = args.string("file")?;
+                let size = args.vec3("size")?;
+                if size.x <= 0.0 || size.z <= 0.0 {
+                    return Err(args.error(stmt.pos, "heightfield: needs a positive width and depth".to_string()));
+                }
+                let mat = self.object_material(&mut args)?;
+                let path = Self::external_path(file, &name);
+                let field = load_heightfield(path, size, mat).map_err(|e| args.error(pos, e.to_string()))?;
+                self.add_object(Arc::new(field));
+            }
+            "volume" => {
+                let (name, pos) = args.string("file")?;
+                let min = args.vec3("min")?;
+                let max = args.vec3("max")?;
+                let scale = args.float("density")?;
+                let color = args.vec3_or("color", Vec3::new(1.0, 1.0, 1.0))?;
+                let g = args.float_or("g", 0.0)?;
+                if !(min.x < max.x && min.y < max.y && min.z < max.z) || scale < 0.0 || g <= -1.0 || g >= 1.0 {
+                    return Err(args.error(stmt.pos, "volume: needs min < max, a density >= 0 and g in -1..1".to_string()));
+                }
+                // Raw files have no header, the grid size comes from the scene
+                let dims = if args.has("dims") {
+                    let d = args.floats("dims", 3)?;
+                    if d.iter().any(|&n| n < 1.0 || n.fract() != 0.0) {
+                        return Err(args.error(stmt.pos, "volume: dims must be whole numbers of at least 1".to_string()));
+                    }
+                    Some([d[0] as usize, d[1] as usize, d[2] as usize])
+                } else {
+                    None
+                };
+                let load = |args: &Args, name: &str, pos: Pos| {
+                    let path = Self::external_path(file, name);
+                    let raw = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("raw"));
+                    if raw && dims.is_none() {
+                        return Err(args.error(pos, "volume: raw files need dims=x,y,z".to_string()));
+                    }
+                    load_voxels(&path, if raw { dims } else { None }).map(Arc::new).map_err(|e| args.error(pos, e.to_string()))
+                };
+                let mut volume = Volume::new(load(&args, &name, pos)?, AaBb::new(min, max), scale, color, g);
+                if let Some((name, pos)) = args.string_or("temperature")? {
+                    let emission = args.vec3_or("emission", Vec3::new(1.0, 1.0, 1.0))?;
+                    volume = volume.with_temperature(load(&args, &name, pos)?, emission);
+                }
+                self.add_object(Arc::new(volume));
+            }
+            "curve" | "curves" => {
+                let (source, segment) = if stmt.keyword == "curves" {
+                    (Some(args.string("file")?), None)
+                } else {
+                    let p: Vec<Point3> = (0..4).map(|i| args.vec3(&format!("p{}", i))).collect::<Result<_, _>>()?;
+                    let width = args.float("width")?;
+                    let end = args.float_or("width1", width)?;
+                    if width < 0.0 || end < 0.0 {
+                        return Err(args.error(stmt.pos, "curve: widths can't be negative".to_string()));
+                    }
+                    (None, Some(CurveSegment::new([p[0].clone(), p[1].clone(), p[2].clone(), p[3].clone()], [width, end])))
+                };
+                let kind = match args.has("kind") {
+                    true => {
+                        let (kind, pos) = args.string("kind")?;
+                        kind.parse().map_err(|e| args.error(pos, format!("{}: {}", stmt.keyword, e)))?
+                    }
+                    false => CurveKind::Flat,
+                };
+                let mat = self.object_material(&mut args)?;
+                let curves = match source {
+                    Some((name, pos)) => load_curves(Self::external_path(file, &name), kind, mat).map_err(|e| args.error(pos, e.to_string()))?,
+                    None => Curves::new(segment.into_iter().collect(), kind, mat),
+                };
+                self.add_object(Arc::new(curves));
+            }
+            "particles" => {
+                let (name, pos) = args.string("file")?;
+                let radius = if args.has("radius") { Some(args.float("radius")?) } else { None };
+                let layout = if args.has("layout") { Some(args.string("layout")?.0) } else { None };
+                let mut materials = vec![];
+                if args.has("materials") {
+                    let (names, pos) = args.string("materials")?;
+                    for name in names.split_whitespace() {
+                        let mat = self.world.find_material(name);
+                        materials.push(mat.ok_or_else(|| args.error(pos, format!("Unknown material '{}'", name)))?);
+                    }
+                }
+                if materials.is_empty() {
+                    materials.extend(self.object_material(&mut args)?);
+                }
+                let path = Self::external_path(file, &name);
+                let cloud = load_particles(path, layout.as_deref(), radius).map_err(|e| args.error(pos, e.to_string()))?;
+                if let Some(id) = cloud.material_ids.iter().find(|id| **id as usize >= materials.len().max(1)) {
+                    return Err(args.error(pos, format!("particles: material id {} but {} materials", id, materials.len().max(1))));
+                }
+                self.add_object(Arc::new(cloud.with_materials(materials)));
+            }
+            "patches" => {
+                let (name, pos) = args.string("file")?;
+                let mat = self.object_material(&mut args)?;
+                let patches = load_bpt(Self::external_path(file, &name), mat).map_err(|e| args.error(pos, e.to_string()))?;
+                self.add_object(Arc::new(patches));
+            }
+            "sdf" => {
+                let (expr, pos) = args.string("expression")?;
+                let shape = parse_sdf(&expr).map_err(|e| args.error(pos, format!("sdf: {}", e)))?;
+                let min = args.vec3("min")?;
+                let max = args.vec3("max")?;
+                if min.x > max.x || min.y > max.y || min.z > max.z {
+                    return Err(args.error(stmt.pos, "sdf: bounds min is above max".to_string()));
+                }
+                let mat = self.object_material(&mut args)?;
+                self.add_object(Arc::new(Sdf::new(shape, AaBb::new(min, max), mat)));
+            }
+            "box" => {
+                let min = args.vec3("min")?;
+                let max = args.vec3("max")?;
+                if (0..3).any(|a| min[a] == max[a]) {
+                    return Err(args.error(stmt.pos, "box: zero extent".to_string()));
+                }
+                let mat = self.object_material(&mut args)?;
+                self.add_object(Arc::new(Cuboid::new(min, max, mat)));
+            }
+            "cube" => {
+                let center = args.vec3("center")?;
+                let size = args.float("size")?;
+                if size <= 0.0 {
+                    return Err(args.error(stmt.pos, "cube: size must be positive".to_string()));
+                }
+                let half = Vec3::new(size / 2.0, size / 2.0, size / 2.0);
+                let mat = self.object_material(&mut args)?;
+                self.add_object(Arc::new(Cuboid::new(&center - &half, &center + &half, mat)));
+            }
+            k => match Self::material(k, &mut args)? {
+                Some(mat) => self.material = Some(self.world.add_material(None, mat)),
+                None => return Err(args.error(stmt.pos, format!("Unknown statement '{}'", k))),
+            },
+        }
+        args.finish()
+    }
+}
+
+/// Parse scene source, all errors found are reported together.
+/// Imports are resolved relative to `file`.
+pub fn load_str(file: &str, source: &str) -> Result<World, SpriosError> {
+    let mut loader = SceneLoader::new(file);
+    loader.stack.push(Path::new(file).canonicalize().unwrap_or_else(|_| PathBuf::from(file)));
+    loader.load(file, source, false);
+    for frame in loader.frames.drain(1..) {
+        let message = format!("'{}' without 'end'", frame.keyword());
+        loader.errors.push(Diagnostic::new(&frame.file, frame.pos, message));
+    }
+    let mut world = loader.world;
+    let root = loader.frames.pop().unwrap();
+    for object in root.objects {
+        world.add(object);
+    }
+    world.lights.extend(root.lights);
+    let mut errors = loader.errors;
+    if !errors.is_empty() {
+        errors.sort_by(|a, b| (&a.file, a.pos.line, a.pos.column).cmp(&(&b.file, b.pos.line, b.pos.column)));
+        return Err(SpriosError::SceneErrors(errors));
+    }
+    Ok(world)
+}
+
+pub fn load_file(path: impl AsRef<Path>) -> Result<World, SpriosError> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| SpriosError::WorldParseError(format!("{}: {}", path.display(), e)))?;
+    load_str(&path.display().to_string(), &source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(result: Result<World, SpriosError>) -> Vec<Diagnostic> {
+        match result {
+            Err(SpriosError::SceneErrors(errors)) => errors,
+            _ => panic!("expected scene errors"),
+        }
+    }
+
+    #[test]
+    fn test_load() {
+        let src = "camera 20 10 10 0 0 0 30.5 0.04 aspect=1.5 # float fov\n\
+                   background color=0.3,0.6,0.9\n\
+                   metal 0.5 0.5 0.8 fuzz=0.1\n\
+                   sphere 0 1 0 radius=1\n\
+                   sphere center=0,-1000,0 1000\n\
+                   environment 0.1 0.1 0.1\n\
+                   glass ior=1.33\n\
+                   sphere 0 1 2 0.5\n\
+                   emissive 4 4 4 two_sided\n\
+                   sphere 0 5 0 1\n\
+                   quad 0 0 0 1 0 0 0 1 0\n\
+                   box 0 0 0 1 2 3\n\
+                   cube 0 1 4 1\n\
+                   disk 0 0 0 0 1 0 2 inner=1\n\
+                   group\nplane 0 -1 0 0 1 0\nend\n";
+        let world = load_str("test", src).unwrap();
+        // A group holding only unbounded objects is still part of the scene
+        assert_eq!(world.objects.len(), 9);
+        assert!(world.objects[8].bbox(0.0, 1.0).is_none());
+        assert_eq!(world.objects[6].bbox(0.0, 0.0).unwrap().centroid(), Vec3::new(0.0, 1.0, 4.0));
+        assert_eq!(world.environment, Some(Vec3::new(0.1, 0.1, 0.1)));
+        let glass = world.objects[2].material().unwrap().describe();
+        assert!(matches!(glass, Some(crate::MaterialDesc::Glass { ior }) if ior == 1.33));
+        assert_eq!(world.background, Vec3::new(0.3, 0.6, 0.9));
+        assert_eq!(world.objects[0].material().unwrap().color(), &Vec3::new(0.5, 0.5, 0.8));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let src = "sphere 0 1\ndiffuse 1 1 1 2\nblob 1 2 3\ncamera 0 0 0 1 1 1 fov=x\n";
+        let errors = errors(load_str("scene.rsc", src));
+        assert_eq!(errors.len(), 4);
+        assert_eq!(errors[0].to_string(), "scene.rsc:1:1: sphere: missing 'center'");
+        assert_eq!((errors[1].pos.line, errors[1].pos.column), (2, 15));
+        assert_eq!(errors[2].message, "Unknown statement 'blob'");
+        assert_eq!((errors[3].pos.line, errors[3].pos.column), (4, 24));
+    }
+
+    #[test]
+    fn test_degenerate_shapes() {
+        let src = "quad 0 0 0 1 0 0 2 0 0\nbox 0 0 0 1 0 1\ncube 0 0 0 0\nplane 0 0 0 0 0 0\ndisk 0 0 0 0 1 0 1 inner=1\n";
+        let shapes = errors(load_str("test", src));
+        let messages: Vec<_> = shapes.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "quad: edges are parallel",
+                "box: zero extent",
+                "cube: size must be positive",
+                "plane: normal is zero",
+                "disk: needs a normal and 0 <= inner < radius"
+            ]
+        );
+        let src = "volume smoke.raw 0 0 0 1 1 1 1 dims=2.5,3,4\nvolume smoke.raw 0 0 0 1 1 1 1 dims=-1,3,4\n";
+        let dims = errors(load_str("test", src));
+        assert_eq!(dims.len(), 2);
+        for (line, error) in dims.iter().enumerate() {
+            assert_eq!(error.message, "volume: dims must be whole numbers of at least 1");
+            assert_eq!((error.pos.line, error.pos.column), (line + 1, 1));
+        }
+    }
+
+    #[test]
+    fn test_named_materials() {
+        let src = "material grey diffuse 0.5 0.5 0.5\n\
+                   material chrome metal 0.8,0.8,0.8 fuzz=0.05\n\
+                   sphere 0 0 0 1 grey\n\
+                   sphere 1 0 0 1 material=chrome\n\
+                   sphere 2 0 0 1 grey\n\
+                   sphere 3 0 0 1 gold\n";
+        let errors = errors(load_str("test", src));
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].pos.line, errors[0].pos.column), (6, 16));
+
+        let world = load_str("test", &src[..src.rfind("sphere").unwrap()]).unwrap();
+        assert_eq!(world.materials.len(), 2);
+        assert!(Arc::ptr_eq(&world.find_material("grey").unwrap(), &world.materials[0]));
+        let first = world.objects[0].material().unwrap() as *const dyn Material as *const u8;
+        let third = world.objects[2].material().unwrap() as *const dyn Material as *const u8;
+        assert_eq!(first, third);
+    }
+
+    #[test]
+    fn test_import() {
+        let dir = std::env::temp_dir().join("sprios_test_import");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.rsm"), "# shared\nmaterial red diffuse 0.9 0.1 0.1\n").unwrap();
+        std::fs::write(dir.join("bad.rsm"), "sphere 0 0 0 1\nimport bad.rsm\n").unwrap();
+        let scene = dir.join("scene.rsc");
+        std::fs::write(&scene, "import \"lib.rsm\"\nsphere 0 0 0 1 red\n").unwrap();
+        let world = load_file(&scene).unwrap();
+        assert_eq!(world.objects[0].material().unwrap().color(), &Vec3::new(0.9, 0.1, 0.1));
+
+        std::fs::write(&scene, "import bad.rsm\nimport missing.rsm\n").unwrap();
+        let errors = errors(load_file(&scene));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].file.ends_with("bad.rsm"));
+        assert!(errors[0].message.contains("not allowed"));
+        assert!(errors[1].message.contains("imports itself"));
+        assert!(errors[2].message.contains("missing.rsm"));
+    }
+
+    #[test]
+    fn test_lights() {
+        let src = "group lamp hidden\n\
+                   \x20 light point 0 1 0 1 1 1\n\
+                   end\n\
+                   transform translate 10 0 0\n\
+                   group\n\
+                   \x20 transform translate 0 5 0\n\
+                   \x20 instance lamp\n\
+                   \x20 transform rotate 90 0 0 1\n\
+                   \x20 light directional 1 0 0 2 2 2\n\
+                   end\n\
+                   light point 0 0 0 3 3 3\n";
+        let world = load_str("test", src).unwrap();
+        assert_eq!(world.lights.len(), 3);
+        assert_eq!(world.lights[0], Light::Point { position: Vec3::new(10.0, 6.0, 0.0), intensity: Vec3::ONE });
+        match &world.lights[1] {
+            Light::Directional { direction, .. } => assert!((direction - &Vec3::new(0.0, 1.0, 0.0)).length() < 1e-5),
+            light => panic!("{:?}", light),
+        }
+        assert_eq!(world.lights[2], Light::Point { position: Vec3::new(10.0, 0.0, 0.0), intensity: Vec3::new(3.0, 3.0, 3.0) });
+    }
+
+    #[test]
+    fn test_groups() {
+        let src = "material grey diffuse 0.5 0.5 0.5\n\
+                   group tree hidden\n\
+                   \x20 sphere 0 1 0 1 grey\n\
+                   \x20 transform translate 0 3 0\n\
+                   \x20 transform scale 0.5\n\
+                   \x20 sphere 0 0 0 1 grey\n\
+                   end\n\
+                   instance tree\n\
+                   transform translate 10 0 0\n\
+                   transform rotate 90 0 0 1\n\
+                   instance tree\n\
+                   transform identity\n\
+                   group\n\
+                   \x20 transform translate 0 0 -5\n\
+                   \x20 sphere 0 0 0 1\n\
+                   end\n";
+        let world = load_str("test", src).unwrap();
+        assert_eq!(world.objects.len(), 3);
+        let names: Vec<_> = world.objects.iter().map(|o| o.name()).collect();
+        assert_eq!(names, vec!["Instance", "Instance", "Bvh"]);
+        let bbox = world.objects[0].bbox(0.0, 1.0).unwrap();
+        assert_eq!(bbox.max, Vec3::new(1.0, 3.5, 1.0));
+        let bbox = world.objects[1].bbox(0.0, 1.0).unwrap();
+        assert!((bbox.min.x - 6.5).abs() < 1e-5 && (bbox.max.x - 10.0).abs() < 1e-5);
+        let bbox = world.objects[2].bbox(0.0, 1.0).unwrap();
+        assert_eq!(bbox.min, Vec3::new(-1.0, -1.0, -6.0));
+
+        let src = "end\ninstance tree\ngroup a\ntransform scale 0\ntransform spin 1\nmotion rotate 90 0 1 0\ncamera 0 0 1 0 0 0 40 open=2 close=3\n";
+        let errors = errors(load_str("test", src));
+        let messages: Vec<_> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "'end' without 'group'",
+                "Unknown group 'tree'",
+                "'group' without 'end'",
+                "transform: matrix can't be inverted",
+                "Unknown transform 'spin'",
+                "motion: only translate and scale can move",
+                "camera: shutter 2..3 must lie within 0..1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_csg() {
+        let src = "material red diffuse 1 0 0\n\
+                   csg difference red\n\
+                   \x20 box -1 -1 -1 1 1 1\n\
+                   \x20 sphere 0 0 0 1.2\n\
+                   \x20 transform translate 0 0 1\n\
+                   \x20 sphere 0 0 0 0.5\n\
+                   end\n";
+        let world = load_str("test", src).unwrap();
+        assert_eq!(world.objects.len(), 1);
+        assert_eq!(world.objects[0].name(), "Csg");
+        // The corner is left, the centre and the front face are cut away
+        let hit = |origin: Vec3, dir: Vec3| {
+            let mut rec = crate::HitRecord::new(world.materials[0].as_ref());
+            let ray = crate::Ray::new(&origin, &dir);
+            world.objects[0].hit(&ray, 0.001, f32::INFINITY, &mut rec).then_some(rec.t)
+        };
+        assert_eq!(hit(Vec3::new(0.9, 0.9, 5.0), Vec3::new(0.0, 0.0, -1.0)), Some(4.0));
+        assert_eq!(hit(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)), None);
+
+        let errors = errors(load_str("test", "csg xor\nend\ncsg union\nsphere 0 0 0 1\nend\ncsg union\n"));
+        let messages: Vec<_> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec!["csg: unknown operation 'xor'", "'end' without 'group'", "csg: needs at least 2 objects", "'csg' without 'end'"]
+        );
+    }
+
+    #[test]
+    fn test_medium() {
+        let src = "fog 0 0.1 1 1 1 0.5 height=2
+                   medium 0.5 1 0.8 0.8 0.8
+                     box -1 -1 -1 1 1 1
+                   end
+";
+        let world = load_str("test", src).unwrap();
+        assert_eq!(world.objects[0].name(), "ConstantMedium");
+        let fog = world.fog.as_ref().unwrap();
+        assert_eq!((fog.height, fog.medium.scattering, fog.medium.g), (Some(2.0), 0.1, 0.5));
+
+        let errors = errors(load_str("test", "medium 1 1 1 1 1 g=1
+end
+medium 1 1 1 1 1
+end
+"));
+        let messages: Vec<_> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["medium: coefficients can't be negative and g is in -1..1", "'end' without 'group'", "medium: needs a boundary object"]);
+    }
+}
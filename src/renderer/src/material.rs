@@ -1,54 +1,34 @@
 use crate::hittable::HitRecord;
 use crate::ray::Ray;
 use crate::vec::{Color, Vec3};
-use std::str::FromStr;
 use std::convert::TryInto;
-use crate::errors::SpriosError::WorldParseError;
+use crate::description::MaterialDesc;
+use crate::texture::Texture;
+use rand::Rng;
+use std::sync::Arc;
 
 pub trait Material: Sync + Send {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: Option<&mut dyn rand::RngCore>) -> Option<Ray>;
     fn color(&self) -> &Color;
-}
-
-
-impl FromStr for Box<dyn Material> {
-    type Err = crate::errors::SpriosError;
-
-    fn from_str(s: &str) -> Result<Box<dyn Material>, Self::Err> {
-        let parts: Vec<&str> = s.split(" ").collect();
-        let mat = *parts.get(0).ok_or_else(||WorldParseError("Missing material type".to_string()))?;
-        let parms = parts.iter()
-            .skip(1)
-            .map(|v| v.parse::<f32>())
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|_|WorldParseError("Could not parse material parms".to_string()))?;
-        match mat
-        {
-            "diffuse" => {
-                if parms.len() < 3 {
-                    return Err(WorldParseError("Color must have 3 components".to_string()));
-                }
-                Ok(Box::new(Lambertian { color: Color::from(&[parms[0], parms[1], parms[2]]) }))
-            }
-            "metal" => {
-                if parms.len() < 3 {
-                    return Err(WorldParseError("Color must have 3 components".to_string()));
-                }
-                Ok(Box::new(Metal {
-                    color: Color::from(&[parms[0], parms[1], parms[2]]),
-                    fuzz: *parms.last().ok_or(WorldParseError("Missing fuzz parm".to_string()))?,
-                }))
-            }
-            "glass" => {
-                return Err(WorldParseError("Glass not supported".to_string()));
-            }
-            m => {
-                return Err(WorldParseError(format!("Unknown material {}", m)));
-            }
-        }
+    /// Colour at the hit, with vertex colours and textures
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.color() * &rec.color
+    }
+    /// Light given off at the hit
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::ZERO
+    }
+    /// BSDF times cosine for light arriving from `wi` and leaving towards `wo`, used to sample lights directly
+    fn eval(&self, _rec: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> Color {
+        Color::ZERO
+    }
+    /// Plain data version for scene files, None if the material can't be saved
+    fn describe(&self) -> Option<MaterialDesc> {
+        None
     }
 }
 
+
 #[derive(Debug)]
 pub struct Lambertian {
     pub color: Color,
@@ -60,8 +40,51 @@ pub struct Metal {
     pub fuzz: f32,
 }
 
+/// Glass and other clear materials, reflects or refracts by the Fresnel term
+pub struct Dielectric {
+    pub ior: f32,
+}
+
+/// Kajiya-Kay hair, diffuse by the sine to the strand with a highlight on the cone of mirror
+/// directions around it. Shades like Lambertian on hits without a tangent.
+pub struct Hair {
+    pub color: Color,
+    pub specular: f32,
+    pub shininess: f32,
+}
+
+/// Area light, emits from the front face unless `two_sided`. `reverse` emits from the back instead.
+pub struct Emissive {
+    pub color: Color,
+    pub two_sided: bool,
+    pub reverse: bool,
+}
+
+/// Metallic-roughness material of glTF. Metals reflect with `roughness` as fuzz, the rest is diffuse.
+pub struct Pbr {
+    pub color: Color,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Multiplies `color`
+    pub color_texture: Option<Arc<Texture>>,
+    /// Roughness in green and metallic in blue, multiplying the factors
+    pub metallic_roughness_texture: Option<Arc<Texture>>,
+}
+
+impl Pbr {
+    fn metallic_roughness(&self, rec: &HitRecord) -> (f32, f32) {
+        match &self.metallic_roughness_texture {
+            Some(tex) => {
+                let mr = tex.sample(rec.u, rec.v);
+                (self.metallic * mr.z, self.roughness * mr.y)
+            }
+            None => (self.metallic, self.roughness),
+        }
+    }
+}
+
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, rng: Option<&mut dyn rand::RngCore>) -> Option<Ray> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: Option<&mut dyn rand::RngCore>) -> Option<Ray> {
         let mut trng: rand::rngs::ThreadRng;
         let mut rng = match rng {
             Some(r) => r,
@@ -71,12 +94,20 @@ impl Material for Lambertian {
             }
         };
         let scatter_direction = &rec.normal + Vec3::random_unit_vector(&mut rng);
-        Some(Ray::new(&rec.p, &scatter_direction))
+        Some(Ray::new(&rec.p, &scatter_direction).with_time(r_in.time))
     }
 
     fn color(&self) -> &Color {
         &self.color
     }
+
+    fn eval(&self, rec: &HitRecord, _wo: &Vec3, wi: &Vec3) -> Color {
+        self.albedo(rec) * (rec.normal.dot(wi).max(0.0) / std::f32::consts::PI)
+    }
+
+    fn describe(&self) -> Option<MaterialDesc> {
+        Some(MaterialDesc::Diffuse { color: self.color.clone() })
+    }
 }
 
 impl Material for Metal {
@@ -90,7 +121,39 @@ impl Material for Metal {
                 &mut trng
             }
         };
-        let scattered = Ray::new(&rec.p, &(reflected + Vec3::random_in_unit_sphere(&mut rng) * self.fuzz));
+        let scattered = Ray::new(&rec.p, &(reflected + Vec3::random_in_unit_sphere(&mut rng) * self.fuzz)).with_time(r_in.time);
+        if scattered.direction.dot(&rec.normal) > 0.0 {
+            return Some(scattered);
+        }
+        None
+    }
+
+    fn color(&self) -> &Color {
+        &self.color
+    }
+
+    fn describe(&self) -> Option<MaterialDesc> {
+        Some(MaterialDesc::Metal { color: self.color.clone(), fuzz: self.fuzz })
+    }
+}
+
+impl Material for Pbr {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: Option<&mut dyn rand::RngCore>) -> Option<Ray> {
+        let mut trng: rand::rngs::ThreadRng;
+        let mut rng = match rng {
+            Some(r) => r,
+            None => {
+                trng = rand::thread_rng();
+                &mut trng
+            }
+        };
+        let (metallic, roughness) = self.metallic_roughness(rec);
+        if rng.gen::<f32>() >= metallic {
+            let scatter_direction = &rec.normal + Vec3::random_unit_vector(&mut rng);
+            return Some(Ray::new(&rec.p, &scatter_direction).with_time(r_in.time));
+        }
+        let reflected = r_in.direction.unit().reflect(&rec.normal);
+        let scattered = Ray::new(&rec.p, &(reflected + Vec3::random_in_unit_sphere(&mut rng) * roughness)).with_time(r_in.time);
         if scattered.direction.dot(&rec.normal) > 0.0 {
             return Some(scattered);
         }
@@ -100,4 +163,125 @@ impl Material for Metal {
     fn color(&self) -> &Color {
         &self.color
     }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        match &self.color_texture {
+            Some(tex) => &self.color * &rec.color * tex.sample(rec.u, rec.v),
+            None => &self.color * &rec.color,
+        }
+    }
+
+    fn eval(&self, rec: &HitRecord, _wo: &Vec3, wi: &Vec3) -> Color {
+        let (metallic, _) = self.metallic_roughness(rec);
+        self.albedo(rec) * ((1.0 - metallic) * rec.normal.dot(wi).max(0.0) / std::f32::consts::PI)
+    }
+
+    /// Textures are not part of scene descriptions
+    fn describe(&self) -> Option<MaterialDesc> {
+        if self.color_texture.is_some() || self.metallic_roughness_texture.is_some() {
+            return None;
+        }
+        Some(MaterialDesc::Pbr { color: self.color.clone(), metallic: self.metallic, roughness: self.roughness })
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: Option<&mut dyn rand::RngCore>) -> Option<Ray> {
+        let mut trng: rand::rngs::ThreadRng;
+        let rng = match rng {
+            Some(r) => r,
+            None => {
+                trng = rand::thread_rng();
+                &mut trng
+            }
+        };
+        let ratio = if rec.front_face { 1.0 / self.ior } else { self.ior };
+        let unit = r_in.direction.unit();
+        let cos = (-&unit).dot(&rec.normal).min(1.0);
+        let sin = (1.0 - cos * cos).sqrt();
+        // Schlick's approximation of the reflectance
+        let r0 = ((1.0 - ratio) / (1.0 + ratio)).powi(2);
+        let reflectance = r0 + (1.0 - r0) * (1.0 - cos).powi(5);
+        let direction = if ratio * sin > 1.0 || reflectance > rng.gen::<f32>() {
+            unit.reflect(&rec.normal)
+        } else {
+            unit.refract(&rec.normal, ratio)
+        };
+        Some(Ray::new(&rec.p, &direction).with_time(r_in.time))
+    }
+
+    fn color(&self) -> &Color {
+        &Color::ONE
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::ONE
+    }
+
+    fn describe(&self) -> Option<MaterialDesc> {
+        Some(MaterialDesc::Glass { ior: self.ior })
+    }
+}
+
+impl Material for Hair {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: Option<&mut dyn rand::RngCore>) -> Option<Ray> {
+        let mut trng: rand::rngs::ThreadRng;
+        let mut rng = match rng {
+            Some(r) => r,
+            None => {
+                trng = rand::thread_rng();
+                &mut trng
+            }
+        };
+        // Strands are thin, light bounces on through them as well as back
+        let direction = if rec.tangent == Vec3::ZERO {
+            &rec.normal + Vec3::random_unit_vector(&mut rng)
+        } else {
+            Vec3::random_unit_vector(&mut rng)
+        };
+        Some(Ray::new(&rec.p, &direction).with_time(r_in.time))
+    }
+
+    fn color(&self) -> &Color {
+        &self.color
+    }
+
+    fn eval(&self, rec: &HitRecord, wo: &Vec3, wi: &Vec3) -> Color {
+        if rec.tangent == Vec3::ZERO {
+            return self.albedo(rec) * (rec.normal.dot(wi).max(0.0) / std::f32::consts::PI);
+        }
+        let (cos_i, cos_o) = (rec.tangent.dot(wi), rec.tangent.dot(wo));
+        let (sin_i, sin_o) = ((1.0 - cos_i * cos_i).max(0.0).sqrt(), (1.0 - cos_o * cos_o).max(0.0).sqrt());
+        let diffuse = self.albedo(rec) * (sin_i / std::f32::consts::PI);
+        // Cosine of the angle between wo and the mirror cone of wi
+        let cone = (sin_i * sin_o - cos_i * cos_o).max(0.0);
+        let specular = self.specular * (self.shininess + 2.0) / (2.0 * std::f32::consts::PI) * cone.powf(self.shininess);
+        diffuse + Color::new(specular, specular, specular)
+    }
+
+    fn describe(&self) -> Option<MaterialDesc> {
+        Some(MaterialDesc::Hair { color: self.color.clone(), specular: self.specular, shininess: self.shininess })
+    }
+}
+
+impl Material for Emissive {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _rng: Option<&mut dyn rand::RngCore>) -> Option<Ray> {
+        None
+    }
+
+    fn color(&self) -> &Color {
+        &self.color
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        if self.two_sided || rec.front_face != self.reverse {
+            self.color.clone()
+        } else {
+            Color::ZERO
+        }
+    }
+
+    fn describe(&self) -> Option<MaterialDesc> {
+        Some(MaterialDesc::Emissive { color: self.color.clone(), two_sided: self.two_sided, reverse: self.reverse })
+    }
 }
@@ -0,0 +1,321 @@
+use crate::bbox::AaBb;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec::{Color, Vec3};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// Absorption and scattering per unit length. `color` tints the scattered light and `g` is the
+/// Henyey-Greenstein asymmetry, 0 scatters evenly and positive values mostly forward.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Medium {
+    pub absorption: f32,
+    pub scattering: f32,
+    pub color: Color,
+    #[serde(default)]
+    pub g: f32,
+}
+
+impl Medium {
+    pub fn extinction(&self) -> f32 {
+        self.absorption + self.scattering
+    }
+
+    /// Phase function at a collision, weighted by the chance of scattering instead of absorbing
+    pub fn phase(&self) -> Phase {
+        let albedo = if self.extinction() > 0.0 { self.scattering / self.extinction() } else { 0.0 };
+        Phase { color: &self.color * albedo, g: self.g.clamp(-0.99, 0.99) }
+    }
+
+    /// Free flight distance for a uniform `u`, infinite in a clear medium
+    pub(crate) fn sample_distance(&self, u: f32) -> f32 {
+        if self.extinction() > 0.0 {
+            -(1.0 - u).ln() / self.extinction()
+        } else {
+            f32::INFINITY
+        }
+    }
+
+    fn transmittance(&self, distance: f32) -> f32 {
+        if self.extinction() > 0.0 {
+            (-self.extinction() * distance).exp()
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Henyey-Greenstein phase function, the "material" of medium collisions
+pub struct Phase {
+    pub color: Color,
+    pub g: f32,
+}
+
+impl Phase {
+    /// Density of scattering by an angle with cosine `cos`
+    pub fn density(&self, cos: f32) -> f32 {
+        let denom = 1.0 + self.g * self.g - 2.0 * self.g * cos;
+        (1.0 - self.g * self.g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    fn sample(&self, dir: &Vec3, rng: &mut dyn rand::RngCore) -> Vec3 {
+        let (u1, u2): (f32, f32) = (rng.gen(), rng.gen());
+        let g = self.g;
+        let cos = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u1);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        let a = if dir.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let t = Vec3::cross(dir, &a).unit();
+        let b = Vec3::cross(dir, &t);
+        t * (sin * phi.cos()) + b * (sin * phi.sin()) + dir * cos
+    }
+}
+
+impl Material for Phase {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: Option<&mut dyn rand::RngCore>) -> Option<Ray> {
+        let mut trng = rand::thread_rng();
+        let rng: &mut dyn rand::RngCore = match rng {
+            Some(r) => r,
+            None => &mut trng,
+        };
+        Some(Ray::new(&rec.p, &self.sample(&r_in.direction.unit(), rng)).with_time(r_in.time))
+    }
+
+    fn color(&self) -> &Color {
+        &self.color
+    }
+
+    fn eval(&self, rec: &HitRecord, wo: &Vec3, wi: &Vec3) -> Color {
+        self.albedo(rec) * self.density(-wo.dot(wi))
+    }
+}
+
+/// Record for a collision at `t`, media have no surface so the normal faces back along the ray
+pub(crate) fn collision<'obj>(phase: &'obj Phase, ray: &Ray, t: f32) -> HitRecord<'obj> {
+    let mut rec = HitRecord::new(phase);
+    rec.t = t;
+    rec.p = ray.at(t);
+    rec.normal = -ray.direction.unit();
+    rec
+}
+
+/// Generator seeded from the ray, `hit` gets none and seeded renders stay repeatable
+pub(crate) fn ray_rng(ray: &Ray, t_min: f32) -> SmallRng {
+    let (o, d) = (&ray.origin, &ray.direction);
+    let mut h: u64 = 0x9e37_79b9_7f4a_7c15;
+    for x in [o.x, o.y, o.z, d.x, d.y, d.z, t_min, ray.time].iter() {
+        h = (h ^ x.to_bits() as u64).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h ^= h >> 31;
+    }
+    SmallRng::seed_from_u64(h)
+}
+
+/// Constant density medium filling a closed boundary, smoke in a box or a glass of milk.
+///
+/// Rays collide at a sampled free flight distance inside and scatter by the phase function,
+/// so shadow rays through it are blocked with the chance light is attenuated.
+pub struct ConstantMedium {
+    pub boundary: Arc<dyn Hittable>,
+    medium: Medium,
+    phase: Phase,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hittable>, medium: Medium) -> ConstantMedium {
+        let phase = medium.phase();
+        ConstantMedium { boundary, medium, phase }
+    }
+
+    pub fn medium(&self) -> &Medium {
+        &self.medium
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        if self.medium.extinction() <= 0.0 {
+            return false;
+        }
+        // Spend the sampled optical depth over the stretches of the ray inside the boundary
+        let crossings = self.boundary.hits(ray, t_min, f32::INFINITY);
+        let mut left = self.medium.sample_distance(ray_rng(ray, t_min).gen()) / ray.direction.length();
+        let mut entered = match crossings.first() {
+            Some(first) if !first.front_face => Some(t_min),
+            _ => None,
+        };
+        for c in &crossings {
+            match entered {
+                Some(start) if !c.front_face => {
+                    let end = c.t.min(t_max);
+                    if start + left < end {
+                        *rec = collision(&self.phase, ray, start + left);
+                        return true;
+                    }
+                    left -= end - start;
+                    entered = None;
+                }
+                None if c.front_face => entered = Some(c.t),
+                _ => {}
+            }
+            if c.t >= t_max {
+                break;
+            }
+        }
+        false
+    }
+
+    fn bbox(&self, t0: f32, t1: f32) -> Option<AaBb> {
+        self.boundary.bbox(t0, t1)
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(&self.phase)
+    }
+
+    fn set_material(&mut self, _mat: Arc<dyn Material>) {}
+
+    fn name(&self) -> &'static str {
+        "ConstantMedium"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        Ok(ObjectDesc::Medium { medium: self.medium.clone(), boundary: Box::new(self.boundary.describe(writer)?) })
+    }
+}
+
+/// Medium filling the world below `height`, or all of it when None.
+///
+/// Without a height nothing reaches the sky or directional lights, which are infinitely far.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fog {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<f32>,
+    pub medium: Medium,
+}
+
+impl Fog {
+    /// Part of `t_min..t_max` inside the fog
+    fn span(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let (mut start, mut end) = (t_min, t_max);
+        if let Some(height) = self.height {
+            let (y, dy) = (ray.origin.y, ray.direction.y);
+            if dy == 0.0 {
+                if y >= height {
+                    return None;
+                }
+            } else {
+                let t = (height - y) / dy;
+                if dy > 0.0 {
+                    end = end.min(t);
+                } else {
+                    start = start.max(t);
+                }
+            }
+        }
+        if start < end {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    /// Distance along `ray` to a collision before `t_max`
+    pub fn sample(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut impl rand::RngCore) -> Option<f32> {
+        let (start, end) = self.span(ray, t_min, t_max)?;
+        let t = start + self.medium.sample_distance(rng.gen()) / ray.direction.length();
+        if t < end {
+            Some(t)
+        } else {
+            None
+        }
+    }
+
+    /// Fraction of light getting through `t_min..t_max` of `ray`
+    pub fn transmittance(&self, ray: &Ray, t_min: f32, t_max: f32) -> f32 {
+        match self.span(ray, t_min, t_max) {
+            Some((start, end)) => self.medium.transmittance((end - start) * ray.direction.length()),
+            None => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Point3;
+    use crate::Cuboid;
+    use rand::SeedableRng;
+
+    fn smoke(absorption: f32, scattering: f32) -> Medium {
+        Medium { absorption, scattering, color: Color::ONE, g: 0.0 }
+    }
+
+    #[test]
+    fn test_phase() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(1);
+        let dir = Vec3::new(0.0, 0.0, 1.0);
+        for &g in &[0.0, 0.6, -0.4] {
+            let phase = Phase { color: Color::ONE, g };
+            // Integrates to one over the sphere, the mean cosine is g
+            let n = 2000;
+            let integral: f32 = (0..n).map(|i| phase.density(-1.0 + 2.0 * (i as f32 + 0.5) / n as f32) * 2.0 * PI * 2.0 / n as f32).sum();
+            assert!((integral - 1.0).abs() < 1e-2, "{} {}", g, integral);
+            let mean = (0..20000).map(|_| phase.sample(&dir, &mut rng).z).sum::<f32>() / 20000.0;
+            assert!((mean - g).abs() < 0.02, "{} {}", g, mean);
+        }
+        let phase = smoke(1.0, 3.0).phase();
+        assert_eq!(phase.color, Color::new(0.75, 0.75, 0.75));
+    }
+
+    #[test]
+    fn test_constant_medium() {
+        let cube = Arc::new(Cuboid::new(Point3::new(0.0, -1.0, -1.0), Point3::new(2.0, 1.0, 1.0), None));
+        let medium = ConstantMedium::new(cube, smoke(0.25, 0.25));
+        let mat = crate::Lambertian { color: Color::ONE };
+        let mut rec = HitRecord::new(&mat);
+        // A 2 unit crossing with extinction 0.5 lets exp(-1) of the rays through
+        let n = 20000;
+        let mut through = 0;
+        for i in 0..n {
+            let y = -0.9 + 1.8 * i as f32 / n as f32;
+            let ray = Ray::new(&Point3::new(-3.0, y, 0.0), &Vec3::new(1.0, 0.0, 0.0));
+            if medium.hit(&ray, 0.001, f32::INFINITY, &mut rec) {
+                assert!(rec.t > 3.0 && rec.t < 5.0);
+            } else {
+                through += 1;
+            }
+        }
+        let expected = (-1.0f32).exp();
+        assert!((through as f32 / n as f32 - expected).abs() < 0.02);
+        // Starting inside, and stopping at t_max
+        let ray = Ray::new(&Point3::new(1.0, 0.0, 0.0), &Vec3::new(1.0, 0.0, 0.0));
+        assert!(!medium.hit(&ray, 0.001, 0.0011, &mut rec));
+        let dense = ConstantMedium::new(Arc::clone(&medium.boundary), smoke(0.0, 1e4));
+        assert!(dense.hit(&ray, 0.001, f32::INFINITY, &mut rec) && rec.t < 0.01);
+    }
+
+    #[test]
+    fn test_fog() {
+        let fog = Fog { height: Some(1.0), medium: smoke(0.5, 0.0) };
+        let up = Ray::new(&Point3::new(0.0, 0.0, 0.0), &Vec3::new(0.0, 2.0, 0.0));
+        assert!((fog.transmittance(&up, 0.0, f32::INFINITY) - (-0.5f32).exp()).abs() < 1e-5);
+        let above = Ray::new(&Point3::new(0.0, 2.0, 0.0), &Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(fog.transmittance(&above, 0.0, f32::INFINITY), 1.0);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(2);
+        assert!(fog.sample(&above, 0.0, f32::INFINITY, &mut rng).is_none());
+        let down = Ray::new(&Point3::new(0.0, 3.0, 0.0), &Vec3::new(0.0, -1.0, 0.0));
+        let t = fog.sample(&down, 0.0, f32::INFINITY, &mut rng).unwrap();
+        assert!(t > 2.0);
+    }
+}
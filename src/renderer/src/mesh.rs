@@ -0,0 +1,233 @@
+use crate::bbox::AaBb;
+use crate::bvh::BvhTree;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec::{Color, Point3, Vec3};
+use crate::Lambertian;
+use std::sync::Arc;
+
+/// Indexed triangles with their own BVH, the bottom level that instances of a mesh share.
+pub struct TriangleMesh {
+    pub positions: Vec<Point3>,
+    /// Per vertex normals, empty for flat shading
+    pub normals: Vec<Vec3>,
+    pub triangles: Vec<[u32; 3]>,
+    /// Per vertex texture coordinates, empty uses the barycentrics
+    pub uvs: Vec<[f32; 2]>,
+    /// Per vertex colours, empty for none
+    pub colors: Vec<Color>,
+    pub material: Arc<dyn Material>,
+    bvh: BvhTree,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Point3>,
+        normals: Vec<Vec3>,
+        triangles: Vec<[u32; 3]>,
+        mat: Option<Arc<dyn Material>>,
+    ) -> TriangleMesh {
+        let bounds: Vec<AaBb> = triangles
+            .iter()
+            .map(|tri| {
+                let mut b = AaBb::empty();
+                for v in tri {
+                    b.grow(&positions[*v as usize]);
+                }
+                b
+            })
+            .collect();
+        TriangleMesh {
+            bvh: BvhTree::build(&bounds),
+            positions,
+            normals,
+            triangles,
+            uvs: vec![],
+            colors: vec![],
+            material: mat.unwrap_or_else(|| Arc::new(Lambertian { color: Color::new(0.8, 0.8, 0.8) })),
+        }
+    }
+
+    pub fn with_uvs(mut self, uvs: Vec<[f32; 2]>) -> TriangleMesh {
+        self.uvs = uvs;
+        self
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Color>) -> TriangleMesh {
+        self.colors = colors;
+        self
+    }
+
+    fn intersect(&self, tri: usize, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+        let [a, b, c] = self.triangles[tri];
+        let p = &self.positions;
+        intersect_triangle([&p[a as usize], &p[b as usize], &p[c as usize]], ray, t_min, t_max)
+    }
+}
+
+/// Möller-Trumbore, returns distance and barycentrics
+pub(crate) fn intersect_triangle(p: [&Point3; 3], ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+    let p0 = p[0];
+    let e1 = p[1] - p0;
+    let e2 = p[2] - p0;
+    let pvec = Vec3::cross(&ray.direction, &e2);
+    let det = e1.dot(&pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let tvec = &ray.origin - p0;
+    let u = tvec.dot(&pvec) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let qvec = Vec3::cross(&tvec, &e1);
+    let v = ray.direction.dot(&qvec) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = e2.dot(&qvec) * inv_det;
+    if t <= t_min || t >= t_max {
+        return None;
+    }
+    Some((t, u, v))
+}
+
+impl Hittable for TriangleMesh {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let mut closest = None;
+        self.bvh.traverse(ray, t_min, t_max, |tri, t_max| {
+            let (t, u, v) = self.intersect(tri, ray, t_min, t_max)?;
+            closest = Some((tri, t, u, v));
+            Some(t)
+        });
+        let (tri, t, u, v) = match closest {
+            Some(hit) => hit,
+            None => return false,
+        };
+        let [a, b, c] = self.triangles[tri];
+        let (a, b, c) = (a as usize, b as usize, c as usize);
+        let p0 = &self.positions[a];
+        let geometric = Vec3::cross(&(&self.positions[b] - p0), &(&self.positions[c] - p0)).unit();
+        let mut normal = if self.normals.is_empty() {
+            geometric.clone()
+        } else {
+            (&self.normals[a] * (1.0 - u - v) + &self.normals[b] * u + &self.normals[c] * v).unit()
+        };
+        // Keep interpolated normals on the side of the actual surface
+        if normal.dot(&geometric) < 0.0 {
+            normal = -normal;
+        }
+        rec.t = t;
+        rec.p = ray.at(t);
+        rec.mat = self.material.as_ref();
+        if self.uvs.is_empty() {
+            rec.u = u;
+            rec.v = v;
+        } else {
+            let (ta, tb, tc) = (self.uvs[a], self.uvs[b], self.uvs[c]);
+            rec.u = ta[0] * (1.0 - u - v) + tb[0] * u + tc[0] * v;
+            rec.v = ta[1] * (1.0 - u - v) + tb[1] * u + tc[1] * v;
+        }
+        rec.color = if self.colors.is_empty() {
+            Color::ONE
+        } else {
+            &self.colors[a] * (1.0 - u - v) + &self.colors[b] * u + &self.colors[c] * v
+        };
+        rec.front_face = ray.direction.dot(&geometric) < 0.0;
+        rec.normal = if rec.front_face { normal } else { -normal };
+        true
+    }
+
+    fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
+        self.bvh.bbox().cloned()
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        self.material = mat;
+    }
+
+    fn name(&self) -> &'static str {
+        "Mesh"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        Ok(ObjectDesc::Mesh {
+            positions: self.positions.clone(),
+            normals: self.normals.clone(),
+            triangles: self.triangles.clone(),
+            uvs: self.uvs.clone(),
+            colors: self.colors.clone(),
+            material: Some(writer.material(self.material.as_ref())?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::Bvh;
+    use crate::instance::{Instance, Transform};
+    use crate::vec::Mat4;
+
+    fn grid(n: u32) -> TriangleMesh {
+        // n x n quads in the xy plane from 0 to 1, facing +z
+        let mut positions = vec![];
+        for y in 0..=n {
+            for x in 0..=n {
+                positions.push(Point3::new(x as f32 / n as f32, y as f32 / n as f32, 0.0));
+            }
+        }
+        let mut triangles = vec![];
+        for y in 0..n {
+            for x in 0..n {
+                let i = y * (n + 1) + x;
+                triangles.push([i, i + 1, i + n + 2]);
+                triangles.push([i, i + n + 2, i + n + 1]);
+            }
+        }
+        TriangleMesh::new(positions, vec![], triangles, None)
+    }
+
+    #[test]
+    fn test_mesh_hit() {
+        let mesh = grid(16);
+        let mat = Lambertian { color: Vec3::ONE };
+        let mut rec = HitRecord::new(&mat);
+        let ray = Ray::new(&Vec3::new(0.3, 0.7, 2.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(mesh.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.t - 2.0).abs() < 1e-5);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+        let ray = Ray::new(&Vec3::new(1.3, 0.7, 2.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(!mesh.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+    }
+
+    #[test]
+    fn test_two_level() {
+        // 1000 instances share one mesh, the top level only stores transforms
+        let mesh: Arc<dyn Hittable> = Arc::new(grid(8));
+        let instances: Vec<Arc<dyn Hittable>> = (0..1000)
+            .map(|i| {
+                let m = Mat4::translate(&Vec3::new((i % 10) as f32 * 2.0, (i / 10 % 10) as f32 * 2.0, -(i / 100) as f32));
+                Arc::new(Instance::new(Arc::clone(&mesh), Transform::new(m).unwrap())) as Arc<dyn Hittable>
+            })
+            .collect();
+        let top = Bvh::new(instances);
+        assert_eq!(Arc::strong_count(&mesh), 1001);
+        let mat = Lambertian { color: Vec3::ONE };
+        let mut rec = HitRecord::new(&mat);
+        let ray = Ray::new(&Vec3::new(6.5, 4.5, 5.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(top.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.t - 5.0).abs() < 1e-5);
+        let ray = Ray::new(&Vec3::new(7.5, 4.5, 5.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(!top.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+    }
+}
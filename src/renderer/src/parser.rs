@@ -0,0 +1,381 @@
+use crate::errors::Diagnostic;
+use crate::lexer::{Lexer, Pos, Token, TokenKind};
+use crate::vec::Vec3;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(f32),
+    List(Vec<f32>),
+    Ident(String),
+    Str(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Arg {
+    pub value: Value,
+    pub pos: Pos,
+}
+
+/// One line of a scene: a keyword followed by positional and `name=value` arguments.
+#[derive(Clone, Debug)]
+pub struct Statement {
+    pub keyword: String,
+    pub pos: Pos,
+    pub args: Vec<Arg>,
+    pub named: Vec<(String, Arg)>,
+}
+
+struct Parser<'a> {
+    file: &'a str,
+    tokens: Vec<Token>,
+    cursor: usize,
+    errors: Vec<Diagnostic>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.cursor]
+    }
+
+    fn next(&mut self) -> Token {
+        let t = self.tokens[self.cursor].clone();
+        if t.kind != TokenKind::Eof {
+            self.cursor += 1;
+        }
+        t
+    }
+
+    fn skip_line(&mut self) {
+        while !matches!(self.peek().kind, TokenKind::Newline | TokenKind::Eof) {
+            self.next();
+        }
+    }
+
+    fn value(&mut self) -> Result<Arg, Diagnostic> {
+        let t = self.next();
+        let value = match t.kind {
+            TokenKind::Number(n) => {
+                if self.peek().kind != TokenKind::Comma {
+                    return Ok(Arg { value: Value::Number(n), pos: t.pos });
+                }
+                let mut list = vec![n];
+                while self.peek().kind == TokenKind::Comma {
+                    self.next();
+                    match self.next() {
+                        Token { kind: TokenKind::Number(n), .. } => list.push(n),
+                        t => return Err(Diagnostic::new(self.file, t.pos, "Expected a number after ','".to_string())),
+                    }
+                }
+                Value::List(list)
+            }
+            TokenKind::Ident(s) => Value::Ident(s),
+            TokenKind::Str(s) => Value::Str(s),
+            _ => return Err(Diagnostic::new(self.file, t.pos, "Expected a value".to_string())),
+        };
+        Ok(Arg { value, pos: t.pos })
+    }
+
+    fn statement(&mut self) -> Result<Statement, Diagnostic> {
+        let t = self.next();
+        let keyword = match t.kind {
+            TokenKind::Ident(s) => s,
+            _ => return Err(Diagnostic::new(self.file, t.pos, "Expected a statement keyword".to_string())),
+        };
+        let mut stmt = Statement { keyword, pos: t.pos, args: vec![], named: vec![] };
+        while !matches!(self.peek().kind, TokenKind::Newline | TokenKind::Eof) {
+            let is_named = matches!(self.peek().kind, TokenKind::Ident(_))
+                && self.tokens.get(self.cursor + 1).map(|t| &t.kind) == Some(&TokenKind::Equals);
+            if is_named {
+                let name = match self.next().kind {
+                    TokenKind::Ident(s) => s,
+                    _ => unreachable!(),
+                };
+                self.next();
+                let arg = self.value()?;
+                stmt.named.push((name, arg));
+            } else {
+                let arg = self.value()?;
+                stmt.args.push(arg);
+            }
+        }
+        Ok(stmt)
+    }
+}
+
+/// Parse scene source into statements. Errors are collected per line, parsing resumes on the next one.
+pub fn parse(file: &str, source: &str) -> (Vec<Statement>, Vec<Diagnostic>) {
+    let (tokens, errors) = Lexer::new(file, source).tokenize();
+    let mut parser = Parser { file, tokens, cursor: 0, errors };
+    let mut statements = Vec::new();
+    loop {
+        match parser.peek().kind {
+            TokenKind::Eof => break,
+            TokenKind::Newline => {
+                parser.next();
+            }
+            _ => match parser.statement() {
+                Ok(stmt) => statements.push(stmt),
+                Err(e) => {
+                    parser.errors.push(e);
+                    parser.skip_line();
+                }
+            },
+        }
+    }
+    (statements, parser.errors)
+}
+
+/// Typed access to the arguments of a statement.
+///
+/// Every getter first looks for a `name=value` argument and otherwise consumes the next
+/// positional values, so `sphere 0 1 0 radius=2` and `sphere center=0,1,0 2` both work.
+pub struct Args<'a> {
+    file: &'a str,
+    stmt: &'a Statement,
+    next: usize,
+    used: Vec<bool>,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(file: &'a str, stmt: &'a Statement) -> Args<'a> {
+        Args { file, stmt, next: 0, used: vec![false; stmt.named.len()] }
+    }
+
+    pub fn error(&self, pos: Pos, message: String) -> Diagnostic {
+        Diagnostic::new(self.file, pos, message)
+    }
+
+    fn missing(&self, name: &str) -> Diagnostic {
+        self.error(self.stmt.pos, format!("{}: missing '{}'", self.stmt.keyword, name))
+    }
+
+    fn named(&mut self, name: &str) -> Option<&'a Arg> {
+        let i = self.stmt.named.iter().position(|(n, _)| n == name)?;
+        self.used[i] = true;
+        Some(&self.stmt.named[i].1)
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.stmt.named.iter().any(|(n, _)| n == name)
+    }
+
+    fn positional_number(&mut self, name: &str) -> Result<f32, Diagnostic> {
+        let arg = self.stmt.args.get(self.next).ok_or_else(|| self.missing(name))?;
+        match arg.value {
+            Value::Number(n) => {
+                self.next += 1;
+                Ok(n)
+            }
+            _ => Err(self.error(arg.pos, format!("{}: expected a number for '{}'", self.stmt.keyword, name))),
+        }
+    }
+
+    pub fn float(&mut self, name: &str) -> Result<f32, Diagnostic> {
+        match self.named(name) {
+            Some(Arg { value: Value::Number(n), .. }) => Ok(*n),
+            Some(arg) => Err(self.error(arg.pos, format!("{}: '{}' must be a number", self.stmt.keyword, name))),
+            None => self.positional_number(name),
+        }
+    }
+
+    pub fn float_or(&mut self, name: &str, default: f32) -> Result<f32, Diagnostic> {
+        if self.has(name) || self.next_is_number() {
+            self.float(name)
+        } else {
+            Ok(default)
+        }
+    }
+
+    /// Named only, `name=true` or `name=false`
+    pub fn bool_or(&mut self, name: &str, default: bool) -> Result<bool, Diagnostic> {
+        match self.named(name) {
+            Some(Arg { value: Value::Ident(s), .. }) if s == "true" || s == "false" => Ok(s == "true"),
+            Some(arg) => Err(self.error(arg.pos, format!("{}: '{}' must be true or false", self.stmt.keyword, name))),
+            None => Ok(default),
+        }
+    }
+
+    /// `name=value`, or the word `name` followed by a number as the next positional values
+    pub fn keyed_float_or(&mut self, name: &str) -> Result<Option<f32>, Diagnostic> {
+        if self.has(name) {
+            return self.float(name).map(Some);
+        }
+        match self.stmt.args.get(self.next) {
+            Some(Arg { value: Value::Ident(word), .. }) if word == name => {
+                self.next += 1;
+                self.positional_number(name).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn next_is_number(&self) -> bool {
+        matches!(self.stmt.args.get(self.next), Some(Arg { value: Value::Number(_), .. }))
+    }
+
+    fn next_is_list(&self) -> bool {
+        matches!(self.stmt.args.get(self.next), Some(Arg { value: Value::List(_), .. }))
+    }
+
+    pub fn floats(&mut self, name: &str, count: usize) -> Result<Vec<f32>, Diagnostic> {
+        let wrong_len = |args: &Self, pos: Pos, len: usize| {
+            args.error(pos, format!("{}: '{}' needs {} values, got {}", args.stmt.keyword, name, count, len))
+        };
+        match self.named(name) {
+            Some(Arg { value: Value::List(l), pos }) if l.len() != count => Err(wrong_len(self, *pos, l.len())),
+            Some(Arg { value: Value::List(l), .. }) => Ok(l.clone()),
+            Some(Arg { value: Value::Number(n), .. }) if count == 1 => Ok(vec![*n]),
+            Some(arg) => Err(wrong_len(self, arg.pos, 1)),
+            None if self.next_is_list() => {
+                let arg = &self.stmt.args[self.next];
+                match &arg.value {
+                    Value::List(l) if l.len() == count => {
+                        self.next += 1;
+                        Ok(l.clone())
+                    }
+                    Value::List(l) => Err(wrong_len(self, arg.pos, l.len())),
+                    _ => unreachable!(),
+                }
+            }
+            None => (0..count).map(|_| self.positional_number(name)).collect(),
+        }
+    }
+
+    /// Any number of values: a list, or all the numbers left in a row
+    pub fn list(&mut self, name: &str) -> Result<Vec<f32>, Diagnostic> {
+        match self.named(name) {
+            Some(Arg { value: Value::List(l), .. }) => Ok(l.clone()),
+            Some(Arg { value: Value::Number(n), .. }) => Ok(vec![*n]),
+            Some(arg) => Err(self.error(arg.pos, format!("{}: '{}' must be numbers", self.stmt.keyword, name))),
+            None if self.next_is_list() => {
+                let arg = &self.stmt.args[self.next];
+                self.next += 1;
+                match &arg.value {
+                    Value::List(l) => Ok(l.clone()),
+                    _ => unreachable!(),
+                }
+            }
+            None => {
+                let mut values = vec![self.positional_number(name)?];
+                while self.next_is_number() {
+                    values.push(self.positional_number(name)?);
+                }
+                Ok(values)
+            }
+        }
+    }
+
+    pub fn vec3(&mut self, name: &str) -> Result<Vec3, Diagnostic> {
+        let v = self.floats(name, 3)?;
+        Ok(Vec3::new(v[0], v[1], v[2]))
+    }
+
+    pub fn vec3_or(&mut self, name: &str, default: Vec3) -> Result<Vec3, Diagnostic> {
+        if self.has(name) || self.next_is_number() || self.next_is_list() {
+            self.vec3(name)
+        } else {
+            Ok(default)
+        }
+    }
+
+    /// A bare word or a quoted string, with its position
+    pub fn string(&mut self, name: &str) -> Result<(String, Pos), Diagnostic> {
+        let arg = match self.named(name) {
+            Some(arg) => arg,
+            None => {
+                let arg = self.stmt.args.get(self.next).ok_or_else(|| self.missing(name))?;
+                self.next += 1;
+                arg
+            }
+        };
+        match &arg.value {
+            Value::Ident(s) | Value::Str(s) => Ok((s.clone(), arg.pos)),
+            _ => Err(self.error(arg.pos, format!("{}: expected a name for '{}'", self.stmt.keyword, name))),
+        }
+    }
+
+    pub fn string_or(&mut self, name: &str) -> Result<Option<(String, Pos)>, Diagnostic> {
+        let next_is_string = matches!(
+            self.stmt.args.get(self.next),
+            Some(Arg { value: Value::Ident(_), .. }) | Some(Arg { value: Value::Str(_), .. })
+        );
+        if self.has(name) || next_is_string {
+            self.string(name).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Fails on arguments nobody asked for
+    pub fn finish(self) -> Result<(), Diagnostic> {
+        if let Some(arg) = self.stmt.args.get(self.next) {
+            return Err(self.error(arg.pos, format!("{}: unexpected extra argument", self.stmt.keyword)));
+        }
+        if let Some(i) = self.used.iter().position(|u| !u) {
+            let (name, arg) = &self.stmt.named[i];
+            return Err(self.error(arg.pos, format!("{}: unknown parameter '{}'", self.stmt.keyword, name)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statements() {
+        let (stmts, errors) = parse("test", "# header\n\nsphere 0 1 0 radius=2 # inline\nmaterial grey diffuse 0.5,0.5,0.5\n");
+        assert!(errors.is_empty());
+        assert_eq!(stmts.len(), 2);
+        assert_eq!(stmts[0].keyword, "sphere");
+        assert_eq!(stmts[0].args.len(), 3);
+        assert_eq!(stmts[0].named[0].0, "radius");
+        assert_eq!(stmts[1].pos.line, 4);
+        assert_eq!(stmts[1].args[2].value, Value::List(vec![0.5, 0.5, 0.5]));
+    }
+
+    #[test]
+    fn test_args() {
+        let (stmts, _) = parse("test", "sphere 0 1 0 radius=2\nsphere center=1,2,3 4 5");
+        let mut a = Args::new("test", &stmts[0]);
+        assert_eq!(a.vec3("center").unwrap(), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(a.float("radius").unwrap(), 2.0);
+        assert!(a.finish().is_ok());
+
+        let mut a = Args::new("test", &stmts[1]);
+        assert_eq!(a.vec3("center").unwrap(), Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(a.float("radius").unwrap(), 4.0);
+        let e = a.finish().unwrap_err();
+        assert_eq!((e.pos.line, e.pos.column), (2, 23));
+
+        let (stmts, _) = parse("test", "cylinder caps=false
+cylinder caps=0");
+        assert!(!Args::new("test", &stmts[0]).bool_or("caps", true).unwrap());
+        let e = Args::new("test", &stmts[1]).bool_or("caps", true).unwrap_err();
+        assert_eq!(e.message, "cylinder: 'caps' must be true or false");
+
+        let (stmts, _) = parse("test", "ply a.ply subdiv 2 grey\nply a.ply subdiv=3\nply a.ply subdiv grey");
+        let mut a = Args::new("test", &stmts[0]);
+        a.string("file").unwrap();
+        assert_eq!(a.keyed_float_or("subdiv").unwrap(), Some(2.0));
+        assert_eq!(a.keyed_float_or("subdiv").unwrap(), None);
+        assert_eq!(a.string("material").unwrap().0, "grey");
+        let mut a = Args::new("test", &stmts[1]);
+        a.string("file").unwrap();
+        assert_eq!(a.keyed_float_or("subdiv").unwrap(), Some(3.0));
+        assert!(a.finish().is_ok());
+        let mut a = Args::new("test", &stmts[2]);
+        a.string("file").unwrap();
+        assert_eq!(a.keyed_float_or("subdiv").unwrap_err().message, "ply: expected a number for 'subdiv'");
+    }
+
+    #[test]
+    fn test_errors_are_collected() {
+        let (stmts, errors) = parse("test", "sphere 0 = 1\nsphere 1 2 3 4\n= 2\n");
+        assert_eq!(stmts.len(), 1);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].pos.line, 1);
+        assert_eq!(errors[1].pos.line, 3);
+    }
+}
@@ -0,0 +1,415 @@
+use crate::bbox::AaBb;
+use crate::bvh::BvhTree;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sphere::hit_sphere;
+use crate::vec::{Color, Point3, Vec3};
+use crate::Lambertian;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Many spheres in flat arrays with one BVH, far smaller than a `Sphere` object each.
+pub struct ParticleCloud {
+    pub positions: Vec<Point3>,
+    /// One radius per particle, or a single one they all share
+    pub radii: Vec<f32>,
+    /// Per particle tint, empty for none
+    pub colors: Vec<Color>,
+    /// Index into `materials` per particle, empty uses the first material for all
+    pub material_ids: Vec<u16>,
+    pub materials: Vec<Arc<dyn Material>>,
+    bvh: BvhTree,
+}
+
+impl ParticleCloud {
+    /// Needs one radius for all particles or one each
+    pub fn new(positions: Vec<Point3>, radii: Vec<f32>, mat: Option<Arc<dyn Material>>) -> Result<ParticleCloud, String> {
+        if radii.len() != 1 && radii.len() != positions.len() {
+            return Err(format!("{} radii for {} particles, needs one or one each", radii.len(), positions.len()));
+        }
+        let bounds: Vec<AaBb> = positions
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let r = radii[if radii.len() == 1 { 0 } else { i }];
+                let r = Vec3::new(r, r, r);
+                AaBb::new(p - &r, p + &r)
+            })
+            .collect();
+        Ok(ParticleCloud {
+            bvh: BvhTree::build(&bounds),
+            positions,
+            radii,
+            colors: vec![],
+            material_ids: vec![],
+            materials: vec![mat.unwrap_or_else(|| Arc::new(Lambertian { color: Color::new(0.8, 0.8, 0.8) }))],
+        })
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Color>) -> ParticleCloud {
+        self.colors = colors;
+        self
+    }
+
+    /// An empty list keeps the current materials
+    pub fn with_materials(mut self, materials: Vec<Arc<dyn Material>>) -> ParticleCloud {
+        if !materials.is_empty() {
+            self.materials = materials;
+        }
+        self
+    }
+
+    /// Ids past the end of `materials` fall back to the first one
+    pub fn with_material_ids(mut self, ids: Vec<u16>) -> ParticleCloud {
+        self.material_ids = ids;
+        self
+    }
+
+    fn radius(&self, i: usize) -> f32 {
+        self.radii[if self.radii.len() == 1 { 0 } else { i }]
+    }
+}
+
+impl Hittable for ParticleCloud {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let mut closest = None;
+        let mat = self.materials[0].as_ref();
+        self.bvh.traverse(ray, t_min, t_max, |i, t_max| {
+            if !hit_sphere(&self.positions[i], self.radius(i), mat, ray, t_min, t_max, rec) {
+                return None;
+            }
+            closest = Some(i);
+            Some(rec.t)
+        });
+        let i = match closest {
+            Some(i) => i,
+            None => return false,
+        };
+        if let Some(id) = self.material_ids.get(i) {
+            rec.mat = self.materials.get(*id as usize).unwrap_or(&self.materials[0]).as_ref();
+        }
+        if let Some(color) = self.colors.get(i) {
+            rec.color = color.clone();
+        }
+        true
+    }
+
+    fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
+        self.bvh.bbox().cloned()
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.materials[0].as_ref())
+    }
+
+    /// Replaces every material of the cloud
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        self.materials = vec![mat];
+        self.material_ids.clear();
+    }
+
+    fn name(&self) -> &'static str {
+        "ParticleCloud"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        Ok(ObjectDesc::Particles {
+            materials: self.materials.iter().map(|m| writer.material(m.as_ref())).collect::<Result<_, _>>()?,
+            positions: self.positions.clone(),
+            radii: self.radii.clone(),
+            colors: self.colors.clone(),
+            material_ids: self.material_ids.clone(),
+        })
+    }
+}
+
+/// Columns of a particle file
+#[derive(Default)]
+struct Columns {
+    position: [usize; 3],
+    radius: Option<usize>,
+    color: Option<[usize; 3]>,
+    material: Option<usize>,
+}
+
+impl Columns {
+    fn from_header(names: &[&str]) -> Result<Columns, String> {
+        let find = |options: &[&str]| names.iter().position(|n| options.contains(&n.trim().to_lowercase().as_str()));
+        let position = match (find(&["x"]), find(&["y"]), find(&["z"])) {
+            (Some(x), Some(y), Some(z)) => [x, y, z],
+            _ => return Err("header needs x, y and z columns".to_string()),
+        };
+        let color = match (find(&["red", "r"]), find(&["green", "g"]), find(&["blue", "b"])) {
+            (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+            _ => None,
+        };
+        Ok(Columns { position, radius: find(&["radius", "pscale"]), color, material: find(&["material", "id"]) })
+    }
+
+    /// Fields a line needs to have all the columns
+    fn width(&self) -> usize {
+        let color = self.color.iter().flatten();
+        let all = self.position.iter().chain(&self.radius).chain(color).chain(&self.material);
+        all.max().map_or(0, |i| i + 1)
+    }
+
+    /// Without a header the count tells `x y z`, `x y z radius`, `x y z radius r g b` or that
+    /// followed by a material id
+    fn from_count(count: usize) -> Result<Columns, String> {
+        let mut columns = Columns { position: [0, 1, 2], ..Columns::default() };
+        match count {
+            3 => {}
+            4 | 7 | 8 => {
+                columns.radius = Some(3);
+                if count >= 7 {
+                    columns.color = Some([4, 5, 6]);
+                }
+                if count == 8 {
+                    columns.material = Some(7);
+                }
+            }
+            n => return Err(format!("{} columns, expected 3, 4, 7 or 8", n)),
+        }
+        Ok(columns)
+    }
+
+    /// Binary records are little-endian floats, `p` a position, `r` a radius, `c` a colour,
+    /// `m` a material id and `_` one skipped float
+    fn from_layout(layout: &str) -> Result<(Columns, usize), String> {
+        let mut columns = Columns::default();
+        let (mut offset, mut has_position) = (0, false);
+        for c in layout.chars() {
+            match c {
+                'p' => {
+                    columns.position = [offset, offset + 1, offset + 2];
+                    has_position = true;
+                    offset += 3;
+                }
+                'c' => {
+                    columns.color = Some([offset, offset + 1, offset + 2]);
+                    offset += 3;
+                }
+                'r' => {
+                    columns.radius = Some(offset);
+                    offset += 1;
+                }
+                'm' => {
+                    columns.material = Some(offset);
+                    offset += 1;
+                }
+                '_' => offset += 1,
+                c => return Err(format!("unknown layout field '{}', use p, r, c, m or _", c)),
+            }
+        }
+        if !has_position {
+            return Err("layout needs a position 'p'".to_string());
+        }
+        Ok((columns, offset))
+    }
+}
+
+/// Particle arrays as they are read, before the BVH is built
+#[derive(Default)]
+struct Builder {
+    positions: Vec<Point3>,
+    radii: Vec<f32>,
+    colors: Vec<Color>,
+    ids: Vec<u16>,
+}
+
+impl Builder {
+    fn push(&mut self, columns: &Columns, values: &[f32]) -> Result<(), String> {
+        let [x, y, z] = columns.position;
+        self.positions.push(Point3::new(values[x], values[y], values[z]));
+        if let Some(r) = columns.radius {
+            if values[r] < 0.0 {
+                return Err(format!("negative radius {}", values[r]));
+            }
+            self.radii.push(values[r]);
+        }
+        if let Some([r, g, b]) = columns.color {
+            self.colors.push(Color::new(values[r], values[g], values[b]));
+        }
+        if let Some(m) = columns.material {
+            let id = values[m];
+            if !(0.0..=u16::MAX as f32).contains(&id) || id.fract() != 0.0 {
+                return Err(format!("bad material id {}", id));
+            }
+            self.ids.push(id as u16);
+        }
+        Ok(())
+    }
+
+    fn build(self, radius: Option<f32>) -> Result<ParticleCloud, String> {
+        let radii = match (self.radii.is_empty(), radius) {
+            (false, _) => self.radii,
+            (true, Some(r)) => vec![r],
+            (true, None) => return Err("no radius column, a radius has to be given".to_string()),
+        };
+        Ok(ParticleCloud::new(self.positions, radii, None)?.with_colors(self.colors).with_material_ids(self.ids))
+    }
+}
+
+/// Read particles from CSV. A first line with names picks the columns from `x`, `y`, `z`,
+/// `radius`, `red`/`r`, `green`/`g`, `blue`/`b` and `material`, without one the column count
+/// decides. `radius` is used when the file has none.
+pub fn read_particles_csv<R: BufRead>(reader: R, radius: Option<f32>) -> Result<ParticleCloud, String> {
+    let mut builder = Builder::default();
+    let mut columns = None;
+    let mut values = vec![];
+    for (n, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').collect();
+        let columns = match &columns {
+            Some(columns) => columns,
+            None => {
+                let header = fields.iter().any(|f| f.trim().parse::<f32>().is_err());
+                let found = match header {
+                    true => Columns::from_header(&fields),
+                    false => Columns::from_count(fields.len()),
+                };
+                columns = Some(found.map_err(|e| format!("line {}: {}", n + 1, e))?);
+                if header {
+                    continue;
+                }
+                columns.as_ref().unwrap()
+            }
+        };
+        values.clear();
+        for f in &fields {
+            values.push(f.trim().parse::<f32>().map_err(|_| format!("line {}: bad number '{}'", n + 1, f.trim()))?);
+        }
+        if values.len() < columns.width() {
+            return Err(format!("line {}: missing columns", n + 1));
+        }
+        builder.push(columns, &values).map_err(|e| format!("line {}: {}", n + 1, e))?;
+    }
+    builder.build(radius)
+}
+
+/// Read particles from records of little-endian `f32`s laid out as `layout` says, see
+/// `read_particles_csv` for `radius`.
+pub fn read_particles_binary<R: Read>(mut reader: R, layout: &str, radius: Option<f32>) -> Result<ParticleCloud, String> {
+    let (columns, stride) = Columns::from_layout(layout)?;
+    let mut builder = Builder::default();
+    let mut record = vec![0u8; stride * 4];
+    let mut values = vec![0.0f32; stride];
+    let mut n = 0;
+    loop {
+        // A clean end falls between records
+        let mut filled = 0;
+        while filled < record.len() {
+            match reader.read(&mut record[filled..]).map_err(|e| e.to_string())? {
+                0 => break,
+                k => filled += k,
+            }
+        }
+        if filled == 0 {
+            break;
+        }
+        if filled < record.len() {
+            return Err(format!("particle {}: file ends inside the record", n));
+        }
+        for (v, bytes) in values.iter_mut().zip(record.chunks_exact(4)) {
+            *v = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        builder.push(&columns, &values).map_err(|e| format!("particle {}: {}", n, e))?;
+        n += 1;
+    }
+    builder.build(radius)
+}
+
+/// Loads `.csv` and `.txt` files as CSV and anything else as binary records with `layout`,
+/// `p` and `radius` when it isn't given.
+pub fn load_particles(path: impl AsRef<Path>, layout: Option<&str>, radius: Option<f32>) -> Result<ParticleCloud, SpriosError> {
+    let path = path.as_ref();
+    let error = |e: &dyn std::fmt::Display| SpriosError::ImportError(format!("{}: {}", path.display(), e));
+    let file = File::open(path).map_err(|e| error(&e))?;
+    let csv = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv") || e.eq_ignore_ascii_case("txt"));
+    let cloud = match csv {
+        true => read_particles_csv(BufReader::new(file), radius),
+        false => read_particles_binary(BufReader::new(file), layout.unwrap_or("p"), radius),
+    };
+    cloud.map_err(|e| error(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(cloud: &ParticleCloud, origin: Point3) -> Option<HitRecord<'_>> {
+        let mut rec = HitRecord::new(cloud.materials[0].as_ref());
+        let ray = Ray::new(&origin, &Vec3::new(0.0, 0.0, -1.0));
+        cloud.hit(&ray, 0.001, f32::INFINITY, &mut rec).then_some(rec)
+    }
+
+    #[test]
+    fn test_hit() {
+        // A row of 1000 particles along x with growing radii, the nearest one along z is hit
+        let positions: Vec<Point3> = (0..1000).map(|i| Point3::new(i as f32, 0.0, -((i % 3) as f32))).collect();
+        let radii = (0..1000).map(|i| 0.1 + (i % 4) as f32 * 0.1).collect();
+        let colors = (0..1000).map(|i| Color::new(i as f32 / 1000.0, 0.0, 0.0)).collect();
+        let metal: Arc<dyn Material> = Arc::new(crate::Metal { color: Color::ONE, fuzz: 0.0 });
+        let cloud = ParticleCloud::new(positions, radii, None)
+            .unwrap()
+            .with_colors(colors)
+            .with_materials(vec![Arc::new(Lambertian { color: Color::ONE }), metal])
+            .with_material_ids((0..1000).map(|i| (i % 2) as u16).collect());
+        let bbox = cloud.bbox(0.0, 1.0).unwrap();
+        assert!((bbox.min.x + 0.1).abs() < 1e-6 && (bbox.max.x - 999.4).abs() < 1e-3);
+
+        let rec = hit(&cloud, Point3::new(501.0, 0.0, 5.0)).unwrap();
+        assert!((rec.t - 4.8).abs() < 1e-4);
+        assert!((rec.color.x - 0.501).abs() < 1e-6);
+        assert!(matches!(rec.mat.describe(), Some(crate::MaterialDesc::Metal { .. })));
+        assert!(hit(&cloud, Point3::new(500.5, 0.0, 5.0)).is_none());
+        let error = ParticleCloud::new(vec![Point3::ZERO; 3], vec![0.1, 0.2], None).err().unwrap();
+        assert_eq!(error, "2 radii for 3 particles, needs one or one each");
+    }
+
+    #[test]
+    fn test_read() {
+        let csv = "x, y, z, radius, material\n0, 0, 0, 0.5, 1\n# comment\n1, 2, 3, 0.25, 0\n";
+        let cloud = read_particles_csv(csv.as_bytes(), None).unwrap();
+        assert_eq!(cloud.positions[1], Point3::new(1.0, 2.0, 3.0));
+        assert_eq!(cloud.radii, vec![0.5, 0.25]);
+        assert_eq!(cloud.material_ids, vec![1, 0]);
+        assert!(cloud.colors.is_empty());
+
+        let cloud = read_particles_csv("1 ,2,3\n4,5,6\n".as_bytes(), Some(0.1)).unwrap();
+        assert_eq!(cloud.radii, vec![0.1]);
+        assert_eq!(read_particles_csv("1,2,3\n".as_bytes(), None).err().unwrap(), "no radius column, a radius has to be given");
+        assert_eq!(read_particles_csv("1,2,3,4,5\n".as_bytes(), None).err().unwrap(), "line 1: 5 columns, expected 3, 4, 7 or 8");
+        assert_eq!(read_particles_csv("x,y,z\n1,2\n".as_bytes(), Some(1.0)).err().unwrap(), "line 2: missing columns");
+
+        let mut data = vec![];
+        for v in &[1.0f32, 2.0, 3.0, 0.5, 0.9, 0.8, 0.7, 4.0, 5.0, 6.0, 0.25, 0.1, 0.2, 0.3] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        let cloud = read_particles_binary(&data[..], "prc", None).unwrap();
+        assert_eq!(cloud.positions, vec![Point3::new(1.0, 2.0, 3.0), Point3::new(4.0, 5.0, 6.0)]);
+        assert_eq!(cloud.radii, vec![0.5, 0.25]);
+        assert_eq!(cloud.colors[1], Color::new(0.1, 0.2, 0.3));
+        assert_eq!(read_particles_binary(&data[..12], "prc", None).err().unwrap(), "particle 0: file ends inside the record");
+        assert!(read_particles_binary(&data[..], "pq", None).is_err());
+
+        // Scene descriptions keep the arrays
+        let mut world = crate::World::new();
+        world.add(Arc::new(cloud));
+        let desc = crate::SceneDescription::from_world(&world).unwrap();
+        let text = desc.to_string(crate::SceneFormat::Json).unwrap();
+        let back = crate::SceneDescription::from_str(&text, crate::SceneFormat::Json).unwrap();
+        assert_eq!(back, desc);
+        assert!(matches!(&desc.objects[0], ObjectDesc::Particles { colors, .. } if colors.len() == 2));
+        assert!(back.to_world().is_ok());
+    }
+}
@@ -0,0 +1,319 @@
+use crate::bbox::AaBb;
+use crate::bvh::BvhTree;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::mesh::intersect_triangle;
+use crate::ray::Ray;
+use crate::vec::{Color, Point3, Vec3};
+use crate::Lambertian;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Cubic Bernstein weights and their derivatives at `t`
+fn bernstein(t: f32) -> ([f32; 4], [f32; 4]) {
+    let s = 1.0 - t;
+    let b = [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t];
+    let d = [-3.0 * s * s, 3.0 * s * s - 6.0 * t * s, 6.0 * t * s - 3.0 * t * t, 3.0 * t * t];
+    (b, d)
+}
+
+/// Point and partial derivatives, rows of the control net run along `u`
+fn eval(p: &[Point3; 16], u: f32, v: f32) -> (Point3, Vec3, Vec3) {
+    let (bu, du) = bernstein(u);
+    let (bv, dv) = bernstein(v);
+    let mut s = Vec3::ZERO;
+    let mut su = Vec3::ZERO;
+    let mut sv = Vec3::ZERO;
+    for i in 0..4 {
+        for j in 0..4 {
+            let c = &p[4 * i + j];
+            s = s + c * (bv[i] * bu[j]);
+            su = su + c * (bv[i] * du[j]);
+            sv = sv + c * (dv[i] * bu[j]);
+        }
+    }
+    (s, su, sv)
+}
+
+/// Bicubic Bézier patches, tessellated for the search and then intersected exactly.
+/// The `.bpt` teapot and teacup files load with `load_bpt`.
+pub struct BezierPatches {
+    pub patches: Vec<[Point3; 16]>,
+    pub material: Arc<dyn Material>,
+    positions: Vec<Point3>,
+    /// Patch parameters at each vertex
+    params: Vec<[f32; 2]>,
+    triangles: Vec<[u32; 3]>,
+    /// Patch each triangle was cut from
+    owners: Vec<u32>,
+    bvh: BvhTree,
+}
+
+impl BezierPatches {
+    pub fn new(patches: Vec<[Point3; 16]>, mat: Option<Arc<dyn Material>>) -> BezierPatches {
+        let mut positions = vec![];
+        let mut params = vec![];
+        let mut triangles = vec![];
+        let mut owners = vec![];
+        for (k, patch) in patches.iter().enumerate() {
+            let n = Self::resolution(patch);
+            let base = positions.len() as u32;
+            for i in 0..=n {
+                for j in 0..=n {
+                    let (u, v) = (j as f32 / n as f32, i as f32 / n as f32);
+                    positions.push(eval(patch, u, v).0);
+                    params.push([u, v]);
+                }
+            }
+            let index = |i: u32, j: u32| base + i * (n + 1) + j;
+            for i in 0..n {
+                for j in 0..n {
+                    triangles.push([index(i, j), index(i, j + 1), index(i + 1, j + 1)]);
+                    triangles.push([index(i, j), index(i + 1, j + 1), index(i + 1, j)]);
+                    owners.extend([k as u32, k as u32]);
+                }
+            }
+        }
+        let bounds: Vec<AaBb> = triangles
+            .iter()
+            .map(|t| {
+                let mut b = AaBb::empty();
+                for &i in t {
+                    b.grow(&positions[i as usize]);
+                }
+                b
+            })
+            .collect();
+        BezierPatches {
+            bvh: BvhTree::build(&bounds),
+            patches,
+            material: mat.unwrap_or_else(|| Arc::new(Lambertian { color: Color::new(0.8, 0.8, 0.8) })),
+            positions,
+            params,
+            triangles,
+            owners,
+        }
+    }
+
+    /// Grid size that keeps the triangles within a thousandth of the patch size of the surface
+    fn resolution(p: &[Point3; 16]) -> u32 {
+        let mut bounds = AaBb::empty();
+        for c in p {
+            bounds.grow(c);
+        }
+        let size = (&bounds.max - &bounds.min).length();
+        let mut bend: f32 = 0.0;
+        for i in 0..4 {
+            for j in 0..2 {
+                let along_u = &p[4 * i + j] - &p[4 * i + j + 1] * 2.0 + &p[4 * i + j + 2];
+                let along_v = &p[4 * j + i] - &p[4 * (j + 1) + i] * 2.0 + &p[4 * (j + 2) + i];
+                bend = bend.max(along_u.length()).max(along_v.length());
+            }
+        }
+        if size <= 0.0 || bend <= 0.0 {
+            return 1;
+        }
+        // A cubic strays at most 6 * bend / (8 * n^2) from its chords
+        (0.75 * bend / (size * 1e-3)).sqrt().ceil().clamp(1.0, 32.0) as u32
+    }
+
+    /// Newton iteration from a triangle hit onto the patch, `None` when it doesn't settle
+    fn refine(&self, patch: usize, ray: &Ray, uv: [f32; 2], t: f32) -> Option<(f32, f32, f32)> {
+        let p = &self.patches[patch];
+        let (mut u, mut v, mut t) = (uv[0], uv[1], t);
+        let d = &ray.direction;
+        let scale = d.length();
+        for _ in 0..8 {
+            let (s, su, sv) = eval(p, u, v);
+            // Solve [su sv -d] * delta = ray(t) - s
+            let f = ray.at(t) - s;
+            let nd = -d;
+            let det = su.dot(&Vec3::cross(&sv, &nd));
+            if det.abs() < 1e-12 {
+                return None;
+            }
+            let du = f.dot(&Vec3::cross(&sv, &nd)) / det;
+            let dv = su.dot(&Vec3::cross(&f, &nd)) / det;
+            let dt = su.dot(&Vec3::cross(&sv, &f)) / det;
+            u = (u + du).clamp(0.0, 1.0);
+            v = (v + dv).clamp(0.0, 1.0);
+            t += dt;
+            if f.length() < 1e-5 * scale.max(1.0) * t.abs().max(1.0) && du.abs() < 1e-5 && dv.abs() < 1e-5 {
+                break;
+            }
+        }
+        let miss = (ray.at(t) - eval(p, u, v).0).length();
+        (miss <= 1e-3 * scale * t.abs().max(1.0)).then_some((t, u, v))
+    }
+}
+
+impl Hittable for BezierPatches {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let mut closest = None;
+        self.bvh.traverse(ray, t_min, t_max, |tri, t_max| {
+            let [a, b, c] = self.triangles[tri];
+            let (a, b, c) = (a as usize, b as usize, c as usize);
+            let (t, u, v) = intersect_triangle([&self.positions[a], &self.positions[b], &self.positions[c]], ray, t_min, t_max)?;
+            let (pa, pb, pc) = (self.params[a], self.params[b], self.params[c]);
+            let guess = [
+                pa[0] * (1.0 - u - v) + pb[0] * u + pc[0] * v,
+                pa[1] * (1.0 - u - v) + pb[1] * u + pc[1] * v,
+            ];
+            let patch = self.owners[tri] as usize;
+            // The exact surface can sit behind the start of a ray leaving it, skip those
+            let (t, u, v) = match self.refine(patch, ray, guess, t) {
+                Some((t, u, v)) if t > t_min && t < t_max => (t, u, v),
+                Some(_) => return None,
+                None => (t, guess[0], guess[1]),
+            };
+            closest = Some((patch, t, u, v));
+            Some(t)
+        });
+        let (patch, t, u, v) = match closest {
+            Some(hit) => hit,
+            None => return false,
+        };
+        let p = &self.patches[patch];
+        let (_, mut su, mut sv) = eval(p, u, v);
+        // Collapsed edges like the teapot's lid have no cross product, step in toward the middle
+        let scale = su.length_squared() + sv.length_squared();
+        if Vec3::cross(&su, &sv).length_squared() < 1e-12 * scale * scale {
+            let (_, a, b) = eval(p, 0.5 + (u - 0.5) * 0.999, 0.5 + (v - 0.5) * 0.999);
+            su = a;
+            sv = b;
+        }
+        let normal = Vec3::cross(&su, &sv).unit();
+        rec.t = t;
+        rec.p = ray.at(t);
+        rec.mat = self.material.as_ref();
+        rec.u = u;
+        rec.v = v;
+        rec.color = Color::ONE;
+        rec.set_face_normal(ray, &normal);
+        true
+    }
+
+    fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
+        // Bézier patches stay inside the hull of their control points
+        let mut b = AaBb::empty();
+        for p in self.patches.iter().flatten() {
+            b.grow(p);
+        }
+        (!self.patches.is_empty()).then_some(b)
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        self.material = mat;
+    }
+
+    fn name(&self) -> &'static str {
+        "BezierPatches"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        Ok(ObjectDesc::Patches {
+            material: Some(writer.material(self.material.as_ref())?),
+            patches: self.patches.clone(),
+        })
+    }
+}
+
+/// Read the `.bpt` format of the classic teapot files: the patch count, then each patch as its
+/// degrees `3 3` followed by 16 control points, one `x y z` per line.
+pub fn read_bpt(source: &str) -> Result<Vec<[Point3; 16]>, String> {
+    let mut words = source.split_whitespace();
+    let mut next = |what: &str| -> Result<f32, String> {
+        let word = words.next().ok_or_else(|| format!("missing {}", what))?;
+        word.parse().map_err(|_| format!("bad number '{}'", word))
+    };
+    let count = next("patch count")?;
+    if count < 0.0 || count.fract() != 0.0 {
+        return Err(format!("bad patch count {}", count));
+    }
+    // The count comes from the file, patches are only stored as they are read
+    let mut patches = vec![];
+    for k in 0..count as usize {
+        let (du, dv) = (next("degree")?, next("degree")?);
+        if du != 3.0 || dv != 3.0 {
+            return Err(format!("patch {}: only bicubic patches are supported, not {} {}", k + 1, du, dv));
+        }
+        let mut points = [Point3::ZERO; 16];
+        for p in points.iter_mut() {
+            *p = Point3::new(next("point")?, next("point")?, next("point")?);
+        }
+        patches.push(points);
+    }
+    Ok(patches)
+}
+
+pub fn load_bpt(path: impl AsRef<Path>, mat: Option<Arc<dyn Material>>) -> Result<BezierPatches, SpriosError> {
+    let path = path.as_ref();
+    let error = |e: &dyn std::fmt::Display| SpriosError::ImportError(format!("{}: {}", path.display(), e));
+    let source = std::fs::read_to_string(path).map_err(|e| error(&e))?;
+    let patches = read_bpt(&source).map_err(|e| error(&e))?;
+    Ok(BezierPatches::new(patches, mat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dome over the unit square, its top at (0.5, 0.5) is 9/16 high
+    fn dome() -> BezierPatches {
+        let height = [0.0, 1.0, 1.0, 0.0];
+        let mut points = [Point3::ZERO; 16];
+        for i in 0..4 {
+            for j in 0..4 {
+                points[4 * i + j] = Point3::new(j as f32 / 3.0, height[i] * height[j], i as f32 / 3.0);
+            }
+        }
+        BezierPatches::new(vec![points], None)
+    }
+
+    #[test]
+    fn test_hit() {
+        let dome = dome();
+        let mut rec = HitRecord::new(dome.material.as_ref());
+        let ray = Ray::new(&Point3::new(0.5, 5.0, 0.5), &Vec3::new(0.0, -1.0, 0.0));
+        assert!(dome.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.p.y - 0.5625).abs() < 1e-5);
+        assert!((rec.u - 0.5).abs() < 1e-4 && (rec.v - 0.5).abs() < 1e-4);
+        // u along x and v along z make the outward side the underside
+        assert!((rec.normal.y - 1.0).abs() < 1e-4 && !rec.front_face);
+        assert_eq!(rec.tangent, Vec3::ZERO);
+
+        // Off centre the hit lands on the surface itself, not the tessellation
+        let ray = Ray::new(&Point3::new(0.2, 5.0, 0.7), &Vec3::new(0.1, -1.0, 0.05));
+        assert!(dome.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        let (s, su, sv) = eval(&dome.patches[0], rec.u, rec.v);
+        assert!((&s - &rec.p).length() < 1e-4);
+        assert!(rec.normal.dot(&su).abs() < 1e-4 && rec.normal.dot(&sv).abs() < 1e-4);
+
+        // Leaving the surface doesn't hit it again
+        let out = Ray::new(&rec.p, &rec.normal);
+        assert!(!dome.hit(&out, 0.001, f32::INFINITY, &mut rec));
+        let bbox = dome.bbox(0.0, 1.0).unwrap();
+        assert!(bbox.max.y >= 0.5625 && bbox.max.x >= 1.0);
+    }
+
+    #[test]
+    fn test_read() {
+        let mut src = String::from("1\n3 3\n");
+        for i in 0..16 {
+            src.push_str(&format!("{} 0 {}\n", i % 4, i / 4));
+        }
+        let patches = read_bpt(&src).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0][5], Point3::new(1.0, 0.0, 1.0));
+        assert_eq!(read_bpt("1\n2 2\n").unwrap_err(), "patch 1: only bicubic patches are supported, not 2 2");
+        assert_eq!(read_bpt("2\n3 3\n0 0 0").unwrap_err(), "missing point");
+        assert_eq!(read_bpt("x").unwrap_err(), "bad number 'x'");
+        assert_eq!(read_bpt("4000000000\n").unwrap_err(), "missing degree");
+    }
+}
@@ -0,0 +1,901 @@
+use crate::bvh::Bvh;
+use crate::description::SettingsDesc;
+use crate::errors::{Diagnostic, SpriosError};
+use crate::hittable::Hittable;
+use crate::instance::{Instance, Transform};
+use crate::lexer::{Lexer, Pos, TokenKind};
+use crate::light::Light;
+use crate::vec::{Color, Mat4, Point3, Vec3};
+use crate::{check_shutter, Camera, Dielectric, Emissive, Lambertian, Material, Metal, PolyMesh, Sphere, TriangleMesh, World};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Number(f32),
+    Integer(i64),
+    Str(String),
+    Bool(bool),
+}
+
+/// A single value or a bracketed list
+#[derive(Clone, Debug)]
+struct Arg {
+    values: Vec<Value>,
+    pos: Pos,
+}
+
+#[derive(Clone, Debug)]
+struct Statement {
+    keyword: String,
+    pos: Pos,
+    args: Vec<Arg>,
+}
+
+fn is_keyword(kind: &TokenKind) -> bool {
+    matches!(kind, TokenKind::Ident(s) if s != "true" && s != "false")
+}
+
+/// pbrt has no statement separator, a statement runs up to the next keyword
+fn parse(file: &str, source: &str) -> (Vec<Statement>, Vec<Diagnostic>) {
+    let (tokens, mut errors) = Lexer::new(file, source).with_integers().tokenize();
+    let mut tokens = tokens.into_iter().filter(|t| t.kind != TokenKind::Newline).peekable();
+    let mut statements: Vec<Statement> = Vec::new();
+    let value = |kind: TokenKind| match kind {
+        TokenKind::Number(n) => Some(Value::Number(n)),
+        TokenKind::Integer(n) => Some(Value::Integer(n)),
+        TokenKind::Str(s) => Some(Value::Str(s)),
+        TokenKind::Ident(s) if s == "true" || s == "false" => Some(Value::Bool(s == "true")),
+        _ => None,
+    };
+    while let Some(t) = tokens.next() {
+        match t.kind {
+            TokenKind::Eof => break,
+            TokenKind::Ident(keyword) if keyword != "true" && keyword != "false" => {
+                statements.push(Statement { keyword, pos: t.pos, args: vec![] })
+            }
+            kind => {
+                let arg = match (kind, statements.last_mut()) {
+                    (_, None) => {
+                        errors.push(Diagnostic::new(file, t.pos, "Expected a statement".to_string()));
+                        continue;
+                    }
+                    (TokenKind::OpenBracket, Some(_)) => {
+                        let mut values = vec![];
+                        loop {
+                            match tokens.next() {
+                                Some(v) if v.kind == TokenKind::CloseBracket => break,
+                                Some(v) if !is_keyword(&v.kind) && v.kind != TokenKind::Eof => match value(v.kind) {
+                                    Some(value) => values.push(value),
+                                    None => errors.push(Diagnostic::new(file, v.pos, "Expected a value".to_string())),
+                                },
+                                _ => {
+                                    errors.push(Diagnostic::new(file, t.pos, "Unclosed '['".to_string()));
+                                    break;
+                                }
+                            }
+                        }
+                        Arg { values, pos: t.pos }
+                    }
+                    (kind, Some(_)) => match value(kind) {
+                        Some(value) => Arg { values: vec![value], pos: t.pos },
+                        None => {
+                            errors.push(Diagnostic::new(file, t.pos, "Expected a value".to_string()));
+                            continue;
+                        }
+                    },
+                };
+                statements.last_mut().unwrap().args.push(arg);
+            }
+        }
+    }
+    (statements, errors)
+}
+
+/// `"type name" value` parameter, `used` finds the ones the importer ignored
+struct Param {
+    ty: String,
+    name: String,
+    values: Vec<Value>,
+    pos: Pos,
+    used: Cell<bool>,
+}
+
+struct ParamSet<'a> {
+    file: &'a str,
+    params: Vec<Param>,
+}
+
+impl<'a> ParamSet<'a> {
+    fn new(file: &'a str, args: &[Arg]) -> Result<ParamSet<'a>, Diagnostic> {
+        let mut params = vec![];
+        for pair in args.chunks(2) {
+            let decl = match pair[0].values.as_slice() {
+                [Value::Str(s)] => s,
+                _ => return Err(Diagnostic::new(file, pair[0].pos, "Expected a parameter declaration".to_string())),
+            };
+            let words: Vec<&str> = decl.split_whitespace().collect();
+            if words.len() != 2 {
+                return Err(Diagnostic::new(file, pair[0].pos, format!("Bad parameter declaration '{}'", decl)));
+            }
+            let values = match pair.get(1) {
+                Some(arg) => arg.values.clone(),
+                None => return Err(Diagnostic::new(file, pair[0].pos, format!("Parameter '{}' has no value", decl))),
+            };
+            let (ty, name) = (words[0].to_string(), words[1].to_string());
+            params.push(Param { ty, name, values, pos: pair[0].pos, used: Cell::new(false) });
+        }
+        Ok(ParamSet { file, params })
+    }
+
+    fn get(&self, name: &str, types: &[&str]) -> Option<&Param> {
+        let param = self.params.iter().find(|p| p.name == name && types.contains(&p.ty.as_str()))?;
+        param.used.set(true);
+        Some(param)
+    }
+
+    fn numbers(&self, name: &str, types: &[&str], multiple: usize) -> Result<Option<Vec<f32>>, Diagnostic> {
+        let param = match self.get(name, types) {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        let numbers: Option<Vec<f32>> = param
+            .values
+            .iter()
+            .map(|v| match v {
+                Value::Number(n) => Some(*n),
+                Value::Integer(n) => Some(*n as f32),
+                _ => None,
+            })
+            .collect();
+        match numbers {
+            Some(n) if !n.is_empty() && n.len() % multiple == 0 => Ok(Some(n)),
+            _ => Err(Diagnostic::new(self.file, param.pos, format!("Bad value for '{} {}'", param.ty, name))),
+        }
+    }
+
+    /// `integer` parameters, kept exact rather than going through f32
+    fn integers(&self, name: &str, multiple: usize) -> Result<Option<Vec<i64>>, Diagnostic> {
+        let param = match self.get(name, &["integer"]) {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        let integers: Option<Vec<i64>> = param
+            .values
+            .iter()
+            .map(|v| match v {
+                Value::Integer(n) => Some(*n),
+                _ => None,
+            })
+            .collect();
+        match integers {
+            Some(n) if !n.is_empty() && n.len() % multiple == 0 => Ok(Some(n)),
+            _ => Err(Diagnostic::new(self.file, param.pos, format!("Bad value for 'integer {}'", name))),
+        }
+    }
+
+    fn float(&self, name: &str, default: f32) -> Result<f32, Diagnostic> {
+        Ok(self.numbers(name, &["float"], 1)?.map_or(default, |n| n[0]))
+    }
+
+    fn int(&self, name: &str, default: i32) -> Result<i32, Diagnostic> {
+        match self.get(name, &["integer"]) {
+            None => Ok(default),
+            Some(p) => match p.values.as_slice() {
+                [Value::Integer(n)] => i32::try_from(*n)
+                    .map_err(|_| Diagnostic::new(self.file, p.pos, format!("'integer {}' out of range", name))),
+                _ => Err(Diagnostic::new(self.file, p.pos, format!("Bad value for 'integer {}'", name))),
+            },
+        }
+    }
+
+    fn vec3s(&self, name: &str, types: &[&str]) -> Result<Vec<Vec3>, Diagnostic> {
+        let n = self.numbers(name, types, 3)?.unwrap_or_default();
+        Ok(n.chunks(3).map(|c| Vec3::new(c[0], c[1], c[2])).collect())
+    }
+
+    fn point(&self, name: &str, default: Point3) -> Result<Point3, Diagnostic> {
+        Ok(self.vec3s(name, &["point3", "point"])?.pop().unwrap_or(default))
+    }
+
+    /// Spectra given as blackbody or sampled values stay unused and are reported
+    fn color(&self, name: &str, default: Color) -> Result<Color, Diagnostic> {
+        Ok(self.vec3s(name, &["rgb", "color"])?.pop().unwrap_or(default))
+    }
+
+    fn string(&self, name: &str) -> Result<Option<String>, Diagnostic> {
+        match self.get(name, &["string"]) {
+            None => Ok(None),
+            Some(p) => match p.values.as_slice() {
+                [Value::Str(s)] => Ok(Some(s.clone())),
+                _ => Err(Diagnostic::new(self.file, p.pos, format!("Bad value for 'string {}'", name))),
+            },
+        }
+    }
+
+    fn bool(&self, name: &str, default: bool) -> Result<bool, Diagnostic> {
+        match self.get(name, &["bool"]) {
+            None => Ok(default),
+            Some(p) => match p.values.as_slice() {
+                [Value::Bool(b)] => Ok(*b),
+                [Value::Str(s)] if s == "true" || s == "false" => Ok(s == "true"),
+                _ => Err(Diagnostic::new(self.file, p.pos, format!("Bad value for 'bool {}'", name))),
+            },
+        }
+    }
+}
+
+/// Attributes saved by `AttributeBegin`
+#[derive(Clone)]
+struct GraphicsState {
+    ctm: Mat4,
+    material: Arc<dyn Material>,
+    /// Radiance and two sidedness of `AreaLightSource`
+    area_light: Option<(Color, bool)>,
+    reverse_orientation: bool,
+}
+
+struct CameraParams {
+    camera_to_world: Mat4,
+    fov: f32,
+    lens_radius: f32,
+    focal_distance: f32,
+    /// pbrt closes at 1 by default, nothing imported moves so it stays shut unless given
+    shutter: (f32, f32),
+    pos: Pos,
+    file: String,
+}
+
+/// Scene converted from pbrt-v3, with the statements and parameters it couldn't honour
+pub struct PbrtScene {
+    pub world: World,
+    pub settings: SettingsDesc,
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Maps the graphics state of pbrt onto World.
+///
+/// pbrt is left handed, world positions are mirrored in x so images are not flipped.
+/// Shapes keep their object space and are placed by an Instance with the full transform.
+struct PbrtImporter {
+    world: World,
+    settings: SettingsDesc,
+    errors: Vec<Diagnostic>,
+    warnings: Vec<Diagnostic>,
+    /// Files being read, to catch include cycles
+    stack: Vec<PathBuf>,
+    state: GraphicsState,
+    attributes: Vec<GraphicsState>,
+    transforms: Vec<Mat4>,
+    coordinate_systems: HashMap<String, Mat4>,
+    camera: Option<CameraParams>,
+    /// Name and shapes of the open `ObjectBegin`
+    object: Option<(String, Vec<Arc<dyn Hittable>>)>,
+    instances: HashMap<String, Arc<dyn Hittable>>,
+    objects: Vec<Arc<dyn Hittable>>,
+}
+
+fn mirror() -> Mat4 {
+    Mat4::scale(&Vec3::new(-1.0, 1.0, 1.0))
+}
+
+fn swaps_handedness(m: &Mat4) -> bool {
+    let m = &m.m;
+    let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    det < 0.0
+}
+
+/// Positional numbers of a statement, brackets are optional
+fn numbers(file: &str, stmt: &Statement, count: usize) -> Result<Vec<f32>, Diagnostic> {
+    let numbers: Vec<f32> = stmt
+        .args
+        .iter()
+        .flat_map(|a| a.values.iter())
+        .filter_map(|v| match v {
+            Value::Number(n) => Some(*n),
+            Value::Integer(n) => Some(*n as f32),
+            _ => None,
+        })
+        .collect();
+    let values = stmt.args.iter().map(|a| a.values.len()).sum::<usize>();
+    if numbers.len() != count || values != count {
+        return Err(Diagnostic::new(file, stmt.pos, format!("{} expects {} numbers", stmt.keyword, count)));
+    }
+    Ok(numbers)
+}
+
+/// Leading string argument, the type of a shape, light or material
+fn name_arg(file: &str, stmt: &Statement) -> Result<String, Diagnostic> {
+    match stmt.args.first().map(|a| a.values.as_slice()) {
+        Some([Value::Str(s)]) => Ok(s.clone()),
+        _ => Err(Diagnostic::new(file, stmt.pos, format!("{} expects a name", stmt.keyword))),
+    }
+}
+
+impl PbrtImporter {
+    fn new() -> PbrtImporter {
+        PbrtImporter {
+            world: World::new(),
+            settings: SettingsDesc::default(),
+            errors: vec![],
+            warnings: vec![],
+            stack: vec![],
+            state: GraphicsState {
+                ctm: Mat4::IDENTITY,
+                material: Arc::new(Lambertian { color: Color::new(0.5, 0.5, 0.5) }),
+                area_light: None,
+                reverse_orientation: false,
+            },
+            attributes: vec![],
+            transforms: vec![],
+            coordinate_systems: HashMap::new(),
+            camera: None,
+            object: None,
+            instances: HashMap::new(),
+            objects: vec![],
+        }
+    }
+
+    fn warn(&mut self, file: &str, pos: Pos, message: String) {
+        self.warnings.push(Diagnostic::new(file, pos, message));
+    }
+
+    fn finish(&mut self, params: ParamSet) {
+        for p in params.params.iter().filter(|p| !p.used.get()) {
+            let message = format!("Ignored parameter '{} {}'", p.ty, p.name);
+            self.warnings.push(Diagnostic::new(params.file, p.pos, message));
+        }
+    }
+
+    fn load(&mut self, file: &str, source: &str) {
+        let (statements, errors) = parse(file, source);
+        self.errors.extend(errors);
+        for stmt in &statements {
+            if let Err(e) = self.statement(file, stmt) {
+                self.errors.push(e);
+            }
+        }
+    }
+
+    fn include(&mut self, file: &str, stmt: &Statement) -> Result<(), Diagnostic> {
+        let name = name_arg(file, stmt)?;
+        let path = Path::new(file).parent().unwrap_or_else(|| Path::new("")).join(&name);
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if self.stack.contains(&canonical) {
+            return Err(Diagnostic::new(file, stmt.pos, format!("Include cycle through '{}'", name)));
+        }
+        let source = std::fs::read_to_string(&path)
+            .map_err(|e| Diagnostic::new(file, stmt.pos, format!("Can't read '{}': {}", path.display(), e)))?;
+        self.stack.push(canonical);
+        self.load(&path.display().to_string(), &source);
+        self.stack.pop();
+        Ok(())
+    }
+
+    fn concat(&mut self, m: Mat4) {
+        self.state.ctm = self.state.ctm * m;
+    }
+
+    fn look_at(file: &str, stmt: &Statement) -> Result<Mat4, Diagnostic> {
+        let n = numbers(file, stmt, 9)?;
+        let pos = Point3::new(n[0], n[1], n[2]);
+        let dir = (Point3::new(n[3], n[4], n[5]) - &pos).unit();
+        let right = Vec3::cross(&Vec3::new(n[6], n[7], n[8]).unit(), &dir);
+        if right.length() == 0.0 {
+            return Err(Diagnostic::new(file, stmt.pos, "LookAt: up and view direction are parallel".to_string()));
+        }
+        let right = right.unit();
+        let up = Vec3::cross(&dir, &right);
+        let camera_to_world = Mat4::new([
+            [right.x, up.x, dir.x, pos.x],
+            [right.y, up.y, dir.y, pos.y],
+            [right.z, up.z, dir.z, pos.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Ok(camera_to_world.inverse().unwrap_or(Mat4::IDENTITY))
+    }
+
+    /// Column major values as pbrt writes them
+    fn matrix(file: &str, stmt: &Statement) -> Result<Mat4, Diagnostic> {
+        let n = numbers(file, stmt, 16)?;
+        let mut m = Mat4::IDENTITY;
+        for (i, v) in n.iter().enumerate() {
+            m.m[i % 4][i / 4] = *v;
+        }
+        Ok(m)
+    }
+
+    fn material(&mut self, file: &str, kind: &str, params: &ParamSet, pos: Pos) -> Result<Arc<dyn Material>, Diagnostic> {
+        let mat: Arc<dyn Material> = match kind {
+            "matte" => Arc::new(Lambertian { color: params.color("Kd", Color::new(0.5, 0.5, 0.5))? }),
+            "metal" => {
+                // Reflectance at normal incidence from the complex index of refraction
+                let eta = params.color("eta", Color::ZERO)?;
+                let k = params.color("k", Color::ZERO)?;
+                let f0 = |n: f32, k: f32| ((n - 1.0).powi(2) + k * k) / ((n + 1.0).powi(2) + k * k);
+                let color = if eta == Color::ZERO && k == Color::ZERO {
+                    Color::new(0.955, 0.638, 0.538)
+                } else {
+                    Color::new(f0(eta.x, k.x), f0(eta.y, k.y), f0(eta.z, k.z))
+                };
+                let roughness = params.float("roughness", 0.01)?;
+                let u = params.float("uroughness", roughness)?;
+                let v = params.float("vroughness", roughness)?;
+                params.bool("remaproughness", true)?;
+                Arc::new(Metal { color, fuzz: ((u + v) / 2.0).min(1.0) })
+            }
+            "mirror" => Arc::new(Metal { color: params.color("Kr", Color::new(0.9, 0.9, 0.9))?, fuzz: 0.0 }),
+            "glass" => {
+                let ior = params.float("index", 1.5)?;
+                Arc::new(Dielectric { ior: params.float("eta", ior)? })
+            }
+            _ => {
+                self.warn(file, pos, format!("Material '{}' not supported, using matte", kind));
+                Arc::new(Lambertian { color: params.color("Kd", Color::new(0.5, 0.5, 0.5))? })
+            }
+        };
+        Ok(self.world.add_material(None, mat))
+    }
+
+    /// Place a shape with the current transform, inside `ObjectBegin` the mirror is left to the instance
+    fn add_shape(&mut self, file: &str, pos: Pos, shape: Arc<dyn Hittable>) -> Result<(), Diagnostic> {
+        let matrix = match self.object {
+            Some(_) => self.state.ctm,
+            None => mirror() * self.state.ctm,
+        };
+        let transform = Transform::new(matrix)
+            .ok_or_else(|| Diagnostic::new(file, pos, "Shape transform is not invertible".to_string()))?;
+        let instance: Arc<dyn Hittable> = Arc::new(Instance::new(shape, transform));
+        match &mut self.object {
+            Some((_, shapes)) => shapes.push(instance),
+            None => self.objects.push(instance),
+        }
+        Ok(())
+    }
+
+    /// Area lights emit on the side of the surface normal, which pbrt flips for
+    /// `ReverseOrientation` and, on spheres, for transforms that swap handedness
+    fn shape_material(&mut self, flips_with_handedness: bool) -> Arc<dyn Material> {
+        match self.state.area_light.clone() {
+            Some((color, two_sided)) => {
+                let swaps = flips_with_handedness && swaps_handedness(&self.state.ctm);
+                let reverse = self.state.reverse_orientation != swaps;
+                self.world.add_material(None, Arc::new(Emissive { color, two_sided, reverse }))
+            }
+            None => Arc::clone(&self.state.material),
+        }
+    }
+
+    fn shape(&mut self, file: &str, stmt: &Statement, kind: &str, params: &ParamSet) -> Result<(), Diagnostic> {
+        let shape: Arc<dyn Hittable> = match kind {
+            "sphere" => {
+                let radius = params.float("radius", 1.0)?;
+                Arc::new(Sphere::new(Point3::ZERO, radius, Some(self.shape_material(true))))
+            }
+            "trianglemesh" => {
+                let positions = params.vec3s("P", &["point3", "point"])?;
+                let indices = match params.integers("indices", 3)? {
+                    Some(i) => i,
+                    None if positions.len() == 3 => vec![0, 1, 2],
+                    None => return Err(Diagnostic::new(file, stmt.pos, "trianglemesh: missing 'indices'".to_string())),
+                };
+                if indices.iter().any(|i| *i < 0 || *i as usize >= positions.len()) {
+                    return Err(Diagnostic::new(file, stmt.pos, "trianglemesh: index out of range".to_string()));
+                }
+                let triangles = indices.chunks(3).map(|t| [t[0] as u32, t[1] as u32, t[2] as u32]).collect();
+                let mut normals = params.vec3s("N", &["normal3", "normal"])?;
+                if normals.len() != positions.len() {
+                    normals.clear();
+                }
+                let mut uvs = vec![];
+                for name in &["uv", "st"] {
+                    if let Some(n) = params.numbers(name, &["point2", "float"], 2)? {
+                        uvs = n.chunks(2).map(|c| [c[0], c[1]]).collect();
+                    }
+                }
+                if uvs.len() != positions.len() {
+                    uvs.clear();
+                }
+                let mat = self.shape_material(false);
+                Arc::new(TriangleMesh::new(positions, normals, triangles, Some(mat)).with_uvs(uvs))
+            }
+            "loopsubdiv" => {
+                let positions = params.vec3s("P", &["point3", "point"])?;
+                let indices = params.integers("indices", 3)?.unwrap_or_default();
+                if indices.iter().any(|i| *i < 0 || *i as usize >= positions.len()) {
+                    return Err(Diagnostic::new(file, stmt.pos, "loopsubdiv: index out of range".to_string()));
+                }
+                let faces = indices.chunks(3).map(|t| vec![t[0] as u32, t[1] as u32, t[2] as u32]).collect();
+                let levels = params.int("levels", 3)?.clamp(0, 8) as u32;
+                let mat = self.shape_material(false);
+                Arc::new(PolyMesh::new(positions, faces).subdivide(levels, Some(mat)))
+            }
+            "plymesh" => {
+                let name = params
+                    .string("filename")?
+                    .ok_or_else(|| Diagnostic::new(file, stmt.pos, "plymesh: missing 'filename'".to_string()))?;
+                let path = Path::new(file).parent().unwrap_or_else(|| Path::new("")).join(name);
+                let mat = self.shape_material(false);
+                let mesh = crate::ply::load_ply(path, Some(mat)).map_err(|e| Diagnostic::new(file, stmt.pos, e.to_string()))?;
+                Arc::new(mesh)
+            }
+            _ => {
+                self.warn(file, stmt.pos, format!("Shape '{}' not supported", kind));
+                return Ok(());
+            }
+        };
+        self.add_shape(file, stmt.pos, shape)
+    }
+
+    fn light(&mut self, file: &str, stmt: &Statement, kind: &str, params: &ParamSet) -> Result<(), Diagnostic> {
+        let m = mirror() * self.state.ctm;
+        let scale = params.color("scale", Color::ONE)?;
+        match kind {
+            "point" | "spot" => {
+                if kind == "spot" {
+                    self.warn(file, stmt.pos, "Spot light imported as a point light".to_string());
+                    params.point("to", Point3::ZERO)?;
+                    params.float("coneangle", 30.0)?;
+                    params.float("conedelta", 5.0)?;
+                }
+                let position = m.transform_point(&params.point("from", Point3::ZERO)?);
+                let intensity = params.color("I", Color::ONE)? * &scale;
+                self.world.lights.push(Light::Point { position, intensity });
+            }
+            "distant" => {
+                let from = params.point("from", Point3::ZERO)?;
+                let to = params.point("to", Point3::new(0.0, 0.0, 1.0))?;
+                let direction = m.transform_vector(&(to - from));
+                let intensity = params.color("L", Color::ONE)? * &scale;
+                self.world.lights.push(Light::Directional { direction, intensity });
+            }
+            "infinite" => {
+                params.int("samples", 1)?;
+                self.world.environment = Some(params.color("L", Color::ONE)? * &scale);
+            }
+            _ => self.warn(file, stmt.pos, format!("Light '{}' not supported", kind)),
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, file: &str, stmt: &Statement) -> Result<(), Diagnostic> {
+        match stmt.keyword.as_str() {
+            "Include" | "Import" => return self.include(file, stmt),
+            "Identity" => self.state.ctm = Mat4::IDENTITY,
+            "Translate" => {
+                let n = numbers(file, stmt, 3)?;
+                self.concat(Mat4::translate(&Vec3::new(n[0], n[1], n[2])));
+            }
+            "Scale" => {
+                let n = numbers(file, stmt, 3)?;
+                self.concat(Mat4::scale(&Vec3::new(n[0], n[1], n[2])));
+            }
+            "Rotate" => {
+                let n = numbers(file, stmt, 4)?;
+                self.concat(Mat4::rotate(n[0], &Vec3::new(n[1], n[2], n[3])));
+            }
+            "LookAt" => {
+                let m = Self::look_at(file, stmt)?;
+                self.concat(m);
+            }
+            "Transform" => self.state.ctm = Self::matrix(file, stmt)?,
+            "ConcatTransform" => {
+                let m = Self::matrix(file, stmt)?;
+                self.concat(m);
+            }
+            "CoordinateSystem" => {
+                let name = name_arg(file, stmt)?;
+                self.coordinate_systems.insert(name, self.state.ctm);
+            }
+            "CoordSysTransform" => {
+                let name = name_arg(file, stmt)?;
+                match self.coordinate_systems.get(&name) {
+                    Some(m) => self.state.ctm = *m,
+                    None => self.warn(file, stmt.pos, format!("Unknown coordinate system '{}'", name)),
+                }
+            }
+            "ReverseOrientation" => self.state.reverse_orientation = !self.state.reverse_orientation,
+            "Camera" => {
+                let kind = name_arg(file, stmt)?;
+                let params = ParamSet::new(file, &stmt.args[1..])?;
+                let camera_to_world = self
+                    .state
+                    .ctm
+                    .inverse()
+                    .ok_or_else(|| Diagnostic::new(file, stmt.pos, "Camera transform is not invertible".to_string()))?;
+                self.coordinate_systems.insert("camera".to_string(), camera_to_world);
+                if kind != "perspective" {
+                    self.warn(file, stmt.pos, format!("Camera '{}' not supported, using perspective", kind));
+                }
+                self.camera = Some(CameraParams {
+                    camera_to_world,
+                    fov: params.float("fov", 90.0)?,
+                    lens_radius: params.float("lensradius", 0.0)?,
+                    focal_distance: params.float("focaldistance", 1e6)?,
+                    shutter: (params.float("shutteropen", 0.0)?, params.float("shutterclose", 0.0)?),
+                    pos: stmt.pos,
+                    file: file.to_string(),
+                });
+                self.finish(params);
+            }
+            "Film" => {
+                name_arg(file, stmt)?;
+                let params = ParamSet::new(file, &stmt.args[1..])?;
+                self.settings.width = Some(params.int("xresolution", 1280)?.max(1) as u32);
+                self.settings.height = Some(params.int("yresolution", 720)?.max(1) as u32);
+                // The output path is chosen on the command line
+                params.string("filename")?;
+                self.finish(params);
+            }
+            "Sampler" => {
+                name_arg(file, stmt)?;
+                let params = ParamSet::new(file, &stmt.args[1..])?;
+                let n = params.int("pixelsamples", 16)?.max(1) as f32;
+                self.settings.samples = Some((n.sqrt().round() as u32).max(1));
+                self.finish(params);
+            }
+            "WorldBegin" => {
+                self.state.ctm = Mat4::IDENTITY;
+                self.coordinate_systems.insert("world".to_string(), Mat4::IDENTITY);
+            }
+            "WorldEnd" => {}
+            "AttributeBegin" => self.attributes.push(self.state.clone()),
+            "AttributeEnd" => match self.attributes.pop() {
+                Some(state) => self.state = state,
+                None => self.warn(file, stmt.pos, "Unmatched AttributeEnd".to_string()),
+            },
+            "TransformBegin" => self.transforms.push(self.state.ctm),
+            "TransformEnd" => match self.transforms.pop() {
+                Some(ctm) => self.state.ctm = ctm,
+                None => self.warn(file, stmt.pos, "Unmatched TransformEnd".to_string()),
+            },
+            "Material" => {
+                let kind = name_arg(file, stmt)?;
+                let params = ParamSet::new(file, &stmt.args[1..])?;
+                self.state.material = self.material(file, &kind, &params, stmt.pos)?;
+                self.finish(params);
+            }
+            "MakeNamedMaterial" => {
+                let name = name_arg(file, stmt)?;
+                let params = ParamSet::new(file, &stmt.args[1..])?;
+                let kind = params.string("type")?.unwrap_or_default();
+                let mat = self.material(file, &kind, &params, stmt.pos)?;
+                self.world.add_material(Some(&name), mat);
+                self.finish(params);
+            }
+            "NamedMaterial" => {
+                let name = name_arg(file, stmt)?;
+                match self.world.find_material(&name) {
+                    Some(mat) => self.state.material = mat,
+                    None => return Err(Diagnostic::new(file, stmt.pos, format!("Unknown material '{}'", name))),
+                }
+            }
+            "Shape" => {
+                let kind = name_arg(file, stmt)?;
+                let params = ParamSet::new(file, &stmt.args[1..])?;
+                self.shape(file, stmt, &kind, &params)?;
+                self.finish(params);
+            }
+            "LightSource" => {
+                let kind = name_arg(file, stmt)?;
+                let params = ParamSet::new(file, &stmt.args[1..])?;
+                self.light(file, stmt, &kind, &params)?;
+                self.finish(params);
+            }
+            "AreaLightSource" => {
+                let kind = name_arg(file, stmt)?;
+                let params = ParamSet::new(file, &stmt.args[1..])?;
+                if kind == "diffuse" {
+                    let color = params.color("L", Color::ONE)? * &params.color("scale", Color::ONE)?;
+                    self.state.area_light = Some((color, params.bool("twosided", false)?));
+                    params.int("samples", 1)?;
+                } else {
+                    self.warn(file, stmt.pos, format!("Area light '{}' not supported", kind));
+                }
+                self.finish(params);
+            }
+            "ObjectBegin" => {
+                let name = name_arg(file, stmt)?;
+                if self.object.is_some() {
+                    return Err(Diagnostic::new(file, stmt.pos, "ObjectBegin inside an object".to_string()));
+                }
+                self.attributes.push(self.state.clone());
+                self.object = Some((name, vec![]));
+            }
+            "ObjectEnd" => {
+                let (name, shapes) = self
+                    .object
+                    .take()
+                    .ok_or_else(|| Diagnostic::new(file, stmt.pos, "ObjectEnd without ObjectBegin".to_string()))?;
+                self.instances.insert(name, Arc::new(Bvh::new(shapes)));
+                if let Some(state) = self.attributes.pop() {
+                    self.state = state;
+                }
+            }
+            "ObjectInstance" => {
+                let name = name_arg(file, stmt)?;
+                let object = self.instances.get(&name).cloned();
+                let object = object.ok_or_else(|| Diagnostic::new(file, stmt.pos, format!("Unknown object '{}'", name)))?;
+                let transform = Transform::new(mirror() * self.state.ctm)
+                    .ok_or_else(|| Diagnostic::new(file, stmt.pos, "Instance transform is not invertible".to_string()))?;
+                self.objects.push(Arc::new(Instance::new(object, transform)));
+            }
+            "Texture" | "MakeNamedMedium" | "MediumInterface" | "Integrator" | "Accelerator" | "PixelFilter"
+            | "ActiveTransform" | "TransformTimes" | "Option" | "ColorSpace" | "Attribute" => {
+                self.warn(file, stmt.pos, format!("{} ignored", stmt.keyword))
+            }
+            k => self.warn(file, stmt.pos, format!("Unknown statement '{}' ignored", k)),
+        }
+        Ok(())
+    }
+
+    /// The field of view of pbrt spans the shorter side of the image
+    fn build_camera(&mut self) {
+        let (width, height) = (self.settings.width.unwrap_or(1280), self.settings.height.unwrap_or(720));
+        let aspect = width as f32 / height as f32;
+        let params = self.camera.take().unwrap_or(CameraParams {
+            camera_to_world: Mat4::IDENTITY,
+            fov: 90.0,
+            lens_radius: 0.0,
+            focal_distance: 1e6,
+            shutter: (0.0, 0.0),
+            pos: Pos { line: 1, column: 1 },
+            file: String::new(),
+        });
+        if !(0.0..180.0).contains(&params.fov) {
+            let message = format!("Camera fov {} out of range", params.fov);
+            self.errors.push(Diagnostic::new(&params.file, params.pos, message));
+            return;
+        }
+        if let Err(e) = check_shutter(params.shutter.0, params.shutter.1) {
+            self.errors.push(Diagnostic::new(&params.file, params.pos, format!("Camera {}", e)));
+            return;
+        }
+        let vfov = if aspect >= 1.0 {
+            params.fov
+        } else {
+            2.0 * ((params.fov.to_radians() / 2.0).tan() / aspect).atan().to_degrees()
+        };
+        let m = mirror() * params.camera_to_world;
+        let from = m.transform_point(&Point3::ZERO);
+        let at = m.transform_point(&Point3::new(0.0, 0.0, 1.0));
+        let up = m.transform_vector(&Vec3::new(0.0, 1.0, 0.0));
+        let focus = if params.lens_radius > 0.0 { params.focal_distance } else { 1.0 };
+        self.world.camera = Camera::new(from, at, up, vfov, aspect, 2.0 * params.lens_radius, focus)
+            .with_shutter(params.shutter.0, params.shutter.1);
+    }
+}
+
+/// Import a pbrt-v3 scene from source, `file` locates includes and PLY meshes
+pub fn load_pbrt_str(file: &str, source: &str) -> Result<PbrtScene, SpriosError> {
+    let mut importer = PbrtImporter::new();
+    importer.stack.push(Path::new(file).canonicalize().unwrap_or_else(|_| PathBuf::from(file)));
+    importer.load(file, source);
+    if let Some((name, _)) = &importer.object {
+        let message = format!("Object '{}' without ObjectEnd", name);
+        importer.errors.push(Diagnostic::new(file, Pos { line: 1, column: 1 }, message));
+    }
+    importer.build_camera();
+    let mut errors = importer.errors;
+    if !errors.is_empty() {
+        errors.sort_by(|a, b| (&a.file, a.pos.line, a.pos.column).cmp(&(&b.file, b.pos.line, b.pos.column)));
+        return Err(SpriosError::SceneErrors(errors));
+    }
+    let mut world = importer.world;
+    for object in importer.objects {
+        world.add(object);
+    }
+    Ok(PbrtScene { world, settings: importer.settings, warnings: importer.warnings })
+}
+
+pub fn load_pbrt(path: impl AsRef<Path>) -> Result<PbrtScene, SpriosError> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| SpriosError::WorldParseError(format!("{}: {}", path.display(), e)))?;
+    load_pbrt_str(&path.display().to_string(), &source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HitRecord, Ray};
+
+    const SCENE: &str = r#"
+LookAt 0 0 0  0 0 1  0 1 0
+Camera "perspective" "float fov" [90]
+Film "image" "integer xresolution" [100] "integer yresolution" [100] "string filename" "out.exr"
+Sampler "halton" "integer pixelsamples" 16
+Integrator "path"
+WorldBegin
+LightSource "infinite" "rgb L" [0.1 0.1 0.1]
+LightSource "point" "point from" [1 2 3] "rgb I" [5 5 5]
+Texture "checks" "spectrum" "checkerboard"
+MakeNamedMaterial "gold" "string type" "metal" "float roughness" 0.2
+AttributeBegin
+  Material "glass" "float eta" 1.33 "float bumpiness" 2
+  Translate 2 0 5
+  Shape "sphere" "float radius" 1
+AttributeEnd
+AttributeBegin
+  AreaLightSource "diffuse" "rgb L" [4 4 4]
+  NamedMaterial "gold"
+  Shape "trianglemesh" "point P" [-1 0 9  1 0 9  0 1 9] "integer indices" [0 2 1]
+AttributeEnd
+Shape "disk"
+WorldEnd
+"#;
+
+    fn hits(world: &World, s: f32, t: f32) -> bool {
+        let ray = world.camera.get_ray(s, t, &mut rand::thread_rng());
+        let mat: Arc<dyn Material> = Arc::new(Lambertian { color: Color::ONE });
+        world.hit(&ray, 0.001, f32::INFINITY, &mut HitRecord::new(mat.as_ref()))
+    }
+
+    #[test]
+    fn test_scene() {
+        let scene = load_pbrt_str("test.pbrt", SCENE).unwrap();
+        assert_eq!((scene.settings.width, scene.settings.height, scene.settings.samples), (Some(100), Some(100), Some(4)));
+        let world = &scene.world;
+        assert_eq!(world.objects.len(), 2);
+        assert_eq!(world.environment, Some(Color::new(0.1, 0.1, 0.1)));
+        // Mirrored in x
+        assert_eq!(world.lights, vec![Light::Point { position: Point3::new(-1.0, 2.0, 3.0), intensity: Color::new(5.0, 5.0, 5.0) }]);
+        assert!(world.find_material("gold").is_some());
+
+        let messages: Vec<&str> = scene.warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Integrator ignored",
+                "Texture ignored",
+                "Ignored parameter 'float bumpiness'",
+                "Shape 'disk' not supported"
+            ]
+        );
+        assert_eq!(scene.warnings[0].pos.line, 6);
+    }
+
+    #[test]
+    fn test_orientation() {
+        let world = load_pbrt_str("test.pbrt", SCENE).unwrap().world;
+        // +x of pbrt is on the right of the image, the sphere sits at 2/5 of the half width
+        assert!(hits(&world, 0.7, 0.5));
+        assert!(!hits(&world, 0.3, 0.5));
+        // The area light triangle is above the centre
+        assert!(hits(&world, 0.5, 0.52));
+        assert!(!hits(&world, 0.5, 0.48));
+
+        let mut rec = HitRecord::new(world.materials[0].as_ref());
+        let ray = Ray::new(&Point3::ZERO, &Vec3::new(0.0, 0.05, 1.0));
+        assert!(world.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert_eq!(rec.mat.emitted(&rec), Color::new(4.0, 4.0, 4.0));
+    }
+
+    #[test]
+    fn test_errors() {
+        let errors = match load_pbrt_str("test.pbrt", "Translate 1 2\nShape \"sphere\" \"float\" 1\nNamedMaterial \"x\"\n[ 1") {
+            Err(SpriosError::SceneErrors(errors)) => errors,
+            _ => panic!("expected errors"),
+        };
+        let lines: Vec<usize> = errors.iter().map(|e| e.pos.line).collect();
+        assert_eq!(lines, vec![1, 2, 3, 4]);
+        assert_eq!(errors[0].message, "Translate expects 3 numbers");
+    }
+
+    #[test]
+    fn test_integers() {
+        // Above 2^24, an f32 would round this to 16777216
+        let scene = load_pbrt_str("test.pbrt", "Film \"image\" \"integer xresolution\" [16777217]\nWorldBegin\nWorldEnd\n").unwrap();
+        assert_eq!(scene.settings.width, Some(16_777_217));
+        let src = "WorldBegin\nShape \"trianglemesh\" \"point P\" [0 0 0 1 0 0 0 1 0] \"integer indices\" [0 1 2.0]\nWorldEnd\n";
+        let errors = match load_pbrt_str("test.pbrt", src) {
+            Err(SpriosError::SceneErrors(errors)) => errors,
+            _ => panic!("expected errors"),
+        };
+        assert_eq!(errors[0].message, "Bad value for 'integer indices'");
+    }
+}
@@ -0,0 +1,218 @@
+use crate::bbox::AaBb;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec::{Color, Point3, Vec3};
+use crate::Lambertian;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// Two unit vectors spanning the plane of unit normal `n`
+fn tangents(n: &Vec3) -> (Vec3, Vec3) {
+    let a = if n.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+    let t = Vec3::cross(n, &a).unit();
+    let b = Vec3::cross(n, &t);
+    (t, b)
+}
+
+/// Distance along the ray to the plane through `point`, None when parallel or out of range
+fn hit_plane(point: &Point3, normal: &Vec3, ray: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
+    let denom = normal.dot(&ray.direction);
+    if denom.abs() < 1e-8 {
+        return None;
+    }
+    let t = normal.dot(&(point - &ray.origin)) / denom;
+    if t <= t_min || t >= t_max {
+        return None;
+    }
+    Some(t)
+}
+
+/// Infinite plane, has no bounds so acceleration structures test it on every ray.
+/// Texture coordinates are distances from `point` along two tangents.
+pub struct Plane {
+    pub point: Point3,
+    pub normal: Vec3,
+    pub material: Arc<dyn Material>,
+    tangents: (Vec3, Vec3),
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vec3, mat: Option<Arc<dyn Material>>) -> Plane {
+        let normal = normal.unit();
+        Plane {
+            tangents: tangents(&normal),
+            point,
+            normal,
+            material: mat.unwrap_or_else(|| Arc::new(Lambertian { color: Color::new(0.8, 0.8, 0.8) })),
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let t = match hit_plane(&self.point, &self.normal, ray, t_min, t_max) {
+            Some(t) => t,
+            None => return false,
+        };
+        rec.mat = self.material.as_ref();
+        rec.t = t;
+        rec.p = ray.at(t);
+        let rel = &rec.p - &self.point;
+        rec.u = rel.dot(&self.tangents.0);
+        rec.v = rel.dot(&self.tangents.1);
+        rec.color = Color::ONE;
+        rec.set_face_normal(ray, &self.normal);
+        true
+    }
+
+    fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
+        None
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        self.material = mat;
+    }
+
+    #[inline]
+    fn name(&self) -> &'static str {
+        "Plane"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        Ok(ObjectDesc::Plane {
+            point: self.point.clone(),
+            normal: self.normal.clone(),
+            material: Some(writer.material(self.material.as_ref())?),
+        })
+    }
+}
+
+/// Flat disk, or a ring when `inner_radius` is above zero.
+/// `u` goes around the centre, `v` from the outer edge to the inner one.
+pub struct Disk {
+    pub center: Point3,
+    pub normal: Vec3,
+    pub radius: f32,
+    pub inner_radius: f32,
+    pub material: Arc<dyn Material>,
+    tangents: (Vec3, Vec3),
+}
+
+impl Disk {
+    pub fn new(center: Point3, normal: Vec3, radius: f32, inner_radius: f32, mat: Option<Arc<dyn Material>>) -> Disk {
+        let normal = normal.unit();
+        Disk {
+            tangents: tangents(&normal),
+            center,
+            normal,
+            radius,
+            inner_radius,
+            material: mat.unwrap_or_else(|| Arc::new(Lambertian { color: Color::new(0.8, 0.8, 0.8) })),
+        }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let t = match hit_plane(&self.center, &self.normal, ray, t_min, t_max) {
+            Some(t) => t,
+            None => return false,
+        };
+        let p = ray.at(t);
+        let rel = &p - &self.center;
+        let r2 = rel.length_squared();
+        if r2 > self.radius * self.radius || r2 < self.inner_radius * self.inner_radius {
+            return false;
+        }
+        let phi = rel.dot(&self.tangents.1).atan2(rel.dot(&self.tangents.0));
+        rec.mat = self.material.as_ref();
+        rec.t = t;
+        rec.p = p;
+        rec.u = if phi < 0.0 { phi + 2.0 * PI } else { phi } / (2.0 * PI);
+        rec.v = (self.radius - r2.sqrt()) / (self.radius - self.inner_radius);
+        rec.color = Color::ONE;
+        rec.set_face_normal(ray, &self.normal);
+        true
+    }
+
+    fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
+        // Extent of the disk along each axis, padded for axis aligned disks
+        let n = &self.normal;
+        let e = |c: f32| self.radius * (1.0 - c * c).max(0.0).sqrt() + 1e-4;
+        let extent = Vec3::new(e(n.x), e(n.y), e(n.z));
+        Some(AaBb::new(&self.center - &extent, &self.center + &extent))
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        self.material = mat;
+    }
+
+    #[inline]
+    fn name(&self) -> &'static str {
+        "Disk"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        Ok(ObjectDesc::Disk {
+            center: self.center.clone(),
+            normal: self.normal.clone(),
+            radius: self.radius,
+            inner_radius: self.inner_radius,
+            material: Some(writer.material(self.material.as_ref())?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::World;
+
+    #[test]
+    fn test_plane() {
+        let plane = Plane::new(Point3::ZERO, Vec3::new(0.0, 2.0, 0.0), None);
+        assert!(plane.bbox(0.0, 1.0).is_none());
+        let mat = Lambertian { color: Color::ONE };
+        let mut rec = HitRecord::new(&mat);
+        let ray = Ray::new(&Point3::new(1000.0, 1.0, -3000.0), &Vec3::new(0.0, -1.0, 0.0));
+        assert!(plane.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert_eq!((rec.t, rec.normal.clone(), rec.front_face), (1.0, Vec3::new(0.0, 1.0, 0.0), true));
+        assert!((rec.u.abs() - 3000.0).abs() < 1e-2 || (rec.v.abs() - 3000.0).abs() < 1e-2);
+        let parallel = Ray::new(&Point3::new(0.0, 1.0, 0.0), &Vec3::new(1.0, 0.0, 0.0));
+        assert!(!plane.hit(&parallel, 0.001, f32::INFINITY, &mut rec));
+
+        // The world is unbounded but still finds the plane next to bounded objects
+        let mut world = World::new();
+        world.add(Arc::new(crate::Sphere::new((0.0, 5.0, 0.0), 1.0, None)));
+        world.add(Arc::new(plane));
+        assert!(world.bbox(0.0, 1.0).is_none());
+        let mut rec = HitRecord::new(&mat);
+        assert!(world.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert_eq!(rec.t, 1.0);
+    }
+
+    #[test]
+    fn test_disk() {
+        let disk = Disk::new(Point3::ZERO, Vec3::new(0.0, 0.0, 1.0), 2.0, 1.0, None);
+        let mat = Lambertian { color: Color::ONE };
+        let mut rec = HitRecord::new(&mat);
+        let at = |x: f32, y: f32| Ray::new(&Point3::new(x, y, 1.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(disk.hit(&at(1.5, 0.0), 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.v - 0.5).abs() < 1e-6);
+        assert!(!disk.hit(&at(0.5, 0.0), 0.001, f32::INFINITY, &mut rec));
+        assert!(!disk.hit(&at(2.5, 0.0), 0.001, f32::INFINITY, &mut rec));
+        let bbox = disk.bbox(0.0, 1.0).unwrap();
+        assert!((bbox.max.x - 2.0).abs() < 1e-3 && bbox.max.z < 1e-3);
+    }
+}
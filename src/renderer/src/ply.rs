@@ -0,0 +1,470 @@
+use crate::errors::SpriosError;
+use crate::material::Material;
+use crate::mesh::TriangleMesh;
+use crate::subdiv::PolyMesh;
+use crate::vec::{Color, Point3, Vec3};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Encoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    /// Integer colours are scaled to 0..1
+    fn color_scale(self) -> f32 {
+        match self {
+            Scalar::U8 | Scalar::I8 => 1.0 / 255.0,
+            Scalar::U16 | Scalar::I16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+/// Where a vertex property goes
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Slot {
+    Position(usize),
+    Normal(usize),
+    Color(usize),
+    Uv(usize),
+    Skip,
+}
+
+#[derive(Clone, Debug)]
+struct Property {
+    name: String,
+    ty: Scalar,
+    /// Type of the count of list properties
+    list: Option<Scalar>,
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+fn ply_error(message: impl Into<String>) -> SpriosError {
+    SpriosError::ImportError(message.into())
+}
+
+/// Reads values from the body without allocating per value
+struct Body<R> {
+    reader: R,
+    encoding: Encoding,
+    token: Vec<u8>,
+}
+
+impl<R: BufRead> Body<R> {
+    fn next_token(&mut self) -> Result<(), SpriosError> {
+        self.token.clear();
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            let mut used = 0;
+            let mut done = false;
+            for b in buf {
+                used += 1;
+                if b.is_ascii_whitespace() {
+                    if !self.token.is_empty() {
+                        done = true;
+                        break;
+                    }
+                } else {
+                    self.token.push(*b);
+                }
+            }
+            self.reader.consume(used);
+            if done {
+                break;
+            }
+        }
+        if self.token.is_empty() {
+            return Err(ply_error("unexpected end of file"));
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, ty: Scalar) -> Result<f64, SpriosError> {
+        if self.encoding == Encoding::Ascii {
+            self.next_token()?;
+            let token = std::str::from_utf8(&self.token).map_err(|_| ply_error("invalid number"))?;
+            return token.parse::<f64>().map_err(|_| ply_error(format!("invalid number '{}'", token)));
+        }
+        let mut bytes = [0u8; 8];
+        let bytes = &mut bytes[..ty.size()];
+        self.reader.read_exact(bytes).map_err(|_| ply_error("unexpected end of file"))?;
+        if self.encoding == Encoding::BigEndian {
+            bytes.reverse();
+        }
+        Ok(match ty {
+            Scalar::I8 => bytes[0] as i8 as f64,
+            Scalar::U8 => bytes[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes([
+                bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+            ]),
+        })
+    }
+
+    fn read_index(&mut self, ty: Scalar) -> Result<u32, SpriosError> {
+        let v = self.read(ty)?;
+        if v < 0.0 || v > u32::MAX as f64 || v.fract() != 0.0 {
+            return Err(ply_error(format!("invalid index {}", v)));
+        }
+        Ok(v as u32)
+    }
+
+    fn skip(&mut self, property: &Property) -> Result<(), SpriosError> {
+        let count = match property.list {
+            Some(count) => self.read_index(count)?,
+            None => 1,
+        };
+        for _ in 0..count {
+            self.read(property.ty)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_header<R: BufRead>(reader: &mut R) -> Result<(Encoding, Vec<Element>), SpriosError> {
+    let mut line = String::new();
+    let mut next_line = |line: &mut String| -> Result<(), SpriosError> {
+        line.clear();
+        if reader.read_line(line)? == 0 {
+            return Err(ply_error("header without end_header"));
+        }
+        Ok(())
+    };
+    next_line(&mut line)?;
+    if line.trim_end() != "ply" {
+        return Err(ply_error("not a ply file"));
+    }
+    let mut encoding = None;
+    let mut elements: Vec<Element> = vec![];
+    loop {
+        next_line(&mut line)?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["end_header"] => break,
+            ["format", format, _version] => {
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::LittleEndian,
+                    "binary_big_endian" => Encoding::BigEndian,
+                    f => return Err(ply_error(format!("unknown format {}", f))),
+                })
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            ["element", name, count] => {
+                let count = count.parse().map_err(|_| ply_error(format!("invalid count of {}", name)))?;
+                elements.push(Element { name: name.to_string(), count, properties: vec![] });
+            }
+            ["property", rest @ ..] => {
+                let element = elements.last_mut().ok_or_else(|| ply_error("property before element"))?;
+                let ty = |name: &str| Scalar::parse(name).ok_or_else(|| ply_error(format!("unknown type {}", name)));
+                let property = match rest {
+                    ["list", count, ty_name, name] => {
+                        Property { name: name.to_string(), ty: ty(ty_name)?, list: Some(ty(count)?) }
+                    }
+                    [ty_name, name] => Property { name: name.to_string(), ty: ty(ty_name)?, list: None },
+                    _ => return Err(ply_error(format!("invalid property '{}'", line.trim_end()))),
+                };
+                element.properties.push(property);
+            }
+            _ => return Err(ply_error(format!("invalid header line '{}'", line.trim_end()))),
+        }
+    }
+    let encoding = encoding.ok_or_else(|| ply_error("missing format"))?;
+    Ok((encoding, elements))
+}
+
+fn vertex_slot(name: &str) -> Slot {
+    match name {
+        "x" => Slot::Position(0),
+        "y" => Slot::Position(1),
+        "z" => Slot::Position(2),
+        "nx" => Slot::Normal(0),
+        "ny" => Slot::Normal(1),
+        "nz" => Slot::Normal(2),
+        "red" | "r" | "diffuse_red" => Slot::Color(0),
+        "green" | "g" | "diffuse_green" => Slot::Color(1),
+        "blue" | "b" | "diffuse_blue" => Slot::Color(2),
+        "u" | "s" | "texture_u" | "texture_s" => Slot::Uv(0),
+        "v" | "t" | "texture_v" | "texture_t" => Slot::Uv(1),
+        _ => Slot::Skip,
+    }
+}
+
+/// Read a mesh from PLY data, faces with more than three corners are triangulated as fans.
+pub fn read_ply<R: BufRead>(reader: R, mat: Option<Arc<dyn Material>>) -> Result<TriangleMesh, SpriosError> {
+    Ok(read_ply_cage(reader)?.triangulate(mat))
+}
+
+/// Read PLY data keeping whole faces for subdivision. An `edge` element with `vertex1`,
+/// `vertex2` and `crease` properties gives the sharpness of edges.
+pub fn read_ply_cage<R: BufRead>(mut reader: R) -> Result<PolyMesh, SpriosError> {
+    let (encoding, elements) = read_header(&mut reader)?;
+    let mut body = Body { reader, encoding, token: vec![] };
+    let (mut positions, mut normals, mut colors, mut uvs) = (vec![], vec![], vec![], vec![]);
+    let mut faces = vec![];
+    let mut creases = vec![];
+    for element in &elements {
+        let context = |e: SpriosError, i: usize| match e {
+            SpriosError::ImportError(e) => ply_error(format!("{} {}: {}", element.name, i, e)),
+            e => e,
+        };
+        match element.name.as_str() {
+            "vertex" => {
+                let slots: Vec<Slot> = element
+                    .properties
+                    .iter()
+                    .map(|p| if p.list.is_some() { Slot::Skip } else { vertex_slot(&p.name) })
+                    .collect();
+                let has = |f: fn(usize) -> Slot| (0..3).all(|i| slots.contains(&f(i)));
+                if !has(Slot::Position) {
+                    return Err(ply_error("vertex needs x, y and z"));
+                }
+                let (has_normals, has_colors) = (has(Slot::Normal), has(Slot::Color));
+                let has_uvs = slots.contains(&Slot::Uv(0)) && slots.contains(&Slot::Uv(1));
+                positions.reserve(element.count);
+                for i in 0..element.count {
+                    let (mut p, mut n, mut c, mut uv) = ([0.0f32; 3], [0.0f32; 3], [0.0f32; 3], [0.0f32; 2]);
+                    for (slot, property) in slots.iter().zip(&element.properties) {
+                        if *slot == Slot::Skip {
+                            body.skip(property).map_err(|e| context(e, i))?;
+                            continue;
+                        }
+                        let v = body.read(property.ty).map_err(|e| context(e, i))? as f32;
+                        match *slot {
+                            Slot::Position(k) => p[k] = v,
+                            Slot::Normal(k) => n[k] = v,
+                            Slot::Color(k) => c[k] = v * property.ty.color_scale(),
+                            Slot::Uv(k) => uv[k] = v,
+                            Slot::Skip => {}
+                        }
+                    }
+                    positions.push(Point3::from(p));
+                    if has_normals {
+                        normals.push(Vec3::from(n));
+                    }
+                    if has_colors {
+                        colors.push(Color::from(c));
+                    }
+                    if has_uvs {
+                        uvs.push(uv);
+                    }
+                }
+            }
+            "face" => {
+                let is_indices = |p: &Property| p.list.is_some() && (p.name == "vertex_indices" || p.name == "vertex_index");
+                if !element.properties.iter().any(is_indices) {
+                    return Err(ply_error("face needs a vertex_indices list"));
+                }
+                faces.reserve(element.count);
+                for i in 0..element.count {
+                    for property in &element.properties {
+                        if !is_indices(property) {
+                            body.skip(property).map_err(|e| context(e, i))?;
+                            continue;
+                        }
+                        let count = body.read_index(property.list.unwrap()).map_err(|e| context(e, i))?;
+                        let mut corners = Vec::with_capacity(count as usize);
+                        for _ in 0..count {
+                            corners.push(body.read_index(property.ty).map_err(|e| context(e, i))?);
+                        }
+                        faces.push(corners);
+                    }
+                }
+            }
+            "edge" => {
+                for i in 0..element.count {
+                    let (mut ends, mut sharpness) = ([0u32; 2], 0.0);
+                    for property in &element.properties {
+                        match property.name.as_str() {
+                            "vertex1" if property.list.is_none() => ends[0] = body.read_index(property.ty).map_err(|e| context(e, i))?,
+                            "vertex2" if property.list.is_none() => ends[1] = body.read_index(property.ty).map_err(|e| context(e, i))?,
+                            "crease" if property.list.is_none() => sharpness = body.read(property.ty).map_err(|e| context(e, i))? as f32,
+                            _ => body.skip(property).map_err(|e| context(e, i))?,
+                        }
+                    }
+                    if sharpness > 0.0 {
+                        creases.push((ends, sharpness));
+                    }
+                }
+            }
+            _ => {
+                for i in 0..element.count {
+                    for property in &element.properties {
+                        body.skip(property).map_err(|e| context(e, i))?;
+                    }
+                }
+            }
+        }
+    }
+    if let Some(i) = faces.iter().flatten().find(|i| **i as usize >= positions.len()) {
+        return Err(ply_error(format!("face index {} out of range, {} vertices", i, positions.len())));
+    }
+    if let Some(i) = creases.iter().flat_map(|(ends, _)| ends).find(|i| **i as usize >= positions.len()) {
+        return Err(ply_error(format!("edge index {} out of range, {} vertices", i, positions.len())));
+    }
+    let mut cage = PolyMesh::new(positions, faces).with_uvs(uvs).with_colors(colors).with_creases(creases);
+    cage.normals = normals;
+    Ok(cage)
+}
+
+pub fn load_ply(path: impl AsRef<Path>, mat: Option<Arc<dyn Material>>) -> Result<TriangleMesh, SpriosError> {
+    Ok(load_ply_cage(path)?.triangulate(mat))
+}
+
+pub fn load_ply_cage(path: impl AsRef<Path>) -> Result<PolyMesh, SpriosError> {
+    let path = path.as_ref();
+    let with_path = |e: SpriosError| match e {
+        SpriosError::ImportError(e) | SpriosError::WorldParseError(e) => {
+            SpriosError::ImportError(format!("{}: {}", path.display(), e))
+        }
+        e => e,
+    };
+    let file = File::open(path).map_err(|e| with_path(e.into()))?;
+    read_ply_cage(BufReader::new(file)).map_err(with_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII: &str = "ply
+format ascii 1.0
+comment a unit square and a pentagon
+element vertex 5
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 2
+property list uchar int vertex_indices
+property uchar flags
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+0.5 2 0 0 0 0
+3 0 1 2 7
+5 0 1 2 4 3 0
+";
+
+    fn binary(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian { "binary_big_endian" } else { "binary_little_endian" };
+        let header = format!(
+            "ply\nformat {} 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+             property float nx\nproperty float ny\nproperty float nz\nproperty float u\nproperty float v\n\
+             element face 1\nproperty list uchar uint vertex_indices\nend_header\n",
+            format
+        );
+        let mut data = header.into_bytes();
+        let vertices: [[f32; 8]; 3] =
+            [[0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0], [0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0]];
+        for v in vertices.iter().flatten() {
+            data.extend_from_slice(&if big_endian { v.to_be_bytes() } else { v.to_le_bytes() });
+        }
+        data.push(3);
+        for i in 0u32..3 {
+            data.extend_from_slice(&if big_endian { i.to_be_bytes() } else { i.to_le_bytes() });
+        }
+        data
+    }
+
+    #[test]
+    fn test_ascii() {
+        let mesh = read_ply(ASCII.as_bytes(), None).unwrap();
+        assert_eq!(mesh.positions.len(), 5);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 1, 2], [0, 2, 4], [0, 4, 3]]);
+        assert_eq!(mesh.colors[3], Color::ONE);
+        assert!(mesh.normals.is_empty() && mesh.uvs.is_empty());
+    }
+
+    #[test]
+    fn test_binary() {
+        for big_endian in &[false, true] {
+            let mesh = read_ply(&binary(*big_endian)[..], None).unwrap();
+            assert_eq!(mesh.positions[1], Point3::new(1.0, 0.0, 0.0));
+            assert_eq!(mesh.normals[2], Vec3::new(0.0, 0.0, 1.0));
+            assert_eq!(mesh.uvs[2], [0.0, 1.0]);
+            assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+        }
+    }
+
+    #[test]
+    fn test_cage() {
+        let src = ASCII
+            .replace("property uchar flags\n", "property uchar flags\nelement edge 2\nproperty int vertex1\nproperty int vertex2\nproperty float crease\n")
+            .replace("5 0 1 2 4 3 0\n", "5 0 1 2 4 3 0\n0 1 2.5\n1 2 0\n");
+        let cage = read_ply_cage(src.as_bytes()).unwrap();
+        assert_eq!(cage.faces, vec![vec![0, 1, 2], vec![0, 1, 2, 4, 3]]);
+        assert_eq!(cage.creases, vec![([0, 1], 2.5)]);
+        let bad = src.replace("0 1 2.5", "0 8 2.5");
+        assert!(read_ply_cage(bad.as_bytes()).err().unwrap().to_string().contains("edge index 8"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let truncated = &ASCII[..ASCII.len() - 8];
+        let err = read_ply(truncated.as_bytes(), None).err().unwrap().to_string();
+        assert!(err.contains("face 1: unexpected end of file"), "{}", err);
+        let bad_index = ASCII.replace("3 0 1 2 7", "3 0 1 9 7");
+        assert!(read_ply(bad_index.as_bytes(), None).is_err());
+        assert!(read_ply("ply\nformat ascii 1.0\n".as_bytes(), None).is_err());
+        assert!(load_ply("missing.ply", None).err().unwrap().to_string().contains("missing.ply"));
+    }
+}
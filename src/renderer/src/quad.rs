@@ -0,0 +1,210 @@
+use crate::bbox::AaBb;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec::{Color, Point3, Vec3};
+use crate::Lambertian;
+use std::sync::Arc;
+
+/// Parallelogram spanned by the edges `u` and `v` from `origin`.
+/// The front face is on the side of `u x v`, texture coordinates run along the edges.
+pub struct Quad {
+    pub origin: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Arc<dyn Material>,
+    normal: Vec3,
+    /// n / (n . n), projects onto the edges
+    w: Vec3,
+}
+
+impl Quad {
+    pub fn new(origin: Point3, u: Vec3, v: Vec3, mat: Option<Arc<dyn Material>>) -> Quad {
+        let n = Vec3::cross(&u, &v);
+        let w = &n / n.dot(&n);
+        Quad {
+            normal: n.unit(),
+            w,
+            origin,
+            u,
+            v,
+            material: mat.unwrap_or_else(|| Arc::new(Lambertian { color: Color::new(0.8, 0.8, 0.8) })),
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let denom = self.normal.dot(&ray.direction);
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+        let t = self.normal.dot(&(&self.origin - &ray.origin)) / denom;
+        if t <= t_min || t >= t_max {
+            return false;
+        }
+        let p = ray.at(t);
+        let rel = &p - &self.origin;
+        let alpha = self.w.dot(&Vec3::cross(&rel, &self.v));
+        let beta = self.w.dot(&Vec3::cross(&self.u, &rel));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return false;
+        }
+        rec.mat = self.material.as_ref();
+        rec.t = t;
+        rec.p = p;
+        rec.u = alpha;
+        rec.v = beta;
+        rec.color = Color::ONE;
+        rec.set_face_normal(ray, &self.normal);
+        true
+    }
+
+    fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
+        let mut bbox = AaBb::empty();
+        for corner in &[&self.origin + &self.u, &self.origin + &self.v, &self.origin + &self.u + &self.v] {
+            bbox.grow(corner);
+        }
+        bbox.grow(&self.origin);
+        // Axis aligned quads would give a flat box
+        let pad = Vec3::new(1e-4, 1e-4, 1e-4);
+        Some(AaBb::new(&bbox.min - &pad, &bbox.max + &pad))
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        self.material = mat;
+    }
+
+    #[inline]
+    fn name(&self) -> &'static str {
+        "Quad"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        Ok(ObjectDesc::Quad {
+            origin: self.origin.clone(),
+            u: self.u.clone(),
+            v: self.v.clone(),
+            material: Some(writer.material(self.material.as_ref())?),
+        })
+    }
+}
+
+/// Axis aligned box made of six outward facing quads
+pub struct Cuboid {
+    pub min: Point3,
+    pub max: Point3,
+    sides: Vec<Quad>,
+}
+
+impl Cuboid {
+    pub fn new(a: Point3, b: Point3, mat: Option<Arc<dyn Material>>) -> Cuboid {
+        let min = Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+        let max = Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+        let mat = mat.unwrap_or_else(|| Arc::new(Lambertian { color: Color::new(0.8, 0.8, 0.8) }));
+        let dx = Vec3::new(max.x - min.x, 0.0, 0.0);
+        let dy = Vec3::new(0.0, max.y - min.y, 0.0);
+        let dz = Vec3::new(0.0, 0.0, max.z - min.z);
+        let side = |origin: Point3, u: &Vec3, v: &Vec3| Quad::new(origin, u.clone(), v.clone(), Some(Arc::clone(&mat)));
+        let sides = vec![
+            side(Point3::new(min.x, min.y, max.z), &dx, &dy),
+            side(Point3::new(max.x, min.y, max.z), &-&dz, &dy),
+            side(Point3::new(max.x, min.y, min.z), &-&dx, &dy),
+            side(Point3::new(min.x, min.y, min.z), &dz, &dy),
+            side(Point3::new(min.x, max.y, max.z), &dx, &-&dz),
+            side(Point3::new(min.x, min.y, min.z), &dx, &dz),
+        ];
+        Cuboid { min, max, sides }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let mut closest = t_max;
+        let mut hit = false;
+        for side in &self.sides {
+            if side.hit(ray, t_min, closest, rec) {
+                closest = rec.t;
+                hit = true;
+            }
+        }
+        hit
+    }
+
+    fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
+        let pad = Vec3::new(1e-4, 1e-4, 1e-4);
+        Some(AaBb::new(&self.min - &pad, &self.max + &pad))
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.sides[0].material.as_ref())
+    }
+
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        for side in &mut self.sides {
+            side.material = Arc::clone(&mat);
+        }
+    }
+
+    #[inline]
+    fn name(&self) -> &'static str {
+        "Cuboid"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        Ok(ObjectDesc::Box {
+            min: self.min.clone(),
+            max: self.max.clone(),
+            material: Some(writer.material(self.sides[0].material.as_ref())?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quad() {
+        let quad = Quad::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 4.0, 0.0), None);
+        let mat = Lambertian { color: Color::ONE };
+        let mut rec = HitRecord::new(&mat);
+        let ray = Ray::new(&Point3::new(1.0, 1.0, 5.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(quad.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert_eq!((rec.t, rec.u, rec.v), (5.0, 0.5, 0.25));
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        let miss = Ray::new(&Point3::new(3.0, 1.0, 5.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(!quad.hit(&miss, 0.001, f32::INFINITY, &mut rec));
+        let bbox = quad.bbox(0.0, 0.0).unwrap();
+        assert!(bbox.min.z < 0.0 && bbox.max.z > 0.0);
+    }
+
+    #[test]
+    fn test_box() {
+        let cube = Cuboid::new(Point3::new(1.0, 1.0, 1.0), Point3::new(-1.0, -1.0, -1.0), None);
+        let mat = Lambertian { color: Color::ONE };
+        let mut rec = HitRecord::new(&mat);
+        let dirs = [(1.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, 0.0, 1.0)];
+        for (x, y, z) in dirs.iter() {
+            for s in &[-1.0, 1.0] {
+                let dir = Vec3::new(x * s, y * s, z * s);
+                let ray = Ray::new(&(&dir * 5.0), &-&dir);
+                assert!(cube.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+                assert_eq!(rec.t, 4.0);
+                assert_eq!(rec.normal, dir);
+                assert!(rec.front_face);
+            }
+        }
+        // From inside the far wall is hit on its back
+        let ray = Ray::new(&Point3::ZERO, &Vec3::new(0.0, 1.0, 0.0));
+        assert!(cube.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert!(!rec.front_face);
+    }
+}
@@ -0,0 +1,570 @@
+use crate::bbox::AaBb;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable};
+use crate::instance::Transform;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec::{Color, Mat4, Point3, Vec3};
+use crate::Lambertian;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+// Shapes are built around the +y axis in object space and placed by their transform.
+// The swept part starts at +x and turns towards +z.
+
+const EPS: f64 = 1e-9;
+
+/// Real roots of `a x² + b x + c`, in increasing order
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a == 0.0 {
+        if b == 0.0 {
+            return None;
+        }
+        return Some((-c / b, -c / b));
+    }
+    let disc = b * b - 4.0 * a * c;
+    if disc < 0.0 {
+        return None;
+    }
+    // Avoids the cancellation of the textbook formula
+    let q = if b < 0.0 { -0.5 * (b - disc.sqrt()) } else { -0.5 * (b + disc.sqrt()) };
+    if q == 0.0 {
+        return Some((0.0, 0.0));
+    }
+    let (t0, t1) = (q / a, c / q);
+    Some(if t0 < t1 { (t0, t1) } else { (t1, t0) })
+}
+
+/// Real roots of the normalized `x³ + a x² + b x + c`
+fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + c) / 2.0;
+    let cb_p = p * p * p;
+    let d = q * q + cb_p;
+    let roots = if d.abs() < EPS {
+        if q.abs() < EPS {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if d < 0.0 {
+        // Three real roots
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![t * phi.cos(), -t * (phi + std::f64::consts::PI / 3.0).cos(), -t * (phi - std::f64::consts::PI / 3.0).cos()]
+    } else {
+        let sqrt_d = d.sqrt();
+        vec![(sqrt_d - q).cbrt() - (sqrt_d + q).cbrt()]
+    };
+    roots.into_iter().map(|x| x - a / 3.0).collect()
+}
+
+/// Real roots of `c[0] x⁴ + c[1] x³ + c[2] x² + c[3] x + c[4]`, by Ferrari's method.
+/// Each root is polished with Newton steps on the original polynomial.
+pub(crate) fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    let (a, b, cc, d) = (c[1] / c[0], c[2] / c[0], c[3] / c[0], c[4] / c[0]);
+    // Depressed quartic y⁴ + p y² + q y + r with x = y - a/4
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = sq_a * a / 8.0 - a * b / 2.0 + cc;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b / 16.0 - a * cc / 4.0 + d;
+    let mut roots = vec![];
+    if r.abs() < EPS {
+        roots.push(0.0);
+        roots.extend(solve_cubic(0.0, p, q));
+    } else {
+        // A root of the resolvent cubic splits the quartic into two quadratics
+        let z = solve_cubic(-p / 2.0, -r, r * p / 2.0 - q * q / 8.0)[0];
+        let root = |x: f64| {
+            if x.abs() < EPS {
+                Some(0.0)
+            } else if x > 0.0 {
+                Some(x.sqrt())
+            } else {
+                None
+            }
+        };
+        let (u, v) = match (root(z * z - r), root(2.0 * z - p)) {
+            (Some(u), Some(v)) => (u, if q < 0.0 { -v } else { v }),
+            _ => return vec![],
+        };
+        for (b, c) in &[(v, z - u), (-v, z + u)] {
+            if let Some((y0, y1)) = solve_quadratic(1.0, *b, *c) {
+                roots.push(y0);
+                roots.push(y1);
+            }
+        }
+    }
+    let f = |x: f64| (((c[0] * x + c[1]) * x + c[2]) * x + c[3]) * x + c[4];
+    let df = |x: f64| ((4.0 * c[0] * x + 3.0 * c[1]) * x + 2.0 * c[2]) * x + c[3];
+    roots
+        .into_iter()
+        .map(|y| {
+            let mut x = y - a / 4.0;
+            for _ in 0..2 {
+                let slope = df(x);
+                if slope != 0.0 {
+                    x -= f(x) / slope;
+                }
+            }
+            x
+        })
+        .collect()
+}
+
+/// Angle around the y axis in 0..2π
+fn phi(p: &Point3) -> f32 {
+    let phi = p.z.atan2(p.x);
+    if phi < 0.0 {
+        phi + 2.0 * PI
+    } else {
+        phi
+    }
+}
+
+/// Hit in object space with the outward normal
+pub struct LocalHit {
+    t: f32,
+    normal: Vec3,
+    u: f32,
+    v: f32,
+}
+
+/// Flat end at height `y` of radius `radius`, facing `side` along y
+fn cap(o: &Point3, d: &Vec3, y: f32, side: f32, radius: f32, phi_max: f32, (t_min, t_max): (f32, f32)) -> Option<LocalHit> {
+    if d.y == 0.0 {
+        return None;
+    }
+    let t = (y - o.y) / d.y;
+    if t <= t_min || t >= t_max {
+        return None;
+    }
+    let p = o + d * t;
+    let r2 = p.x * p.x + p.z * p.z;
+    if r2 > radius * radius || phi(&p) > phi_max {
+        return None;
+    }
+    Some(LocalHit { t, normal: Vec3::new(0.0, side, 0.0), u: phi(&p) / phi_max, v: r2.sqrt() / radius })
+}
+
+/// First root of a quadric surface in range, `accept` checks the height and sweep of the point
+fn first_root(
+    roots: Option<(f64, f64)>,
+    o: &Point3,
+    d: &Vec3,
+    t_min: f32,
+    t_max: f32,
+    mut accept: impl FnMut(&Point3) -> Option<LocalHit>,
+) -> Option<LocalHit> {
+    let (t0, t1) = roots?;
+    for t in [t0 as f32, t1 as f32].iter() {
+        if *t <= t_min || *t >= t_max {
+            continue;
+        }
+        if let Some(mut hit) = accept(&(o + d * *t)) {
+            hit.t = *t;
+            return Some(hit);
+        }
+    }
+    None
+}
+
+/// Keep the closer of two hits
+fn closer(a: Option<LocalHit>, b: Option<LocalHit>) -> Option<LocalHit> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a.t <= b.t { a } else { b }),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Rigid placement with the y axis along `axis`
+pub fn axis_transform(origin: &Point3, axis: &Vec3) -> Transform {
+    let y = axis.unit();
+    let a = if y.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+    let z = Vec3::cross(&a, &y).unit();
+    let x = Vec3::cross(&y, &z);
+    let m = Mat4::new([
+        [x.x, y.x, z.x, origin.x],
+        [x.y, y.y, z.y, origin.y],
+        [x.z, y.z, z.z, origin.z],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+    Transform { matrix: m, inverse: m.inverse().unwrap_or(Mat4::IDENTITY) }
+}
+
+/// Surface around the +y axis in object space, swept from +x by `phi_max` radians.
+/// `Placed` gives it a material and a transform.
+pub trait Shape: Send + Sync {
+    /// Closest hit in `t_min..t_max`, `caps` closes the flat ends of shapes that have them
+    fn intersect(&self, o: &Point3, d: &Vec3, t_min: f32, t_max: f32, phi_max: f32, caps: bool) -> Option<LocalHit>;
+    fn bounds(&self) -> AaBb;
+    fn name(&self) -> &'static str;
+    fn describe(&self, sweep: f32, caps: bool, transform: Mat4, material: Option<String>) -> ObjectDesc;
+}
+
+/// A `Shape` with its sweep, caps, placement and material
+pub struct Placed<S> {
+    pub shape: S,
+    /// Swept angle in degrees
+    pub sweep: f32,
+    /// Ignored by shapes without flat ends
+    pub caps: bool,
+    pub transform: Transform,
+    pub material: Arc<dyn Material>,
+}
+
+impl<S: Shape> Placed<S> {
+    pub fn new(shape: S, mat: Option<Arc<dyn Material>>) -> Placed<S> {
+        Placed {
+            shape,
+            sweep: 360.0,
+            caps: true,
+            transform: Transform::IDENTITY,
+            material: mat.unwrap_or_else(|| Arc::new(Lambertian { color: Color::new(0.8, 0.8, 0.8) })),
+        }
+    }
+
+    pub fn with_sweep(mut self, degrees: f32) -> Placed<S> {
+        self.sweep = degrees;
+        self
+    }
+
+    pub fn with_caps(mut self, caps: bool) -> Placed<S> {
+        self.caps = caps;
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Placed<S> {
+        self.transform = transform;
+        self
+    }
+}
+
+impl<S: Shape> Hittable for Placed<S> {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let inv = &self.transform.inverse;
+        let o = inv.transform_point(&ray.origin);
+        let d = inv.transform_vector(&ray.direction);
+        let hit = match self.shape.intersect(&o, &d, t_min, t_max, self.sweep.to_radians(), self.caps) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        rec.mat = self.material.as_ref();
+        rec.t = hit.t;
+        rec.p = ray.at(hit.t);
+        rec.u = hit.u;
+        rec.v = hit.v;
+        rec.color = Color::ONE;
+        rec.set_face_normal(ray, &inv.transform_normal(&hit.normal).unit());
+        true
+    }
+
+    fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
+        Some(self.transform.transform_bbox(&self.shape.bounds()))
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        self.material = mat;
+    }
+
+    #[inline]
+    fn name(&self) -> &'static str {
+        self.shape.name()
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        let material = Some(writer.material(self.material.as_ref())?);
+        Ok(self.shape.describe(self.sweep, self.caps, self.transform.matrix, material))
+    }
+}
+
+/// Open or capped cylinder from y = 0 to `height`
+pub struct Cylinder {
+    pub radius: f32,
+    pub height: f32,
+}
+
+impl Shape for Cylinder {
+    fn intersect(&self, o: &Point3, d: &Vec3, t_min: f32, t_max: f32, phi_max: f32, caps: bool) -> Option<LocalHit> {
+        let (r, h) = (self.radius, self.height);
+        let (ox, oz, dx, dz) = (o.x as f64, o.z as f64, d.x as f64, d.z as f64);
+        let roots = solve_quadratic(dx * dx + dz * dz, 2.0 * (ox * dx + oz * dz), ox * ox + oz * oz - (r * r) as f64);
+        let side = first_root(roots, o, d, t_min, t_max, |p| {
+            if p.y < 0.0 || p.y > h || phi(p) > phi_max {
+                return None;
+            }
+            Some(LocalHit { t: 0.0, normal: Vec3::new(p.x, 0.0, p.z), u: phi(p) / phi_max, v: p.y / h })
+        });
+        if !caps {
+            return side;
+        }
+        let t_max = side.as_ref().map_or(t_max, |h| h.t);
+        let bottom = cap(o, d, 0.0, -1.0, r, phi_max, (t_min, t_max));
+        let top = cap(o, d, h, 1.0, r, phi_max, (t_min, t_max));
+        closer(side, closer(bottom, top))
+    }
+
+    fn bounds(&self) -> AaBb {
+        let (r, h) = (self.radius, self.height);
+        AaBb::new(Point3::new(-r, 0.0, -r), Point3::new(r, h, r))
+    }
+
+    fn name(&self) -> &'static str {
+        "Cylinder"
+    }
+
+    fn describe(&self, sweep: f32, caps: bool, transform: Mat4, material: Option<String>) -> ObjectDesc {
+        ObjectDesc::Cylinder { radius: self.radius, height: self.height, sweep, caps, transform, material }
+    }
+}
+
+/// Cone with its base of `radius` at y = 0 and the apex at `height`
+pub struct Cone {
+    pub radius: f32,
+    pub height: f32,
+}
+
+impl Shape for Cone {
+    fn intersect(&self, o: &Point3, d: &Vec3, t_min: f32, t_max: f32, phi_max: f32, caps: bool) -> Option<LocalHit> {
+        let (r, h) = (self.radius, self.height);
+        // x² + z² = k² (h - y)²
+        let k2 = (r as f64 / h as f64).powi(2);
+        let (ox, oz, dx, dy, dz) = (o.x as f64, o.z as f64, d.x as f64, d.y as f64, d.z as f64);
+        let e = h as f64 - o.y as f64;
+        let a = dx * dx + dz * dz - k2 * dy * dy;
+        let b = 2.0 * (ox * dx + oz * dz + k2 * e * dy);
+        let c = ox * ox + oz * oz - k2 * e * e;
+        let side = first_root(solve_quadratic(a, b, c), o, d, t_min, t_max, |p| {
+            if p.y < 0.0 || p.y > h || phi(p) > phi_max {
+                return None;
+            }
+            let normal = Vec3::new(p.x, k2 as f32 * (h - p.y), p.z);
+            Some(LocalHit { t: 0.0, normal, u: phi(p) / phi_max, v: p.y / h })
+        });
+        if !caps {
+            return side;
+        }
+        let t_max = side.as_ref().map_or(t_max, |h| h.t);
+        closer(side, cap(o, d, 0.0, -1.0, r, phi_max, (t_min, t_max)))
+    }
+
+    fn bounds(&self) -> AaBb {
+        let (r, h) = (self.radius, self.height);
+        AaBb::new(Point3::new(-r, 0.0, -r), Point3::new(r, h, r))
+    }
+
+    fn name(&self) -> &'static str {
+        "Cone"
+    }
+
+    fn describe(&self, sweep: f32, caps: bool, transform: Mat4, material: Option<String>) -> ObjectDesc {
+        ObjectDesc::Cone { radius: self.radius, height: self.height, sweep, caps, transform, material }
+    }
+}
+
+/// Paraboloid y = height (x² + z²) / radius², a cup open at the top
+pub struct Paraboloid {
+    pub radius: f32,
+    pub height: f32,
+}
+
+impl Shape for Paraboloid {
+    fn intersect(&self, o: &Point3, d: &Vec3, t_min: f32, t_max: f32, phi_max: f32, caps: bool) -> Option<LocalHit> {
+        let (r, h) = (self.radius, self.height);
+        let (hf, r2) = (h as f64, (r * r) as f64);
+        let (ox, oy, oz, dx, dy, dz) = (o.x as f64, o.y as f64, o.z as f64, d.x as f64, d.y as f64, d.z as f64);
+        let a = hf * (dx * dx + dz * dz);
+        let b = 2.0 * hf * (ox * dx + oz * dz) - r2 * dy;
+        let c = hf * (ox * ox + oz * oz) - r2 * oy;
+        let side = first_root(solve_quadratic(a, b, c), o, d, t_min, t_max, |p| {
+            if p.y > h || phi(p) > phi_max {
+                return None;
+            }
+            let normal = Vec3::new(2.0 * h * p.x, -r * r, 2.0 * h * p.z);
+            Some(LocalHit { t: 0.0, normal, u: phi(p) / phi_max, v: p.y / h })
+        });
+        if !caps {
+            return side;
+        }
+        let t_max = side.as_ref().map_or(t_max, |h| h.t);
+        closer(side, cap(o, d, h, 1.0, r, phi_max, (t_min, t_max)))
+    }
+
+    fn bounds(&self) -> AaBb {
+        let (r, h) = (self.radius, self.height);
+        AaBb::new(Point3::new(-r, 0.0, -r), Point3::new(r, h, r))
+    }
+
+    fn name(&self) -> &'static str {
+        "Paraboloid"
+    }
+
+    fn describe(&self, sweep: f32, caps: bool, transform: Mat4, material: Option<String>) -> ObjectDesc {
+        ObjectDesc::Paraboloid { radius: self.radius, height: self.height, sweep, caps, transform, material }
+    }
+}
+
+/// Torus around the y axis, the tube of radius `minor` circles at distance `major`
+pub struct Torus {
+    pub major: f32,
+    pub minor: f32,
+}
+
+impl Shape for Torus {
+    fn intersect(&self, o: &Point3, d: &Vec3, t_min: f32, t_max: f32, phi_max: f32, _caps: bool) -> Option<LocalHit> {
+        let (big, small) = (self.major as f64, self.minor as f64);
+        // Solve for a unit direction, starting where the ray enters the bounding sphere.
+        // Keeping the origin close to the torus keeps the quartic well conditioned.
+        let len = d.length() as f64;
+        let dir = [d.x as f64 / len, d.y as f64 / len, d.z as f64 / len];
+        let org = [o.x as f64, o.y as f64, o.z as f64];
+        let od = org[0] * dir[0] + org[1] * dir[1] + org[2] * dir[2];
+        let oo = org[0] * org[0] + org[1] * org[1] + org[2] * org[2];
+        let bound = big + small;
+        let disc = od * od - (oo - bound * bound);
+        if disc < 0.0 {
+            return None;
+        }
+        let shift = -od - disc.sqrt();
+        let o = [org[0] + dir[0] * shift, org[1] + dir[1] * shift, org[2] + dir[2] * shift];
+
+        let od = o[0] * dir[0] + o[1] * dir[1] + o[2] * dir[2];
+        let k = o[0] * o[0] + o[1] * o[1] + o[2] * o[2] + big * big - small * small;
+        let four_r2 = 4.0 * big * big;
+        let coeffs = [
+            1.0,
+            4.0 * od,
+            4.0 * od * od + 2.0 * k - four_r2 * (dir[0] * dir[0] + dir[2] * dir[2]),
+            4.0 * od * k - 2.0 * four_r2 * (o[0] * dir[0] + o[2] * dir[2]),
+            k * k - four_r2 * (o[0] * o[0] + o[2] * o[2]),
+        ];
+        let mut roots: Vec<f32> = solve_quartic(coeffs).into_iter().map(|s| ((s + shift) / len) as f32).collect();
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let (origin, d) = (Point3::new(org[0] as f32, org[1] as f32, org[2] as f32), d);
+        for t in roots {
+            if t <= t_min || t >= t_max {
+                continue;
+            }
+            let p = &origin + d * t;
+            if phi(&p) > phi_max {
+                continue;
+            }
+            let rho = (p.x * p.x + p.z * p.z).sqrt();
+            let normal = &p - Vec3::new(p.x, 0.0, p.z) * (self.major / rho);
+            let theta = p.y.atan2(rho - self.major);
+            return Some(LocalHit { t, normal, u: phi(&p) / phi_max, v: (theta + PI) / (2.0 * PI) });
+        }
+        None
+    }
+
+    fn bounds(&self) -> AaBb {
+        let (r, m) = (self.major + self.minor, self.minor);
+        AaBb::new(Point3::new(-r, -m, -r), Point3::new(r, m, r))
+    }
+
+    fn name(&self) -> &'static str {
+        "Torus"
+    }
+
+    fn describe(&self, sweep: f32, _caps: bool, transform: Mat4, material: Option<String>) -> ObjectDesc {
+        ObjectDesc::Torus { major: self.major, minor: self.minor, sweep, transform, material }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(object: &dyn Hittable, origin: (f32, f32, f32), dir: (f32, f32, f32)) -> Option<(f32, Vec3, bool)> {
+        let mat = Lambertian { color: Color::ONE };
+        let mut rec = HitRecord::new(&mat);
+        let ray = Ray::new(&Point3::new(origin.0, origin.1, origin.2), &Vec3::new(dir.0, dir.1, dir.2));
+        if object.hit(&ray, 0.001, f32::INFINITY, &mut rec) {
+            Some((rec.t, rec.normal.clone(), rec.front_face))
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn test_quartic() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        let mut roots = solve_quartic([1.0, -10.0, 35.0, -50.0, 24.0]);
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(roots.len(), 4);
+        for (root, expected) in roots.iter().zip(&[1.0, 2.0, 3.0, 4.0]) {
+            assert!((root - expected).abs() < 1e-9, "{:?}", roots);
+        }
+        // x⁴ + 1 has no real roots
+        assert!(solve_quartic([1.0, 0.0, 0.0, 0.0, 1.0]).is_empty());
+    }
+
+    #[test]
+    fn test_cylinder() {
+        let cyl = Placed::new(Cylinder { radius: 1.0, height: 2.0 }, None);
+        let (t, n, front) = hit(&cyl, (-5.0, 1.0, 0.0), (1.0, 0.0, 0.0)).unwrap();
+        assert!((t - 4.0).abs() < 1e-5 && front);
+        assert_eq!(n, Vec3::new(-1.0, 0.0, 0.0));
+        // Down the axis onto the top cap, or through the open end
+        let (t, n, _) = hit(&cyl, (0.0, 5.0, 0.0), (0.0, -1.0, 0.0)).unwrap();
+        assert_eq!((t, n), (3.0, Vec3::new(0.0, 1.0, 0.0)));
+        let open = Placed::new(Cylinder { radius: 1.0, height: 2.0 }, None).with_caps(false);
+        assert!(hit(&open, (0.0, 5.0, 0.0), (0.0, -1.0, 0.0)).is_none());
+        // Half cylinder, the far wall seen through the missing half
+        let half = Placed::new(Cylinder { radius: 1.0, height: 2.0 }, None).with_sweep(180.0).with_caps(false);
+        let (t, _, front) = hit(&half, (0.0, 1.0, -5.0), (0.0, 0.0, 1.0)).unwrap();
+        assert!((t - 6.0).abs() < 1e-5 && !front);
+
+        let x_axis = axis_transform(&Point3::ZERO, &Vec3::new(1.0, 0.0, 0.0));
+        let placed = Placed::new(Cylinder { radius: 1.0, height: 2.0 }, None).with_transform(x_axis);
+        let bbox = placed.bbox(0.0, 1.0).unwrap();
+        assert!((bbox.max.x - 2.0).abs() < 1e-5 && (bbox.min.y + 1.0).abs() < 1e-5);
+        let (t, n, _) = hit(&placed, (5.0, 0.0, 0.0), (-1.0, 0.0, 0.0)).unwrap();
+        assert!((t - 3.0).abs() < 1e-5 && (n.x - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_cone_paraboloid() {
+        let cone = Placed::new(Cone { radius: 1.0, height: 1.0 }, None);
+        let (t, n, _) = hit(&cone, (-5.0, 0.5, 0.0), (1.0, 0.0, 0.0)).unwrap();
+        assert!((t - 4.5).abs() < 1e-5);
+        assert!(n.x < 0.0 && (n.x + n.y).abs() < 1e-5);
+        assert!(hit(&cone, (-5.0, 1.5, 0.0), (1.0, 0.0, 0.0)).is_none());
+
+        let cup = Placed::new(Paraboloid { radius: 1.0, height: 1.0 }, None);
+        // The vertex from below, and the cap from above
+        let (t, n, _) = hit(&cup, (0.0, -1.0, 0.0), (0.0, 1.0, 0.0)).unwrap();
+        assert_eq!((t, n), (1.0, Vec3::new(0.0, -1.0, 0.0)));
+        let (t, _, _) = hit(&cup, (0.5, 5.0, 0.0), (0.0, -1.0, 0.0)).unwrap();
+        assert_eq!(t, 4.0);
+        let open = Placed::new(Paraboloid { radius: 1.0, height: 1.0 }, None).with_caps(false);
+        let (t, _, front) = hit(&open, (0.5, 5.0, 0.0), (0.0, -1.0, 0.0)).unwrap();
+        assert!((t - 4.75).abs() < 1e-5 && !front);
+    }
+
+    #[test]
+    fn test_torus() {
+        let torus = Placed::new(Torus { major: 2.0, minor: 0.5 }, None);
+        let (t, n, front) = hit(&torus, (-10.0, 0.0, 0.0), (1.0, 0.0, 0.0)).unwrap();
+        assert!((t - 7.5).abs() < 1e-4 && front);
+        assert!((n.x + 1.0).abs() < 1e-4);
+        // Through the hole
+        assert!(hit(&torus, (0.0, 10.0, 0.0), (0.0, -1.0, 0.0)).is_none());
+        // Far away with a long direction vector
+        let (t, _, _) = hit(&torus, (2.0, 1000.0, 0.0), (0.0, -10.0, 0.0)).unwrap();
+        assert!((t - 99.95).abs() < 1e-3);
+        // Inside the tube
+        let (t, _, front) = hit(&torus, (2.0, 0.0, 0.0), (0.0, 1.0, 0.0)).unwrap();
+        assert!((t - 0.5).abs() < 1e-4 && !front);
+        let half = Placed::new(Torus { major: 2.0, minor: 0.5 }, None).with_sweep(180.0);
+        assert!(hit(&half, (2.0, 10.0, -0.0001), (0.0, -1.0, 0.0)).is_none());
+        assert!(hit(&half, (2.0, 10.0, 0.0001), (0.0, -1.0, 0.0)).is_some());
+    }
+}
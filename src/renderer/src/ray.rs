@@ -1,8 +1,24 @@
 use crate::vec::{Point3, Vec3};
 
+/// Where a motion keyed at times 0 and 1 is at `time`, objects hold still outside that interval
+pub(crate) fn motion_blend(time: f32) -> f32 {
+    time.clamp(0.0, 1.0)
+}
+
+/// Motion is keyed at times 0 and 1, a shutter has to open and close within them
+pub fn check_shutter(open: f32, close: f32) -> Result<(), String> {
+    if 0.0 <= open && open <= close && close <= 1.0 {
+        Ok(())
+    } else {
+        Err(format!("shutter {}..{} must lie within 0..1", open, close))
+    }
+}
+
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
+    /// Moment within the shutter interval, what moving objects are placed at
+    pub time: f32,
 }
 
 impl Ray {
@@ -10,8 +26,14 @@ impl Ray {
         Ray {
             origin: origin.clone(),
             direction: direction.clone(),
+            time: 0.0,
         }
     }
+
+    pub fn with_time(mut self, time: f32) -> Self {
+        self.time = time;
+        self
+    }
     // pub fn zero() -> Self {
     //     Ray { origin: Point3::ZERO, direction: Vec3::ZERO }
     // }
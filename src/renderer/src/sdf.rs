@@ -0,0 +1,512 @@
+use crate::bbox::AaBb;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable, MAX_CROSSINGS};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec::{Color, Point3, Vec3};
+use crate::Lambertian;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const MAX_STEPS: usize = 512;
+/// Distance counted as being on the surface
+const EPSILON: f32 = 1e-4;
+
+fn default_power() -> f32 {
+    8.0
+}
+
+fn default_iterations() -> u32 {
+    12
+}
+
+/// Tree of distance functions, leaves are shapes at the origin.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SdfNode {
+    Sphere { radius: f32 },
+    /// Box of half extents `size`
+    Box { size: Vec3 },
+    /// Torus around the y axis
+    Torus { major: f32, minor: f32 },
+    Capsule { a: Point3, b: Point3, radius: f32 },
+    Mandelbulb {
+        #[serde(default = "default_power")]
+        power: f32,
+        #[serde(default = "default_iterations")]
+        iterations: u32,
+    },
+    /// Blends the two shapes over a distance of `smooth`, a plain union when zero
+    Union {
+        #[serde(default)]
+        smooth: f32,
+        a: Box<SdfNode>,
+        b: Box<SdfNode>,
+    },
+    /// `a` with `b` cut away
+    Subtract { a: Box<SdfNode>, b: Box<SdfNode> },
+    Translate { offset: Vec3, node: Box<SdfNode> },
+    Scale { factor: f32, node: Box<SdfNode> },
+    /// Endless copies every `period`, zero components don't repeat
+    Repeat { period: Vec3, node: Box<SdfNode> },
+    /// Rotates around the y axis by `degrees` per unit of height
+    Twist { degrees: f32, node: Box<SdfNode> },
+}
+
+impl SdfNode {
+    /// Distance to the surface, negative inside
+    pub fn distance(&self, p: &Point3) -> f32 {
+        match self {
+            SdfNode::Sphere { radius } => p.length() - radius,
+            SdfNode::Box { size } => {
+                let q = Vec3::new(p.x.abs() - size.x, p.y.abs() - size.y, p.z.abs() - size.z);
+                let outside = Vec3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).length();
+                outside + q.x.max(q.y.max(q.z)).min(0.0)
+            }
+            SdfNode::Torus { major, minor } => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major;
+                (ring * ring + p.y * p.y).sqrt() - minor
+            }
+            SdfNode::Capsule { a, b, radius } => {
+                let pa = p - a;
+                let ba = b - a;
+                let h = (pa.dot(&ba) / ba.dot(&ba).max(1e-12)).clamp(0.0, 1.0);
+                (&pa - &ba * h).length() - radius
+            }
+            SdfNode::Mandelbulb { power, iterations } => mandelbulb(p, *power, *iterations),
+            SdfNode::Union { smooth, a, b } => {
+                let (da, db) = (a.distance(p), b.distance(p));
+                if *smooth <= 0.0 {
+                    return da.min(db);
+                }
+                let h = (0.5 + 0.5 * (db - da) / smooth).clamp(0.0, 1.0);
+                db + (da - db) * h - smooth * h * (1.0 - h)
+            }
+            SdfNode::Subtract { a, b } => a.distance(p).max(-b.distance(p)),
+            SdfNode::Translate { offset, node } => node.distance(&(p - offset)),
+            SdfNode::Scale { factor, node } => node.distance(&(p / *factor)) * factor,
+            SdfNode::Repeat { period, node } => {
+                let wrap = |x: f32, period: f32| if period > 0.0 { x - period * (x / period).round() } else { x };
+                node.distance(&Vec3::new(wrap(p.x, period.x), wrap(p.y, period.y), wrap(p.z, period.z)))
+            }
+            SdfNode::Twist { degrees, node } => {
+                let rate = degrees.to_radians();
+                let (s, c) = (rate * p.y).sin_cos();
+                let q = Vec3::new(c * p.x - s * p.z, p.y, s * p.x + c * p.z);
+                // Twisting stretches distances further from the axis, keep the steps safe
+                let r = (p.x * p.x + p.z * p.z).sqrt();
+                node.distance(&q) / (1.0 + rate * rate * r * r).sqrt()
+            }
+        }
+    }
+}
+
+/// Distance estimate of the power `n` Mandelbulb with z up
+fn mandelbulb(p: &Point3, n: f32, iterations: u32) -> f32 {
+    let mut z = p.clone();
+    let mut dr = 1.0;
+    let mut r = z.length();
+    for _ in 0..iterations {
+        if r > 2.0 || r == 0.0 {
+            break;
+        }
+        let theta = (z.z / r).acos() * n;
+        let phi = z.y.atan2(z.x) * n;
+        dr = r.powf(n - 1.0) * n * dr + 1.0;
+        let zr = r.powf(n);
+        z = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()) * zr + p;
+        r = z.length();
+    }
+    if r == 0.0 {
+        return 0.0;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+/// Surface of a distance function, found by sphere tracing inside `bounds`.
+/// The bounds are supplied by the user and have to enclose the whole surface.
+pub struct Sdf {
+    pub shape: SdfNode,
+    pub bounds: AaBb,
+    pub material: Arc<dyn Material>,
+}
+
+impl Sdf {
+    pub fn new(shape: SdfNode, bounds: AaBb, mat: Option<Arc<dyn Material>>) -> Sdf {
+        Sdf { shape, bounds, material: mat.unwrap_or_else(|| Arc::new(Lambertian { color: Color::new(0.8, 0.8, 0.8) })) }
+    }
+
+    /// Central differences of the distance
+    fn normal(&self, p: &Point3) -> Vec3 {
+        let d = |x: f32, y: f32, z: f32| self.shape.distance(&(p + &Vec3::new(x, y, z)));
+        let h = EPSILON;
+        let n = Vec3::new(d(h, 0.0, 0.0) - d(-h, 0.0, 0.0), d(0.0, h, 0.0) - d(0.0, -h, 0.0), d(0.0, 0.0, h) - d(0.0, 0.0, -h));
+        if n.length_squared() == 0.0 {
+            return Vec3::new(0.0, 1.0, 0.0);
+        }
+        n.unit()
+    }
+
+    /// Range of the ray inside the bounds
+    fn clip(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let (mut t0, mut t1) = (t_min, t_max);
+        for a in 0..3 {
+            let inv = 1.0 / ray.direction[a];
+            let near = (self.bounds.min[a] - ray.origin[a]) * inv;
+            let far = (self.bounds.max[a] - ray.origin[a]) * inv;
+            if near.is_nan() || far.is_nan() {
+                continue;
+            }
+            t0 = t0.max(near.min(far));
+            t1 = t1.min(near.max(far));
+        }
+        if t0 <= t1 {
+            Some((t0, t1))
+        } else {
+            None
+        }
+    }
+
+    /// Distances where the ray crosses the surface, at most `limit` of them.
+    /// Marches on the absolute distance so surfaces are found from either side.
+    fn crossings(&self, ray: &Ray, t_min: f32, t_max: f32, limit: usize) -> Vec<f32> {
+        let mut out = vec![];
+        let (mut t, t_end) = match self.clip(ray, t_min, t_max) {
+            Some(range) => range,
+            None => return out,
+        };
+        let scale = 1.0 / ray.direction.length();
+        // Start past a surface the ray leaves from, not one at the edge of the bounds
+        let mut leaving = t <= t_min && self.shape.distance(&ray.at(t)).abs() < EPSILON;
+        for _ in 0..MAX_STEPS {
+            if t > t_end {
+                break;
+            }
+            let d = self.shape.distance(&ray.at(t)).abs();
+            if leaving {
+                leaving = d < EPSILON;
+                t += d.max(EPSILON) * scale;
+            } else if d < EPSILON {
+                if t > t_min {
+                    out.push(t);
+                    if out.len() >= limit {
+                        break;
+                    }
+                }
+                leaving = true;
+                t += EPSILON * scale;
+            } else {
+                t += d * scale;
+            }
+        }
+        out
+    }
+
+    fn record<'obj>(&'obj self, ray: &Ray, t: f32, rec: &mut HitRecord<'obj>) {
+        rec.mat = self.material.as_ref();
+        rec.t = t;
+        rec.p = ray.at(t);
+        let normal = self.normal(&rec.p);
+        rec.u = 0.5 + normal.z.atan2(normal.x) / (2.0 * std::f32::consts::PI);
+        rec.v = normal.y.clamp(-1.0, 1.0).acos() / std::f32::consts::PI;
+        rec.color = Color::ONE;
+        rec.set_face_normal(ray, &normal);
+    }
+}
+
+impl Hittable for Sdf {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        match self.crossings(ray, t_min, t_max, 1).first() {
+            Some(t) => {
+                self.record(ray, *t, rec);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn hits<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32) -> Vec<HitRecord<'obj>> {
+        let ts = self.crossings(ray, t_min, t_max, MAX_CROSSINGS);
+        ts.into_iter()
+            .map(|t| {
+                let mut rec = HitRecord::new(self.material.as_ref());
+                self.record(ray, t, &mut rec);
+                rec
+            })
+            .collect()
+    }
+
+    fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
+        Some(self.bounds.clone())
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        self.material = mat;
+    }
+
+    #[inline]
+    fn name(&self) -> &'static str {
+        "Sdf"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        Ok(ObjectDesc::Sdf {
+            min: self.bounds.min.clone(),
+            max: self.bounds.max.clone(),
+            material: Some(writer.material(self.material.as_ref())?),
+            shape: self.shape.clone(),
+        })
+    }
+}
+
+/// Argument of an expression call, numbers separated by spaces or a nested call
+enum ExprArg {
+    Numbers(Vec<f32>),
+    Node(SdfNode),
+}
+
+/// Reads expressions like `union(sphere(1), move(2 0 0, box(0.5 0.5 0.5)))`
+struct ExprParser<'src> {
+    src: &'src str,
+    pos: usize,
+}
+
+impl<'src> ExprParser<'src> {
+    fn error(&self, message: String) -> String {
+        format!("{} at character {}", message, self.pos + 1)
+    }
+
+    fn skip_space(&mut self) {
+        let rest = &self.src[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_space();
+        self.src[self.pos..].chars().next()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.peek() {
+            Some(got) if got == c => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.error(format!("expected '{}'", c))),
+        }
+    }
+
+    fn word(&mut self, pred: impl Fn(char) -> bool) -> &'src str {
+        self.skip_space();
+        let rest = &self.src[self.pos..];
+        let len = rest.find(|c: char| !pred(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn arg(&mut self) -> Result<ExprArg, String> {
+        match self.peek() {
+            Some(c) if c.is_ascii_alphabetic() => Ok(ExprArg::Node(self.call()?)),
+            _ => {
+                let mut numbers = vec![];
+                while let Some(c) = self.peek() {
+                    if c == ',' || c == ')' {
+                        break;
+                    }
+                    let word = self.word(|c| c.is_ascii_digit() || c == '.' || c == '-' || c == '+' || c == 'e');
+                    let value = word.parse().map_err(|_| self.error(format!("expected a number, got '{}'", word)))?;
+                    numbers.push(value);
+                }
+                if numbers.is_empty() {
+                    return Err(self.error("expected an argument".to_string()));
+                }
+                Ok(ExprArg::Numbers(numbers))
+            }
+        }
+    }
+
+    fn call(&mut self) -> Result<SdfNode, String> {
+        let name = self.word(|c| c.is_ascii_alphanumeric());
+        let start = self.pos;
+        self.expect('(')?;
+        let mut args = vec![];
+        if self.peek() != Some(')') {
+            loop {
+                args.push(self.arg()?);
+                if self.peek() != Some(',') {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+        self.expect(')')?;
+        let here = self.pos;
+        self.pos = start;
+        let node = self.build(name, args);
+        if node.is_ok() {
+            self.pos = here;
+        }
+        node
+    }
+
+    fn build(&self, name: &str, args: Vec<ExprArg>) -> Result<SdfNode, String> {
+        let mut numbers = vec![];
+        let mut nodes = vec![];
+        for arg in args {
+            match arg {
+                ExprArg::Numbers(v) => numbers.push(v),
+                ExprArg::Node(n) => nodes.push(n),
+            }
+        }
+        let float = |i: usize| match numbers.get(i).map(|v| v.as_slice()) {
+            Some([x]) => Ok(*x),
+            _ => Err(self.error(format!("{}: argument {} should be a number", name, i + 1))),
+        };
+        let vec3 = |i: usize| match numbers.get(i).map(|v| v.as_slice()) {
+            Some([x]) => Ok(Vec3::new(*x, *x, *x)),
+            Some([x, y, z]) => Ok(Vec3::new(*x, *y, *z)),
+            _ => Err(self.error(format!("{}: argument {} should be 1 or 3 numbers", name, i + 1))),
+        };
+        let folded = |smooth: f32, nodes: Vec<SdfNode>| {
+            let mut nodes = nodes.into_iter();
+            let first = nodes.next().ok_or_else(|| self.error(format!("{}: needs at least 2 shapes", name)))?;
+            let mut out = first;
+            let mut joined = 0;
+            for node in nodes {
+                out = SdfNode::Union { smooth, a: Box::new(out), b: Box::new(node) };
+                joined += 1;
+            }
+            if joined == 0 {
+                return Err(self.error(format!("{}: needs at least 2 shapes", name)));
+            }
+            Ok(out)
+        };
+        let single = |mut nodes: Vec<SdfNode>| match nodes.len() {
+            1 => Ok(Box::new(nodes.pop().unwrap())),
+            _ => Err(self.error(format!("{}: needs one shape", name))),
+        };
+        let node = match name {
+            "sphere" => SdfNode::Sphere { radius: float(0)? },
+            "box" => SdfNode::Box { size: vec3(0)? },
+            "torus" => SdfNode::Torus { major: float(0)?, minor: float(1)? },
+            "capsule" => SdfNode::Capsule { a: vec3(0)?, b: vec3(1)?, radius: float(2)? },
+            "mandelbulb" => SdfNode::Mandelbulb {
+                power: if numbers.is_empty() { default_power() } else { float(0)? },
+                iterations: if numbers.len() < 2 { default_iterations() } else { float(1)? as u32 },
+            },
+            "union" => folded(0.0, nodes)?,
+            "smooth" => folded(float(0)?, nodes)?,
+            "subtract" => {
+                let mut nodes = nodes.into_iter();
+                let first = nodes.next().ok_or_else(|| self.error("subtract: needs at least 2 shapes".to_string()))?;
+                let rest: Vec<_> = nodes.collect();
+                if rest.is_empty() {
+                    return Err(self.error("subtract: needs at least 2 shapes".to_string()));
+                }
+                rest.into_iter().fold(first, |a, b| SdfNode::Subtract { a: Box::new(a), b: Box::new(b) })
+            }
+            "move" => SdfNode::Translate { offset: vec3(0)?, node: single(nodes)? },
+            "scale" => {
+                let factor = float(0)?;
+                if factor <= 0.0 {
+                    return Err(self.error("scale: factor must be positive".to_string()));
+                }
+                SdfNode::Scale { factor, node: single(nodes)? }
+            }
+            "repeat" => SdfNode::Repeat { period: vec3(0)?, node: single(nodes)? },
+            "twist" => SdfNode::Twist { degrees: float(0)?, node: single(nodes)? },
+            _ => return Err(self.error(format!("unknown function '{}'", name))),
+        };
+        Ok(node)
+    }
+}
+
+/// Parses an SDF expression of the scene format.
+///
+/// Shapes: `sphere(r)`, `box(x y z)`, `torus(major, minor)`, `capsule(a, b, r)`, `mandelbulb([power[, iterations]])`.
+/// Operations: `union(..)`, `smooth(k, ..)`, `subtract(a, ..)`, `move(x y z, s)`, `scale(f, s)`,
+/// `repeat(x y z, s)` and `twist(degrees, s)`.
+pub fn parse_sdf(src: &str) -> Result<SdfNode, String> {
+    let mut parser = ExprParser { src, pos: 0 };
+    let node = parser.call()?;
+    if parser.peek().is_some() {
+        return Err(parser.error("unexpected text after the expression".to_string()));
+    }
+    Ok(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds(r: f32) -> AaBb {
+        AaBb::new(Point3::new(-r, -r, -r), Point3::new(r, r, r))
+    }
+
+    #[test]
+    fn test_distance() {
+        let at = |x: f32, y: f32, z: f32| Point3::new(x, y, z);
+        assert_eq!(SdfNode::Box { size: Vec3::ONE }.distance(&at(3.0, 0.0, 0.0)), 2.0);
+        assert_eq!(SdfNode::Box { size: Vec3::ONE }.distance(&at(0.0, 0.5, 0.0)), -0.5);
+        assert_eq!(SdfNode::Torus { major: 2.0, minor: 0.5 }.distance(&at(0.0, 0.0, 2.0)), -0.5);
+        let capsule = SdfNode::Capsule { a: at(0.0, -1.0, 0.0), b: at(0.0, 1.0, 0.0), radius: 0.5 };
+        assert_eq!(capsule.distance(&at(0.0, 3.0, 0.0)), 1.5);
+        let repeat = parse_sdf("repeat(4 0 0, sphere(1))").unwrap();
+        assert!((repeat.distance(&at(8.0, 2.0, 0.0)) - 1.0).abs() < 1e-6);
+        // Inside the bulb and well outside of it
+        let bulb = parse_sdf("mandelbulb()").unwrap();
+        assert!(bulb.distance(&at(0.0, 0.0, 0.1)) < 1e-3);
+        assert!(bulb.distance(&at(0.0, 0.0, 3.0)) > 0.5);
+        // The smooth union swells where the shapes meet
+        let smooth = parse_sdf("smooth(0.5, sphere(1), move(2 0 0, sphere(1)))").unwrap();
+        assert!(smooth.distance(&at(1.0, 0.0, 0.0)) < 0.0);
+    }
+
+    #[test]
+    fn test_parse() {
+        let node = parse_sdf(" subtract(box(1), sphere(1.2), twist(90, scale(2, torus(1, 0.1))))").unwrap();
+        match node {
+            SdfNode::Subtract { a, b } => {
+                assert!(matches!(*a, SdfNode::Subtract { .. }));
+                assert!(matches!(*b, SdfNode::Twist { degrees, .. } if degrees == 90.0));
+            }
+            _ => panic!("{:?}", node),
+        }
+        assert_eq!(parse_sdf("blob(1)").unwrap_err(), "unknown function 'blob' at character 5");
+        assert_eq!(parse_sdf("sphere(1").unwrap_err(), "expected ')' at character 9");
+        assert_eq!(parse_sdf("union(sphere(1))").unwrap_err(), "union: needs at least 2 shapes at character 6");
+        assert_eq!(parse_sdf("box(1 2)").unwrap_err(), "box: argument 1 should be 1 or 3 numbers at character 4");
+        assert!(parse_sdf("sphere(1) x").is_err());
+        assert_eq!(parse_sdf("scale(0, sphere(1))").unwrap_err(), "scale: factor must be positive at character 6");
+        assert!(parse_sdf("scale(-2, sphere(1))").is_err());
+    }
+
+    #[test]
+    fn test_hit() {
+        let sdf = Sdf::new(parse_sdf("sphere(1)").unwrap(), bounds(1.0), None);
+        let mat = Lambertian { color: Color::ONE };
+        let mut rec = HitRecord::new(&mat);
+        let ray = Ray::new(&Point3::new(0.0, 0.0, 5.0), &Vec3::new(0.0, 0.0, -2.0));
+        assert!(sdf.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.t - 2.0).abs() < 1e-4 && rec.front_face);
+        assert!((&rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-3);
+        let ts: Vec<_> = sdf.hits(&ray, 0.001, f32::INFINITY).iter().map(|r| (r.t, r.front_face)).collect();
+        assert_eq!(ts.len(), 2);
+        assert!((ts[1].0 - 3.0).abs() < 1e-4 && !ts[1].1);
+        // Leaving from the surface only finds the far side
+        let inner = Ray::new(&Point3::new(0.0, 0.0, 1.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(sdf.hit(&inner, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.t - 2.0).abs() < 1e-4 && !rec.front_face);
+        let miss = Ray::new(&Point3::new(0.0, 1.5, 5.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(!sdf.hit(&miss, 0.001, f32::INFINITY, &mut rec));
+
+        let bulb = Sdf::new(parse_sdf("mandelbulb(8)").unwrap(), bounds(1.3), None);
+        assert!(bulb.hit(&Ray::new(&Point3::new(0.0, 0.0, 4.0), &Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::INFINITY, &mut rec));
+        assert!(rec.t > 2.7 && bulb.shape.distance(&rec.p).abs() < 1e-3);
+    }
+}
@@ -1,4 +1,6 @@
+use crate::camera::Camera;
 use crate::sampler::Distribution;
+use std::time::Duration;
 
 #[derive(Copy, Clone)]
 pub struct RenderSettings {
@@ -7,6 +9,21 @@ pub struct RenderSettings {
     pub bucket: u32,
     pub samples: u32,
     pub distribution: Distribution,
+    pub denoise: bool,
+    pub seed: u64,
+    pub time_limit: Option<Duration>,
+    /// Open and close times replacing the shutter of the scene camera
+    pub shutter: Option<(f32, f32)>,
+}
+
+impl RenderSettings {
+    /// The camera to render `camera` with, carrying the shutter of the settings if they set one
+    pub fn camera(&self, camera: &Camera) -> Camera {
+        match self.shutter {
+            Some((open, close)) => camera.clone().with_shutter(open, close),
+            None => camera.clone(),
+        }
+    }
 }
 
 pub struct SettingsBuilder {
@@ -15,6 +32,10 @@ pub struct SettingsBuilder {
     bucket: u32,
     samples: u32,
     distribution: Distribution,
+    denoise: bool,
+    seed: u64,
+    time_limit: Option<Duration>,
+    shutter: Option<(f32, f32)>,
 }
 
 impl SettingsBuilder {
@@ -25,6 +46,10 @@ impl SettingsBuilder {
             bucket: 16,
             samples: 3,
             distribution: Distribution::Random,
+            denoise: false,
+            seed: 0,
+            time_limit: None,
+            shutter: None,
         }
     }
 
@@ -48,6 +73,27 @@ impl SettingsBuilder {
         self.distribution = v;
         self
     }
+
+    pub fn denoise(mut self, v: bool) -> Self {
+        self.denoise = v;
+        self
+    }
+
+    pub fn seed(mut self, v: u64) -> Self {
+        self.seed = v;
+        self
+    }
+
+    pub fn time_limit(mut self, v: Option<Duration>) -> Self {
+        self.time_limit = v;
+        self
+    }
+
+    pub fn shutter(mut self, v: Option<(f32, f32)>) -> Self {
+        self.shutter = v;
+        self
+    }
+
     pub fn build(self) -> RenderSettings {
         RenderSettings {
             width: self.width,
@@ -55,6 +101,10 @@ impl SettingsBuilder {
             bucket: self.bucket,
             samples: self.samples,
             distribution: self.distribution,
+            denoise: self.denoise,
+            seed: self.seed,
+            time_limit: self.time_limit,
+            shutter: self.shutter,
         }
     }
 }
@@ -1,55 +1,63 @@
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::Material;
-use crate::ray::Ray;
+use crate::ray::{motion_blend, Ray};
 use crate::vec::*;
 use crate::bbox::AaBb;
+use std::sync::Arc;
 use crate::Lambertian;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
 
 pub struct Sphere {
     pub center: Point3,
     pub radius: f32,
-    pub material: Box<dyn Material>,
+    pub material: Arc<dyn Material>,
 }
 
 impl Sphere {
-    pub fn new<P: Into<Point3>>(center: P, radius: f32, mat: Option<Box<dyn Material>>) -> Sphere {
+    pub fn new<P: Into<Point3>>(center: P, radius: f32, mat: Option<Arc<dyn Material>>) -> Sphere {
         Sphere {
             center: center.into(),
             radius,
-            material: mat.unwrap_or(Box::new(Lambertian{color: Color::new(0.8, 0.8, 0.8)})),
+            material: mat.unwrap_or(Arc::new(Lambertian{color: Color::new(0.8, 0.8, 0.8)})),
         }
     }
 }
 
-impl Hittable for Sphere {
-    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
-        let oc = &ray.origin - &self.center;
-        let a = ray.direction.length_squared();
-        let half_b = oc.dot(&ray.direction);
-        let c = oc.length_squared() - self.radius * self.radius;
-        let discriminant = half_b * half_b - a * c;
-        if discriminant > 0.0 {
-            let root = discriminant.sqrt();
-            let temp = (-half_b - root) / a;
+pub(crate) fn hit_sphere<'obj>(
+    center: &Point3,
+    radius: f32,
+    material: &'obj dyn Material,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+    rec: &mut HitRecord<'obj>,
+) -> bool {
+    let oc = &ray.origin - center;
+    let a = ray.direction.length_squared();
+    let half_b = oc.dot(&ray.direction);
+    let c = oc.length_squared() - radius * radius;
+    let discriminant = half_b * half_b - a * c;
+    if discriminant > 0.0 {
+        let root = discriminant.sqrt();
+        for temp in [(-half_b - root) / a, (-half_b + root) / a] {
             if temp < t_max && temp > t_min {
-                rec.mat = self.material.as_ref();
+                rec.mat = material;
                 rec.t = temp;
+                rec.color = Color::ONE;
                 rec.p = ray.at(temp);
-                let outward_normal = (&rec.p - &self.center) / self.radius;
+                let outward_normal = (&rec.p - center) / radius;
                 rec.set_face_normal(ray, &outward_normal);
                 return true;
             }
-            let temp = (-half_b + root) / a;
-            if temp < t_max && temp > t_min {
-                rec.mat = self.material.as_ref();
-                rec.t = temp;
-                rec.p = ray.at(temp);
-                let outward_normal = (&rec.p - &self.center) / self.radius;
-                rec.set_face_normal(ray, &outward_normal);
-                return false;
-            }
         }
-        false
+    }
+    false
+}
+
+impl Hittable for Sphere {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        hit_sphere(&self.center, self.radius, self.material.as_ref(), ray, t_min, t_max, rec)
     }
 
     fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
@@ -61,7 +69,7 @@ impl Hittable for Sphere {
         Some(self.material.as_ref())
     }
 
-    fn set_material(&mut self, mat: Box<dyn Material>) {
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
         self.material = mat;
     }
 
@@ -69,4 +77,113 @@ impl Hittable for Sphere {
     fn name(&self) -> &'static str {
         "Sphere"
     }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        Ok(ObjectDesc::Sphere {
+            center: self.center.clone(),
+            radius: self.radius,
+            material: Some(writer.material(self.material.as_ref())?),
+        })
+    }
+}
+
+/// Sphere moving in a straight line from `center0` at time 0 to `center1` at time 1
+pub struct MovingSphere {
+    pub center0: Point3,
+    pub center1: Point3,
+    pub radius: f32,
+    pub material: Arc<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn new(center0: Point3, center1: Point3, radius: f32, mat: Option<Arc<dyn Material>>) -> MovingSphere {
+        MovingSphere {
+            center0,
+            center1,
+            radius,
+            material: mat.unwrap_or_else(|| Arc::new(Lambertian { color: Color::new(0.8, 0.8, 0.8) })),
+        }
+    }
+
+    pub fn center(&self, time: f32) -> Point3 {
+        let s = motion_blend(time);
+        &self.center0 * (1.0 - s) + &self.center1 * s
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        hit_sphere(&self.center(ray.time), self.radius, self.material.as_ref(), ray, t_min, t_max, rec)
+    }
+
+    /// Covers the path between the two times
+    fn bbox(&self, t0: f32, t1: f32) -> Option<AaBb> {
+        let rad = Vec3::new(self.radius, self.radius, self.radius);
+        let (c0, c1) = (self.center(t0), self.center(t1));
+        Some(AaBb::surrounding_box(&AaBb::new(&c0 - &rad, &c0 + &rad), &AaBb::new(&c1 - &rad, &c1 + &rad)))
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
+        self.material = mat;
+    }
+
+    #[inline]
+    fn name(&self) -> &'static str {
+        "MovingSphere"
+    }
+
+    fn describe(&self, writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        Ok(ObjectDesc::MovingSphere {
+            center0: self.center0.clone(),
+            center1: self.center1.clone(),
+            radius: self.radius,
+            material: Some(writer.material(self.material.as_ref())?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hit_from_inside() {
+        let sphere = Sphere::new(Point3::new(1.0, 0.0, 0.0), 2.0, None);
+        let mut rec = HitRecord::new(sphere.material.as_ref());
+        let ray = Ray::new(&Point3::new(1.0, 0.0, 0.0), &Vec3::new(1.0, 0.0, 0.0));
+        assert!(sphere.hit(&ray, 0.001, f32::INFINITY, &mut rec));
+        assert_eq!(rec.t, 2.0);
+        assert_eq!(rec.p, Point3::new(3.0, 0.0, 0.0));
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(-1.0, 0.0, 0.0));
+        // Past the far root
+        assert!(!sphere.hit(&ray, 0.001, 1.5, &mut rec));
+    }
+
+    #[test]
+    fn test_moving_sphere() {
+        let sphere = MovingSphere::new(Point3::ZERO, Point3::new(4.0, 0.0, 0.0), 1.0, None);
+        let mat = Lambertian { color: Color::ONE };
+        let mut rec = HitRecord::new(&mat);
+        let down = |x: f32, time: f32| Ray::new(&Point3::new(x, 5.0, 0.0), &Vec3::new(0.0, -1.0, 0.0)).with_time(time);
+        assert!(sphere.hit(&down(0.0, 0.0), 0.001, f32::INFINITY, &mut rec));
+        assert!(!sphere.hit(&down(0.0, 0.5), 0.001, f32::INFINITY, &mut rec));
+        assert!(sphere.hit(&down(2.0, 0.5), 0.001, f32::INFINITY, &mut rec));
+        assert_eq!(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+        // Past the end of the motion it stays at the last position
+        assert!(sphere.hit(&down(4.0, 3.0), 0.001, f32::INFINITY, &mut rec));
+        let bbox = sphere.bbox(0.0, 1.0).unwrap();
+        assert_eq!((bbox.min.x, bbox.max.x), (-1.0, 5.0));
+        let bbox = sphere.bbox(0.0, 0.5).unwrap();
+        assert_eq!(bbox.max.x, 3.0);
+
+        // Exits are found from inside too
+        let inside = Ray::new(&Point3::ZERO, &Vec3::new(0.0, 1.0, 0.0));
+        assert!(sphere.hit(&inside, 0.001, f32::INFINITY, &mut rec));
+        assert!(!rec.front_face && rec.t == 1.0);
+    }
 }
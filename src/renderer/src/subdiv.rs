@@ -0,0 +1,577 @@
+use crate::material::Material;
+use crate::mesh::TriangleMesh;
+use crate::vec::{Color, Point3, Vec3};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Polygon cage for subdivision surfaces, faces keep all their corners.
+pub struct PolyMesh {
+    pub positions: Vec<Point3>,
+    /// Per vertex normals, only used when the cage is rendered as it is
+    pub normals: Vec<Vec3>,
+    pub faces: Vec<Vec<u32>>,
+    pub uvs: Vec<[f32; 2]>,
+    /// Corners of each face into `uvs` when the uvs are split along seams, empty when they
+    /// follow the vertices
+    pub uv_faces: Vec<Vec<u32>>,
+    pub colors: Vec<Color>,
+    /// Sharp edges, each level of subdivision takes one off their sharpness
+    pub creases: Vec<([u32; 2], f32)>,
+}
+
+/// Weights of the old vertices that make a new one
+type Stencil = Vec<(u32, f32)>;
+
+fn key(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+fn blend(a: Stencil, b: Stencil, t: f32) -> Stencil {
+    let a = a.into_iter().map(|(i, w)| (i, w * (1.0 - t)));
+    a.chain(b.into_iter().map(|(i, w)| (i, w * t))).collect()
+}
+
+struct Edge {
+    v: [u32; 2],
+    faces: Vec<u32>,
+    sharpness: f32,
+}
+
+/// Adjacency of one level of the cage
+struct Topology {
+    edges: Vec<Edge>,
+    lookup: HashMap<(u32, u32), u32>,
+    vertex_edges: Vec<Vec<u32>>,
+    vertex_faces: Vec<Vec<u32>>,
+}
+
+impl Topology {
+    fn new(vertices: usize, faces: &[Vec<u32>], creases: &HashMap<(u32, u32), f32>) -> Topology {
+        let mut topo = Topology {
+            edges: vec![],
+            lookup: HashMap::new(),
+            vertex_edges: vec![vec![]; vertices],
+            vertex_faces: vec![vec![]; vertices],
+        };
+        for (f, face) in faces.iter().enumerate() {
+            for (i, &a) in face.iter().enumerate() {
+                let b = face[(i + 1) % face.len()];
+                topo.vertex_faces[a as usize].push(f as u32);
+                let edges = &mut topo.edges;
+                let vertex_edges = &mut topo.vertex_edges;
+                let e = *topo.lookup.entry(key(a, b)).or_insert_with(|| {
+                    let sharpness = creases.get(&key(a, b)).copied().unwrap_or(0.0);
+                    edges.push(Edge { v: [a, b], faces: vec![], sharpness });
+                    vertex_edges[a as usize].push(edges.len() as u32 - 1);
+                    vertex_edges[b as usize].push(edges.len() as u32 - 1);
+                    edges.len() as u32 - 1
+                });
+                topo.edges[e as usize].faces.push(f as u32);
+            }
+        }
+        topo
+    }
+
+    fn edge(&self, a: u32, b: u32) -> u32 {
+        self.lookup[&key(a, b)]
+    }
+
+    /// Boundary and non-manifold edges are always sharp
+    fn sharpness(&self, e: u32) -> f32 {
+        let edge = &self.edges[e as usize];
+        if edge.faces.len() != 2 {
+            f32::INFINITY
+        } else {
+            edge.sharpness
+        }
+    }
+
+    fn other(&self, e: u32, v: u32) -> u32 {
+        let [a, b] = self.edges[e as usize].v;
+        if a == v {
+            b
+        } else {
+            a
+        }
+    }
+
+    /// Sharp edges bend a vertex along them, three or more pin it in place like the corners of an
+    /// open cage. Semi-sharp edges blend the result with the smooth rule.
+    fn vertex_rule(&self, v: u32, smooth: Stencil) -> Stencil {
+        let sharp: Vec<u32> = self.vertex_edges[v as usize].iter().copied().filter(|e| self.sharpness(*e) > 0.0).collect();
+        if sharp.len() < 2 {
+            return smooth;
+        }
+        let rule = if sharp.len() == 2 && self.vertex_faces[v as usize].len() > 1 {
+            vec![(v, 0.75), (self.other(sharp[0], v), 0.125), (self.other(sharp[1], v), 0.125)]
+        } else {
+            vec![(v, 1.0)]
+        };
+        let strength = sharp.iter().map(|e| self.sharpness(*e).min(1.0)).sum::<f32>() / sharp.len() as f32;
+        if strength >= 1.0 {
+            rule
+        } else {
+            blend(smooth, rule, strength)
+        }
+    }
+
+    /// Sharp edges are split at their middle, semi-sharp ones blend with the smooth rule
+    fn edge_rule(&self, e: u32, smooth: impl FnOnce() -> Stencil) -> Stencil {
+        let [a, b] = self.edges[e as usize].v;
+        let mid = vec![(a, 0.5), (b, 0.5)];
+        match self.sharpness(e) {
+            s if s >= 1.0 => mid,
+            s if s <= 0.0 => smooth(),
+            s => blend(smooth(), mid, s),
+        }
+    }
+}
+
+/// One level of the cage, attributes are positions, uvs and colours as far as given
+struct Level {
+    attributes: Vec<Vec<Vec3>>,
+    faces: Vec<Vec<u32>>,
+    creases: HashMap<(u32, u32), f32>,
+}
+
+impl Level {
+    fn vertex_count(&self) -> usize {
+        self.attributes[0].len()
+    }
+
+    fn refine(&self, stencils: Vec<Stencil>, faces: Vec<Vec<u32>>, creases: HashMap<(u32, u32), f32>) -> Level {
+        let attributes = self
+            .attributes
+            .iter()
+            .map(|values| {
+                stencils
+                    .iter()
+                    .map(|s| s.iter().fold(Vec3::ZERO, |acc, (i, w)| acc + &values[*i as usize] * *w))
+                    .collect()
+            })
+            .collect();
+        Level { attributes, faces, creases }
+    }
+
+    /// Creases go on to both halves of their edge one less sharp, `point` is the new vertex of an edge
+    fn split_creases(&self, topo: &Topology, point: impl Fn(u32) -> u32) -> HashMap<(u32, u32), f32> {
+        let mut creases = HashMap::new();
+        for (&(a, b), &s) in &self.creases {
+            if s > 1.0 {
+                if let Some(&e) = topo.lookup.get(&(a, b)) {
+                    creases.insert(key(a, point(e)), s - 1.0);
+                    creases.insert(key(point(e), b), s - 1.0);
+                }
+            }
+        }
+        creases
+    }
+
+    fn loop_step(&self) -> Level {
+        let nv = self.vertex_count() as u32;
+        let topo = Topology::new(nv as usize, &self.faces, &self.creases);
+        let mut stencils = Vec::with_capacity(nv as usize + topo.edges.len());
+        for v in 0..nv {
+            let neighbours: Vec<u32> = topo.vertex_edges[v as usize].iter().map(|e| topo.other(*e, v)).collect();
+            let n = neighbours.len() as f32;
+            let smooth = if neighbours.is_empty() {
+                vec![(v, 1.0)]
+            } else {
+                let beta = if neighbours.len() == 3 { 3.0 / 16.0 } else { 3.0 / (8.0 * n) };
+                let mut s = vec![(v, 1.0 - n * beta)];
+                s.extend(neighbours.iter().map(|o| (*o, beta)));
+                s
+            };
+            stencils.push(topo.vertex_rule(v, smooth));
+        }
+        for (e, edge) in topo.edges.iter().enumerate() {
+            stencils.push(topo.edge_rule(e as u32, || {
+                let [a, b] = edge.v;
+                let mut s = vec![(a, 0.375), (b, 0.375)];
+                for f in &edge.faces {
+                    let opposite = self.faces[*f as usize].iter().find(|c| **c != a && **c != b);
+                    s.extend(opposite.map(|c| (*c, 0.125)));
+                }
+                s
+            }));
+        }
+        let point = |e: u32| nv + e;
+        let mut faces = Vec::with_capacity(self.faces.len() * 4);
+        for face in &self.faces {
+            let [a, b, c] = [face[0], face[1], face[2]];
+            let (ab, bc, ca) = (point(topo.edge(a, b)), point(topo.edge(b, c)), point(topo.edge(c, a)));
+            faces.extend([vec![a, ab, ca], vec![ab, b, bc], vec![ca, bc, c], vec![ab, bc, ca]]);
+        }
+        let creases = self.split_creases(&topo, point);
+        self.refine(stencils, faces, creases)
+    }
+
+    fn catmull_clark_step(&self) -> Level {
+        let nv = self.vertex_count() as u32;
+        let topo = Topology::new(nv as usize, &self.faces, &self.creases);
+        let ne = topo.edges.len() as u32;
+        let centroid = |f: u32| -> Stencil {
+            let face = &self.faces[f as usize];
+            face.iter().map(|c| (*c, 1.0 / face.len() as f32)).collect()
+        };
+        let mut stencils = Vec::with_capacity((nv + ne) as usize + self.faces.len());
+        for v in 0..nv {
+            let edges = &topo.vertex_edges[v as usize];
+            let faces = &topo.vertex_faces[v as usize];
+            let n = edges.len() as f32;
+            let smooth = if edges.is_empty() || faces.is_empty() {
+                vec![(v, 1.0)]
+            } else {
+                // (F + 2R + (n - 3)P) / n with F the average centroid and R the average edge middle
+                let mut s = vec![(v, (n - 3.0) / n)];
+                for e in edges {
+                    s.extend([(v, 1.0 / (n * n)), (topo.other(*e, v), 1.0 / (n * n))]);
+                }
+                for f in faces {
+                    s.extend(centroid(*f).into_iter().map(|(i, w)| (i, w / (n * faces.len() as f32))));
+                }
+                s
+            };
+            stencils.push(topo.vertex_rule(v, smooth));
+        }
+        for (e, edge) in topo.edges.iter().enumerate() {
+            stencils.push(topo.edge_rule(e as u32, || {
+                let [a, b] = edge.v;
+                let mut s = vec![(a, 0.25), (b, 0.25)];
+                for f in &edge.faces {
+                    s.extend(centroid(*f).into_iter().map(|(i, w)| (i, w * 0.25)));
+                }
+                s
+            }));
+        }
+        stencils.extend((0..self.faces.len() as u32).map(centroid));
+        let point = |e: u32| nv + e;
+        let mut faces = vec![];
+        for (f, face) in self.faces.iter().enumerate() {
+            let centre = nv + ne + f as u32;
+            for (i, &c) in face.iter().enumerate() {
+                let next = face[(i + 1) % face.len()];
+                let prev = face[(i + face.len() - 1) % face.len()];
+                faces.push(vec![c, point(topo.edge(c, next)), centre, point(topo.edge(prev, c))]);
+            }
+        }
+        let creases = self.split_creases(&topo, point);
+        self.refine(stencils, faces, creases)
+    }
+}
+
+/// Area weighted normal of a polygon
+fn face_normal(positions: &[Point3], face: &[u32]) -> Vec3 {
+    let p0 = &positions[face[0] as usize];
+    let mut n = Vec3::ZERO;
+    for k in 1..face.len() - 1 {
+        let (a, b) = (&positions[face[k] as usize], &positions[face[k + 1] as usize]);
+        n = n + Vec3::cross(&(a - p0), &(b - p0));
+    }
+    n
+}
+
+fn find(parent: &mut [u32], mut i: u32) -> u32 {
+    while parent[i as usize] != i {
+        parent[i as usize] = parent[parent[i as usize] as usize];
+        i = parent[i as usize];
+    }
+    i
+}
+
+impl PolyMesh {
+    pub fn new(positions: Vec<Point3>, faces: Vec<Vec<u32>>) -> PolyMesh {
+        PolyMesh { positions, normals: vec![], faces, uvs: vec![], uv_faces: vec![], colors: vec![], creases: vec![] }
+    }
+
+    pub fn with_uvs(mut self, uvs: Vec<[f32; 2]>) -> PolyMesh {
+        self.uvs = uvs;
+        self
+    }
+
+    /// Face-varying uvs, `uv_faces` has the index into `uvs` of every corner of `faces`
+    pub fn with_face_uvs(mut self, uvs: Vec<[f32; 2]>, uv_faces: Vec<Vec<u32>>) -> PolyMesh {
+        self.uvs = uvs;
+        self.uv_faces = uv_faces;
+        self
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Color>) -> PolyMesh {
+        self.colors = colors;
+        self
+    }
+
+    pub fn with_creases(mut self, creases: Vec<([u32; 2], f32)>) -> PolyMesh {
+        self.creases = creases;
+        self
+    }
+
+    /// Marks edges between faces turning by more than `degrees` as fully sharp
+    pub fn with_crease_angle(mut self, degrees: f32) -> PolyMesh {
+        let normals: Vec<Vec3> = self.faces.iter().map(|f| face_normal(&self.positions, f).unit()).collect();
+        let mut first: HashMap<(u32, u32), usize> = HashMap::new();
+        let cos = degrees.to_radians().cos();
+        for (f, face) in self.faces.iter().enumerate() {
+            for (i, &a) in face.iter().enumerate() {
+                let b = face[(i + 1) % face.len()];
+                match first.get(&key(a, b)) {
+                    Some(&g) if normals[f].dot(&normals[g]) < cos => self.creases.push(([a, b], f32::INFINITY)),
+                    Some(_) => {}
+                    None => {
+                        first.insert(key(a, b), f);
+                    }
+                }
+            }
+        }
+        self
+    }
+
+    /// Gives every pair of vertex and uv its own vertex, so the uvs follow the vertices
+    fn split_seams(self) -> PolyMesh {
+        let mut vertex_of: HashMap<(u32, u32), u32> = HashMap::new();
+        let (mut positions, mut normals, mut uvs, mut colors) = (vec![], vec![], vec![], vec![]);
+        let mut faces = Vec::with_capacity(self.faces.len());
+        for (face, uv_face) in self.faces.iter().zip(&self.uv_faces) {
+            let mut corners = Vec::with_capacity(face.len());
+            for (&v, &t) in face.iter().zip(uv_face) {
+                let index = *vertex_of.entry((v, t)).or_insert_with(|| {
+                    positions.push(self.positions[v as usize].clone());
+                    if !self.normals.is_empty() {
+                        normals.push(self.normals[v as usize].clone());
+                    }
+                    if !self.colors.is_empty() {
+                        colors.push(self.colors[v as usize].clone());
+                    }
+                    uvs.push(self.uvs[t as usize]);
+                    positions.len() as u32 - 1
+                });
+                corners.push(index);
+            }
+            faces.push(corners);
+        }
+        let mut cage = PolyMesh::new(positions, faces).with_uvs(uvs).with_colors(colors);
+        cage.normals = normals;
+        cage
+    }
+
+    /// Triangulates each face as a fan around its first corner
+    pub fn triangulate(self, mat: Option<Arc<dyn Material>>) -> TriangleMesh {
+        if !self.uv_faces.is_empty() {
+            return self.split_seams().triangulate(mat);
+        }
+        let mut triangles = Vec::with_capacity(self.faces.len());
+        for face in &self.faces {
+            for k in 1..face.len().saturating_sub(1) {
+                triangles.push([face[0], face[k], face[k + 1]]);
+            }
+        }
+        TriangleMesh::new(self.positions, self.normals, triangles, mat).with_uvs(self.uvs).with_colors(self.colors)
+    }
+
+    /// Smooths the cage `levels` times, with Loop's scheme when all faces are triangles and
+    /// Catmull-Clark's otherwise. Normals are averaged over the faces around each vertex and
+    /// split along the edges still sharp at the end. Face-varying uvs are smoothed on a cage of
+    /// their own, where seams are boundaries.
+    pub fn subdivide(&self, levels: u32, mat: Option<Arc<dyn Material>>) -> TriangleMesh {
+        let to_vec3 = |uv: &[f32; 2]| Vec3::new(uv[0], uv[1], 0.0);
+        let face_varying = !self.uv_faces.is_empty();
+        let mut attributes = vec![self.positions.clone()];
+        if !self.uvs.is_empty() && !face_varying {
+            attributes.push(self.uvs.iter().map(to_vec3).collect());
+        }
+        if !self.colors.is_empty() {
+            attributes.push(self.colors.clone());
+        }
+        let creases = self.creases.iter().filter(|(_, s)| *s > 0.0).map(|([a, b], s)| (key(*a, *b), *s)).collect();
+        let kept: Vec<usize> = (0..self.faces.len()).filter(|f| self.faces[*f].len() >= 3).collect();
+        let faces = kept.iter().map(|f| self.faces[*f].clone()).collect();
+        let mut level = Level { attributes, faces, creases };
+        // Both cages split their faces in the same order, so face f of one is face f of the other
+        let mut uv_level = match face_varying {
+            true => Some(Level {
+                attributes: vec![self.uvs.iter().map(to_vec3).collect()],
+                faces: kept.iter().map(|f| self.uv_faces[*f].clone()).collect(),
+                creases: HashMap::new(),
+            }),
+            false => None,
+        };
+        let triangles = level.faces.iter().all(|f| f.len() == 3);
+        let step = |level: &Level| if triangles { level.loop_step() } else { level.catmull_clark_step() };
+        for _ in 0..levels {
+            level = step(&level);
+            uv_level = uv_level.as_ref().map(step);
+        }
+        level.into_mesh(uv_level, !self.uvs.is_empty() && !face_varying, !self.colors.is_empty(), mat)
+    }
+}
+
+impl Level {
+    /// Corners around a vertex share a normal unless a sharp edge separates them, and a vertex
+    /// unless their uvs in `uv_level` differ as well
+    fn into_mesh(self, uv_level: Option<Level>, has_uvs: bool, has_colors: bool, mat: Option<Arc<dyn Material>>) -> TriangleMesh {
+        let positions = &self.attributes[0];
+        let topo = Topology::new(positions.len(), &self.faces, &self.creases);
+        let mut offsets = Vec::with_capacity(self.faces.len());
+        let mut corners = 0u32;
+        for face in &self.faces {
+            offsets.push(corners);
+            corners += face.len() as u32;
+        }
+        let corner = |f: u32, v: u32| {
+            let face = &self.faces[f as usize];
+            offsets[f as usize] + face.iter().position(|c| *c == v).unwrap() as u32
+        };
+        let mut parent: Vec<u32> = (0..corners).collect();
+        for (e, edge) in topo.edges.iter().enumerate() {
+            if topo.sharpness(e as u32) > 0.0 {
+                continue;
+            }
+            let (f, g) = (edge.faces[0], edge.faces[1]);
+            for v in edge.v {
+                let (a, b) = (find(&mut parent, corner(f, v)), find(&mut parent, corner(g, v)));
+                parent[a as usize] = b;
+            }
+        }
+        let face_normals: Vec<Vec3> = self.faces.iter().map(|f| face_normal(positions, f)).collect();
+        let mut vertex_of: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut group_normals: HashMap<u32, Vec3> = HashMap::new();
+        let (mut out_positions, mut groups, mut uvs, mut colors) = (vec![], vec![], vec![], vec![]);
+        let mut remapped = Vec::with_capacity(self.faces.len());
+        for (f, face) in self.faces.iter().enumerate() {
+            let mut corners = Vec::with_capacity(face.len());
+            for (i, &v) in face.iter().enumerate() {
+                let root = find(&mut parent, offsets[f] + i as u32);
+                let t = uv_level.as_ref().map_or(v, |l| l.faces[f][i]);
+                let index = *vertex_of.entry((root, t)).or_insert_with(|| {
+                    out_positions.push(positions[v as usize].clone());
+                    groups.push(root);
+                    if let Some(uv_level) = &uv_level {
+                        let uv = &uv_level.attributes[0][t as usize];
+                        uvs.push([uv.x, uv.y]);
+                    } else if has_uvs {
+                        let uv = &self.attributes[1][v as usize];
+                        uvs.push([uv.x, uv.y]);
+                    }
+                    if has_colors {
+                        colors.push(self.attributes[self.attributes.len() - 1][v as usize].clone());
+                    }
+                    out_positions.len() as u32 - 1
+                });
+                let normal = group_normals.entry(root).or_insert(Vec3::ZERO);
+                *normal = &*normal + &face_normals[f];
+                corners.push(index);
+            }
+            remapped.push(corners);
+        }
+        // Vertices split at a uv seam keep the normal of their group
+        let normals = groups
+            .iter()
+            .map(|root| {
+                let n = &group_normals[root];
+                if n.length_squared() > 0.0 {
+                    n.unit()
+                } else {
+                    n.clone()
+                }
+            })
+            .collect();
+        let mut cage = PolyMesh::new(out_positions, remapped).with_uvs(uvs).with_colors(colors);
+        cage.normals = normals;
+        cage.triangulate(mat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube() -> PolyMesh {
+        let positions = (0..8).map(|i| Point3::new((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32)).collect();
+        let faces = vec![
+            vec![0, 2, 3, 1],
+            vec![4, 5, 7, 6],
+            vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3],
+            vec![0, 4, 6, 2],
+            vec![1, 3, 7, 5],
+        ];
+        PolyMesh::new(positions, faces)
+    }
+
+    #[test]
+    fn test_catmull_clark() {
+        let mesh = cube().subdivide(2, None);
+        // Six quads become 96, each split in two triangles
+        assert_eq!(mesh.triangles.len(), 192);
+        assert_eq!(mesh.positions.len(), 98);
+        let centre = Point3::new(0.5, 0.5, 0.5);
+        for (p, n) in mesh.positions.iter().zip(&mesh.normals) {
+            let r = (p - &centre).length();
+            assert!(r > 0.3 && r < 0.7, "{:?}", p);
+            assert!(n.dot(&(p - &centre).unit()) > 0.8);
+        }
+        // The corners of the cube pull in to 5/9 of their distance after one level
+        let once = cube().subdivide(1, None);
+        let diagonal = Vec3::new(1.0, 1.0, 1.0).unit();
+        let corner = once.positions.iter().map(|p| (&centre - p).dot(&diagonal)).fold(0.0, f32::max);
+        assert!((corner - 0.75f32.sqrt() * 5.0 / 9.0).abs() < 1e-5);
+
+        // Fully sharp edges keep the cube
+        let sharp = cube().with_crease_angle(30.0).subdivide(2, None);
+        assert!(sharp.positions.iter().all(|p| [p.x, p.y, p.z].iter().any(|c| *c == 0.0 || *c == 1.0)));
+        assert!(sharp.normals.iter().all(|n| [n.x, n.y, n.z].iter().any(|c| (c.abs() - 1.0).abs() < 1e-6)));
+        assert!(sharp.positions.len() > mesh.positions.len());
+    }
+
+    #[test]
+    fn test_face_uvs() {
+        // Each face of the cube gets the whole uv square, so every edge is a seam
+        let square = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        let uv_faces: Vec<Vec<u32>> = (0..6).map(|f| (0..4).map(|c| f * 4 + c).collect()).collect();
+        let cube = || cube().with_face_uvs(square.iter().cycle().take(24).copied().collect(), uv_faces.clone());
+        assert_eq!(cube().triangulate(None).positions.len(), 24);
+
+        let mesh = cube().subdivide(2, None);
+        assert_eq!(mesh.triangles.len(), 192);
+        // The 98 points of the smooth cube, those on the edges split between their faces
+        let mut points: Vec<_> = mesh.positions.iter().map(|p| [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()]).collect();
+        points.sort_unstable();
+        points.dedup();
+        assert_eq!(points.len(), 98);
+        assert_eq!(mesh.positions.len(), 6 * 25);
+        // Split vertices still share the normal of the smooth surface
+        for (i, p) in mesh.positions.iter().enumerate() {
+            for (j, q) in mesh.positions.iter().enumerate().skip(i + 1) {
+                if p == q {
+                    assert!((&mesh.normals[i] - &mesh.normals[j]).length() < 1e-6);
+                }
+            }
+        }
+        assert!(mesh.uvs.iter().all(|uv| (0.0..=1.0).contains(&uv[0]) && (0.0..=1.0).contains(&uv[1])));
+        assert!(mesh.uvs.contains(&[0.5, 0.5]));
+    }
+
+    #[test]
+    fn test_loop() {
+        // Flat open square of two triangles, the boundary corners stay put
+        let positions = vec![Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(1.0, 1.0, 0.0), Point3::new(0.0, 1.0, 0.0)];
+        let square = PolyMesh::new(positions, vec![vec![0, 1, 2], vec![0, 2, 3]])
+            .with_uvs(vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]);
+        let mesh = square.subdivide(2, None);
+        assert_eq!(mesh.triangles.len(), 32);
+        assert_eq!(mesh.positions.len(), 25);
+        assert!(mesh.positions.iter().any(|p| *p == Point3::new(1.0, 0.0, 0.0)));
+        assert!(mesh.normals.iter().all(|n| (n.z - 1.0).abs() < 1e-6));
+        for (p, uv) in mesh.positions.iter().zip(&mesh.uvs) {
+            assert!((p.x - uv[0]).abs() < 1e-5 && (p.y - uv[1]).abs() < 1e-5);
+        }
+
+        // A tetrahedron shrinks a lot on its way to a rounded blob
+        let positions = vec![Point3::new(1.0, 1.0, 1.0), Point3::new(1.0, -1.0, -1.0), Point3::new(-1.0, 1.0, -1.0), Point3::new(-1.0, -1.0, 1.0)];
+        let tetra = PolyMesh::new(positions, vec![vec![0, 1, 2], vec![0, 3, 1], vec![0, 2, 3], vec![1, 3, 2]]);
+        let mesh = tetra.subdivide(3, None);
+        assert_eq!(mesh.triangles.len(), 4 * 64);
+        let radius: Vec<f32> = mesh.positions.iter().map(|p| p.length()).collect();
+        assert!(radius.iter().all(|r| *r > 0.25 && *r < 0.35));
+    }
+}
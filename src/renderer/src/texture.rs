@@ -0,0 +1,74 @@
+use crate::vec::Color;
+
+/// RGB image in linear colour, sampled bilinearly and repeating outside 0..1
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl Texture {
+    /// Interleaved 8 bit channels, one channel is grey. Colour textures are stored in sRGB.
+    pub fn from_bytes(width: u32, height: u32, channels: usize, data: &[u8], srgb: bool) -> Texture {
+        let convert = |b: u8| {
+            let v = b as f32 / 255.0;
+            if srgb {
+                srgb_to_linear(v)
+            } else {
+                v
+            }
+        };
+        let pixels = data
+            .chunks_exact(channels)
+            .map(|p| match p.len() {
+                1 | 2 => Color::new(convert(p[0]), convert(p[0]), convert(p[0])),
+                _ => Color::new(convert(p[0]), convert(p[1]), convert(p[2])),
+            })
+            .collect();
+        Texture { width, height, pixels }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> &Color {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        &self.pixels[y * self.width as usize + x]
+    }
+
+    /// `v` runs down the image like in glTF
+    pub fn sample(&self, u: f32, v: f32) -> Color {
+        let x = u * self.width as f32 - 0.5;
+        let y = v * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self.texel(x0, y0) * (1.0 - fx) + self.texel(x0 + 1, y0) * fx;
+        let bottom = self.texel(x0, y0 + 1) * (1.0 - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample() {
+        // 2x1, black and white
+        let tex = Texture::from_bytes(2, 1, 3, &[0, 0, 0, 255, 255, 255], false);
+        assert_eq!(tex.sample(0.25, 0.5), Color::ZERO);
+        assert_eq!(tex.sample(0.75, 0.5), Color::ONE);
+        assert!((tex.sample(0.5, 0.5).x - 0.5).abs() < 1e-6);
+        // Wraps around
+        assert!((tex.sample(1.0, 0.5).x - 0.5).abs() < 1e-6);
+        let srgb = Texture::from_bytes(1, 1, 4, &[188, 188, 188, 255], true);
+        assert!((srgb.sample(0.5, 0.5).x - 0.5).abs() < 0.01);
+    }
+}
@@ -3,11 +3,15 @@ use rand::Rng;
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, Index};
 use std::str::FromStr;
 use std::convert::TryFrom;
+use crate::errors::SpriosError;
+use serde::{Deserialize, Serialize};
 
 pub type Point3 = Vec3;
 pub type Color = Vec3;
 
-#[derive(Debug, Clone, PartialOrd, PartialEq, Default)]
+/// Serialized as `[x, y, z]`
+#[derive(Debug, Clone, PartialOrd, PartialEq, Default, Serialize, Deserialize)]
+#[serde(from = "[f32; 3]", into = "[f32; 3]")]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -15,18 +19,14 @@ pub struct Vec3 {
 }
 
 impl FromStr for Vec3 {
-    type Err = std::num::ParseFloatError;
+    type Err = SpriosError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let res: Result<Vec<f32>, _> = s.split(" ")
-            .map(|s| s.trim().parse::<f32>()).collect();
-        return match res {
-            Ok(r) => {
-                // NOT Okay to unwrap if len != 3
-                Ok(Self::try_from(&r).unwrap())
-            },
-            Err(e) => Err(e)
-        }
+        let values = s.split_whitespace()
+            .map(|s| s.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()?;
+        Self::try_from(&values)
+            .map_err(|_| SpriosError::WorldParseError(format!("Expected 3 values, got {}", values.len())))
     }
 }
 
@@ -40,6 +40,18 @@ impl From<(f32, f32, f32)> for Vec3 {
     }
 }
 
+impl From<[f32; 3]> for Vec3 {
+    fn from(a: [f32; 3]) -> Self {
+        Vec3::from(&a)
+    }
+}
+
+impl From<Vec3> for [f32; 3] {
+    fn from(v: Vec3) -> Self {
+        [v.x, v.y, v.z]
+    }
+}
+
 impl From<&[f32; 3]> for Vec3 {
     fn from(a: &[f32; 3]) -> Self {
         Vec3 {
@@ -118,6 +130,13 @@ impl Vec3 {
     pub fn reflect(&self, other: &Vec3) -> Vec3 {
         self - other * 2.0 * self.dot(other)
     }
+    /// Refract a unit vector through a surface with unit normal `n`, `eta_ratio` is eta_in / eta_out
+    pub fn refract(&self, n: &Vec3, eta_ratio: f32) -> Vec3 {
+        let cos_theta = (-self).dot(n).min(1.0);
+        let perp = (self + n * cos_theta) * eta_ratio;
+        let parallel = n * -(1.0 - perp.length_squared()).abs().sqrt();
+        perp + parallel
+    }
     pub fn random(rng: &mut impl rand::RngCore) -> Self {
         Self::random_in(0.0, 1.0, rng)
     }
@@ -280,3 +299,183 @@ impl<'a> DivAssign<f32> for Vec3 {
         self.z /= other;
     }
 }
+
+/// Row major 4x4 matrix for affine transforms, column vectors: `p' = M * p`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Mat4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Mat4::IDENTITY
+    }
+}
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 = Mat4 {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    pub fn new(m: [[f32; 4]; 4]) -> Mat4 {
+        Mat4 { m }
+    }
+
+    pub fn translate(v: &Vec3) -> Mat4 {
+        let mut t = Mat4::IDENTITY;
+        t.m[0][3] = v.x;
+        t.m[1][3] = v.y;
+        t.m[2][3] = v.z;
+        t
+    }
+
+    pub fn scale(v: &Vec3) -> Mat4 {
+        let mut s = Mat4::IDENTITY;
+        s.m[0][0] = v.x;
+        s.m[1][1] = v.y;
+        s.m[2][2] = v.z;
+        s
+    }
+
+    /// Rotation by `degrees` around `axis`, counter clockwise looking down the axis
+    pub fn rotate(degrees: f32, axis: &Vec3) -> Mat4 {
+        let a = axis.unit();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let k = 1.0 - cos;
+        Mat4::new([
+            [a.x * a.x * k + cos, a.x * a.y * k - a.z * sin, a.x * a.z * k + a.y * sin, 0.0],
+            [a.y * a.x * k + a.z * sin, a.y * a.y * k + cos, a.y * a.z * k - a.x * sin, 0.0],
+            [a.z * a.x * k - a.y * sin, a.z * a.y * k + a.x * sin, a.z * a.z * k + cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut t = Mat4::IDENTITY;
+        for (i, row) in self.m.iter().enumerate() {
+            for (j, v) in row.iter().enumerate() {
+                t.m[j][i] = *v;
+            }
+        }
+        t
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, None for singular matrices
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::IDENTITY.m;
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|i, j| a[*i][col].abs().partial_cmp(&a[*j][col].abs()).unwrap())?;
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let d = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= d;
+                inv[col][j] *= d;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= f * a[col][j];
+                        inv[row][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Mat4::new(inv))
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1.0 {
+            Point3::new(x, y, z)
+        } else {
+            Point3::new(x / w, y / w, z / w)
+        }
+    }
+
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// Multiplies by the transpose. Called on the inverse it maps normals out of object space.
+    pub fn transform_normal(&self, n: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+}
+
+impl Mul<&Mat4> for &Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: &Mat4) -> Mat4 {
+        let mut r = Mat4::new([[0.0; 4]; 4]);
+        for i in 0..4 {
+            for j in 0..4 {
+                r.m[i][j] = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        r
+    }
+}
+
+impl Mul<Mat4> for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        &self * &other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &Vec3, b: &Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn test_mat4() {
+        let m = Mat4::translate(&Vec3::new(1.0, 2.0, 3.0))
+            * Mat4::rotate(90.0, &Vec3::new(0.0, 1.0, 0.0))
+            * Mat4::scale(&Vec3::new(2.0, 2.0, 2.0));
+        let p = m.transform_point(&Vec3::new(1.0, 0.0, 0.0));
+        assert!(close(&p, &Vec3::new(1.0, 2.0, 1.0)));
+        assert!(close(&m.transform_vector(&Vec3::new(0.0, 0.0, 1.0)), &Vec3::new(2.0, 0.0, 0.0)));
+
+        let inv = m.inverse().unwrap();
+        assert!(close(&inv.transform_point(&p), &Vec3::new(1.0, 0.0, 0.0)));
+        let id = m * inv;
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((id.m[i][j] - Mat4::IDENTITY.m[i][j]).abs() < 1e-5);
+            }
+        }
+        assert!(Mat4::scale(&Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+        assert_eq!(m.transpose().transpose(), m);
+    }
+}
@@ -0,0 +1,395 @@
+use crate::bbox::AaBb;
+use crate::description::{ObjectDesc, SceneWriter};
+use crate::errors::SpriosError;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::medium::{collision, ray_rng, Phase};
+use crate::ray::Ray;
+use crate::vec::{Color, Point3, Vec3};
+use rand::Rng;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Dense grid of voxel values, x varies fastest and z slowest
+pub struct VoxelGrid {
+    pub dims: [usize; 3],
+    pub data: Vec<f32>,
+    /// Largest value, the majorant of the grid
+    pub max: f32,
+    /// File the grid was read from, needed to save the scene
+    pub file: Option<PathBuf>,
+}
+
+impl VoxelGrid {
+    /// Needs at least one voxel on each axis and one value per voxel
+    pub fn new(dims: [usize; 3], data: Vec<f32>) -> Result<VoxelGrid, String> {
+        if dims.contains(&0) {
+            return Err("empty volume".to_string());
+        }
+        if voxel_count(dims) != Some(data.len()) {
+            return Err(format!("{} values for {}x{}x{} voxels", data.len(), dims[0], dims[1], dims[2]));
+        }
+        let max = data.iter().cloned().fold(0.0, f32::max);
+        Ok(VoxelGrid { dims, data, max, file: None })
+    }
+
+    fn at(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.data[x + self.dims[0] * (y + self.dims[1] * z)]
+    }
+
+    /// Trilinear value at `p` in `0..1` on each axis, voxel centres sit at `(i + 0.5) / n`
+    pub fn sample(&self, p: &Point3) -> f32 {
+        let mut cell = [0usize; 3];
+        let mut frac = [0.0f32; 3];
+        for (axis, &p) in [p.x, p.y, p.z].iter().enumerate() {
+            let n = self.dims[axis];
+            let x = (p * n as f32 - 0.5).clamp(0.0, (n - 1) as f32);
+            cell[axis] = (x as usize).min(n.saturating_sub(2));
+            frac[axis] = x - cell[axis] as f32;
+        }
+        let [x, y, z] = cell;
+        let (x1, y1, z1) = ((x + 1).min(self.dims[0] - 1), (y + 1).min(self.dims[1] - 1), (z + 1).min(self.dims[2] - 1));
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let plane = |z: usize| {
+            let near = lerp(self.at(x, y, z), self.at(x1, y, z), frac[0]);
+            let far = lerp(self.at(x, y1, z), self.at(x1, y1, z), frac[0]);
+            lerp(near, far, frac[1])
+        };
+        lerp(plane(z), plane(z1), frac[2])
+    }
+}
+
+/// Voxels of a grid, `None` if the count overflows
+fn voxel_count(dims: [usize; 3]) -> Option<usize> {
+    dims[0].checked_mul(dims[1])?.checked_mul(dims[2])
+}
+
+/// Bytes per voxel of a NRRD type
+fn type_size(kind: &str) -> Option<usize> {
+    match kind {
+        "uchar" | "unsigned char" | "uint8" | "uint8_t" => Some(1),
+        "ushort" | "unsigned short" | "uint16" | "uint16_t" => Some(2),
+        "float" => Some(4),
+        _ => None,
+    }
+}
+
+/// Values as floats, integer types map to `0..1`
+fn decode(bytes: &[u8], size: usize, big_endian: bool) -> Vec<f32> {
+    match size {
+        1 => bytes.iter().map(|&b| b as f32 / 255.0).collect(),
+        2 => bytes
+            .chunks_exact(2)
+            .map(|c| if big_endian { u16::from_be_bytes([c[0], c[1]]) } else { u16::from_le_bytes([c[0], c[1]]) } as f32 / 65535.0)
+            .collect(),
+        _ => bytes
+            .chunks_exact(4)
+            .map(|c| {
+                let b = [c[0], c[1], c[2], c[3]];
+                if big_endian { f32::from_be_bytes(b) } else { f32::from_le_bytes(b) }
+            })
+            .collect(),
+    }
+}
+
+/// 3D NRRD with raw encoding and the data in the same file
+fn parse_nrrd(bytes: &[u8]) -> Result<([usize; 3], Vec<f32>), String> {
+    let mut rest = bytes;
+    let line = |rest: &mut &[u8]| -> Option<String> {
+        let end = rest.iter().position(|&b| b == b'\n')?;
+        let text = String::from_utf8_lossy(&rest[..end]).trim_end_matches('\r').to_string();
+        *rest = &rest[end + 1..];
+        Some(text)
+    };
+    if !line(&mut rest).is_some_and(|l| l.starts_with("NRRD")) {
+        return Err("not a NRRD file".to_string());
+    }
+    let (mut kind, mut sizes, mut big_endian) = (None, None, false);
+    loop {
+        let text = line(&mut rest).ok_or("header without data")?;
+        if text.is_empty() {
+            break;
+        }
+        if text.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match text.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim_start_matches('=').trim()),
+            None => return Err(format!("bad header line '{}'", text)),
+        };
+        match key {
+            "type" => kind = Some(value.to_string()),
+            "dimension" if value != "3" => return Err(format!("{} dimensions, volumes need 3", value)),
+            "sizes" => {
+                let n: Vec<usize> = value.split_whitespace().map(|s| s.parse().map_err(|_| format!("bad size '{}'", s))).collect::<Result<_, _>>()?;
+                match n.as_slice() {
+                    [x, y, z] => sizes = Some([*x, *y, *z]),
+                    _ => return Err("sizes needs 3 values".to_string()),
+                }
+            }
+            "encoding" if value != "raw" => return Err(format!("unsupported encoding '{}'", value)),
+            "endian" => big_endian = value == "big",
+            "data file" | "datafile" => return Err("detached data files are not supported".to_string()),
+            _ => {}
+        }
+    }
+    let kind: String = kind.ok_or("missing type")?;
+    let size = type_size(&kind).ok_or_else(|| format!("unsupported type '{}'", kind))?;
+    let dims = sizes.ok_or("missing sizes")?;
+    let len = voxel_count(dims).and_then(|n| n.checked_mul(size)).ok_or("sizes are too large")?;
+    if rest.len() < len {
+        return Err(format!("{} bytes of data, expected {}", rest.len(), len));
+    }
+    Ok((dims, decode(&rest[rest.len() - len..], size, big_endian)))
+}
+
+/// Read a `.nrrd` volume, or a headerless `.raw` one of `dims` 8 bit, 16 bit or float voxels
+/// told apart by the file size. Raw data is little endian.
+pub fn load_voxels(path: impl AsRef<Path>, dims: Option<[usize; 3]>) -> Result<VoxelGrid, SpriosError> {
+    let path = path.as_ref();
+    let error = |e: &dyn std::fmt::Display| SpriosError::ImportError(format!("{}: {}", path.display(), e));
+    let bytes = std::fs::read(path).map_err(|e| error(&e))?;
+    let (dims, data) = match dims {
+        None => parse_nrrd(&bytes).map_err(|e| error(&e))?,
+        Some(dims) => {
+            let n = voxel_count(dims).ok_or_else(|| error(&"dims are too large"))?;
+            if n == 0 {
+                return Err(error(&"empty volume"));
+            }
+            match bytes.len() / n {
+                1 | 2 | 4 if bytes.len() % n == 0 => (dims, decode(&bytes, bytes.len() / n, false)),
+                _ => return Err(error(&format!("{} bytes don't fit {}x{}x{} voxels", bytes.len(), dims[0], dims[1], dims[2]))),
+            }
+        }
+    };
+    let mut grid = VoxelGrid::new(dims, data).map_err(|e| error(&e))?;
+    grid.file = Some(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+    Ok(grid)
+}
+
+/// Phase function of a volume, glowing where it is hot.
+/// Collisions carry the normalized temperature in `rec.u`, volumes have no surface coordinates.
+struct VolumeMaterial {
+    phase: Phase,
+    emission: Color,
+}
+
+impl Material for VolumeMaterial {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, rng: Option<&mut dyn rand::RngCore>) -> Option<Ray> {
+        self.phase.scatter(r_in, rec, rng)
+    }
+
+    fn color(&self) -> &Color {
+        &self.phase.color
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        &self.emission * rec.u
+    }
+
+    fn eval(&self, rec: &HitRecord, wo: &Vec3, wi: &Vec3) -> Color {
+        self.phase.eval(rec, wo, wi)
+    }
+}
+
+/// Smoke or clouds from a density grid stretched over `bounds`.
+///
+/// Delta tracking against the grid maximum samples collisions, so shadow rays through the
+/// volume are blocked with the chance light is attenuated. An optional temperature grid,
+/// normalized by its maximum, scales `emission` at each collision.
+pub struct Volume {
+    pub density: Arc<VoxelGrid>,
+    pub temperature: Option<Arc<VoxelGrid>>,
+    pub bounds: AaBb,
+    /// Extinction where the density is 1
+    pub scale: f32,
+    material: VolumeMaterial,
+}
+
+impl Volume {
+    /// `color` is the single scattering albedo and `g` the Henyey-Greenstein asymmetry
+    pub fn new(density: Arc<VoxelGrid>, bounds: AaBb, scale: f32, color: Color, g: f32) -> Volume {
+        let material = VolumeMaterial { phase: Phase { color, g: g.clamp(-0.99, 0.99) }, emission: Color::ZERO };
+        Volume { density, temperature: None, bounds, scale, material }
+    }
+
+    pub fn with_temperature(mut self, temperature: Arc<VoxelGrid>, emission: Color) -> Volume {
+        self.temperature = Some(temperature);
+        self.material.emission = emission;
+        self
+    }
+
+    /// Part of `t_min..t_max` inside the bounds
+    fn span(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let (mut t0, mut t1) = (t_min, t_max);
+        let (min, max) = (&self.bounds.min, &self.bounds.max);
+        for (o, d, lo, hi) in [(ray.origin.x, ray.direction.x, min.x, max.x), (ray.origin.y, ray.direction.y, min.y, max.y), (ray.origin.z, ray.direction.z, min.z, max.z)].iter() {
+            let inv = 1.0 / d;
+            let (mut a, mut b) = ((lo - o) * inv, (hi - o) * inv);
+            if inv < 0.0 {
+                std::mem::swap(&mut a, &mut b);
+            }
+            // NaN from a zero direction on a slab edge keeps the old limits
+            t0 = if a > t0 { a } else { t0 };
+            t1 = if b < t1 { b } else { t1 };
+            if t1 <= t0 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+
+    fn local(&self, p: &Point3) -> Point3 {
+        let size = &self.bounds.max - &self.bounds.min;
+        let d = p - &self.bounds.min;
+        Point3::new(d.x / size.x, d.y / size.y, d.z / size.z)
+    }
+}
+
+impl Hittable for Volume {
+    fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
+        let majorant = self.scale * self.density.max;
+        if majorant <= 0.0 {
+            return false;
+        }
+        let (mut t, end) = match self.span(ray, t_min, t_max) {
+            Some(span) => span,
+            None => return false,
+        };
+        let mut rng = ray_rng(ray, t_min);
+        let step = 1.0 / (majorant * ray.direction.length());
+        loop {
+            t -= (1.0 - rng.gen::<f32>()).ln() * step;
+            if t >= end {
+                return false;
+            }
+            let local = self.local(&ray.at(t));
+            // Null collisions keep going, real ones are taken with the ratio to the majorant
+            if rng.gen::<f32>() * self.density.max < self.density.sample(&local) {
+                *rec = collision(&self.material.phase, ray, t);
+                rec.mat = &self.material;
+                if let Some(temperature) = &self.temperature {
+                    rec.u = if temperature.max > 0.0 { temperature.sample(&local) / temperature.max } else { 0.0 };
+                }
+                return true;
+            }
+        }
+    }
+
+    fn bbox(&self, _t0: f32, _t1: f32) -> Option<AaBb> {
+        Some(AaBb::new(self.bounds.min.clone(), self.bounds.max.clone()))
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(&self.material)
+    }
+
+    fn set_material(&mut self, _mat: Arc<dyn Material>) {}
+
+    fn name(&self) -> &'static str {
+        "Volume"
+    }
+
+    fn describe(&self, _writer: &mut SceneWriter) -> Result<ObjectDesc, SpriosError> {
+        let file = |grid: &VoxelGrid| -> Result<(String, Option<[usize; 3]>), SpriosError> {
+            let path = grid.file.as_ref().ok_or_else(|| SpriosError::SerializeError("Volume has no voxel file".to_string()))?;
+            let nrrd = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("nrrd"));
+            Ok((path.display().to_string(), if nrrd { None } else { Some(grid.dims) }))
+        };
+        let (density, dims) = file(&self.density)?;
+        let temperature = match &self.temperature {
+            Some(grid) => Some(file(grid)?),
+            None => None,
+        };
+        Ok(ObjectDesc::Volume {
+            file: density,
+            dims,
+            min: self.bounds.min.clone(),
+            max: self.bounds.max.clone(),
+            scale: self.scale,
+            color: self.material.phase.color.clone(),
+            g: self.material.phase.g,
+            temperature: temperature.as_ref().map(|t| t.0.clone()),
+            temperature_dims: temperature.and_then(|t| t.1),
+            emission: self.material.emission.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample() {
+        // Rises along x, 0 at the first voxel centre and 1 at the last
+        let data = (0..27).map(|i| (i % 3) as f32 / 2.0).collect();
+        let grid = VoxelGrid::new([3, 3, 3], data).unwrap();
+        assert_eq!(grid.max, 1.0);
+        assert!((grid.sample(&Point3::new(0.5, 0.3, 0.9)) - 0.5).abs() < 1e-6);
+        assert!((grid.sample(&Point3::new(1.0 / 3.0, 0.5, 0.5)) - 0.25).abs() < 1e-6);
+        assert_eq!(grid.sample(&Point3::new(0.0, 0.0, 0.0)), 0.0);
+        assert_eq!(grid.sample(&Point3::new(1.0, 1.0, 1.0)), 1.0);
+        let flat = VoxelGrid::new([1, 1, 1], vec![0.5]).unwrap();
+        assert_eq!(flat.sample(&Point3::new(0.2, 0.7, 0.1)), 0.5);
+        assert!(VoxelGrid::new([0, 1, 1], vec![]).is_err());
+        assert!(VoxelGrid::new([2, 1, 1], vec![0.5]).is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir().join(format!("sprios_volume_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut nrrd = b"NRRD0004\n# test\ntype: unsigned short\ndimension: 3\nsizes: 2 1 2\nendian: big\nencoding: raw\n\n".to_vec();
+        for v in [0u16, 65535, 0, 32768].iter() {
+            nrrd.extend_from_slice(&v.to_be_bytes());
+        }
+        std::fs::write(dir.join("smoke.nrrd"), &nrrd).unwrap();
+        let grid = load_voxels(dir.join("smoke.nrrd"), None).unwrap();
+        assert_eq!(grid.dims, [2, 1, 2]);
+        assert_eq!(grid.data[1], 1.0);
+        assert!((grid.data[3] - 0.5).abs() < 1e-4);
+
+        let raw: Vec<u8> = [0.25f32, 2.0].iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+        std::fs::write(dir.join("smoke.raw"), &raw).unwrap();
+        let grid = load_voxels(dir.join("smoke.raw"), Some([2, 1, 1])).unwrap();
+        assert_eq!((grid.data.clone(), grid.max), (vec![0.25, 2.0], 2.0));
+        assert!(load_voxels(dir.join("smoke.raw"), Some([3, 1, 1])).is_err());
+        std::fs::write(dir.join("bad.nrrd"), b"NRRD0004\ntype: float\ndimension: 3\nsizes: 2 2 2\nencoding: gzip\n\n").unwrap();
+        let error = load_voxels(dir.join("bad.nrrd"), None).err().unwrap().to_string();
+        assert!(error.ends_with("unsupported encoding 'gzip'"), "{}", error);
+        let huge = format!("NRRD0004\ntype: float\ndimension: 3\nsizes: {} {} 2\nencoding: raw\n\n", usize::MAX / 2, 2);
+        std::fs::write(dir.join("huge.nrrd"), huge).unwrap();
+        let error = load_voxels(dir.join("huge.nrrd"), None).err().unwrap().to_string();
+        assert!(error.ends_with("sizes are too large"), "{}", error);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hit() {
+        // Density 1 in one half of the grid and 0 in the other, through the dense half only
+        let data = (0..8).map(|i| if i % 2 == 0 { 0.0 } else { 1.0 }).collect();
+        let grid = Arc::new(VoxelGrid::new([2, 2, 2], data).unwrap());
+        let bounds = AaBb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(8.0, 2.0, 2.0));
+        let volume = Volume::new(grid, bounds, 0.5, Color::ONE, 0.0).with_temperature(Arc::new(VoxelGrid::new([1, 1, 1], vec![3.0]).unwrap()), Color::ONE);
+        let mat = crate::Lambertian { color: Color::ONE };
+        let mut rec = HitRecord::new(&mat);
+        let n = 20000;
+        let mut through = 0;
+        for i in 0..n {
+            let z = 0.1 + 1.8 * i as f32 / n as f32;
+            let ray = Ray::new(&Point3::new(6.5, -1.0, z), &Vec3::new(0.0, 1.0, 0.0));
+            if volume.hit(&ray, 0.001, f32::INFINITY, &mut rec) {
+                assert!(rec.t > 1.0 && rec.t < 3.0 && rec.u == 1.0);
+                assert_eq!(rec.mat.emitted(&rec), Color::ONE);
+            } else {
+                through += 1;
+            }
+        }
+        // 2 units at extinction 0.5
+        let expected = (-1.0f32).exp();
+        assert!((through as f32 / n as f32 - expected).abs() < 0.02, "{}", through);
+        let clear = Ray::new(&Point3::new(0.5, -1.0, 1.0), &Vec3::new(0.0, 1.0, 0.0));
+        assert!(!volume.hit(&clear, 0.001, f32::INFINITY, &mut rec));
+    }
+}
@@ -4,8 +4,13 @@ use crate::errors::SpriosError;
 use std::sync::Arc;
 use crate::bbox::AaBb;
 use std::path::Path;
-use std::io::Read;
 use std::rc::Rc;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use crate::bvh::Bvh;
+use crate::description::{SceneDescription, SceneFormat};
+use crate::light::Light;
+use crate::medium::Fog;
 
 trait Foo: Send + Sync {}
 
@@ -13,22 +18,24 @@ pub struct World {
     pub objects: Vec<Arc<dyn Hittable>>,
     pub camera: Camera,
     pub background: Color,
+    /// Uniform light for rays that miss, replaces the sky gradient
+    pub environment: Option<Color>,
+    /// Atmosphere scattering light between surfaces
+    pub fog: Option<Fog>,
+    /// Material table, objects share entries through the Arc
+    pub materials: Vec<Arc<dyn Material>>,
+    material_names: HashMap<String, usize>,
+    pub lights: Vec<Light>,
+    /// Top level BVH over `objects`, built on the first hit. `add` resets it,
+    /// call `invalidate` after changing `objects` directly.
+    accel: OnceLock<Bvh>,
 }
 
 impl Hittable for World {
     fn hit<'obj>(&'obj self, ray: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'obj>) -> bool {
-        let mut temp_rec = rec.clone();
-        let mut been_hit = false;
-        let mut closest_so_far = t_max;
-
-        for obj in &self.objects {
-            if obj.hit(ray, t_min, closest_so_far, &mut temp_rec) {
-                been_hit = true;
-                closest_so_far = temp_rec.t;
-                *rec = temp_rec.clone();
-            }
-        }
-        been_hit
+        self.accel
+            .get_or_init(|| Bvh::new(self.objects.clone()))
+            .hit(ray, t_min, t_max, rec)
     }
 
     fn bbox(&self, t0: f32, t1: f32) -> Option<AaBb> {
@@ -57,7 +64,7 @@ impl Hittable for World {
         None
     }
 
-    fn set_material(&mut self, mat: Box<dyn Material>) {
+    fn set_material(&mut self, mat: Arc<dyn Material>) {
     }
 
     fn name(&self) -> &'static str {
@@ -67,7 +74,17 @@ impl Hittable for World {
 
 impl World {
     pub fn new() -> World {
-        World { objects: vec![], camera: Camera::default(), background: Color::new(0.5, 0.7, 1.0) }
+        World {
+            objects: vec![],
+            camera: Camera::default(),
+            background: Color::new(0.5, 0.7, 1.0),
+            environment: None,
+            fog: None,
+            materials: vec![],
+            material_names: HashMap::new(),
+            lights: vec![],
+            accel: OnceLock::new(),
+        }
     }
     /*
         Why 'static is needed here??
@@ -76,38 +93,58 @@ impl World {
         which requires the data to be 'static
      */
     pub fn add(&mut self, object: Arc<dyn Hittable>) {
-        self.objects.push(object)
+        self.objects.push(object);
+        self.invalidate();
     }
 
-    pub fn from_file(path: impl AsRef<Path>) -> Result<World, SpriosError> {
-        let mut file = std::fs::File::open(path)?;
-        let mut content = String::new();
-        file.read_to_string(&mut content);
+    pub fn invalidate(&mut self) {
+        self.accel = OnceLock::new();
+    }
 
-        let mut world = World::new();
-        let mut material = None;
-        for line in content.lines() {
-            if line.starts_with('#') || line.is_empty() {
-                continue;
-            }
-            if let Ok(cam) = line.parse::<Camera>() {
-                world.camera = cam;
-            } else if let Ok(mut obj) =  crate::hittable::from_string(line){
-                if material.is_some() {
-                    Arc::get_mut(&mut obj).unwrap().set_material(material.take().unwrap())
-                }
-                world.add(obj);
-
-            } else if line.starts_with("background") {
-                world.background = line.splitn(2, " ").nth(1)
-                    .ok_or(SpriosError::WorldParseError("background".to_string()))?.parse()?;
-            } else if let Ok(mat) = line.parse::<Box<dyn Material>>() {
-                material = Some(mat);
-            } else {
-                return Err(SpriosError::WorldParseError(format!("Could not parse line: {}", line)))
-            }
+    /// Add a material to the table, a named material replaces an earlier one with the same name
+    pub fn add_material(&mut self, name: Option<&str>, material: Arc<dyn Material>) -> Arc<dyn Material> {
+        self.materials.push(Arc::clone(&material));
+        if let Some(name) = name {
+            self.material_names.insert(name.to_string(), self.materials.len() - 1);
         }
-        Ok(world)
+        material
+    }
+
+    pub fn find_material(&self, name: &str) -> Option<Arc<dyn Material>> {
+        self.material_names.get(name).map(|i| Arc::clone(&self.materials[*i]))
+    }
+
+    /// Materials of the table that have a name, sorted by name
+    pub fn named_materials(&self) -> Vec<(&str, &Arc<dyn Material>)> {
+        let mut named: Vec<_> = self.material_names.iter().map(|(k, v)| (k.as_str(), &self.materials[*v])).collect();
+        named.sort_by(|a, b| a.0.cmp(b.0));
+        named
+    }
+
+    /// Reads `.rsc` scenes, scene descriptions from `.json`, `.toml` and `.ron` files,
+    /// glTF assets, PLY meshes and pbrt-v3 scenes. `load_pbrt` also returns the warnings of the pbrt importer.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<World, SpriosError> {
+        let path = path.as_ref();
+        if path.extension().is_some_and(|e| e == "gltf" || e == "glb") {
+            return Ok(crate::gltf_import::import_gltf(path)?.into_world());
+        }
+        if path.extension().is_some_and(|e| e == "ply") {
+            let mut world = World::new();
+            world.add(Arc::new(crate::ply::load_ply(path, None)?));
+            return Ok(world);
+        }
+        if path.extension().is_some_and(|e| e == "pbrt") {
+            return Ok(crate::pbrt::load_pbrt(path)?.world);
+        }
+        match SceneFormat::from_path(path) {
+            Some(_) => SceneDescription::load(path)?.to_world(),
+            None => crate::loader::load_file(path),
+        }
+    }
+
+    /// Save as a scene description, the format follows the extension of `path`
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SpriosError> {
+        SceneDescription::from_world(self)?.save(path)
     }
 }
 
@@ -123,23 +160,36 @@ mod tests {
         let mut world = World::new();
         world.add(
             Arc::new(Sphere::new((0.0, 0.0, 0.0), 0.5,
-                        Some(Box::new(Lambertian { color: Color::ONE })))),
+                        Some(Arc::new(Lambertian { color: Color::ONE })))),
         );
         world.add(
             Arc::new(Sphere::new((1.0, 0.0, 0.0), 0.5,
-                        Some(Box::new(Lambertian { color: Color::ONE })))),
+                        Some(Arc::new(Lambertian { color: Color::ONE })))),
         );
         let bbox = world.bbox(0.0, 0.0).unwrap();
         assert_eq!(&bbox.min, &Point3::new(-0.5, -0.5, -0.5));
         assert_eq!(&bbox.max, &Point3::new(1.5, 0.5, 0.5));
         world.add(
             Arc::new(Sphere::new((1.0, 1.0, 0.0), 0.5,
-                        Some(Box::new(Lambertian { color: Color::ONE })))),
+                        Some(Arc::new(Lambertian { color: Color::ONE })))),
         );
         let bbox = world.bbox(0.0, 0.0).unwrap();
         assert_eq!(&bbox.max, &Point3::new(1.5, 1.5, 0.5));
     }
 
+    #[test]
+    fn test_materials() {
+        let mut world = World::new();
+        let grey = world.add_material(Some("grey"), Arc::new(Lambertian { color: Color::new(0.5, 0.5, 0.5) }));
+        world.add_material(None, Arc::new(Lambertian { color: Color::ONE }));
+        for x in 0..3 {
+            world.add(Arc::new(Sphere::new((x as f32, 0.0, 0.0), 0.5, world.find_material("grey"))));
+        }
+        assert_eq!(world.materials.len(), 2);
+        assert_eq!(Arc::strong_count(&grey), 5);
+        assert!(world.find_material("red").is_none());
+    }
+
     #[test]
     fn test_read_file() {
         assert!(World::from_file(Path::new("Foo")).is_err());
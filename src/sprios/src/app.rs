@@ -4,22 +4,22 @@ use gdk_pixbuf::{PixbufLoader};
 use gio::ApplicationExt;
 use glib::{clone};
 use glib::signal::Inhibit;
-use gtk::{ApplicationWindow, Box as GtkBox, BoxExt, Button, ButtonExt, ContainerExt, GtkWindowExt, Image, ImageExt, Label, LabelExt, Orientation, Paned, PanedExt, ProgressBar, ProgressBarExt, SpinButton, SpinButtonExt, WidgetExt, ComboBoxText, ComboBoxTextExt};
+use gtk::{ApplicationWindow, Box as GtkBox, BoxExt, Button, ButtonExt, CheckButton, ContainerExt, ToggleButtonExt, GtkWindowExt, Image, ImageExt, Label, LabelExt, Orientation, Paned, PanedExt, ProgressBar, ProgressBarExt, SpinButton, SpinButtonExt, WidgetExt, ComboBoxText, ComboBoxTextExt};
 use gdk_pixbuf::PixbufLoaderExt;
 use glib::Bytes;
 use num_cpus;
-use renderer::{render, RenderStats, SettingsBuilder, Camera, Vec3, Point3, Distribution, SampleStat, RenderEvent};
+use renderer::{render, RenderStats, RenderSettings, SettingsBuilder, Camera, Vec3, Point3, Distribution, SampleStat, RenderEvent, FeatureBuffers, denoise};
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::atomic::AtomicPtr;
-use std::sync::{Arc};
+use std::sync::Arc;
 use threadpool::{ThreadPool};
 use gtk::prelude::ComboBoxExtManual;
 use viewer::ImageViewer;
 
 static LOGO: &[u8;33647] = &include_bytes!("../rust-logo.png");
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub enum Event {
     Progress(u32),
     RenderEvent(RenderEvent),
@@ -83,6 +83,9 @@ impl App {
         fov_label.set_sensitive(false);
         fov.set_value(20.0);
 
+        // Denoise progressive snapshots
+        let denoise_btn = CheckButton::new_with_label("Denoise");
+
         // Logo
         let logo = Image::new();
         let loader = PixbufLoader::new_with_type("png").unwrap();
@@ -140,6 +143,7 @@ impl App {
         left_panel.pack_start(&res_box, false, true, 3);
         left_panel.pack_start(&fov_box, false, true, 3);
         left_panel.pack_start(&aperture_box, false, true, 3);
+        left_panel.pack_start(&denoise_btn, false, true, 3);
         left_panel.pack_end(&render_btn, false, true, 3);
         left_panel.pack_end(&progress, false, true, 3);
         left_panel.pack_end(&logo, false, true, 3);
@@ -150,12 +154,17 @@ impl App {
         let (sx, rx) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
         let progress_clone = progress.clone();
         let image_buf = Rc::new(RefCell::new(Vec::<f32>::new()));
+        let features = Rc::new(RefCell::new(Option::<FeatureBuffers>::None));
+        // What the guide buffers of the current render are made from, once denoising asks for them
+        let guide_source = Rc::new(RefCell::new(Option::<(RenderSettings, Arc<World>, usize)>::None));
         let thread_pool = RefCell::new(ThreadPool::new(num_cpus::get_physical()));
         render_btn.connect_clicked(
             clone!(@weak image_buf,
                      @weak res_width,
                      @weak sampler,
                      @strong thread_pool,
+                     @strong features,
+                     @strong guide_source,
                      @weak fov,
                      @weak aperture => move |_| {
             let distrib = match sampler.get_active_text() {
@@ -177,6 +186,7 @@ impl App {
                 .build();
             let mut world = World::from_file("scene_1.rsc").unwrap();
             let world = Arc::new(world);
+            features.borrow_mut().take();
 
             let cap = (settings.width * settings.height * 3) as usize;
             image_buf.borrow_mut().resize(cap, 0.0);
@@ -185,6 +195,7 @@ impl App {
             let event_sx = sx.clone();
             let buffer_ptr = Arc::new(AtomicPtr::new(image_buf.borrow_mut().as_mut_ptr()));
             let num_threads = num_threads.get_value() as usize;
+            guide_source.borrow_mut().replace((settings, Arc::clone(&world), num_threads));
             std::thread::spawn(
                 clone!(@strong sx, @strong thread_pool, @strong world, @strong event_sx => move || {
                 let stats = render(
@@ -199,7 +210,7 @@ impl App {
                 event_sx.send(Event::RenderEvent(RenderEvent::Completed(stats))).unwrap();
             }));
         }));
-        rx.attach(None, clone!(@strong image_buf, @strong render_view, @strong stat_label => move |event| {
+        rx.attach(None, clone!(@strong image_buf, @strong render_view, @strong stat_label, @strong features, @strong guide_source => move |event| {
             match event {
                 Event::RenderEvent(rv) => {
                     match rv {
@@ -207,7 +218,19 @@ impl App {
                             stat_label.set_text(&format!("Time: {:.2} sec | FPS: {:.2} | MRays: {:.2}", stat.render_time, stat.fps, stat.mrays));
                         }
                         RenderEvent::SampleDone(stat) => {
-                            let bytes = utils::convert_buffer(&image_buf.borrow(), stat.sample);
+                            // The first denoised snapshot of a render makes its guide buffers
+                            if denoise_btn.get_active() && features.borrow().is_none() {
+                                if let Some((settings, world, num_threads)) = guide_source.borrow_mut().take() {
+                                    features.borrow_mut().replace(FeatureBuffers::render(&settings, &world, num_threads));
+                                }
+                            }
+                            let bytes = match (denoise_btn.get_active(), features.borrow().as_ref()) {
+                                (true, Some(features)) => {
+                                    let denoised = denoise(&image_buf.borrow(), stat.sample, features);
+                                    utils::convert_buffer(&denoised, stat.sample)
+                                }
+                                _ => utils::convert_buffer(&image_buf.borrow(), stat.sample)
+                            };
                             let loader = PixbufLoader::new_with_type("pnm").unwrap();
                             let image_width = res_width.get_value() as u32;
                             let image_height = (image_width as f32 / ASPECT_RATIO) as u32;
@@ -223,6 +246,7 @@ impl App {
                             let frac = num as f64 / 100 as f64;
                             progress.set_fraction(frac);
                         }
+                        RenderEvent::CheckpointFailed(_) => {}
                     }
                 }
                 _ => {}
@@ -0,0 +1,438 @@
+use crate::image_io::{to_display, Format, Image};
+use crate::worlds;
+use renderer::{
+    check_shutter, load_pbrt, render, render_from, resume, Camera, CheckpointConfig, Material, PbrtScene, Point3, RenderEvent,
+    SceneDescription, SceneFormat, SettingsBuilder, SettingsDesc, Vec3, World,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const USAGE: &str = "Usage: sprios <command> [options]
+
+Commands:
+    render   Render a scene to an image (default when the first argument is a scene)
+    info     Print objects, materials, bounds and camera of a scene
+    convert  Convert between image formats, or save a scene as json, toml or ron
+    diff     Compare two images, report RMSE, PSNR and SSIM
+    bench    Render the standard scenes and report Mrays/s
+
+Run `sprios <command> --help` for the options of a command.";
+
+fn opt_value<T: std::str::FromStr>(args: &getopts::Matches, name: &str, default: T) -> Result<T, String> {
+    match args.opt_str(name) {
+        Some(s) => s.parse().map_err(|_| format!("Invalid value for --{}: {}", name, s)),
+        None => Ok(default),
+    }
+}
+
+fn parse(opts: &mut getopts::Options, args: Vec<String>, brief: &str) -> Result<Option<getopts::Matches>, String> {
+    opts.optflag("h", "help", "print help");
+    let matches = opts.parse(args).map_err(|e| e.to_string())?;
+    if matches.opt_present("h") {
+        println!("{}", opts.usage(brief));
+        return Ok(None);
+    }
+    Ok(Some(matches))
+}
+
+/// Scene file, or one of the built-in worlds: @ivan, @book, @final
+pub fn load_scene(path: &str) -> Result<World, String> {
+    let camera = || {
+        Camera::new(
+            Point3::new(13.0, 2.0, 3.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            20.0,
+            16.0 / 9.0,
+            0.1,
+            10.0,
+        )
+    };
+    let mut world = match path {
+        "@ivan" => worlds::world_ivan(&Vec3::new(-0.5, -0.5, -0.5), 0.5, 2, 3),
+        "@book" => worlds::world_book(),
+        "@final" => worlds::final_world(),
+        _ if Path::new(path).extension().is_some_and(|e| e == "pbrt") => return Ok(load_pbrt_scene(path)?.world),
+        _ => return World::from_file(path).map_err(|e| e.to_string()),
+    };
+    world.camera = camera();
+    Ok(world)
+}
+
+/// Like `load_scene`, but also returns the render settings saved in scene descriptions
+fn load_scene_settings(path: &str) -> Result<(World, Option<SettingsDesc>), String> {
+    if Path::new(path).extension().is_some_and(|e| e == "pbrt") {
+        let scene = load_pbrt_scene(path)?;
+        return Ok((scene.world, Some(scene.settings)));
+    }
+    if SceneFormat::from_path(Path::new(path)).is_none() {
+        return Ok((load_scene(path)?, None));
+    }
+    let desc = SceneDescription::load(path).map_err(|e| e.to_string())?;
+    Ok((desc.to_world().map_err(|e| e.to_string())?, desc.settings))
+}
+
+/// pbrt-v3 scene, the importer's warnings about skipped features go to stderr
+fn load_pbrt_scene(path: &str) -> Result<PbrtScene, String> {
+    let scene = load_pbrt(path).map_err(|e| e.to_string())?;
+    for warning in &scene.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    Ok(scene)
+}
+
+fn is_scene(path: &Path) -> bool {
+    path.to_str().is_some_and(|p| p.starts_with('@'))
+        || path.extension().is_some_and(|e| e == "rsc" || e == "gltf" || e == "glb" || e == "ply" || e == "pbrt")
+        || SceneFormat::from_path(path).is_some()
+}
+
+fn fmt_vec(v: &Vec3) -> String {
+    format!("({:.3}, {:.3}, {:.3})", v.x, v.y, v.z)
+}
+
+pub fn render_cmd(args: Vec<String>) -> Result<(), String> {
+    let mut opts = getopts::Options::new();
+    opts.optopt("o", "output", "Output image, default image.png", "PATH");
+    opts.optopt("f", "format", "Output format: png, ppm, pfm, exr. Default from extension", "FORMAT");
+    opts.optopt("w", "width", "Image width", "WIDTH");
+    opts.optopt("H", "height", "Image height, default keeps 16:9", "HEIGHT");
+    opts.optopt("s", "samples", "Pixel samples (squared)", "SAMPLES");
+    opts.optopt("t", "threads", "Number of threads", "THREADS");
+    opts.optopt("b", "bucket", "Bucket size", "BUCKET");
+    opts.optopt("", "seed", "Random seed", "SEED");
+    opts.optopt("", "time-limit", "Stop after the pass running when the limit is hit", "SECONDS");
+    opts.optopt("", "shutter", "Shutter open and close times within 0..1, default from the camera", "OPEN,CLOSE");
+    opts.optflag("d", "denoise", "Denoise the final image");
+    opts.optopt("c", "checkpoint", "Write progress to a checkpoint file", "PATH");
+    opts.optopt("", "checkpoint-interval", "Seconds between checkpoints, default 60", "SECONDS");
+    opts.optflag("r", "resume", "Continue from the checkpoint file");
+    let args = match parse(&mut opts, args, "Usage: sprios render [options] SCENE")? {
+        Some(args) => args,
+        None => return Ok(()),
+    };
+    let scene = args.free.first().ok_or("Missing scene, see sprios render --help")?;
+    let output = PathBuf::from(args.opt_str("o").unwrap_or_else(|| "image.png".to_string()));
+    let format = match args.opt_str("f") {
+        Some(f) => Format::from_name(&f).ok_or(format!("Unknown image format {}", f))?,
+        None => Format::from_path(&output).ok_or(format!("Can't guess image format of {}", output.display()))?,
+    };
+    let (mut world, file_settings) = load_scene_settings(scene)?;
+    // Settings saved with the scene replace the defaults, options replace both
+    let file_settings = file_settings.unwrap_or_default();
+    let mut builder = file_settings.apply(SettingsBuilder::new().size(720, None).bucket(32).samples(10));
+    if args.opt_present("width") || args.opt_present("height") {
+        let width: u32 = opt_value(&args, "width", file_settings.width.unwrap_or(720))?;
+        let height: Option<u32> = match args.opt_str("height") {
+            Some(_) => Some(opt_value(&args, "height", 0)?),
+            None => None,
+        };
+        builder = builder.size(width, height);
+    }
+    if args.opt_present("samples") {
+        builder = builder.samples(opt_value(&args, "samples", 10)?);
+    }
+    if args.opt_present("bucket") {
+        builder = builder.bucket(opt_value(&args, "bucket", 32)?);
+    }
+    if args.opt_present("seed") {
+        builder = builder.seed(opt_value(&args, "seed", 0)?);
+    }
+    if let Some(s) = args.opt_str("shutter") {
+        let times: Vec<f32> = s.split(',').map(|t| t.trim().parse()).collect::<Result<_, _>>().map_err(|_| format!("Invalid value for --shutter: {}", s))?;
+        match times.as_slice() {
+            [open, close] if check_shutter(*open, *close).is_ok() => builder = builder.shutter(Some((*open, *close))),
+            _ => return Err(format!("Invalid value for --shutter: {}", s)),
+        }
+    }
+    if args.opt_present("d") {
+        builder = builder.denoise(true);
+    }
+    let num_threads: usize = opt_value(&args, "threads", num_cpus::get())?;
+    let time_limit = match args.opt_str("time-limit") {
+        Some(_) => Some(Duration::from_secs_f32(opt_value(&args, "time-limit", 0.0)?)),
+        None => None,
+    };
+    let settings = builder.time_limit(time_limit).build();
+
+    world.camera.set_aspect_ratio(settings.width as f32 / settings.height as f32);
+    let world = Arc::new(world);
+    let checkpoint = match args.opt_str("checkpoint") {
+        Some(path) => {
+            let interval = Duration::from_secs_f32(opt_value(&args, "checkpoint-interval", 60.0)?);
+            Some(CheckpointConfig::new(path, interval, &world))
+        }
+        None => None,
+    };
+
+    let mut film = vec![0.0f32; (settings.width * settings.height * 3) as usize];
+    let film_ptr = Arc::new(AtomicPtr::new(film.as_mut_ptr()));
+    let total = settings.samples.pow(2);
+    let pass = Arc::new(AtomicU32::new(1));
+    let progress = move |event| match event {
+        RenderEvent::Percent(p) => {
+            eprint!("\rSample {}/{} {:3}%", pass.load(Ordering::Relaxed), total, p);
+            std::io::stderr().flush().unwrap();
+        }
+        RenderEvent::SampleDone(s) => pass.store(s.sample + 1, Ordering::Relaxed),
+        RenderEvent::Completed(_) => {}
+        RenderEvent::CheckpointFailed(e) => eprintln!("\nCould not write checkpoint: {}", e),
+    };
+    let stat = match checkpoint {
+        Some(config) if args.opt_present("resume") => {
+            resume(config, settings, film_ptr, num_threads, Arc::clone(&world), progress)
+                .map_err(|e| e.to_string())?
+        }
+        None if args.opt_present("resume") => return Err("--resume needs --checkpoint".to_string()),
+        checkpoint => render_from(settings, film_ptr, num_threads, Arc::clone(&world), 0, checkpoint, progress),
+    };
+
+    eprintln!("\nSaving {}", output.display());
+    Image::from_film(&film, settings.width, settings.height, stat.samples).save(&output, format)?;
+    eprintln!(
+        "Time: {:.2} sec | Samples: {} | MRays: {:.2}",
+        stat.render_time, stat.samples, stat.mrays
+    );
+    Ok(())
+}
+
+pub fn info(args: Vec<String>) -> Result<(), String> {
+    let mut opts = getopts::Options::new();
+    let args = match parse(&mut opts, args, "Usage: sprios info SCENE")? {
+        Some(args) => args,
+        None => return Ok(()),
+    };
+    let scene = args.free.first().ok_or("Missing scene, see sprios info --help")?;
+    let world = load_scene(scene)?;
+
+    let mut kinds = BTreeMap::new();
+    let mut materials = HashSet::new();
+    for obj in &world.objects {
+        *kinds.entry(obj.name()).or_insert(0) += 1;
+        if let Some(mat) = obj.material() {
+            materials.insert(mat as *const dyn Material as *const u8 as usize);
+        }
+    }
+    let kinds: Vec<String> = kinds.iter().map(|(k, n)| format!("{}: {}", k, n)).collect();
+    println!("Scene:      {}", scene);
+    println!("Objects:    {} ({})", world.objects.len(), kinds.join(", "));
+    println!("Materials:  {}", materials.len());
+    use renderer::Hittable;
+    match world.bbox(0.0, 1.0) {
+        Some(b) => println!("Bounds:     {} - {}", fmt_vec(&b.min), fmt_vec(&b.max)),
+        None => println!("Bounds:     unbounded"),
+    }
+    let cam = &world.camera;
+    let center = &cam.lower_left_corner + &cam.horizontal / 2.0 + &cam.vertical / 2.0;
+    let focus = (&center - &cam.origin).length();
+    let vfov = 2.0 * (cam.vertical.length() / 2.0 / focus).atan().to_degrees();
+    println!("Camera:     from {} at {}", fmt_vec(&cam.origin), fmt_vec(&center));
+    println!(
+        "            vfov {:.1}, aspect {:.3}, aperture {:.3}, focus {:.3}",
+        vfov,
+        cam.horizontal.length() / cam.vertical.length(),
+        cam.lens_radius * 2.0,
+        focus
+    );
+    println!("Background: {}", fmt_vec(&world.background));
+    Ok(())
+}
+
+pub fn convert(args: Vec<String>) -> Result<(), String> {
+    let mut opts = getopts::Options::new();
+    opts.optopt("f", "format", "Output format, default from extension", "FORMAT");
+    let args = match parse(&mut opts, args, "Usage: sprios convert [options] INPUT OUTPUT\n\nImages convert to other image formats, scenes and built-in worlds (@ivan, @book, @final) are saved as scene descriptions.")? {
+        Some(args) => args,
+        None => return Ok(()),
+    };
+    if args.free.len() != 2 {
+        return Err("convert needs an input and an output, see sprios convert --help".to_string());
+    }
+    let (input, output) = (Path::new(&args.free[0]), Path::new(&args.free[1]));
+    if is_scene(input) {
+        if SceneFormat::from_path(output).is_none() {
+            return Err(format!("Scenes are saved as .json, .toml or .ron, not {}", output.display()));
+        }
+        return load_scene(&args.free[0])?.save(output).map_err(|e| e.to_string());
+    }
+    if is_scene(output) {
+        return Err(format!("{} is not a scene", input.display()));
+    }
+    let format = match args.opt_str("f") {
+        Some(f) => Format::from_name(&f).ok_or(format!("Unknown image format {}", f))?,
+        None => Format::from_path(output).ok_or(format!("Can't guess image format of {}", output.display()))?,
+    };
+    Image::load(input)?.save(output, format)
+}
+
+fn luminance(values: &[f32]) -> Vec<f32> {
+    values.chunks(3).map(|p| 0.2126 * p[0] + 0.7152 * p[1] + 0.0722 * p[2]).collect()
+}
+
+/// Root mean square of the differences
+fn rmse(a: &[f32], b: &[f32]) -> f64 {
+    let sum_sq: f64 = a.iter().zip(b).map(|(x, y)| (*x as f64 - *y as f64).powi(2)).sum();
+    (sum_sq / a.len().max(1) as f64).sqrt()
+}
+
+/// Mean SSIM over 8x8 windows of the luminance, `peak` is the dynamic range of the values.
+fn ssim(la: &[f32], lb: &[f32], width: u32, height: u32, peak: f64) -> f64 {
+    const WIN: u32 = 8;
+    const STRIDE: u32 = 4;
+    let c1 = (0.01 * peak) * (0.01 * peak);
+    let c2 = (0.03 * peak) * (0.03 * peak);
+    let mut total = 0.0;
+    let mut count = 0;
+    let mut y = 0;
+    while y + WIN <= height {
+        let mut x = 0;
+        while x + WIN <= width {
+            let (mut ma, mut mb, mut va, mut vb, mut cov) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for j in y..y + WIN {
+                for i in x..x + WIN {
+                    let idx = (j * width + i) as usize;
+                    ma += la[idx] as f64;
+                    mb += lb[idx] as f64;
+                }
+            }
+            let n = (WIN * WIN) as f64;
+            ma /= n;
+            mb /= n;
+            for j in y..y + WIN {
+                for i in x..x + WIN {
+                    let idx = (j * width + i) as usize;
+                    let (da, db) = (la[idx] as f64 - ma, lb[idx] as f64 - mb);
+                    va += da * da;
+                    vb += db * db;
+                    cov += da * db;
+                }
+            }
+            va /= n - 1.0;
+            vb /= n - 1.0;
+            cov /= n - 1.0;
+            total += ((2.0 * ma * mb + c1) * (2.0 * cov + c2)) / ((ma * ma + mb * mb + c1) * (va + vb + c2));
+            count += 1;
+            x += STRIDE;
+        }
+        y += STRIDE;
+    }
+    if count == 0 {
+        1.0
+    } else {
+        total / count as f64
+    }
+}
+
+fn heat(t: f32) -> [f32; 3] {
+    // Black - blue - red - yellow - white
+    let stops = [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [1.0, 1.0, 1.0]];
+    let t = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+    let i = (t as usize).min(stops.len() - 2);
+    let f = t - i as f32;
+    let mut c = [0.0; 3];
+    for k in 0..3 {
+        c[k] = stops[i][k] * (1.0 - f) + stops[i + 1][k] * f;
+    }
+    c
+}
+
+pub fn diff(args: Vec<String>) -> Result<(), String> {
+    let mut opts = getopts::Options::new();
+    opts.optopt("o", "output", "Difference heatmap, default diff.png", "PATH");
+    opts.optflag("", "display", "Compare the values as shown on screen, gamma corrected and clipped to 1");
+    let args = match parse(&mut opts, args, "Usage: sprios diff [options] A B")? {
+        Some(args) => args,
+        None => return Ok(()),
+    };
+    if args.free.len() != 2 {
+        return Err("diff needs two images, see sprios diff --help".to_string());
+    }
+    let a = Image::load(Path::new(&args.free[0]))?;
+    let b = Image::load(Path::new(&args.free[1]))?;
+    if a.width != b.width || a.height != b.height {
+        return Err(format!("Image sizes differ: {}x{} and {}x{}", a.width, a.height, b.width, b.height));
+    }
+    // Linear values keep the differences above 1 of HDR images
+    let (va, vb) = match args.opt_present("display") {
+        true => (a.pixels.iter().map(|v| to_display(*v)).collect(), b.pixels.iter().map(|v| to_display(*v)).collect()),
+        false => (a.pixels.clone(), b.pixels.clone()),
+    };
+    let peak = va.iter().chain(&vb).fold(1.0f32, |m, v| m.max(*v)) as f64;
+    let mut heatmap = Image::new(a.width, a.height);
+    let errors: Vec<f32> = va.chunks(3).zip(vb.chunks(3)).map(|(pa, pb)| rmse(pa, pb) as f32).collect();
+    let rms = rmse(&va, &vb);
+    let psnr = if rms == 0.0 { f64::INFINITY } else { 20.0 * (peak / rms).log10() };
+    let max_err = errors.iter().cloned().fold(0.0, f32::max);
+    for (px, e) in heatmap.pixels.chunks_mut(3).zip(errors.iter()) {
+        let c = heat(if max_err > 0.0 { e / max_err } else { 0.0 });
+        // Image stores linear values, undo the gamma applied on save
+        for k in 0..3 {
+            px[k] = c[k] * c[k];
+        }
+    }
+    let output = PathBuf::from(args.opt_str("o").unwrap_or_else(|| "diff.png".to_string()));
+    let format = Format::from_path(&output).ok_or(format!("Can't guess image format of {}", output.display()))?;
+    heatmap.save(&output, format)?;
+    println!("RMSE: {:.6}", rms);
+    println!("PSNR: {:.2} dB (peak {})", psnr, peak);
+    println!("SSIM: {:.4}", ssim(&luminance(&va), &luminance(&vb), a.width, a.height, peak));
+    println!("Heatmap: {} (max error {:.4})", output.display(), max_err);
+    Ok(())
+}
+
+pub fn bench(args: Vec<String>) -> Result<(), String> {
+    let mut opts = getopts::Options::new();
+    opts.optopt("w", "width", "Image width, default 320", "WIDTH");
+    opts.optopt("s", "samples", "Pixel samples (squared), default 2", "SAMPLES");
+    opts.optopt("t", "threads", "Number of threads", "THREADS");
+    opts.optopt("b", "bucket", "Bucket size", "BUCKET");
+    let args = match parse(&mut opts, args, "Usage: sprios bench [options] [SCENE...]")? {
+        Some(args) => args,
+        None => return Ok(()),
+    };
+    let settings = SettingsBuilder::new()
+        .size(opt_value(&args, "width", 320)?, None)
+        .samples(opt_value(&args, "samples", 2)?)
+        .bucket(opt_value(&args, "bucket", 32)?)
+        .build();
+    let num_threads: usize = opt_value(&args, "threads", num_cpus::get())?;
+    let mut scenes: Vec<String> = vec!["@book".into(), "@final".into(), "@ivan".into()];
+    scenes.extend(args.free.iter().cloned());
+
+    println!("{}x{}, {} samples, {} threads", settings.width, settings.height, settings.samples.pow(2), num_threads);
+    println!("{:<20} {:>10} {:>12} {:>10}", "Scene", "Time (s)", "Rays", "Mrays/s");
+    for scene in &scenes {
+        let mut world = load_scene(scene)?;
+        world.camera.set_aspect_ratio(settings.width as f32 / settings.height as f32);
+        let mut film = vec![0.0f32; (settings.width * settings.height * 3) as usize];
+        let film_ptr = Arc::new(AtomicPtr::new(film.as_mut_ptr()));
+        let stat = render(settings, film_ptr, num_threads, Arc::new(world), |_| {});
+        println!("{:<20} {:>10.3} {:>12} {:>10.2}", scene, stat.render_time, stat.num_ray_shot, stat.mrays);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        assert_eq!(rmse(&[0.0, 0.0, 0.0, 0.0], &[0.5, 0.5, 0.5, 0.5]), 0.5);
+        // Above 1 the display values are clipped alike, the linear ones still differ
+        let (a, b) = ([2.0f32; 3], [4.0f32; 3]);
+        assert_eq!(rmse(&a, &b), 2.0);
+        let display = |v: &[f32]| v.iter().map(|v| to_display(*v)).collect::<Vec<_>>();
+        assert_eq!(rmse(&display(&a), &display(&b)), 0.0);
+
+        let flat = vec![0.0f32; 64];
+        assert!((ssim(&flat, &flat, 8, 8, 1.0) - 1.0).abs() < 1e-9);
+        // Flat windows only differ in the mean, C1 / (mb² + C1) with mb = 0.01 is one half
+        let lifted = vec![0.01f32; 64];
+        assert!((ssim(&flat, &lifted, 8, 8, 1.0) - 0.5).abs() < 1e-6);
+    }
+}
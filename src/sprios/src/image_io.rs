@@ -0,0 +1,183 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Ppm,
+    Pfm,
+    Png,
+    Exr,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "ppm" => Some(Format::Ppm),
+            "pfm" => Some(Format::Pfm),
+            "png" => Some(Format::Png),
+            "exr" => Some(Format::Exr),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Format> {
+        path.extension().and_then(|e| e.to_str()).and_then(Format::from_name)
+    }
+}
+
+/// Linear RGB image, rows top to bottom.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<f32>,
+}
+
+/// Linear value to the gamma 2 display space used by the viewer, clipped to [0, 1]
+#[inline]
+pub fn to_display(v: f32) -> f32 {
+    v.max(0.0).sqrt().min(1.0)
+}
+
+#[inline]
+fn to_byte(v: f32) -> u8 {
+    (256.0 * to_display(v).min(0.999)) as u8
+}
+
+impl Image {
+    pub fn from_film(film: &[f32], width: u32, height: u32, samples: u32) -> Image {
+        let scale = 1.0 / samples.max(1) as f32;
+        Image {
+            width,
+            height,
+            pixels: film.iter().map(|v| v * scale).collect(),
+        }
+    }
+
+    pub fn new(width: u32, height: u32) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![0.0; (width * height * 3) as usize],
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Image, String> {
+        let err = |e: &dyn std::fmt::Display| format!("Could not read {}: {}", path.display(), e);
+        match Format::from_path(path) {
+            Some(Format::Pfm) => Self::read_pfm(path).map_err(|e| err(&e)),
+            Some(format) => {
+                let img = image::open(path).map_err(|e| err(&e))?;
+                let (width, height) = (img.width(), img.height());
+                let pixels = if format == Format::Exr {
+                    img.into_rgb32f().into_raw()
+                } else {
+                    // 8 bit images carry the gamma of the writer
+                    img.into_rgb8().into_raw().iter().map(|b| (*b as f32 / 255.0).powi(2)).collect()
+                };
+                Ok(Image { width, height, pixels })
+            }
+            None => Err(err(&"unknown image format")),
+        }
+    }
+
+    pub fn save(&self, path: &Path, format: Format) -> Result<(), String> {
+        let err = |e: &dyn std::fmt::Display| format!("Could not write {}: {}", path.display(), e);
+        match format {
+            Format::Pfm => self.write_pfm(path).map_err(|e| err(&e)),
+            Format::Exr => {
+                let img = image::Rgb32FImage::from_raw(self.width, self.height, self.pixels.clone())
+                    .ok_or_else(|| err(&"bad image size"))?;
+                image::DynamicImage::ImageRgb32F(img)
+                    .save_with_format(path, image::ImageFormat::OpenExr)
+                    .map_err(|e| err(&e))
+            }
+            Format::Png | Format::Ppm => {
+                let bytes = self.pixels.iter().map(|v| to_byte(*v)).collect();
+                let img = image::RgbImage::from_raw(self.width, self.height, bytes)
+                    .ok_or_else(|| err(&"bad image size"))?;
+                let fmt = if format == Format::Png { image::ImageFormat::Png } else { image::ImageFormat::Pnm };
+                img.save_with_format(path, fmt).map_err(|e| err(&e))
+            }
+        }
+    }
+
+    fn read_pfm(path: &Path) -> std::io::Result<Image> {
+        use std::io::{Error, ErrorKind};
+        let bad = |what: &str| Error::new(ErrorKind::InvalidData, what.to_string());
+        let mut r = BufReader::new(File::open(path)?);
+        let mut header = Vec::new();
+        // Magic, size and scale, each terminated by a single whitespace
+        while header.len() < 3 {
+            let mut line = String::new();
+            if r.read_line(&mut line)? == 0 {
+                return Err(bad("truncated header"));
+            }
+            header.extend(line.split_whitespace().map(str::to_string));
+        }
+        if header[0] != "PF" {
+            return Err(bad("only color PFM is supported"));
+        }
+        let width: u32 = header[1].parse().map_err(|_| bad("bad width"))?;
+        let height: u32 = header.get(2).and_then(|h| h.parse().ok()).ok_or_else(|| bad("bad height"))?;
+        let scale: f32 = match header.get(3) {
+            Some(s) => s.parse().map_err(|_| bad("bad scale"))?,
+            None => {
+                let mut line = String::new();
+                r.read_line(&mut line)?;
+                line.trim().parse().map_err(|_| bad("bad scale"))?
+            }
+        };
+        let size = (width as usize).checked_mul(height as usize).and_then(|n| n.checked_mul(3 * 4));
+        let size = size.ok_or_else(|| bad("image too large"))?;
+        // The header can claim any size, only keep what the file holds
+        let mut data = Vec::new();
+        r.take(size as u64).read_to_end(&mut data)?;
+        if data.len() < size {
+            return Err(bad("truncated data"));
+        }
+        let values: Vec<f32> = data
+            .chunks(4)
+            .map(|b| {
+                let b = [b[0], b[1], b[2], b[3]];
+                if scale < 0.0 { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) }
+            })
+            .collect();
+        let row = (width * 3) as usize;
+        let pixels = values.chunks(row).rev().flatten().copied().collect();
+        Ok(Image { width, height, pixels })
+    }
+
+    fn write_pfm(&self, path: &Path) -> std::io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        // Negative scale means little endian, scanlines go bottom to top
+        write!(w, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        let row = (self.width * 3) as usize;
+        for line in self.pixels.chunks(row).rev() {
+            for v in line {
+                w.write_all(&v.to_le_bytes())?;
+            }
+        }
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pfm() {
+        // Rows differ so a flipped image would not match, values above 1 survive
+        let img = Image { width: 2, height: 2, pixels: vec![0.0, 0.5, 1.0, 2.0, 4.0, 8.0, -1.0, 0.25, 16.0, 3.0, 2.0, 1.0] };
+        let path = std::env::temp_dir().join(format!("sprios_test_{}.pfm", std::process::id()));
+        img.save(&path, Format::Pfm).unwrap();
+        let back = Image::load(&path).unwrap();
+        std::fs::write(&path, b"PF\n100000 100000\n-1.0\n").unwrap();
+        let error = Image::load(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((back.width, back.height), (2, 2));
+        assert_eq!(back.pixels, img.pixels);
+        assert!(error.ends_with("truncated data"), "{}", error);
+    }
+}
@@ -1,8 +1,10 @@
 mod worlds;
+#[cfg(not(feature = "command"))]
 mod utils;
-use worlds::*;
-
-use renderer::{render, Camera, World, Lambertian, Sphere, Vec3, Point3, RenderSettings, SettingsBuilder};
+#[cfg(feature = "command")]
+mod cli;
+#[cfg(feature = "command")]
+mod image_io;
 
 #[cfg(not(feature = "command"))]
 mod app;
@@ -17,103 +19,39 @@ fn gui() {
     app.run(&args);
 }
 
-
 #[cfg(feature = "command")]
-fn cmd() {
-    use std::io::BufWriter;
-    use std::io::Write;
-    use std::sync::atomic::AtomicPtr;
-    use std::sync::Arc;
-
-    let args: Vec<String> = std::env::args().collect();
-    let mut opts = getopts::Options::new();
-    let aspect_ratio: f32 = 16.0 / 9.0;
-    opts.optopt("w", "width", "Image width", "WIDTH");
-    opts.optopt("s", "samples", "Pixel samples", "SAMPLES");
-    opts.optopt("t", "threads", "Number of threads", "THREADS");
-    opts.optopt("b", "bucket", "Bucket size", "BUCKET");
-    opts.optflag("h", "help", "print help");
-
-    let args = match opts.parse(args) {
-        Ok(m) => m,
-        Err(e) => { panic!(e.to_string()) }
-    };
-
-    if args.opt_present("h") {
-        println!("{}", opts.short_usage("SPRIOS"));
-        return;
+fn cmd() -> Result<(), String> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() {
+        println!("{}", cli::USAGE);
+        return Ok(());
     }
-
-    let image_width: u32 = match args.opt_str("w") {
-        Some(s) => { s.parse().unwrap() }
-        None => 720
-    };
-    let image_height: u32 = (image_width as f32 / aspect_ratio) as u32;
-    let samples: u32 = match args.opt_str("s") {
-        Some(s) => { s.parse().unwrap() }
-        None => 10
-    };
-    let bucket: u32 = match args.opt_str("b") {
-        Some(s) => { s.parse().unwrap() }
-        None => 32
-    };
-    let num_threads: usize = match args.opt_str("t") {
-        Some(s) => { s.parse().unwrap() }
-        None => num_cpus::get()
-    };
-
-    let pool = threadpool::ThreadPool::new(num_threads);
-    let rs = SettingsBuilder::new().size(image_width, None).bucket(bucket).samples(samples).build();
-
-    let mut img_buf: Vec<u8> = Vec::new();
-    img_buf.resize((rs.width * rs.height * 3) as usize, 0);
-    let img_ptr = Arc::new(AtomicPtr::new(img_buf.as_mut_ptr()));
-
-    let camera = Arc::new(Camera::new(
-        Point3::new(0.0, 0.0, 2.0),
-        Point3::new(0.0, 0.0, -1.0),
-        Vec3::new(0.0, 1.0, 0.0),
-        40,
-        16.0/9.0,
-        2.0,
-        rs.width as f32 / rs.height as f32));
-    // let world = Arc::new(world_book());
-    let world = Arc::new(world_ivan(&Vec3::new(-0.5, -0.5, -0.5), 0.5, 2, 3));
-    let stat = render(
-        rs,
-        img_ptr,
-        Some(&pool),
-        world,
-        camera,
-        |prog| {
-            eprint!("\rRendering: {}%", prog);
-            std::io::stderr().flush().unwrap();
-        },
-    );
-
-    eprintln!("\nSaving image.ppm");
-    use std::fs::File;
-    let f = File::create("image.ppm").expect("Could not create ppm");
-    let mut buf = BufWriter::with_capacity(img_buf.len(), &f);
-
-    writeln!(buf, "P3\n{} {}\n255", image_width, image_height).unwrap();
-
-    for mut i in 0..img_buf.len() / 3 {
-        i *= 3;
-        buf.write_fmt(format_args!(
-            "{} {} {}\n",
-            img_buf[i + 0],
-            img_buf[i + 1],
-            img_buf[i + 2]
-        )).unwrap();
+    let command = args[0].clone();
+    match command.as_str() {
+        "render" | "info" | "convert" | "diff" | "bench" => {
+            args.remove(0);
+        }
+        "-h" | "--help" | "help" => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        _ => {}
+    }
+    match command.as_str() {
+        "info" => cli::info(args),
+        "convert" => cli::convert(args),
+        "diff" => cli::diff(args),
+        "bench" => cli::bench(args),
+        _ => cli::render_cmd(args),
     }
-    buf.flush().unwrap();
-    eprintln!("{:?}", stat);
 }
 
 fn main() {
     #[cfg(feature = "command")]
-        cmd();
+    if let Err(e) = cmd() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
     #[cfg(not(feature = "command"))]
         gui();
 }
@@ -1,21 +1,26 @@
-use renderer::{Lambertian, Metal, Sphere, Vec3, Material, Color, Point3};
+use renderer::{Lambertian, Metal, ParticleCloud, Sphere, Vec3, Material, Color, Point3};
 pub use renderer::World;
 use rand;
 use rand::Rng;
+use std::convert::TryFrom;
 use std::sync::Arc;
 
 pub fn world_ivan(loc: &Vec3, rad: f32, splits: u32, recur: u32) -> World {
-    fn recurse(world: &mut World, loc: &Vec3, rad: f32, splits: u32, recur: u32) {
+    #[derive(Default)]
+    struct Particles {
+        positions: Vec<Point3>,
+        radii: Vec<f32>,
+        colors: Vec<Color>,
+    }
+    fn recurse(particles: &mut Particles, loc: &Vec3, rad: f32, splits: u32, recur: u32) {
         let mut rng = rand::thread_rng();
         for _ in 0..splits {
             let center = Vec3::random_in_unit_sphere(&mut rng).unit() * rad * 1.5 + loc;
-            world.add(Arc::new(Sphere::new(
-                center.clone(),
-                rad * 0.5,
-                Some(Box::new(Lambertian { color: (rng.gen(), rng.gen(), rng.gen()).into() })),
-            )));
+            particles.positions.push(center.clone());
+            particles.radii.push(rad * 0.5);
+            particles.colors.push((rng.gen(), rng.gen(), rng.gen()).into());
             if recur > 0 {
-                recurse(world, &center, rad * 0.5, splits, recur - 1);
+                recurse(particles, &center, rad * 0.5, splits, recur - 1);
             }
         }
     }
@@ -24,11 +29,17 @@ pub fn world_ivan(loc: &Vec3, rad: f32, splits: u32, recur: u32) -> World {
     world.add(Arc::new(Sphere::new(
         (0.0, -100.5, -1.0),
         100.0,
-        Some(Box::new(Lambertian {
+        Some(Arc::new(Lambertian {
             color: (0.5, 0.5, 0.5).into(),
         }),
         ))));
-    recurse(&mut world, loc, rad, splits, recur);
+    let mut particles = Particles::default();
+    recurse(&mut particles, loc, rad, splits, recur);
+    // One white material tinted by the particle colours
+    let mat = Arc::new(Lambertian { color: Color::ONE });
+    let cloud = ParticleCloud::new(particles.positions, particles.radii, Some(mat)).expect("a radius per particle");
+    let cloud = cloud.with_colors(particles.colors);
+    world.add(Arc::new(cloud));
     world
 }
 
@@ -38,7 +49,7 @@ pub fn world_book() -> World {
     world.add(Arc::new(Sphere::new(
         (0.0, -100.5, -1.0),
         100.0,
-        Some(Box::new(Lambertian {
+        Some(Arc::new(Lambertian {
             color: (0.5, 0.5, 0.5).into(),
         })),
     )));
@@ -46,7 +57,7 @@ pub fn world_book() -> World {
     world.add(Arc::new(Sphere::new(
         (-1.0, 0.0, -1.0),
         0.5,
-        Some(Box::new(Lambertian {
+        Some(Arc::new(Lambertian {
             color: (0.9, 0.1, 0.1).into(),
         })),
     )));
@@ -54,7 +65,7 @@ pub fn world_book() -> World {
     world.add(Arc::new(Sphere::new(
         (0.0, 0.0, -1.0),
         0.5,
-        Some(Box::new(Lambertian {
+        Some(Arc::new(Lambertian {
             color: (0.1, 0.9, 0.1).into(),
         }))),
     ));
@@ -62,7 +73,7 @@ pub fn world_book() -> World {
     world.add(Arc::new(Sphere::new(
         (1.0, 0.0, -1.0),
         0.5,
-        Some(Box::new(Lambertian {
+        Some(Arc::new(Lambertian {
             color: (0.1, 0.1, 0.9).into(),
         })),
     )));
@@ -80,23 +91,43 @@ pub fn final_world() -> World {
     world.add(Arc::new(Sphere::new(
         (0.0, -1000.0, 0.0),
         1000.0,
-        Some(Box::new(Lambertian {
+        Some(Arc::new(Lambertian {
             color: (0.5, 0.5, 0.5).into(),
         })),
     )));
+    // Small spheres share one diffuse, each metal keeps its own fuzz, all tinted per particle
+    let mut materials: Vec<Arc<dyn Material>> = vec![Arc::new(Lambertian { color: Color::ONE })];
+    let (mut positions, mut colors, mut ids) = (vec![], vec![], vec![]);
     for a in -5..5 {
         for b in -5..5 {
             let center = Vec3::new(a as f32 + 0.9 * rng.gen::<f32>(), 0.2, b as f32 + 0.9f32 * rng.gen::<f32>());
             if (&center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                let mat = match [Mats::Lambert, Mats::Metal].choose(&mut rng).unwrap() {
-                    Mats::Lambert => Box::new(Lambertian { color: Color::random(&mut rng) }) as Box<dyn Material>,
-                    Mats::Metal => Box::new(Metal { color: Color::random_in(0.5, 1.0, &mut rng), fuzz: rng.gen_range(0.0, 0.5) }) as Box<dyn Material>,
+                match [Mats::Lambert, Mats::Metal].choose(&mut rng).unwrap() {
+                    Mats::Lambert => {
+                        colors.push(Color::random(&mut rng));
+                        ids.push(0);
+                    }
+                    Mats::Metal => {
+                        colors.push(Color::random_in(0.5, 1.0, &mut rng));
+                        let metal = Arc::new(Metal { color: Color::ONE, fuzz: rng.gen_range(0.0, 0.5) });
+                        // Once the ids run out the last metal is shared by the rest
+                        match u16::try_from(materials.len()) {
+                            Ok(id) => {
+                                materials.push(metal);
+                                ids.push(id);
+                            }
+                            Err(_) => ids.push(u16::MAX),
+                        }
+                    }
                 };
-                world.add(Arc::new(Sphere { center: center.clone(), radius: 0.2, material: mat }));
+                positions.push(center);
             }
         }
     }
-    world.add(Arc::new(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, Some(Box::new(Lambertian { color: (0.4, 0.2, 0.1).into() })))));
-    world.add(Arc::new(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, Some(Box::new(Metal { color: (0.7, 0.6, 0.5).into(), fuzz: 0.0 })))));
+    let cloud = ParticleCloud::new(positions, vec![0.2], None).expect("one radius for all particles");
+    let cloud = cloud.with_colors(colors).with_materials(materials).with_material_ids(ids);
+    world.add(Arc::new(cloud));
+    world.add(Arc::new(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, Some(Arc::new(Lambertian { color: (0.4, 0.2, 0.1).into() })))));
+    world.add(Arc::new(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, Some(Arc::new(Metal { color: (0.7, 0.6, 0.5).into(), fuzz: 0.0 })))));
     world
 }